@@ -0,0 +1,127 @@
+//! The `frozen` list: records cfdns never creates, changes or deletes.
+//! Entries are a record name, `example.com`, a name and type,
+//! `example.com/MX`, or a Cloudflare record tag, `tag:keep`.
+
+use serde::Deserialize;
+
+use crate::models::Record;
+use crate::names::to_ascii;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "Vec<String>")]
+pub struct Frozen {
+    entries: Vec<Entry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Entry {
+    Name {
+        name: String,
+        record_type: Option<String>,
+    },
+    Tag(String),
+}
+
+impl Entry {
+    fn parse(text: &str) -> Result<Entry, String> {
+        if let Some(tag) = text.strip_prefix("tag:") {
+            return match tag.trim() {
+                "" => Err(format!("frozen entry {:?} lacks a tag", text)),
+                tag => Ok(Entry::Tag(tag.to_string())),
+            };
+        }
+        let (name, record_type) = match text.split_once('/') {
+            Some((name, record_type)) => (name, Some(record_type.trim().to_uppercase())),
+            None => (text, None),
+        };
+        Ok(Entry::Name {
+            name: to_ascii(name).map_err(|e| format!("frozen entry: {}", e))?,
+            record_type,
+        })
+    }
+
+    fn matches(&self, name: &str, record_type: &str, tags: &[String]) -> bool {
+        match self {
+            Entry::Name {
+                name: frozen,
+                record_type: frozen_type,
+            } => {
+                frozen.eq_ignore_ascii_case(name)
+                    && frozen_type.as_ref().is_none_or(|t| t == record_type)
+            }
+            // Cloudflare tags are `name` or `name:value`.
+            Entry::Tag(tag) => tags.iter().any(|t| {
+                t == tag
+                    || t.strip_prefix(tag.as_str())
+                        .is_some_and(|rest| rest.starts_with(':'))
+            }),
+        }
+    }
+}
+
+impl Frozen {
+    /// The empty list.
+    pub const fn new() -> Frozen {
+        Frozen {
+            entries: Vec::new(),
+        }
+    }
+
+    /// The `frozen` list of the config file.
+    pub fn parse(entries: &[String]) -> Result<Frozen, String> {
+        Ok(Frozen {
+            entries: entries
+                .iter()
+                .map(|entry| Entry::parse(entry))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Whether a `record_type` record called `name` with `tags` is frozen.
+    pub fn contains(&self, name: &str, record_type: &str, tags: &[String]) -> bool {
+        self.entries
+            .iter()
+            .any(|entry| entry.matches(name, record_type, tags))
+    }
+
+    /// Whether `record` is frozen.
+    pub fn contains_record(&self, record: &Record) -> bool {
+        let tags: Vec<String> = record
+            .tags
+            .iter()
+            .filter_map(|tag| tag.as_str().map(str::to_string))
+            .collect();
+        self.contains(&record.name, &record.type_field, &tags)
+    }
+}
+
+impl TryFrom<Vec<String>> for Frozen {
+    type Error = String;
+
+    fn try_from(entries: Vec<String>) -> Result<Self, Self::Error> {
+        Frozen::parse(&entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frozen_entries_match_names_types_and_tags() {
+        let frozen = Frozen::parse(&[
+            "Example.com/mx".to_string(),
+            "verify.example.com".to_string(),
+            "tag:keep".to_string(),
+        ])
+        .unwrap();
+        assert!(frozen.contains("example.com", "MX", &[]));
+        assert!(!frozen.contains("example.com", "A", &[]));
+        assert!(frozen.contains("verify.example.com", "TXT", &[]));
+        assert!(frozen.contains("home.example.com", "A", &["keep".to_string()]));
+        assert!(frozen.contains("home.example.com", "A", &["keep:forever".to_string()]));
+        assert!(!frozen.contains("home.example.com", "A", &["keeper".to_string()]));
+        assert!(Frozen::parse(&["tag:".to_string()]).is_err());
+        assert!(Frozen::parse(&["a b.example.com".to_string()]).is_err());
+    }
+}
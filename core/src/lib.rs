@@ -0,0 +1,310 @@
+//! What cfdns decides about records, apart from how it talks to Cloudflare:
+//! which records a run changes and how, and whether cfdns owns them. There
+//! is no I/O, threading or blocking in here, so that it builds for
+//! wasm32-unknown-unknown as well and runs inside a Cloudflare Worker, see
+//! `worker/` and the `worker` feature.
+
+use std::net::IpAddr;
+
+use serde::{Deserialize, Serialize};
+
+pub mod frozen;
+pub mod models;
+pub mod names;
+#[cfg(feature = "worker")]
+mod worker;
+
+use frozen::Frozen;
+use models::Record;
+
+/// Put in the comment of records cfdns manages when `mark_records` is on.
+pub const OWNERSHIP_MARKER: &str = "managed-by=cfdns";
+
+/// Which other Cloudflare system owns `record`, if any. Changing such a
+/// record behind its owner's back breaks it, so it's left alone unless
+/// `--force` is given.
+pub fn foreign_owner(record: &Record) -> Option<&'static str> {
+    if record.meta.managed_by_argo_tunnel {
+        Some("Argo Tunnel")
+    } else if record.meta.managed_by_apps {
+        Some("Cloudflare Apps")
+    } else if record.meta.read_only == Some(true) {
+        Some("read only")
+    } else {
+        None
+    }
+}
+
+/// Whether the record's comment says cfdns owns it.
+pub fn is_marked(record: &Record) -> bool {
+    record
+        .comment
+        .as_deref()
+        .is_some_and(|comment| comment.contains(OWNERSHIP_MARKER))
+}
+
+/// `comment` with the ownership marker added, keeping what was there.
+pub fn marked_comment(comment: Option<&str>) -> String {
+    match comment.map(str::trim) {
+        Some(comment) if comment.contains(OWNERSHIP_MARKER) => comment.to_string(),
+        Some(comment) if !comment.is_empty() => format!("{} {}", comment, OWNERSHIP_MARKER),
+        _ => OWNERSHIP_MARKER.to_string(),
+    }
+}
+
+/// One change of a record, addressed by a JSON Pointer
+/// `/<zone>/<name>/<type>/content`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Operation {
+    pub op: &'static str,
+    pub path: String,
+    pub value: String,
+    /// What Cloudflare had before, not part of RFC 6902
+    pub old: String,
+}
+
+/// Escapes a JSON Pointer reference token, RFC 6901.
+fn token(text: &str) -> String {
+    text.replace('~', "~0").replace('/', "~1")
+}
+
+impl Operation {
+    pub fn content(zone: &str, name: &str, record_type: &str, old: &str, value: &str) -> Operation {
+        Operation::attribute(zone, name, record_type, "content", old, value)
+    }
+
+    pub fn attribute(
+        zone: &str,
+        name: &str,
+        record_type: &str,
+        attribute: &str,
+        old: &str,
+        value: &str,
+    ) -> Operation {
+        Operation {
+            op: "replace",
+            path: format!(
+                "/{}/{}/{}/{}",
+                token(zone),
+                token(name),
+                token(record_type),
+                token(attribute)
+            ),
+            value: value.to_string(),
+            old: old.to_string(),
+        }
+    }
+}
+
+/// `record` with the TTL and proxied flag of its zone, `proxied` and `ttl`
+/// being what the zone asks for.
+pub fn wanted(proxied: Option<bool>, ttl: Option<i64>, record: &Record) -> Record {
+    let mut wanted = record.clone();
+    wanted.proxied = proxied.unwrap_or(record.proxied);
+    // Proxied records always have the automatic TTL.
+    if wanted.proxied {
+        wanted.ttl = 1;
+    } else if let Some(ttl) = ttl {
+        wanted.ttl = ttl;
+    }
+    wanted
+}
+
+/// How the TTL and proxied flag of `record` in `zone` have to change to
+/// become those of `wanted`.
+pub fn attribute_changes(zone: &str, record: &Record, wanted: &Record) -> Vec<Operation> {
+    let change = |attribute, old: String, value: String| {
+        (old != value).then(|| {
+            let (name, record_type) = (&record.name, &record.type_field);
+            Operation::attribute(zone, name, record_type, attribute, &old, &value)
+        })
+    };
+    let proxied = change(
+        "proxied",
+        record.proxied.to_string(),
+        wanted.proxied.to_string(),
+    );
+    let ttl = change("ttl", record.ttl.to_string(), wanted.ttl.to_string());
+    proxied.into_iter().chain(ttl).collect()
+}
+
+/// A zone as the Worker is configured with it, like `[[zones]]` of cfdns.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Zone {
+    pub id: String,
+    pub records: Vec<String>,
+    #[serde(default)]
+    pub proxied: Option<bool>,
+    #[serde(default)]
+    pub ttl: Option<i64>,
+    /// Only change records carrying the ownership marker
+    #[serde(default)]
+    pub safe_mode: bool,
+    /// Records never to change, like `frozen` of cfdns
+    #[serde(default)]
+    pub frozen: Frozen,
+    /// Change records other Cloudflare systems own, like `--force`
+    #[serde(default)]
+    pub force: bool,
+}
+
+/// A record to be changed, to `content`, `proxied` and `ttl`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Update {
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub record_type: String,
+    pub content: String,
+    pub proxied: bool,
+    pub ttl: i64,
+    pub changes: Vec<Operation>,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Plan {
+    pub updates: Vec<Update>,
+    /// Configured names without a record of the type
+    pub missing: Vec<String>,
+    /// Names of outdated records cfdns must not change, with the reason
+    pub skipped: Vec<(String, String)>,
+}
+
+/// Why cfdns must not change `record`, if it must not: it is `frozen`, it
+/// has a [`foreign_owner`] and `force` isn't given, or `safe_mode` is on and
+/// it isn't [`is_marked`].
+pub fn refusal(record: &Record, frozen: bool, force: bool, safe_mode: bool) -> Option<String> {
+    if frozen {
+        return Some("it is on the frozen list".to_string());
+    }
+    match foreign_owner(record) {
+        Some(owner) if !force => {
+            return Some(format!(
+                "it is managed by {}; pass --force to override",
+                owner
+            ))
+        }
+        _ => {}
+    }
+    (safe_mode && !is_marked(record)).then(|| {
+        format!(
+            "safe mode is on and its comment lacks the {} marker",
+            OWNERSHIP_MARKER
+        )
+    })
+}
+
+/// What a run does to point the configured names of `zone` at `ip`, given
+/// the `records` Cloudflare lists for the zone. Fails if a configured name
+/// is not a valid domain name.
+pub fn plan(zone: &Zone, records: &[Record], ip: IpAddr) -> Result<Plan, String> {
+    let record_type = match ip {
+        IpAddr::V4(_) => "A",
+        IpAddr::V6(_) => "AAAA",
+    };
+    let content = ip.to_string();
+    let mut plan = Plan::default();
+    for name in &zone.records {
+        let name = names::to_ascii(name)?;
+        let Some(record) = names::find(records, &name, record_type) else {
+            plan.missing.push(name);
+            continue;
+        };
+        let wanted = wanted(zone.proxied, zone.ttl, record);
+        let mut changes = attribute_changes(&zone.id, record, &wanted);
+        if record.ip_addr != content {
+            let change = Operation::content(
+                &zone.id,
+                &record.name,
+                record_type,
+                &record.ip_addr,
+                &content,
+            );
+            changes.insert(0, change);
+        }
+        if changes.is_empty() {
+            continue;
+        }
+        let frozen = zone.frozen.contains_record(record);
+        if let Some(reason) = refusal(record, frozen, zone.force, zone.safe_mode) {
+            plan.skipped.push((record.name.clone(), reason));
+            continue;
+        }
+        plan.updates.push(Update {
+            id: record.id.clone(),
+            name: record.name.clone(),
+            record_type: record_type.to_string(),
+            content: content.clone(),
+            proxied: wanted.proxied,
+            ttl: wanted.ttl,
+            changes,
+        });
+    }
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_operation_path_is_a_json_pointer() {
+        let operation =
+            Operation::content("z", "home.example.com", "A", "192.0.2.7", "198.51.100.1");
+        assert_eq!(operation.path, "/z/home.example.com/A/content");
+        assert_eq!(token("a/b~c"), "a~1b~0c");
+        assert_eq!(
+            serde_json::to_string(&operation).unwrap(),
+            r#"{"op":"replace","path":"/z/home.example.com/A/content","value":"198.51.100.1","old":"192.0.2.7"}"#
+        );
+    }
+
+    #[test]
+    fn test_plan_updates_only_outdated_records_it_may_change() {
+        let record = |name: &str, content: &str| Record {
+            id: format!("id-{}", name),
+            name: name.to_string(),
+            type_field: "A".to_string(),
+            ip_addr: content.to_string(),
+            ttl: 1,
+            ..Default::default()
+        };
+        let mut tunnel = record("tunnel.example.com", "192.0.2.7");
+        tunnel.meta.managed_by_argo_tunnel = true;
+        let records = [
+            record("home.example.com", "192.0.2.7"),
+            record("nas.example.com", "198.51.100.1"),
+            record("mail.example.com", "192.0.2.7"),
+            tunnel,
+        ];
+        let zone = Zone {
+            id: "z".to_string(),
+            records: [
+                "Home.example.com.",
+                "nas.example.com",
+                "tunnel.example.com",
+                "mail.example.com",
+                "vpn.example.com",
+            ]
+            .map(String::from)
+            .to_vec(),
+            frozen: Frozen::parse(&["mail.example.com".to_string()]).unwrap(),
+            ..Zone::default()
+        };
+        let plan = plan(&zone, &records, "198.51.100.1".parse().unwrap()).unwrap();
+        assert_eq!(plan.updates.len(), 1);
+        assert_eq!(plan.updates[0].id, "id-home.example.com");
+        assert_eq!(plan.updates[0].content, "198.51.100.1");
+        assert_eq!(plan.missing, vec!["vpn.example.com"]);
+        assert_eq!(plan.skipped[0].0, "tunnel.example.com");
+        assert_eq!(plan.skipped[1].0, "mail.example.com");
+        assert!(plan.skipped[1].1.contains("frozen"));
+
+        let zone = Zone {
+            force: true,
+            ..zone
+        };
+        let plan = super::plan(&zone, &records, "198.51.100.1".parse().unwrap()).unwrap();
+        assert_eq!(plan.updates[1].id, "id-tunnel.example.com");
+    }
+}
@@ -0,0 +1,54 @@
+//! The DNS records of the Cloudflare API. Parsing is lenient: fields
+//! Cloudflare adds are ignored and fields it leaves out get their defaults.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(rename_all = "camelCase")]
+#[serde(rename = "result")]
+pub struct Record {
+    pub id: String,
+    #[serde(rename = "zone_id")]
+    pub zone_id: String,
+    #[serde(rename = "zone_name")]
+    pub zone_name: String,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub type_field: String,
+    #[serde(rename = "content")]
+    pub ip_addr: String,
+    pub proxiable: bool,
+    pub proxied: bool,
+    pub ttl: i64,
+    pub locked: bool,
+    pub meta: Meta,
+    pub comment: Option<String>,
+    pub tags: Vec<Value>,
+    #[serde(rename = "created_on")]
+    pub created_on: String,
+    #[serde(rename = "modified_on")]
+    pub modified_on: String,
+    pub priority: Option<i64>,
+    /// The configured account the record was listed with, not Cloudflare's
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(rename_all = "camelCase")]
+pub struct Meta {
+    #[serde(rename = "auto_added")]
+    pub auto_added: bool,
+    #[serde(rename = "managed_by_apps")]
+    pub managed_by_apps: bool,
+    #[serde(rename = "managed_by_argo_tunnel")]
+    pub managed_by_argo_tunnel: bool,
+    pub source: String,
+    #[serde(rename = "email_routing")]
+    pub email_routing: Option<bool>,
+    #[serde(rename = "read_only")]
+    pub read_only: Option<bool>,
+}
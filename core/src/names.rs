@@ -0,0 +1,29 @@
+//! Record names as Cloudflare has them: lower case ASCII, with
+//! internationalized labels in punycode (`xn--...`) and without a trailing
+//! dot. Configured names are brought into this form once, and then compared
+//! with what Cloudflare lists as they are.
+
+use crate::models::Record;
+
+/// `name` in the form Cloudflare uses, e.g. `bücher.example.com.` becomes
+/// `xn--bcher-kva.example.com`.
+pub fn to_ascii(name: &str) -> Result<String, String> {
+    let trimmed = name.trim().trim_end_matches('.');
+    let invalid = || format!("{:?} is not a valid domain name", name);
+    let ascii = idna::domain_to_ascii(trimmed).map_err(|_| invalid())?;
+    match ascii
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '*' | '.'))
+    {
+        true => Ok(ascii),
+        false => Err(invalid()),
+    }
+}
+
+/// The `record_type` record called `name`, already [`to_ascii`], among
+/// `records`.
+pub fn find<'a>(records: &'a [Record], name: &str, record_type: &str) -> Option<&'a Record> {
+    records
+        .iter()
+        .find(|record| record.name == name && record.type_field == record_type)
+}
@@ -0,0 +1,17 @@
+//! [`plan`](crate::plan) for the Worker's JavaScript, which does the
+//! fetching: everything goes in and out as JSON.
+
+use wasm_bindgen::prelude::*;
+
+use crate::models::Record;
+use crate::Zone;
+
+/// The plan for `zone`, one entry of the Worker's `ZONES`, given the
+/// `records` Cloudflare listed for it and the `ip` the home machine reported.
+#[wasm_bindgen]
+pub fn plan(zone: &str, records: &str, ip: &str) -> Result<String, JsError> {
+    let zone: Zone = serde_json::from_str(zone)?;
+    let records: Vec<Record> = serde_json::from_str(records)?;
+    let plan = crate::plan(&zone, &records, ip.parse()?).map_err(|e| JsError::new(&e))?;
+    Ok(serde_json::to_string(&plan)?)
+}
@@ -0,0 +1,120 @@
+//! Keeping the IP include rule of a Zero Trust Access policy on the current
+//! IP, for "allow from home" policies.
+//!
+//! Single address IP rules (`/32` and `/128`) in the policy's include list
+//! are taken to be ours and replaced by the current IP. Ranges and all other
+//! kinds of rules are left alone.
+
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+use log::info;
+use serde_derive::Deserialize;
+use serde_json::{json, Value};
+
+use crate::cloudflare::CloudflareClient;
+use crate::sync::{target_report, RecordReport, SyncState};
+use crate::{send_api, Error};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccessPolicyConfig {
+    pub account_id: String,
+    pub policy_id: String,
+    /// For policies that belong to one application instead of being reusable
+    pub app_id: Option<String>,
+}
+
+fn policy_url(policy: &AccessPolicyConfig) -> String {
+    match &policy.app_id {
+        Some(app) => format!(
+            "/accounts/{}/access/apps/{}/policies/{}",
+            policy.account_id, app, policy.policy_id
+        ),
+        None => format!(
+            "/accounts/{}/access/policies/{}",
+            policy.account_id, policy.policy_id
+        ),
+    }
+}
+
+/// The address of an include rule, if it is an IP rule for a single host.
+fn single_address(rule: &Value) -> Option<IpAddr> {
+    let net: IpNet = rule["ip"]["ip"].as_str()?.parse().ok()?;
+    (net.prefix_len() == net.max_prefix_len()).then(|| net.addr())
+}
+
+/// `include` with its single address rules replaced by one for `ip`, or
+/// `None` when that is what it already has.
+fn with_address(include: &[Value], ip: IpAddr) -> Option<Vec<Value>> {
+    let addresses: Vec<IpAddr> = include.iter().filter_map(single_address).collect();
+    if addresses == [ip] {
+        return None;
+    }
+    let mut rules: Vec<Value> = include
+        .iter()
+        .filter(|rule| single_address(rule).is_none())
+        .cloned()
+        .collect();
+    rules.push(json!({ "ip": { "ip": IpNet::from(ip).to_string() } }));
+    Some(rules)
+}
+
+fn update(
+    cloudflare: &CloudflareClient,
+    policy: &AccessPolicyConfig,
+    ip: IpAddr,
+) -> Result<SyncState, Error> {
+    let url = policy_url(policy);
+    let mut current = send_api(cloudflare.get(&url), "Access policy request")?;
+    let include = current["include"].as_array().cloned().unwrap_or_default();
+    let Some(include) = with_address(&include, ip) else {
+        info!("Access policy {} already allows {}", policy.policy_id, ip);
+        return Ok(SyncState::InSync);
+    };
+
+    current["include"] = Value::Array(include);
+    let body = serde_json::to_string(&current)?;
+    send_api(cloudflare.put(&url).body(body), "Access policy update")?;
+    info!("Updated Access policy {} to allow {}", policy.policy_id, ip);
+    Ok(SyncState::Updated)
+}
+
+/// Brings every configured policy in line with `ip`.
+pub fn sync(
+    cloudflare: &CloudflareClient,
+    policies: &[AccessPolicyConfig],
+    ip: IpAddr,
+) -> Vec<RecordReport> {
+    policies
+        .iter()
+        .map(|policy| {
+            let result = update(cloudflare, policy, ip);
+            target_report(&policy.policy_id, "access policy".to_string(), ip, result)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_single_addresses_are_replaced() {
+        let include = vec![
+            json!({ "ip": { "ip": "203.0.113.7/32" } }),
+            json!({ "ip": { "ip": "198.51.100.0/24" } }),
+            json!({ "email": { "email": "me@example.com" } }),
+        ];
+        let ip: IpAddr = "203.0.113.9".parse().unwrap();
+        assert_eq!(
+            with_address(&include, ip).unwrap(),
+            vec![
+                json!({ "ip": { "ip": "198.51.100.0/24" } }),
+                json!({ "email": { "email": "me@example.com" } }),
+                json!({ "ip": { "ip": "203.0.113.9/32" } }),
+            ]
+        );
+        assert_eq!(with_address(&include, "203.0.113.7".parse().unwrap()), None);
+    }
+}
@@ -0,0 +1,100 @@
+//! Zone status. Cloudflare only serves the records of active zones: a
+//! `pending` zone still waits for its registrar to point at Cloudflare's
+//! name servers, and a `moved` one has left. Syncing warns about the former
+//! and refuses the latter, or with `--wait-active` waits for pending zones
+//! to become active, for automation that has only just added the zone.
+//!
+//! Zones found active are remembered in the ID cache, so routine runs don't
+//! ask again.
+
+use std::collections::BTreeSet;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use log::{info, warn};
+
+use crate::config::Settings;
+use crate::ids::IdCache;
+use crate::{get_zone, names, Error};
+
+const POLL_EVERY: Duration = Duration::from_secs(30);
+
+/// What a zone's `status` means for its records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Verdict {
+    Active,
+    /// Not served yet, but it will be
+    Pending,
+    /// Not served, and changing its records is a mistake
+    Refused,
+}
+
+fn verdict(status: &str) -> Verdict {
+    match status {
+        "active" => Verdict::Active,
+        "pending" | "initializing" => Verdict::Pending,
+        _ => Verdict::Refused,
+    }
+}
+
+/// Checks the status of every configured zone, waiting up to `wait` for
+/// pending ones if given.
+pub fn check(settings: &Settings, wait: Option<Duration>) -> Result<(), Error> {
+    let mut cache = IdCache::load(settings);
+    let ids: BTreeSet<&str> = settings.zones.iter().map(|z| z.id.as_str()).collect();
+    let deadline = wait.map(|wait| Instant::now() + wait);
+    for id in ids {
+        if cache.is_active(id, SystemTime::now()) {
+            continue;
+        }
+        loop {
+            let zone = get_zone(settings.client(id), id)?;
+            let name = names::display(&zone.name);
+            match (verdict(&zone.status), deadline) {
+                (Verdict::Active, _) => {
+                    cache.set_active(id, SystemTime::now());
+                    break;
+                }
+                (Verdict::Pending, None) => {
+                    warn!(
+                        "Zone {} is {}, Cloudflare won't serve its records until it is active",
+                        name, zone.status
+                    );
+                    break;
+                }
+                (Verdict::Pending, Some(deadline)) if Instant::now() + POLL_EVERY < deadline => {
+                    info!(
+                        "Zone {} is {}, waiting for it to become active",
+                        name, zone.status
+                    );
+                    thread::sleep(POLL_EVERY);
+                }
+                (Verdict::Pending, Some(_)) => {
+                    return Err(format!("Zone {} is still {}", name, zone.status).into());
+                }
+                (Verdict::Refused, _) => {
+                    return Err(format!(
+                        "Zone {} is {}, not touching its records",
+                        name, zone.status
+                    )
+                    .into());
+                }
+            }
+        }
+    }
+    cache.save();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_active_zones_are_served() {
+        assert_eq!(verdict("active"), Verdict::Active);
+        assert_eq!(verdict("pending"), Verdict::Pending);
+        assert_eq!(verdict("initializing"), Verdict::Pending);
+        assert_eq!(verdict("moved"), Verdict::Refused);
+    }
+}
@@ -0,0 +1,233 @@
+//! `cfdns adopt`: brings existing records under management without
+//! retyping them. The A/AAAA records of the zones that match every
+//! `--filter` and aren't managed yet are appended to the config file, as
+//! another `[[zones]]` entry for each zone.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use log::warn;
+use serde_derive::Serialize;
+
+use crate::cloudflare::models::Record;
+use crate::config::{Config, Settings};
+use crate::output::{self, Format};
+use crate::{list_dns_records, names, paths, Error};
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct AdoptArgs {
+    /// Only adopt records where FIELD contains TEXT (`FIELD~TEXT`) or is
+    /// VALUE (`FIELD=VALUE`), FIELD being name, type, content or comment;
+    /// may be given multiple times
+    #[arg(long, value_parser = parse_filter)]
+    filter: Vec<Filter>,
+
+    /// Only show what would be added to the config file
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Filter {
+    field: Field,
+    value: String,
+    /// `~`, rather than `=`
+    contains: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Name,
+    Type,
+    Content,
+    Comment,
+}
+
+fn parse_filter(text: &str) -> Result<Filter, String> {
+    let Some(at) = text.find(['~', '=']) else {
+        return Err(format!("{:?} is not FIELD~TEXT or FIELD=VALUE", text));
+    };
+    let field = match &text[..at] {
+        "name" => Field::Name,
+        "type" => Field::Type,
+        "content" => Field::Content,
+        "comment" => Field::Comment,
+        field => {
+            return Err(format!(
+                "Can't filter by {:?}, only by name, type, content or comment",
+                field
+            ))
+        }
+    };
+    Ok(Filter {
+        field,
+        value: text[at + 1..].to_string(),
+        contains: text[at..].starts_with('~'),
+    })
+}
+
+impl Filter {
+    fn matches(&self, record: &Record) -> bool {
+        let value = match self.field {
+            Field::Name => record.name.as_str(),
+            Field::Type => record.type_field.as_str(),
+            Field::Content => record.ip_addr.as_str(),
+            Field::Comment => record.comment.as_deref().unwrap_or_default(),
+        };
+        match (self.contains, self.field) {
+            (true, Field::Name) => names::matches(value, &self.value),
+            (true, _) => value.to_lowercase().contains(&self.value.to_lowercase()),
+            (false, Field::Name) => names::to_ascii(&self.value).is_ok_and(|name| name == value),
+            (false, _) => value.eq_ignore_ascii_case(&self.value),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Adopted<'a> {
+    zones: [AdoptedZone<'a>; 1],
+}
+
+#[derive(Debug, Serialize)]
+struct AdoptedZone<'a> {
+    id: &'a str,
+    records: Vec<&'a str>,
+}
+
+/// The `[[zones]]` entry managing `records` of zone `id`, called `name`.
+fn entry(id: &str, name: &str, records: &[Record]) -> String {
+    let mut names: Vec<&str> = records.iter().map(|r| r.name.as_str()).collect();
+    names.dedup();
+    let adopted = Adopted {
+        zones: [AdoptedZone { id, records: names }],
+    };
+    let toml = toml::to_string(&adopted).expect("adopted records are serializable");
+    format!("\n# {}, adopted by cfdns adopt\n{}", name, toml)
+}
+
+/// The config file `contents` with the adopted `entries` appended, as long
+/// as it stays valid.
+fn adopted_config(config: &Path, contents: &str, entries: &str) -> Result<String, Error> {
+    let adopted = format!("{}{}", contents, entries);
+    if let Err(e) = toml::from_str::<Config>(&adopted) {
+        return Err(format!(
+            "Adding the records would leave {} invalid, add them by hand: {}",
+            config.display(),
+            e
+        )
+        .into());
+    }
+    Ok(adopted)
+}
+
+/// The records of the zones to adopt, by zone, leaving out the managed ones.
+fn candidates(settings: &Settings, args: &AdoptArgs) -> Result<Vec<(String, Vec<Record>)>, Error> {
+    let mut adopted: Vec<(String, Vec<Record>)> = Vec::new();
+    for zone in &settings.zones {
+        if adopted.iter().any(|(id, _)| *id == zone.id) {
+            continue;
+        }
+        if zone.account.is_some() {
+            warn!(
+                "Not adopting the records of {}, add them to its account's zones by hand",
+                zone.id
+            );
+            continue;
+        }
+        let managed = |record: &Record| {
+            settings
+                .zones
+                .iter()
+                .filter(|z| z.id == zone.id)
+                .any(|z| z.records.contains(&record.name))
+        };
+        let mut records: Vec<Record> = list_dns_records(settings.client(&zone.id), &zone.id)?
+            .into_iter()
+            .filter(|r| matches!(r.type_field.as_str(), "A" | "AAAA"))
+            .filter(|r| args.filter.iter().all(|filter| filter.matches(r)))
+            .filter(|r| !managed(r))
+            .collect();
+        records.sort_by(|a, b| a.name.cmp(&b.name));
+        adopted.push((zone.id.clone(), records));
+    }
+    Ok(adopted)
+}
+
+pub fn run(
+    settings: &Settings,
+    args: &AdoptArgs,
+    config: &Path,
+    format: Format,
+) -> Result<(), Error> {
+    if settings.zones.is_empty() {
+        return Err("No zone to adopt records from, pass --zone".into());
+    }
+    if config.extension().is_some_and(|e| e != "toml") || config.to_string_lossy().contains("://") {
+        return Err(format!(
+            "Records can only be adopted into a local TOML config file, not {}",
+            config.display()
+        )
+        .into());
+    }
+    let mut text = String::new();
+    let mut all = Vec::new();
+    for (zone, records) in candidates(settings, args)? {
+        let Some(first) = records.first() else {
+            continue;
+        };
+        text.push_str(&entry(&zone, &names::display(&first.zone_name), &records));
+        all.extend(records);
+    }
+    if all.is_empty() {
+        println!("No unmanaged A/AAAA records match");
+        return Ok(());
+    }
+    output::print(format, &all)?;
+    if args.dry_run {
+        print!("{}", text);
+        return Ok(());
+    }
+    let contents = match fs::read_to_string(config) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(format!("Could not read {}: {}", config.display(), e).into()),
+    };
+    paths::replace(config, adopted_config(config, &contents, &text)?)
+        .map_err(|e| format!("Could not write {}: {}", config.display(), e))?;
+    println!("Added {} records to {}", all.len(), config.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filters_and_entry() {
+        let record = Record {
+            name: "nas.example.com".to_string(),
+            type_field: "A".to_string(),
+            comment: Some("DDNS, set by hand".to_string()),
+            ..Default::default()
+        };
+        assert!(parse_filter("comment~ddns").unwrap().matches(&record));
+        assert!(parse_filter("type=a").unwrap().matches(&record));
+        assert!(parse_filter("name=NAS.example.com.")
+            .unwrap()
+            .matches(&record));
+        assert!(!parse_filter("name~vpn").unwrap().matches(&record));
+        assert!(parse_filter("ttl=300").is_err() && parse_filter("ddns").is_err());
+
+        let entry = entry("zone-a", "example.com", &[record]);
+        let config: crate::config::Config = toml::from_str(&entry).unwrap();
+        assert_eq!(config.zones[0].id, "zone-a");
+        assert_eq!(config.zones[0].records, vec!["nas.example.com"]);
+
+        let path = Path::new("config.toml");
+        let tables = "[[zones]]\nid = \"zone-b\"\nrecords = [\"vpn.example.com\"]\n";
+        assert!(adopted_config(path, tables, &entry).is_ok());
+        let inline = "zones = [{ id = \"zone-b\", records = [\"vpn.example.com\"] }]\n";
+        assert!(adopted_config(path, inline, &entry).is_err());
+    }
+}
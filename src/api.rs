@@ -0,0 +1,138 @@
+//! Optional HTTP status API served by the daemon.
+//!
+//! * `GET /` is a small dashboard built on top of `/status`
+//! * `GET /healthz` answers 200 while the last run succeeded, 503 otherwise
+//! * `GET /livez` answers 200 unless a run is hung, for liveness probes
+//! * `GET /readyz` answers 200 while the token works and the last run
+//!   succeeded, for readiness probes
+//! * `GET /status` returns the daemon [`Status`] as JSON
+//! * `POST /reconcile` schedules an immediate run
+//!
+//! When a token is configured every endpoint except the probes and the
+//! dashboard page itself requires an `Authorization: Bearer <token>` header.
+//! The dashboard picks the token up from the URL fragment, `/#token=...`.
+
+use std::io::Cursor;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use log::{info, warn};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::daemon::{Event, Status};
+use crate::token::Secret;
+use crate::Error;
+
+type HttpResponse = Response<Cursor<Vec<u8>>>;
+
+const DASHBOARD: &str = include_str!("dashboard.html");
+
+/// Starts serving on `address` in a background thread.
+pub fn serve(
+    address: &str,
+    token: Option<Secret<String>>,
+    events: Sender<Event>,
+    status: Arc<Mutex<Status>>,
+) -> Result<(), Error> {
+    let server = Server::http(address)
+        .map_err(|e| format!("could not serve status API on {}: {}", address, e))?;
+    info!("Serving status API on http://{}", address);
+
+    std::thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = respond(
+                &request,
+                token.as_ref().map(|t| t.expose().as_str()),
+                &events,
+                &status,
+            );
+            if let Err(e) = request.respond(response) {
+                warn!("Could not answer status API request: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+fn respond(
+    request: &Request,
+    token: Option<&str>,
+    events: &Sender<Event>,
+    status: &Arc<Mutex<Status>>,
+) -> HttpResponse {
+    let path = request.url().split('?').next().unwrap_or_default();
+    let authorization = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Authorization"))
+        .map(|header| header.value.as_str());
+    if !matches!(path, "/" | "/healthz" | "/livez" | "/readyz") && !authorized(authorization, token)
+    {
+        return text(401, "unauthorized");
+    }
+
+    match (request.method(), path) {
+        (Method::Get, "/") => Response::from_string(DASHBOARD)
+            .with_header(content_type("text/html; charset=utf-8"))
+            .with_status_code(200),
+        (Method::Get, "/healthz") => match status.lock().unwrap().healthy() {
+            true => text(200, "ok"),
+            false => text(503, "unhealthy"),
+        },
+        (Method::Get, "/livez") => match status.lock().unwrap().live(Instant::now()) {
+            true => text(200, "ok"),
+            false => text(503, "hung"),
+        },
+        (Method::Get, "/readyz") => match status.lock().unwrap().ready() {
+            true => text(200, "ok"),
+            false => text(503, "not ready"),
+        },
+        (Method::Get, "/status") => {
+            let body = serde_json::to_vec_pretty(&*status.lock().unwrap())
+                .expect("status is always serializable");
+            Response::from_data(body)
+                .with_header(content_type("application/json"))
+                .with_status_code(200)
+        }
+        (Method::Post, "/reconcile") => match events.send(Event::ReconcileNow) {
+            Ok(()) => text(202, "accepted"),
+            Err(_) => text(503, "shutting down"),
+        },
+        (_, "/" | "/healthz" | "/livez" | "/readyz" | "/status" | "/reconcile") => {
+            text(405, "method not allowed")
+        }
+        _ => text(404, "not found"),
+    }
+}
+
+fn authorized(authorization: Option<&str>, token: Option<&str>) -> bool {
+    match token {
+        Some(token) => authorization.and_then(|value| value.strip_prefix("Bearer ")) == Some(token),
+        None => true,
+    }
+}
+
+fn text(status: u16, body: &str) -> HttpResponse {
+    Response::from_string(body)
+        .with_header(content_type("text/plain; charset=utf-8"))
+        .with_status_code(status)
+}
+
+fn content_type(value: &str) -> Header {
+    Header::from_bytes("Content-Type", value).expect("valid header")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::authorized;
+
+    #[test]
+    fn test_bearer_token_check() {
+        assert!(authorized(None, None));
+        assert!(authorized(Some("Bearer s3cret"), Some("s3cret")));
+        assert!(!authorized(Some("Bearer wrong"), Some("s3cret")));
+        assert!(!authorized(Some("s3cret"), Some("s3cret")));
+        assert!(!authorized(None, Some("s3cret")));
+    }
+}
@@ -0,0 +1,279 @@
+//! `cfdns audit`: the DNS record changes in Cloudflare's audit log of each
+//! configured zone, each one marked with whether cfdns made it, going by its
+//! journal. Changes nobody journaled came from the dashboard, another tool
+//! or another token, which is where "who changed this record" starts.
+
+use std::collections::BTreeSet;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Utc};
+use log::info;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::cloudflare::CloudflareClient;
+use crate::config::Settings;
+use crate::journal::{self, Entry};
+use crate::output::{self, Format, Highlight, Tabular};
+use crate::{get_zone, names, send_api, Error};
+
+/// The audit log and the journal each take their own time, a change counts
+/// as cfdns's if both are this close.
+const WINDOW: Duration = Duration::from_secs(5 * 60);
+
+const PER_PAGE: usize = 100;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct AuditArgs {
+    /// How far back to look, e.g. 24h or 7d
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "24h")]
+    since: Duration,
+
+    /// Only show changes cfdns didn't make
+    #[arg(long)]
+    others: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+struct LogEntry {
+    action: Action,
+    actor: Actor,
+    interface: Option<String>,
+    metadata: Value,
+    resource: Resource,
+    when: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+struct Action {
+    #[serde(rename = "type")]
+    type_field: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+struct Actor {
+    email: Option<String>,
+    #[serde(rename = "type")]
+    type_field: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+struct Resource {
+    id: String,
+    #[serde(rename = "type")]
+    type_field: String,
+}
+
+impl LogEntry {
+    fn is_dns_record(&self) -> bool {
+        self.resource.type_field.to_lowercase().contains("dns")
+            || self.action.type_field.starts_with("rec_")
+    }
+
+    fn metadata(&self, key: &str) -> String {
+        self.metadata[key].as_str().unwrap_or_default().to_string()
+    }
+
+    fn actor(&self) -> String {
+        let actor = self
+            .actor
+            .email
+            .clone()
+            .unwrap_or(self.actor.type_field.clone());
+        match &self.interface {
+            Some(interface) if !interface.is_empty() => format!("{} ({})", actor, interface),
+            _ => actor,
+        }
+    }
+}
+
+/// A change from the audit log of a zone.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuditRow {
+    pub time: String,
+    pub actor: String,
+    pub action: String,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub record_type: String,
+    pub content: String,
+    pub by_cfdns: bool,
+}
+
+impl Tabular for AuditRow {
+    fn headers() -> Vec<&'static str> {
+        vec![
+            "TIME", "ACTOR", "ACTION", "NAME", "TYPE", "CONTENT", "CFDNS",
+        ]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.time.clone(),
+            self.actor.clone(),
+            self.action.clone(),
+            names::display(&self.name),
+            self.record_type.clone(),
+            names::display_content(&self.record_type, &self.content),
+            match self.by_cfdns {
+                true => "yes".to_string(),
+                false => "no".to_string(),
+            },
+        ]
+    }
+
+    fn highlights(&self) -> Vec<Option<Highlight>> {
+        let mut highlights = vec![None; 6];
+        highlights.push(Some(match self.by_cfdns {
+            true => Highlight::Green,
+            false => Highlight::Yellow,
+        }));
+        highlights
+    }
+}
+
+fn parse_time(text: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(text)
+        .ok()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+/// Whether the journal has a change to the same record of `zone` at about
+/// the time the audit log has `entry`.
+fn by_cfdns(entry: &LogEntry, zone: &str, history: &[Entry]) -> bool {
+    let Some(when) = parse_time(&entry.when) else {
+        return false;
+    };
+    let (name, record_type) = (entry.metadata("name"), entry.metadata("type"));
+    history.iter().any(|journaled| {
+        let record = journaled.record();
+        let same_record = (!entry.resource.id.is_empty() && record.id == entry.resource.id)
+            || (record.name == name && record.type_field == record_type);
+        let close = parse_time(&journaled.time)
+            .is_some_and(|time| (time - when).abs().to_std().is_ok_and(|gap| gap <= WINDOW));
+        journaled.zone == zone && same_record && close
+    })
+}
+
+/// The DNS record entries of the audit log of zone `name` in `account`
+/// since `since`, newest first.
+fn log_entries(
+    cloudflare: &CloudflareClient,
+    account: &str,
+    name: &str,
+    since: SystemTime,
+) -> Result<Vec<LogEntry>, Error> {
+    let since = humantime::format_rfc3339_seconds(since).to_string();
+    let mut entries = Vec::new();
+    for page in 1.. {
+        let request = cloudflare
+            .get(&format!("/accounts/{account}/audit_logs"))
+            .query(&[
+                ("zone.name", name),
+                ("since", &since),
+                ("direction", "desc"),
+                ("per_page", &PER_PAGE.to_string()),
+                ("page", &page.to_string()),
+            ]);
+        let listed: Vec<LogEntry> = serde_json::from_value(send_api(request, "Audit log request")?)
+            .map_err(|e| format!("could not parse the audit log: {}", e))?;
+        let done = listed.len() < PER_PAGE;
+        entries.extend(listed.into_iter().filter(LogEntry::is_dns_record));
+        if done {
+            break;
+        }
+    }
+    Ok(entries)
+}
+
+pub fn run(settings: &Settings, args: &AuditArgs, format: Format) -> Result<(), Error> {
+    if settings.zones.is_empty() {
+        return Err("No zone to audit, pass --zone or a --config file".into());
+    }
+    let history = journal::history(settings)?;
+    let since = SystemTime::now() - args.since;
+    let mut rows = Vec::new();
+    let zones: BTreeSet<&str> = settings.zones.iter().map(|z| z.id.as_str()).collect();
+    for id in zones {
+        let cloudflare = settings.client(id);
+        let zone = get_zone(cloudflare, id)?;
+        let Some(account) = zone.account.filter(|a| !a.id.is_empty()) else {
+            return Err(format!("Cloudflare did not say which account {} is in", zone.name).into());
+        };
+        info!("Reading the audit log of {}", zone.name);
+        for entry in log_entries(cloudflare, &account.id, &zone.name, since)? {
+            let by_cfdns = by_cfdns(&entry, id, &history);
+            if args.others && by_cfdns {
+                continue;
+            }
+            rows.push(AuditRow {
+                time: entry.when.clone(),
+                actor: entry.actor(),
+                action: entry.action.type_field.clone(),
+                name: entry.metadata("name"),
+                record_type: entry.metadata("type"),
+                content: entry.metadata("content"),
+                by_cfdns,
+            });
+        }
+    }
+    output::print(format, &rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cloudflare::models::Record;
+    use crate::journal::Change;
+
+    #[test]
+    fn test_changes_are_matched_with_the_journal() {
+        let entry: LogEntry = serde_json::from_value(serde_json::json!({
+            "action": { "type": "rec_set" },
+            "actor": { "email": "ops@example.com", "type": "user" },
+            "interface": "API",
+            "metadata": { "name": "home.example.com", "type": "A", "content": "198.51.100.7" },
+            "resource": { "id": "rec-1", "type": "DNS_record" },
+            "when": "2024-05-01T10:00:30Z"
+        }))
+        .unwrap();
+        assert!(entry.is_dns_record());
+        assert_eq!(entry.actor(), "ops@example.com (API)");
+
+        let journaled = |time: &str, id: &str| Entry {
+            id: 1,
+            time: time.to_string(),
+            zone: "zone".to_string(),
+            change: Change::Updated {
+                before: Record {
+                    id: id.to_string(),
+                    name: "home.example.com".to_string(),
+                    type_field: "A".to_string(),
+                    ..Default::default()
+                },
+                content: "198.51.100.7".to_string(),
+            },
+            undoes: None,
+        };
+        assert!(by_cfdns(
+            &entry,
+            "zone",
+            &[journaled("2024-05-01T10:00:00Z", "rec-1")]
+        ));
+        assert!(!by_cfdns(
+            &entry,
+            "other",
+            &[journaled("2024-05-01T10:00:00Z", "rec-1")]
+        ));
+        assert!(!by_cfdns(
+            &entry,
+            "zone",
+            &[journaled("2024-05-01T09:00:00Z", "rec-1")]
+        ));
+        assert!(!by_cfdns(&entry, "zone", &[]));
+    }
+}
@@ -0,0 +1,149 @@
+//! Reading what Cloudflare publishes for a record straight from the zone's
+//! authoritative name servers, which costs no API quota and sees changes as
+//! soon as Cloudflare serves them, without any resolver cache in between.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use log::debug;
+
+use crate::config::Settings;
+use crate::Error;
+use crate::{dns, doh};
+
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Name server addresses by zone apex, these hardly ever change.
+static NAME_SERVERS: Mutex<Option<HashMap<String, Vec<SocketAddr>>>> = Mutex::new(None);
+
+/// The apex candidates of `name`, longest first, leaving out the TLD.
+fn parents(name: &str) -> Vec<&str> {
+    let name = name.trim_end_matches('.');
+    let mut candidates = vec![name];
+    let mut rest = name;
+    while let Some((_, parent)) = rest.split_once('.') {
+        if !parent.contains('.') {
+            break;
+        }
+        candidates.push(parent);
+        rest = parent;
+    }
+    candidates
+}
+
+/// Finds the apex of the zone `name` is in through `resolver`, and the
+/// addresses of the name servers the apex delegates to.
+fn name_servers(resolver: SocketAddr, name: &str) -> Result<Vec<SocketAddr>, Error> {
+    for apex in parents(name) {
+        if let Some(known) = NAME_SERVERS
+            .lock()
+            .unwrap()
+            .get_or_insert_with(HashMap::new)
+            .get(apex)
+        {
+            return Ok(known.clone());
+        }
+        let hosts = dns::query_ns(resolver, apex, QUERY_TIMEOUT)?;
+        if hosts.is_empty() {
+            continue;
+        }
+        let mut servers = Vec::new();
+        for host in &hosts {
+            for address in dns::query(resolver, host, dns::TYPE_A, QUERY_TIMEOUT)? {
+                servers.push(SocketAddr::new(address, 53));
+            }
+        }
+        if servers.is_empty() {
+            return Err(format!("no addresses for the name servers of {}", apex).into());
+        }
+        debug!("Name servers of {}: {}", apex, hosts.join(", "));
+        NAME_SERVERS
+            .lock()
+            .unwrap()
+            .get_or_insert_with(HashMap::new)
+            .insert(apex.to_string(), servers.clone());
+        return Ok(servers);
+    }
+    Err(format!("could not find the zone {} belongs to", name).into())
+}
+
+/// The content the authoritative servers publish for `name`, or the
+/// DoH endpoint when `doh` is set, as name servers can't be reached then.
+fn published(
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+    resolver: SocketAddr,
+    name: &str,
+    record_type: &str,
+) -> Result<Vec<IpAddr>, Error> {
+    let qtype = dns::query_type(record_type);
+    if let Some(url) = &settings.doh {
+        return doh::query(client, url, name, qtype, QUERY_TIMEOUT);
+    }
+    let mut last_error = None;
+    for server in name_servers(resolver, name)? {
+        match dns::query(server, name, qtype, QUERY_TIMEOUT) {
+            Ok(answers) => return Ok(answers),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| "no name server answered".into()))
+}
+
+/// The TXT records the authoritative servers publish for `name`.
+pub fn txt(settings: &Settings, name: &str) -> Result<Vec<String>, Error> {
+    let Some(resolver) = settings.resolvers.first().copied() else {
+        return Err("no resolver to find the name servers with".into());
+    };
+    let mut last_error = None;
+    for server in name_servers(resolver, name)? {
+        match dns::query_txt(server, name, QUERY_TIMEOUT) {
+            Ok(answers) => return Ok(answers),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| "no name server answered".into()))
+}
+
+/// Whether every configured record is published as exactly `ip`. Any doubt,
+/// including proxied records that resolve to Cloudflare's edge, answers
+/// `false` so that the caller asks the API instead.
+pub fn all_published(client: &reqwest::blocking::Client, settings: &Settings, ip: IpAddr) -> bool {
+    let Some(resolver) = settings.resolvers.first().copied() else {
+        return false;
+    };
+    let record_type = crate::ip::record_type(ip);
+    settings
+        .zones
+        .iter()
+        .flat_map(|zone| &zone.records)
+        .all(
+            |name| match published(client, settings, resolver, name, record_type) {
+                Ok(answers) if answers == [ip] => true,
+                Ok(answers) => {
+                    debug!("{} is published as {:?}, checking the API", name, answers);
+                    false
+                }
+                Err(e) => {
+                    debug!("Could not look up {} directly: {}", name, e);
+                    false
+                }
+            },
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parents;
+
+    #[test]
+    fn test_apex_candidates() {
+        assert_eq!(
+            parents("home.lab.example.com."),
+            vec!["home.lab.example.com", "lab.example.com", "example.com"]
+        );
+        assert_eq!(parents("example.com"), vec!["example.com"]);
+    }
+}
@@ -0,0 +1,129 @@
+//! Circuit breaker for `ip_sources`. A source that failed `source_failures`
+//! times in a row is quarantined for `source_quarantine` and only asked again
+//! after that, once, as a probe. A failed probe doubles the quarantine, up to
+//! a day; an answer restores the source. So an echo service that is down for
+//! days costs one timeout per quarantine instead of one per check.
+//!
+//! The state lives as long as the process, which matters for the daemon.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+const MAX_QUARANTINE: Duration = Duration::from_secs(24 * 60 * 60);
+
+static BREAKERS: Mutex<Breakers> = Mutex::new(Breakers::new());
+
+#[derive(Debug, Default)]
+struct Source {
+    failures: u32,
+    quarantined_until: Option<Instant>,
+    quarantine: Duration,
+}
+
+#[derive(Debug)]
+struct Breakers {
+    sources: BTreeMap<String, Source>,
+}
+
+impl Breakers {
+    const fn new() -> Breakers {
+        Breakers {
+            sources: BTreeMap::new(),
+        }
+    }
+
+    fn allows(&self, source: &str, now: Instant) -> bool {
+        self.sources
+            .get(source)
+            .and_then(|s| s.quarantined_until)
+            .is_none_or(|until| now >= until)
+    }
+
+    fn succeeded(&mut self, source: &str) {
+        if let Some(state) = self.sources.remove(source) {
+            if state.quarantined_until.is_some() {
+                info!("IP source {} answers again, no longer skipping it", source);
+            }
+        }
+    }
+
+    fn failed(&mut self, source: &str, threshold: u32, quarantine: Duration, now: Instant) {
+        if threshold == 0 {
+            return;
+        }
+        let state = self.sources.entry(source.to_string()).or_default();
+        state.failures += 1;
+        if state.quarantined_until.is_some() {
+            state.quarantine = (state.quarantine * 2).min(MAX_QUARANTINE);
+            warn!(
+                "IP source {} still fails, skipping it for {}",
+                source,
+                humantime::format_duration(state.quarantine)
+            );
+        } else if state.failures >= threshold {
+            state.quarantine = quarantine;
+            warn!(
+                "IP source {} failed {} times in a row, skipping it for {}",
+                source,
+                state.failures,
+                humantime::format_duration(quarantine)
+            );
+        } else {
+            return;
+        }
+        state.quarantined_until = Some(now + state.quarantine);
+    }
+}
+
+/// Whether `source` may be asked now: it isn't quarantined, or its
+/// quarantine is over and it gets a probe.
+pub fn allows(source: &str) -> bool {
+    BREAKERS.lock().unwrap().allows(source, Instant::now())
+}
+
+pub fn succeeded(source: &str) {
+    BREAKERS.lock().unwrap().succeeded(source)
+}
+
+pub fn failed(source: &str, threshold: u32, quarantine: Duration) {
+    BREAKERS
+        .lock()
+        .unwrap()
+        .failed(source, threshold, quarantine, Instant::now())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sources_are_quarantined_and_probed() {
+        let minute = Duration::from_secs(60);
+        let now = Instant::now();
+        let mut breakers = Breakers::new();
+        breakers.failed("a", 2, minute, now);
+        assert!(breakers.allows("a", now));
+        breakers.failed("a", 2, minute, now);
+        assert!(!breakers.allows("a", now));
+        assert!(breakers.allows("b", now));
+
+        // The probe after a minute fails, so the next one is two minutes away.
+        let probe = now + minute;
+        assert!(breakers.allows("a", probe));
+        breakers.failed("a", 2, minute, probe);
+        assert!(!breakers.allows("a", probe + minute));
+        assert!(breakers.allows("a", probe + 2 * minute));
+
+        breakers.succeeded("a");
+        assert!(breakers.allows("a", probe));
+        breakers.failed("a", 2, minute, probe);
+        assert!(breakers.allows("a", probe));
+
+        breakers.failed("c", 0, minute, now);
+        breakers.failed("c", 0, minute, now);
+        assert!(breakers.allows("c", now));
+    }
+}
@@ -0,0 +1,497 @@
+//! The client all Cloudflare API requests go through. The token is set once
+//! as a default header when the client is built, and connections are kept
+//! alive from one request to the next. Everything else, such as asking echo
+//! services for the public IP, uses a plain client that never sees the token.
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use log::warn;
+
+use reqwest::blocking::{Client, ClientBuilder, RequestBuilder, Response};
+use reqwest::header::{self, HeaderMap, HeaderValue};
+use reqwest::{Method, StatusCode};
+use serde_json::Value;
+
+use crate::token::{self, Secret};
+use crate::trace::Traced;
+use crate::{Args, Error};
+
+pub mod models;
+
+pub const API: &str = "https://api.cloudflare.com/client/v4";
+
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// With `read_only`, [`send`] refuses every request that isn't a GET, so
+/// that nothing can change DNS whatever path leads there.
+pub fn set_read_only(read_only: bool) {
+    READ_ONLY.store(read_only, Ordering::Relaxed);
+}
+
+/// The method of `request` if it may change something. Requests that can't
+/// be inspected count as changing.
+fn mutating_method(request: &RequestBuilder) -> Option<Method> {
+    let method = request
+        .try_clone()
+        .and_then(|request| request.build().ok())
+        .map_or(Method::POST, |request| request.method().clone());
+    (method != Method::GET).then_some(method)
+}
+
+/// Why `request` may not be sent, if `read_only` is set and it may change
+/// something.
+pub fn refusal(request: &RequestBuilder, read_only: bool) -> Option<String> {
+    let method = mutating_method(request).filter(|_| read_only)?;
+    Some(format!(
+        "read_only is set, not sending a {} request to Cloudflare",
+        method
+    ))
+}
+
+/// How HTTP clients connect, as chosen on the command line.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Connection {
+    pub local_address: Option<IpAddr>,
+    pub interface: Option<String>,
+}
+
+impl Connection {
+    pub fn from_args(args: &Args) -> Connection {
+        // Binding to the unspecified address of a family only connects over it.
+        let local_address = args
+            .bind_address
+            .or(match (args.force_ipv4, args.force_ipv6) {
+                (true, _) => Some(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+                (_, true) => Some(IpAddr::V6(Ipv6Addr::UNSPECIFIED)),
+                _ => None,
+            });
+        Connection {
+            local_address,
+            interface: args.bind_interface.clone(),
+        }
+    }
+
+    pub fn builder(&self) -> Result<ClientBuilder, Error> {
+        let mut builder = Client::builder().redirect(reqwest::redirect::Policy::none());
+        if let Some(address) = self.local_address {
+            builder = builder.local_address(address);
+        }
+        if let Some(interface) = &self.interface {
+            builder = bind_interface(builder, interface)?;
+        }
+        Ok(builder)
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+fn bind_interface(builder: ClientBuilder, interface: &str) -> Result<ClientBuilder, Error> {
+    Ok(builder.interface(interface))
+}
+
+#[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+fn bind_interface(_: ClientBuilder, _: &str) -> Result<ClientBuilder, Error> {
+    Err("--bind-interface is only supported on Linux, use --bind-address".into())
+}
+
+#[derive(Clone, Default)]
+pub struct CloudflareClient {
+    http: Client,
+}
+
+impl CloudflareClient {
+    pub fn new(connection: &Connection, token: &Secret<String>) -> Result<CloudflareClient, Error> {
+        CloudflareClient::from_builder(connection.builder()?, token)
+    }
+
+    pub fn from_builder(
+        builder: ClientBuilder,
+        token: &Secret<String>,
+    ) -> Result<CloudflareClient, Error> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        headers.insert(header::AUTHORIZATION, token::bearer(token));
+        Ok(CloudflareClient {
+            http: builder.default_headers(headers).build()?,
+        })
+    }
+
+    /// A request to `path` of the API, e.g. `/zones`.
+    pub fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.http.request(method, format!("{API}{path}"))
+    }
+
+    pub fn get(&self, path: &str) -> RequestBuilder {
+        self.request(Method::GET, path)
+    }
+
+    pub fn post(&self, path: &str) -> RequestBuilder {
+        self.request(Method::POST, path)
+    }
+
+    pub fn put(&self, path: &str) -> RequestBuilder {
+        self.request(Method::PUT, path)
+    }
+
+    pub fn patch(&self, path: &str) -> RequestBuilder {
+        self.request(Method::PATCH, path)
+    }
+
+    pub fn delete(&self, path: &str) -> RequestBuilder {
+        self.request(Method::DELETE, path)
+    }
+}
+
+impl fmt::Debug for CloudflareClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CloudflareClient")
+    }
+}
+
+/// `error` with what caused it, which for connection failures is where the
+/// useful part is, e.g. `dns error: failed to lookup address information`.
+pub fn send_error(error: reqwest::Error) -> Error {
+    let mut message = format!("could not send request to cloudflare api: {}", error);
+    let mut source = std::error::Error::source(&error);
+    while let Some(cause) = source {
+        message.push_str(&format!(": {}", cause));
+        source = cause.source();
+    }
+    message.into()
+}
+
+/// Header Cloudflare's answers carry the Ray ID of the request in, which its
+/// support can look the request up by.
+const RAY_HEADER: &str = "cf-ray";
+
+pub fn ray_id(response: &Response) -> Option<String> {
+    let ray = response.headers().get(RAY_HEADER)?.to_str().ok()?;
+    Some(ray.to_string())
+}
+
+/// `message` with the Ray ID of the request it is about, if there is one.
+pub fn with_ray(message: impl fmt::Display, ray: Option<&str>) -> String {
+    match ray {
+        Some(ray) => format!("{} (cf-ray {})", message, ray),
+        None => message.to_string(),
+    }
+}
+
+/// The body of an API response and the Ray ID it came with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Answer {
+    pub body: String,
+    pub ray: Option<String>,
+}
+
+/// How often a request Cloudflare rate limited or failed with a 5xx is
+/// sent again, and how long it waits at most before each time.
+const RETRIES: u32 = 3;
+const MAX_RETRY_WAIT: Duration = Duration::from_secs(60);
+
+/// How long to wait before sending a request again that got `status`, going
+/// by `Retry-After` if Cloudflare sent it and doubling from a second if not.
+/// POSTs aren't sent again after a 5xx, they may have gone through.
+fn retry_wait(
+    method: &Method,
+    status: StatusCode,
+    headers: &HeaderMap,
+    attempt: u32,
+    now: DateTime<Utc>,
+) -> Option<Duration> {
+    let retried = status == StatusCode::TOO_MANY_REQUESTS
+        || (status.is_server_error() && method != Method::POST);
+    if !retried || attempt >= RETRIES {
+        return None;
+    }
+    let after = headers
+        .get(header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| match value.trim().parse::<u64>() {
+            Ok(seconds) => Some(Duration::from_secs(seconds)),
+            Err(_) => DateTime::parse_from_rfc2822(value)
+                .ok()
+                .map(|at| (at.with_timezone(&Utc) - now).to_std().unwrap_or_default()),
+        });
+    Some(
+        after
+            .unwrap_or(Duration::from_secs(1 << attempt))
+            .min(MAX_RETRY_WAIT),
+    )
+}
+
+/// What Cloudflare said about the failure, from its error envelope.
+fn envelope_message(body: &str) -> Option<String> {
+    let value: Value = serde_json::from_str(body).ok()?;
+    let messages: Vec<&str> = value["errors"]
+        .as_array()?
+        .iter()
+        .filter_map(|e| e["message"].as_str())
+        .collect();
+    (!messages.is_empty()).then(|| messages.join(", "))
+}
+
+/// Sends an API request and reads the answer, failing with
+/// [`token::Unauthorized`] on 401 and 403. Rate limited requests, and those
+/// failing with a 5xx, are sent again.
+pub fn send(request: RequestBuilder) -> Result<Answer, Error> {
+    if let Some(refusal) = refusal(&request, READ_ONLY.load(Ordering::Relaxed)) {
+        return Err(refusal.into());
+    }
+    let send_once = |request: RequestBuilder| {
+        request
+            .send_traced()
+            .map_err(send_error)
+            .and_then(token::check_authorized)
+    };
+    let method = request
+        .try_clone()
+        .and_then(|request| request.build().ok())
+        .map(|request| request.method().clone());
+    let mut attempt = 0;
+    let response = loop {
+        // Requests with a streamed body can't be sent twice.
+        let (Some(method), Some(retry)) = (&method, request.try_clone()) else {
+            break send_once(request)?;
+        };
+        let response = send_once(retry)?;
+        let status = response.status();
+        let Some(wait) = retry_wait(method, status, response.headers(), attempt, Utc::now()) else {
+            break response;
+        };
+        let ray = ray_id(&response);
+        let message = match response
+            .text()
+            .ok()
+            .and_then(|body| envelope_message(&body))
+        {
+            Some(said) => format!("Cloudflare answered {}: {}", status, said),
+            None => format!("Cloudflare answered {}", status),
+        };
+        warn!(
+            "{}, trying again in {}",
+            with_ray(message, ray.as_deref()),
+            humantime::format_duration(wait)
+        );
+        thread::sleep(wait);
+        attempt += 1;
+    };
+    let ray = ray_id(&response);
+    match response.text() {
+        Ok(body) => Ok(Answer { body, ray }),
+        Err(e) => {
+            let message = format!("could not get response body: {}", e);
+            Err(with_ray(message, ray.as_deref()).into())
+        }
+    }
+}
+
+/// A request Cloudflare answered with `success: false`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiError {
+    pub what: String,
+    pub errors: Vec<Value>,
+    pub ray: Option<String>,
+}
+
+impl ApiError {
+    pub fn new(what: &str, errors: Vec<Value>) -> ApiError {
+        ApiError {
+            what: what.to_string(),
+            errors,
+            ray: None,
+        }
+    }
+
+    pub fn with_ray(self, ray: Option<String>) -> ApiError {
+        ApiError { ray, ..self }
+    }
+
+    /// Cloudflare's codes for what went wrong, e.g. 9109 for a bad zone ID.
+    pub fn codes(&self) -> Vec<i64> {
+        self.errors
+            .iter()
+            .filter_map(|e| e["code"].as_i64())
+            .collect()
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let errors: Vec<String> = self
+            .errors
+            .iter()
+            .map(|e| match (e["code"].as_i64(), e["message"].as_str()) {
+                (Some(code), Some(message)) => format!("{} ({})", message, code),
+                _ => e.to_string(),
+            })
+            .collect();
+        let message = format!("{} failed: {}", self.what, errors.join(", "));
+        f.write_str(&with_ray(message, self.ray.as_deref()))
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn test_every_request_carries_the_token() {
+        let token = Secret::new("cf-token".to_string());
+        let client = CloudflareClient::from_builder(Client::builder(), &token).unwrap();
+        let request = client.get("/zones").build().unwrap();
+        assert_eq!(request.url().as_str(), format!("{API}/zones"));
+        assert!(!format!("{:?}", client).contains("cf-token"));
+
+        // Both requests arrive over the one connection, with the token.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let sent = std::thread::spawn(move || {
+            for _ in 0..2 {
+                client.http.get(&url).send().unwrap();
+            }
+        });
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        for _ in 0..2 {
+            let mut head = Vec::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                head.push(line.trim().to_lowercase());
+            }
+            assert!(head.contains(&"authorization: bearer cf-token".to_string()));
+            (&stream)
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .unwrap();
+        }
+        sent.join().unwrap();
+    }
+
+    #[test]
+    fn test_only_reads_pass_read_only() {
+        let client = CloudflareClient::default();
+        assert_eq!(mutating_method(&client.get("/zones")), None);
+        let update = client.put("/zones/z/dns_records/r").body("{}");
+        assert_eq!(mutating_method(&update), Some(Method::PUT));
+        assert_eq!(
+            mutating_method(&client.delete("/zones/z/dns_records/r")),
+            Some(Method::DELETE)
+        );
+    }
+
+    #[test]
+    fn test_rate_limited_requests_wait_and_retry() {
+        let now = DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let after = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::RETRY_AFTER, HeaderValue::from_str(value).unwrap());
+            headers
+        };
+        let limited = StatusCode::TOO_MANY_REQUESTS;
+        let wait = |method, status, headers: &HeaderMap, attempt| {
+            retry_wait(&method, status, headers, attempt, now)
+        };
+        assert_eq!(
+            wait(Method::POST, limited, &after("7"), 0),
+            Some(Duration::from_secs(7))
+        );
+        assert_eq!(
+            wait(
+                Method::GET,
+                limited,
+                &after("Wed, 01 May 2024 12:00:30 GMT"),
+                0
+            ),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            wait(Method::GET, limited, &after("3600"), 0),
+            Some(MAX_RETRY_WAIT)
+        );
+        let unavailable = StatusCode::SERVICE_UNAVAILABLE;
+        assert_eq!(
+            wait(Method::PUT, unavailable, &HeaderMap::new(), 2),
+            Some(Duration::from_secs(4))
+        );
+        assert_eq!(wait(Method::POST, unavailable, &HeaderMap::new(), 0), None);
+        assert_eq!(wait(Method::GET, limited, &HeaderMap::new(), RETRIES), None);
+        assert_eq!(
+            wait(Method::GET, StatusCode::OK, &HeaderMap::new(), 0),
+            None
+        );
+        assert_eq!(
+            envelope_message(
+                r#"{"success":false,"errors":[{"code":971,"message":"Please wait and consider throttling your request speed"}]}"#
+            ),
+            Some("Please wait and consider throttling your request speed".to_string())
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let served = std::thread::spawn(move || {
+            let answers: [&[u8]; 2] = [
+                b"HTTP/1.1 429 Too Many Requests\r\nretry-after: 0\r\ncontent-length: 0\r\n\r\n",
+                b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\n{}",
+            ];
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            for answer in answers {
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                stream.write_all(answer).unwrap();
+            }
+        });
+        assert_eq!(send(Client::new().get(&url)).unwrap().body, "{}");
+        served.join().unwrap();
+    }
+
+    #[test]
+    fn test_errors_carry_the_ray_id() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let served = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut line = String::new();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            stream
+                .write_all(b"HTTP/1.1 403 Forbidden\r\ncf-ray: 8a1b2c3d4e5f6789-AMS\r\ncontent-length: 0\r\n\r\n")
+                .unwrap();
+        });
+        let error = send(Client::new().get(&url)).unwrap_err();
+        served.join().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Cloudflare rejected the API token (403) (cf-ray 8a1b2c3d4e5f6789-AMS)"
+        );
+
+        let errors = vec![serde_json::json!({"code": 81057, "message": "Record already exists."})];
+        let error = ApiError::new("Record creation", errors).with_ray(Some("8a1b-AMS".to_string()));
+        assert_eq!(
+            error.to_string(),
+            "Record creation failed: Record already exists. (81057) (cf-ray 8a1b-AMS)"
+        );
+    }
+}
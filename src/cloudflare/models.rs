@@ -0,0 +1,229 @@
+//! What the Cloudflare API sends and takes, as far as cfdns uses it.
+//!
+//! Parsing is lenient: fields Cloudflare adds are ignored and fields it
+//! leaves out get their defaults, so that API changes don't break updates.
+//! `--strict-parse` turns both into errors instead, to find out what
+//! changed when something looks off.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::Error;
+
+pub use cfdns_core::models::Record;
+
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+/// Makes every later [`parse`] strict, for `--strict-parse`.
+pub fn set_strict(strict: bool) {
+    STRICT.store(strict, Ordering::Relaxed);
+}
+
+/// Parses an API response, strictly if `--strict-parse` asks for it.
+pub fn parse<T: DeserializeOwned + Serialize>(text: &str) -> Result<T, Error> {
+    parse_value(serde_json::from_str(text)?)
+}
+
+pub fn parse_value<T: DeserializeOwned + Serialize>(value: Value) -> Result<T, Error> {
+    parse_with(value, STRICT.load(Ordering::Relaxed))
+}
+
+/// Strict parsing parses leniently, then compares the fields of the result
+/// with those that came in: anything the model left out is unknown to it,
+/// anything but an empty optional field it has on top was missing from the
+/// response.
+fn parse_with<T: DeserializeOwned + Serialize>(value: Value, strict: bool) -> Result<T, Error> {
+    if !strict {
+        return Ok(serde_json::from_value(value)?);
+    }
+    let parsed: T = serde_json::from_value(value.clone())?;
+    let mut problems = Vec::new();
+    compare(&value, &serde_json::to_value(&parsed)?, "", &mut problems);
+    match problems.is_empty() {
+        true => Ok(parsed),
+        false => Err(format!("response does not match the model: {}", problems.join(", ")).into()),
+    }
+}
+
+fn compare(input: &Value, model: &Value, path: &str, problems: &mut Vec<String>) {
+    match (input, model) {
+        (Value::Object(input), Value::Object(model)) => {
+            for (key, value) in input {
+                let path = format!("{}/{}", path, key);
+                match model.get(key) {
+                    Some(known) => compare(value, known, &path, problems),
+                    None => problems.push(format!("unknown field {}", path)),
+                }
+            }
+            // Optional fields, null in the model, may be left out.
+            let missing = model
+                .iter()
+                .filter(|(key, value)| !value.is_null() && !input.contains_key(*key));
+            for (key, _) in missing {
+                problems.push(format!("missing field {}/{}", path, key));
+            }
+        }
+        (Value::Array(input), Value::Array(model)) => {
+            for (index, (value, known)) in input.iter().zip(model).enumerate() {
+                compare(value, known, &format!("{}/{}", path, index), problems);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(rename_all = "camelCase")]
+#[serde(rename = "Root")]
+pub struct Response {
+    #[serde(rename = "result")]
+    pub records: Vec<Record>,
+    pub success: bool,
+    pub errors: Vec<Value>,
+    pub messages: Vec<Value>,
+    #[serde(rename = "result_info")]
+    pub result_info: ResultInfo,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(rename_all = "camelCase")]
+pub struct ResultInfo {
+    pub page: i64,
+    #[serde(rename = "per_page")]
+    pub per_page: i64,
+    pub count: i64,
+    #[serde(rename = "total_count")]
+    pub total_count: i64,
+    #[serde(rename = "total_pages")]
+    pub total_pages: i64,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecordResponse {
+    #[serde(rename = "result")]
+    pub record: Option<Record>,
+    pub success: bool,
+    pub errors: Vec<Value>,
+    pub messages: Vec<Value>,
+}
+
+/// Body of a record to create.
+#[derive(Default, Debug, Clone, PartialEq, Serialize)]
+pub struct NewRecord {
+    #[serde(rename = "type")]
+    pub type_field: String,
+    pub name: String,
+    pub content: String,
+    pub proxied: bool,
+    pub ttl: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+/// Envelope of API responses whose result is handled as plain JSON.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApiResponse {
+    pub success: bool,
+    pub errors: Vec<Value>,
+    pub messages: Vec<Value>,
+    pub result: Value,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ZonesResponse {
+    #[serde(rename = "result")]
+    pub zones: Vec<Zone>,
+    pub success: bool,
+    pub errors: Vec<Value>,
+    pub messages: Vec<Value>,
+    #[serde(rename = "result_info")]
+    pub result_info: ResultInfo,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Zone {
+    pub id: String,
+    pub name: String,
+    pub status: String,
+    pub paused: bool,
+    #[serde(rename = "name_servers")]
+    pub name_servers: Vec<String>,
+    pub account: Option<ZoneAccount>,
+}
+
+/// The account a zone belongs to.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ZoneAccount {
+    pub id: String,
+    pub name: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RECORDS: &str = include_str!("fixtures/dns_records.json");
+    const RECORD_2024: &str = include_str!("fixtures/dns_record_2024.json");
+    const ZONES: &str = include_str!("fixtures/zones.json");
+
+    fn value(text: &str) -> Value {
+        serde_json::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn test_fixtures_parse_leniently() {
+        let records: Response = parse_with(value(RECORDS), false).unwrap();
+        assert_eq!(records.records.len(), 2);
+        assert_eq!(records.records[0].ip_addr, "198.51.100.4");
+        assert_eq!(records.records[1].priority, Some(10));
+        assert_eq!(records.result_info.total_pages, 1);
+
+        // Newer responses drop `locked` and the `meta` fields and add others.
+        let record: RecordResponse = parse_with(value(RECORD_2024), false).unwrap();
+        let record = record.record.unwrap();
+        assert!(!record.locked);
+        assert_eq!(record.meta, cfdns_core::models::Meta::default());
+
+        let zones: ZonesResponse = parse_with(value(ZONES), false).unwrap();
+        assert_eq!(zones.zones[0].name_servers.len(), 2);
+    }
+
+    #[test]
+    fn test_strict_parsing_names_the_differences() {
+        let records: Response = parse_with(value(RECORDS), true).unwrap();
+        assert_eq!(records.records.len(), 2);
+
+        let e = parse_with::<RecordResponse>(value(RECORD_2024), true).unwrap_err();
+        let message = e.to_string();
+        assert!(
+            message.contains("unknown field /result/settings"),
+            "{}",
+            message
+        );
+        assert!(
+            message.contains("missing field /result/locked"),
+            "{}",
+            message
+        );
+        assert!(
+            message.contains("missing field /result/meta/source"),
+            "{}",
+            message
+        );
+
+        let e = parse_with::<ZonesResponse>(value(ZONES), true).unwrap_err();
+        assert!(e
+            .to_string()
+            .contains("unknown field /result/0/development_mode"));
+    }
+}
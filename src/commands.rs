@@ -0,0 +1,379 @@
+//! Read-only commands: `status`, `list`, `zones` and `search`.
+
+use clap::ValueEnum;
+use log::info;
+use serde_derive::Serialize;
+
+use crate::cloudflare::models::{Record, Zone};
+use crate::config::Settings;
+use crate::output::{self, Format, Highlight, Selectable, Tabular};
+use crate::sync::fetch_managed;
+use crate::{dns_records, ip, list_dns_records, list_zones, names, Error};
+
+/// A configured record compared with the detected public IP.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StatusRow {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    pub zone: String,
+    pub name: String,
+    pub detected: String,
+    pub published: Option<String>,
+    pub ttl: Option<i64>,
+    pub proxied: Option<bool>,
+    pub in_sync: bool,
+}
+
+impl Tabular for StatusRow {
+    fn headers() -> Vec<&'static str> {
+        vec![
+            "ZONE",
+            "NAME",
+            "DETECTED",
+            "PUBLISHED",
+            "TTL",
+            "PROXIED",
+            "IN SYNC",
+        ]
+    }
+
+    fn row(&self) -> Vec<String> {
+        let missing = || "-".to_string();
+        vec![
+            self.zone.clone(),
+            names::display(&self.name),
+            self.detected.clone(),
+            self.published.clone().unwrap_or_else(missing),
+            self.ttl.map(output::ttl).unwrap_or_else(missing),
+            self.proxied.map(|p| p.to_string()).unwrap_or_else(missing),
+            match (self.in_sync, &self.published) {
+                (true, _) => "yes".to_string(),
+                (false, Some(_)) => "no".to_string(),
+                (false, None) => "missing".to_string(),
+            },
+        ]
+    }
+
+    fn highlights(&self) -> Vec<Option<Highlight>> {
+        let state = match (self.in_sync, &self.published) {
+            (true, _) => Highlight::Green,
+            (false, Some(_)) => Highlight::Yellow,
+            (false, None) => Highlight::Red,
+        };
+        let mut highlights = vec![None; 6];
+        highlights.push(Some(state));
+        highlights
+    }
+}
+
+/// A row with the account it is in as the first column, for configs with
+/// `accounts`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(transparent)]
+struct InAccount<T>(T);
+
+impl Tabular for InAccount<StatusRow> {
+    fn headers() -> Vec<&'static str> {
+        let mut headers = vec!["ACCOUNT"];
+        headers.extend(StatusRow::headers());
+        headers
+    }
+
+    fn row(&self) -> Vec<String> {
+        let mut row = vec![self.0.account.clone().unwrap_or_else(|| "-".to_string())];
+        row.extend(self.0.row());
+        row
+    }
+
+    fn highlights(&self) -> Vec<Option<Highlight>> {
+        let mut highlights = vec![None];
+        highlights.extend(self.0.highlights());
+        highlights
+    }
+}
+
+impl Tabular for Record {
+    fn headers() -> Vec<&'static str> {
+        vec!["NAME", "TYPE", "CONTENT", "TTL", "PROXIED"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            names::display(&self.name),
+            self.type_field.clone(),
+            names::display_content(&self.type_field, &self.ip_addr),
+            output::ttl(self.ttl),
+            self.proxied.to_string(),
+        ]
+    }
+}
+
+impl Tabular for Zone {
+    fn headers() -> Vec<&'static str> {
+        vec!["ID", "NAME", "STATUS"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.id.clone(),
+            names::display(&self.name),
+            self.status.clone(),
+        ]
+    }
+}
+
+/// `cfdns status`: compares every configured record with the current public
+/// IP without changing anything.
+pub fn status(
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+    format: Format,
+) -> Result<(), Error> {
+    settings.require_records()?;
+    let current_ip = ip::detect(client, settings)?;
+    let record_type = ip::record_type(current_ip);
+    let current_ip = current_ip.to_string();
+    let rows: Vec<StatusRow> = fetch_managed(settings, record_type)?
+        .into_iter()
+        .map(|managed| StatusRow {
+            account: settings.account(&managed.zone).map(str::to_string),
+            zone: managed.zone,
+            name: managed.name,
+            detected: current_ip.clone(),
+            in_sync: managed.record.as_ref().map(|r| &r.ip_addr) == Some(&current_ip),
+            published: managed.record.as_ref().map(|r| r.ip_addr.clone()),
+            ttl: managed.record.as_ref().map(|r| r.ttl),
+            proxied: managed.record.as_ref().map(|r| r.proxied),
+        })
+        .collect();
+    match settings.accounts.is_empty() {
+        true => output::print(format, &rows),
+        false => output::print(format, &rows.into_iter().map(InAccount).collect::<Vec<_>>()),
+    }
+}
+
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct ListArgs {
+    /// Only records of this type, e.g. A or CNAME
+    #[arg(long = "type")]
+    record_type: Option<String>,
+
+    /// Only records whose name contains this text
+    #[arg(long)]
+    name_contains: Option<String>,
+
+    /// Only records with exactly this content
+    #[arg(long)]
+    content: Option<String>,
+
+    /// Only proxied (true) or DNS only (false) records
+    #[arg(long)]
+    proxied: Option<bool>,
+
+    /// Sort records by this column
+    #[arg(long, value_enum, default_value_t)]
+    sort: Column,
+
+    /// Comma separated columns to show, e.g. name,content,modified
+    #[arg(long, value_enum, value_delimiter = ',')]
+    columns: Vec<Column>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Column {
+    #[default]
+    Name,
+    Type,
+    Content,
+    Ttl,
+    Proxied,
+    Zone,
+    Account,
+    Id,
+    Comment,
+    Modified,
+}
+
+impl Selectable<Record> for Column {
+    fn header(&self) -> &'static str {
+        match self {
+            Column::Name => "NAME",
+            Column::Type => "TYPE",
+            Column::Content => "CONTENT",
+            Column::Ttl => "TTL",
+            Column::Proxied => "PROXIED",
+            Column::Zone => "ZONE",
+            Column::Account => "ACCOUNT",
+            Column::Id => "ID",
+            Column::Comment => "COMMENT",
+            Column::Modified => "MODIFIED",
+        }
+    }
+
+    fn field(&self) -> &'static str {
+        match self {
+            Column::Name => "name",
+            Column::Type => "type",
+            Column::Content => "content",
+            Column::Ttl => "ttl",
+            Column::Proxied => "proxied",
+            Column::Zone => "zone_name",
+            Column::Account => "account",
+            Column::Id => "id",
+            Column::Comment => "comment",
+            Column::Modified => "modified_on",
+        }
+    }
+
+    fn show(&self, record: &Record) -> String {
+        match self {
+            Column::Name => names::display(&record.name),
+            Column::Type => record.type_field.clone(),
+            Column::Content => names::display_content(&record.type_field, &record.ip_addr),
+            Column::Ttl => output::ttl(record.ttl),
+            Column::Proxied => record.proxied.to_string(),
+            Column::Zone => names::display(&record.zone_name),
+            Column::Account => record.account.clone().unwrap_or_else(|| "-".to_string()),
+            Column::Id => record.id.clone(),
+            Column::Comment => record.comment.clone().unwrap_or_default(),
+            Column::Modified => record.modified_on.clone(),
+        }
+    }
+}
+
+impl ListArgs {
+    fn matches(&self, record: &Record) -> bool {
+        self.record_type
+            .as_ref()
+            .is_none_or(|t| t.eq_ignore_ascii_case(&record.type_field))
+            && self
+                .name_contains
+                .as_ref()
+                .is_none_or(|pattern| names::matches(&record.name, pattern))
+            && self.content.as_ref().is_none_or(|c| *c == record.ip_addr)
+            && self.proxied.is_none_or(|p| p == record.proxied)
+    }
+
+    /// Filters and sorts `records` in place.
+    fn apply(&self, records: &mut Vec<Record>) {
+        records.retain(|record| self.matches(record));
+        match self.sort {
+            Column::Ttl => records.sort_by_key(|record| record.ttl),
+            Column::Proxied => records.sort_by_key(|record| record.proxied),
+            column => records.sort_by_cached_key(|record| column.show(record)),
+        }
+    }
+}
+
+/// `cfdns list`: every record in the configured zones.
+pub fn list(settings: &Settings, args: &ListArgs, format: Format) -> Result<(), Error> {
+    if settings.zones.is_empty() {
+        return Err("No zone to list, pass --zone or a --config file".into());
+    }
+    let mut records = Vec::new();
+    for zone in &settings.zones {
+        let listed = list_dns_records(settings.client(&zone.id), &zone.id)?;
+        records.extend(listed.into_iter().map(|record| Record {
+            account: zone.account.clone(),
+            ..record
+        }));
+    }
+    args.apply(&mut records);
+
+    let columns = match (args.columns.is_empty(), settings.accounts.is_empty()) {
+        (true, true) => return output::print(format, &records),
+        (true, false) => vec![
+            Column::Account,
+            Column::Name,
+            Column::Type,
+            Column::Content,
+            Column::Ttl,
+            Column::Proxied,
+        ],
+        (false, _) => args.columns.clone(),
+    };
+    println!("{}", output::render_columns(format, &records, &columns)?);
+    Ok(())
+}
+
+/// `cfdns zones`: every zone the tokens have access to.
+pub fn zones(settings: &Settings, format: Format) -> Result<(), Error> {
+    let mut zones = Vec::new();
+    for (_, cloudflare) in settings.clients() {
+        zones.extend(list_zones(cloudflare)?);
+    }
+    output::print(format, &zones)
+}
+
+/// `cfdns search`: records matching `pattern` in every accessible zone.
+pub fn search(settings: &Settings, pattern: &str, format: Format) -> Result<(), Error> {
+    let pattern = pattern.to_lowercase();
+    let mut matches = Vec::new();
+    for (account, cloudflare) in settings.clients() {
+        for zone in list_zones(cloudflare)? {
+            info!("Searching zone {}", zone.name);
+            for record in dns_records(cloudflare, &zone.id, None) {
+                let record = record?;
+                if names::matches(&record.name, &pattern)
+                    || names::matches(&record.ip_addr, &pattern)
+                {
+                    matches.push(Record {
+                        account: account.map(str::to_string),
+                        ..record
+                    });
+                }
+            }
+        }
+    }
+    let mut columns = vec![
+        Column::Zone,
+        Column::Name,
+        Column::Type,
+        Column::Content,
+        Column::Id,
+    ];
+    if !settings.accounts.is_empty() {
+        columns.insert(0, Column::Account);
+    }
+    println!("{}", output::render_columns(format, &matches, &columns)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(name: &str, type_field: &str, content: &str, ttl: i64) -> Record {
+        Record {
+            name: name.to_string(),
+            type_field: type_field.to_string(),
+            ip_addr: content.to_string(),
+            ttl,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_list_filter_and_sort() {
+        let mut records = vec![
+            record("www.example.com", "CNAME", "example.com", 1),
+            record("vpn.example.com", "A", "198.51.100.7", 300),
+            record("home.example.com", "A", "198.51.100.7", 60),
+        ];
+        let args = ListArgs {
+            record_type: Some("a".to_string()),
+            sort: Column::Ttl,
+            ..Default::default()
+        };
+        args.apply(&mut records);
+        let names: Vec<_> = records.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["home.example.com", "vpn.example.com"]);
+
+        let args = ListArgs {
+            name_contains: Some("VPN".to_string()),
+            ..Default::default()
+        };
+        args.apply(&mut records);
+        assert_eq!(records.len(), 1);
+    }
+}
@@ -0,0 +1,1235 @@
+use std::collections::HashMap;
+use std::env;
+use std::net::{Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use ipnet::IpNet;
+use serde::de::DeserializeOwned;
+use serde_derive::Deserialize;
+
+use crate::access::AccessPolicyConfig;
+use crate::cloudflare::{CloudflareClient, Connection};
+use crate::deregister::OnShutdown;
+use crate::failover::FailoverConfig;
+use crate::fleet;
+use crate::frozen::Frozen;
+use crate::grafana::Grafana;
+use crate::guard;
+use crate::ids;
+use crate::influx::Influx;
+use crate::interface::Ipv6Selection;
+use crate::journal;
+use crate::lists::IpListConfig;
+use crate::names;
+use crate::paths;
+use crate::pools::PoolOriginConfig;
+use crate::probe::{self, Probe};
+use crate::propagation;
+use crate::remote;
+use crate::schedule::{Cron, Timezone, UpdateWindows, WindowsConfig};
+use crate::secrets::SecretSource;
+use crate::statsd::Statsd;
+use crate::syslog::Syslog;
+use crate::template::HostTemplate;
+use crate::templated::{self, TemplatedRecord};
+use crate::token::{Secret, TokenSource};
+use crate::{control, Args, Error, DEFAULT_IP_SOURCE};
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const DEFAULT_MAX_INTERVAL: Duration = Duration::from_secs(15 * 60);
+const DEFAULT_PARALLELISM: usize = 4;
+const DEFAULT_PROPAGATION_TIMEOUT: Duration = Duration::from_secs(60);
+const DEFAULT_SOURCE_FAILURES: u32 = 3;
+const DEFAULT_SOURCE_QUARANTINE: Duration = Duration::from_secs(10 * 60);
+
+/// Layout of the TOML file passed with `--config`.
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// API token, `CF_TOKEN` is used when missing
+    pub token: Option<Secret<String>>,
+    /// File holding the API token, re-read when Cloudflare rejects it
+    pub token_file: Option<PathBuf>,
+    /// Secret store holding the API token
+    pub token_source: Option<SecretSource>,
+    #[serde(default, with = "humantime_serde_opt")]
+    pub interval: Option<Duration>,
+    /// Cron expression for daemon runs, instead of `interval`
+    pub schedule: Option<String>,
+    /// Randomly vary waits by up to this fraction, e.g. 0.1 for 10%
+    pub jitter: Option<f64>,
+    /// IANA timezone `schedule` and `update_windows` are read in
+    pub timezone: Option<String>,
+    #[serde(default)]
+    pub adaptive: bool,
+    /// Check Cloudflare on every daemon run and revert external changes
+    #[serde(default)]
+    pub enforce: bool,
+    /// Webhook that is POSTed a JSON event when a record had to be reverted
+    pub notify_url: Option<String>,
+    /// Also POST a summary of every run that changed or failed something
+    #[serde(default)]
+    pub notify_summary: bool,
+    /// Log at daemon start when a newer release is out
+    #[serde(default)]
+    pub update_check: bool,
+    #[serde(default, with = "humantime_serde_opt")]
+    pub max_interval: Option<Duration>,
+    pub parallelism: Option<usize>,
+    /// Unix socket `cfdns ctl` connects to
+    pub control_socket: Option<PathBuf>,
+    /// Address the status API listens on in daemon mode
+    pub http_listen: Option<String>,
+    /// JSON report of every sync run is written here
+    pub report_file: Option<PathBuf>,
+    /// Where metrics of every run are written in InfluxDB line protocol:
+    /// udp://, tcp://, an http(s):// write URL or - for stdout
+    pub influx: Option<String>,
+    /// Token for an InfluxDB write URL
+    pub influx_token: Option<Secret<String>>,
+    /// Log to syslog instead of stderr: local for /dev/log, or udp://,
+    /// tcp:// or tls:// with host:port of a remote server
+    pub syslog: Option<String>,
+    /// PEM file of the CA that signed the certificate of a tls:// syslog
+    pub syslog_ca: Option<PathBuf>,
+    /// Grafana to annotate IP changes in, e.g. https://grafana.example.net
+    pub grafana_url: Option<String>,
+    /// Service account token allowed to write annotations
+    pub grafana_token: Option<Secret<String>>,
+    /// Tags of the annotations, "cfdns" unless given
+    pub grafana_tags: Option<Vec<String>>,
+    /// UID of the dashboard to annotate, all of them unless given
+    pub grafana_dashboard: Option<String>,
+    /// `host:port` of a statsd server or Datadog agent to send metrics to
+    pub statsd: Option<String>,
+    /// Put in front of every statsd metric, "cfdns" unless given
+    pub statsd_prefix: Option<String>,
+    /// DogStatsD tags added to every metric, e.g. "env:home"
+    #[serde(default)]
+    pub statsd_tags: Vec<String>,
+    /// Bearer token required by the status API
+    pub http_token: Option<Secret<String>>,
+    /// Add the ownership marker to the comment of records cfdns updates
+    #[serde(default)]
+    pub mark_records: bool,
+    /// Only modify or delete records carrying the ownership marker
+    #[serde(default)]
+    pub safe_mode: bool,
+    /// Only publish a new IP once a service answers on it, e.g. "tcp:443"
+    /// or "http://{ip}/health"
+    pub reachability_check: Option<String>,
+    /// Service outside the LAN that runs `reachability_check` instead
+    pub reachability_checker: Option<String>,
+    /// Delete the records named after this host when the daemon stops
+    #[serde(default)]
+    pub cleanup_on_shutdown: bool,
+    /// What the daemon does to its records when it stops: "keep", "delete"
+    /// or fallback addresses to point them at
+    pub on_shutdown: Option<String>,
+    /// Refuse anything that would change DNS
+    #[serde(default)]
+    pub read_only: bool,
+    /// Records cfdns never changes: names, `name/TYPE` or `tag:<tag>`
+    #[serde(default)]
+    pub frozen: Vec<String>,
+    /// Publish private and otherwise reserved addresses
+    #[serde(default)]
+    pub allow_private: bool,
+    /// Only publish addresses in these CIDR ranges
+    #[serde(default)]
+    pub allowed_ranges: Vec<IpNet>,
+    /// Echo services answering with the public IP, tried in order
+    #[serde(default)]
+    pub ip_sources: Vec<String>,
+    /// Ask all `ip_sources` at once and require a majority to agree
+    #[serde(default)]
+    pub ip_consensus: bool,
+    /// Failures in a row after which an IP source is skipped for a while,
+    /// 0 never skips
+    pub source_failures: Option<u32>,
+    /// How long a failing IP source is skipped at first
+    #[serde(default, with = "humantime_serde_opt")]
+    pub source_quarantine: Option<Duration>,
+    /// Which IPv6 address `interface:` sources publish
+    #[serde(default)]
+    pub ipv6_selection: Ipv6Selection,
+    /// Interface identifier to look for with `ipv6_selection = "match-suffix"`
+    pub ipv6_suffix: Option<Ipv6Addr>,
+    /// Wait for public resolvers to serve updated records
+    #[serde(default)]
+    pub verify_propagation: bool,
+    #[serde(default, with = "humantime_serde_opt")]
+    pub propagation_timeout: Option<Duration>,
+    /// Ask the zone's name servers before the API, to spend no API quota
+    /// while nothing changed
+    #[serde(default)]
+    pub dns_precheck: bool,
+    /// Only contact Cloudflare when the IP differs from the cached one
+    #[serde(default)]
+    pub from_cache: bool,
+    /// DNS-over-HTTPS endpoint for `verify_propagation` and `dns_precheck`
+    pub doh: Option<String>,
+    /// Resolvers asked by `verify_propagation`, `ip` or `ip:port`
+    #[serde(default)]
+    pub resolvers: Vec<String>,
+    /// Compare the detected IP with the router's WAN address over UPnP
+    #[serde(default)]
+    pub upnp_check: bool,
+    /// Do not update records while behind carrier-grade NAT
+    #[serde(default)]
+    pub skip_behind_cgnat: bool,
+    /// Refuse to update a record more often than this per hour
+    pub max_updates_per_hour: Option<u32>,
+    /// Where the update times counted by `max_updates_per_hour` are kept
+    pub update_history: Option<PathBuf>,
+    /// Records `cfdns new-host` creates
+    pub new_host: Option<HostTemplate>,
+    /// Where the record changes `cfdns undo` reverts are journaled
+    pub journal: Option<PathBuf>,
+    /// Where zone and record IDs are cached
+    pub id_cache: Option<PathBuf>,
+    /// How long cached IDs are trusted, "0s" turns the cache off
+    #[serde(default, with = "humantime_serde_opt")]
+    pub id_cache_ttl: Option<Duration>,
+    /// Times of day updates are allowed or suppressed at
+    pub update_windows: Option<WindowsConfig>,
+    /// Cloudflare IP List entries to keep on the current IP
+    #[serde(default)]
+    pub ip_lists: Vec<IpListConfig>,
+    /// Zero Trust Access policies whose IP include rule follows the IP
+    #[serde(default)]
+    pub access_policies: Vec<AccessPolicyConfig>,
+    /// Load Balancer pool origins whose address follows the IP
+    #[serde(default)]
+    pub pool_origins: Vec<PoolOriginConfig>,
+    /// Short names for zones, usable wherever a zone ID or name is
+    #[serde(default)]
+    pub zone_aliases: HashMap<String, String>,
+    #[serde(default)]
+    pub zones: Vec<ZoneConfig>,
+    /// Further Cloudflare accounts, each with a token and zones of its own
+    #[serde(default)]
+    pub accounts: Vec<AccountConfig>,
+    /// Further config files merged into this one, e.g. "zones.d/*.toml"
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Named sets of settings replacing the ones above, picked by --profile
+    #[serde(default)]
+    pub profiles: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ZoneConfig {
+    pub id: String,
+    #[serde(default)]
+    pub records: Vec<String>,
+    /// Also manage the records whose comment starts with this
+    pub comment_prefix: Option<String>,
+    /// Shared names this host adds its own record to, for DNS round-robin
+    #[serde(default)]
+    pub round_robin: Vec<String>,
+    /// Records whose name and content are templates, filled in on every run
+    #[serde(default)]
+    pub templated: Vec<TemplatedRecord>,
+    /// Records switched to a secondary address while the primary fails
+    #[serde(default)]
+    pub failover: Vec<FailoverConfig>,
+    /// Purge the zone's cache after the IP changed
+    #[serde(default)]
+    pub purge_cache: bool,
+    /// Purge only these URLs after the IP changed, instead of everything
+    #[serde(default)]
+    pub purge_urls: Vec<String>,
+    /// TTL to keep the records at, 1 for automatic
+    pub ttl: Option<i64>,
+    /// Whether the records should be proxied
+    pub proxied: Option<bool>,
+    /// Name of the entry of `accounts` the zone is listed under
+    #[serde(skip)]
+    pub account: Option<String>,
+    /// Those of `records` that came from a name with `{hostname}`
+    #[serde(skip)]
+    pub host_records: Vec<String>,
+}
+
+/// An entry of `accounts`, e.g. a customer's Cloudflare account.
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccountConfig {
+    /// Shown in the ACCOUNT column and in messages
+    pub name: String,
+    pub token: Option<Secret<String>>,
+    pub token_file: Option<PathBuf>,
+    pub token_source: Option<SecretSource>,
+    #[serde(default)]
+    pub zones: Vec<ZoneConfig>,
+}
+
+/// A configured account, with its own client for the Cloudflare API.
+#[derive(Debug, Clone)]
+pub struct Account {
+    pub name: String,
+    pub token_source: TokenSource,
+    pub token: Secret<String>,
+    pub cloudflare: CloudflareClient,
+}
+
+/// Puts the names of `zone` the way Cloudflare has them, qualifying relative
+/// ones when the zone is given by name. Those of zones given by ID are
+/// qualified once [`ids::resolve_zones`] found the zone's name.
+fn normalize_names(zone: &mut ZoneConfig) -> Result<(), Error> {
+    for template in &zone.templated {
+        templated::check(&template.name)?;
+        templated::check(&template.content)?;
+    }
+    for pair in &zone.failover {
+        pair.validate()?;
+    }
+    if !ids::is_zone_name(&zone.id) {
+        for name in names_of(zone) {
+            if !names::is_relative(name) {
+                *name = names::to_ascii(name)?;
+            }
+        }
+        return Ok(());
+    }
+    zone.id = names::to_ascii(&zone.id)?;
+    let id = zone.id.clone();
+    for name in names_of(zone) {
+        *name = names::qualify(name, &id)?;
+    }
+    Ok(())
+}
+
+/// The record names of `zone` that are qualified with its name.
+pub fn names_of(zone: &mut ZoneConfig) -> impl Iterator<Item = &mut String> {
+    zone.records
+        .iter_mut()
+        .chain(&mut zone.round_robin)
+        .chain(&mut zone.host_records)
+}
+
+impl ZoneConfig {
+    /// Whether the cache is purged when a proxied record gets a new origin.
+    pub fn purges_cache(&self) -> bool {
+        self.purge_cache || !self.purge_urls.is_empty()
+    }
+}
+
+/// Everything a run needs, resolved from the command line and config file.
+#[derive(Default, Debug, Clone)]
+pub struct Settings {
+    pub token: Secret<String>,
+    pub token_source: TokenSource,
+    pub connection: Connection,
+    /// Client for the Cloudflare API, authenticated with `token`
+    pub cloudflare: CloudflareClient,
+    pub zones: Vec<ZoneConfig>,
+    pub accounts: Vec<Account>,
+    pub interval: Duration,
+    pub schedule: Option<Cron>,
+    pub timezone: Timezone,
+    pub jitter: f64,
+    pub adaptive: bool,
+    pub enforce: bool,
+    pub notify_url: Option<String>,
+    pub notify_summary: bool,
+    pub update_check: bool,
+    pub max_interval: Duration,
+    pub parallelism: usize,
+    pub control_socket: PathBuf,
+    pub http_listen: Option<String>,
+    pub report_file: Option<PathBuf>,
+    pub influx: Option<Influx>,
+    pub influx_token: Option<Secret<String>>,
+    pub statsd: Option<Statsd>,
+    pub syslog: Option<Syslog>,
+    pub grafana: Option<Grafana>,
+    #[cfg_attr(not(feature = "http-api"), allow(dead_code))]
+    pub http_token: Option<Secret<String>>,
+    /// Modify records other Cloudflare systems own
+    pub force: bool,
+    pub mark_records: bool,
+    pub safe_mode: bool,
+    pub cleanup_on_shutdown: bool,
+    pub on_shutdown: OnShutdown,
+    pub reachability_check: Option<Probe>,
+    pub reachability_checker: Option<String>,
+    pub read_only: bool,
+    pub frozen: Frozen,
+    pub allow_private: bool,
+    pub allowed_ranges: Vec<IpNet>,
+    pub ip_sources: Vec<String>,
+    pub ip_consensus: bool,
+    pub source_failures: u32,
+    pub source_quarantine: Duration,
+    pub ipv6_selection: Ipv6Selection,
+    pub ipv6_suffix: Option<Ipv6Addr>,
+    pub upnp_check: bool,
+    pub skip_behind_cgnat: bool,
+    pub verify_propagation: bool,
+    pub propagation_timeout: Duration,
+    pub dns_precheck: bool,
+    pub from_cache: bool,
+    pub refresh_cache: bool,
+    pub doh: Option<String>,
+    pub resolvers: Vec<SocketAddr>,
+    pub ip_lists: Vec<IpListConfig>,
+    pub access_policies: Vec<AccessPolicyConfig>,
+    pub pool_origins: Vec<PoolOriginConfig>,
+    pub max_updates_per_hour: Option<u32>,
+    pub update_history: PathBuf,
+    pub update_windows: Option<UpdateWindows>,
+    pub id_cache: PathBuf,
+    pub id_cache_ttl: Duration,
+    pub journal: PathBuf,
+    pub new_host: Option<HostTemplate>,
+}
+
+impl Config {
+    /// Reads a TOML config file, or a YAML/JSON one by its extension. The
+    /// latter may be encrypted with SOPS when built with the `sops` feature.
+    pub fn from_file(path: &Path) -> Result<Config, Error> {
+        let config: Config = Config::read(path)?;
+        if config.include.is_empty() {
+            return Ok(config);
+        }
+        serde_json::from_value(Config::document(path, None)?).map_err(|e| {
+            format!(
+                "could not parse {} with its includes: {}",
+                path.display(),
+                e
+            )
+            .into()
+        })
+    }
+
+    /// The config in `path` with the keys of `profile` from its `profiles`
+    /// table replacing the top level ones.
+    pub fn with_profile(path: &Path, profile: Option<&str>) -> Result<Config, Error> {
+        let Some(profile) = profile else {
+            return Config::from_file(path);
+        };
+        serde_json::from_value(Config::document(path, Some(profile))?).map_err(|e| {
+            format!(
+                "could not parse profile {} of {}: {}",
+                profile,
+                path.display(),
+                e
+            )
+            .into()
+        })
+    }
+
+    /// The config file, if any, with `profile` and the `CFDNS_*` environment
+    /// variables applied.
+    pub fn load(path: Option<&Path>, profile: Option<&str>) -> Result<Config, Error> {
+        let overrides = env_overrides(env::vars());
+        let mut document = match (path, profile) {
+            (None, Some(_)) => return Err("--profile needs a --config file".into()),
+            (Some(path), profile) if overrides.is_empty() => {
+                return Config::with_profile(path, profile)
+            }
+            (None, None) if overrides.is_empty() => return Ok(Config::default()),
+            (Some(path), profile) => Config::document(path, profile)?,
+            (None, None) => serde_json::Value::Object(Default::default()),
+        };
+        for (path, value) in overrides {
+            set(&mut document, &path, value);
+        }
+        serde_json::from_value(document)
+            .map_err(|e| format!("could not apply the CFDNS_ environment variables: {}", e).into())
+    }
+
+    /// The file as a plain document with its includes merged in, and the
+    /// keys of `profile` replacing the top level ones.
+    fn document(path: &Path, profile: Option<&str>) -> Result<serde_json::Value, Error> {
+        let mut document: serde_json::Value = Config::read(path)?;
+        let patterns: Vec<String> = match document.get("include") {
+            Some(include) => serde_json::from_value(include.clone())
+                .map_err(|e| format!("bad include in {}: {}", path.display(), e))?,
+            None => Vec::new(),
+        };
+        let dir = path.parent().unwrap_or(Path::new("."));
+        for pattern in &patterns {
+            for fragment in expand(dir, pattern)? {
+                let mut fragment: serde_json::Value = Config::read(&fragment)?;
+                if let Some(fragment) = fragment.as_object_mut() {
+                    fragment.remove("include");
+                }
+                merge(&mut document, fragment);
+            }
+        }
+        let Some(profile) = profile else {
+            return Ok(document);
+        };
+        let overrides = match document.get("profiles").and_then(|p| p.get(profile)) {
+            Some(serde_json::Value::Object(overrides)) => overrides.clone(),
+            _ => {
+                return Err(format!("No profile {} in {}", profile, path.display()).into());
+            }
+        };
+        let Some(top) = document.as_object_mut() else {
+            return Err(format!("{} is not a table of settings", path.display()).into());
+        };
+        top.remove("profiles");
+        top.extend(overrides);
+        Ok(document)
+    }
+
+    fn read<T: DeserializeOwned>(path: &Path) -> Result<T, Error> {
+        if remote::is_remote(path) {
+            return Config::read(&remote::fetch(path)?);
+        }
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("could not read config file {}: {}", path.display(), e))?;
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default();
+        let parsed = match extension {
+            "yaml" | "yml" | "json" => {
+                let text = match is_sops(&text) {
+                    true => decrypt_sops(path)?,
+                    false => text,
+                };
+                serde_yaml::from_str(&text).map_err(Error::from)
+            }
+            _ => toml::from_str(&text).map_err(Error::from),
+        };
+        parsed.map_err(|e| format!("could not parse config file {}: {}", path.display(), e).into())
+    }
+}
+
+/// A step into the config document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// Config overrides from `CFDNS_*` variables. The rest of the name is the
+/// key in upper case, with numbers indexing into lists and `__` stepping into
+/// a table: `CFDNS_INTERVAL`, `CFDNS_ZONES_0_ID`, `CFDNS_UPDATE_WINDOWS__TIMEZONE`.
+/// Values are read as TOML values where they parse as one, like `true`, `4`
+/// or `["a", "b"]`, and as plain strings otherwise.
+fn env_overrides(
+    vars: impl Iterator<Item = (String, String)>,
+) -> Vec<(Vec<Segment>, serde_json::Value)> {
+    vars.filter(|(name, _)| name != "CFDNS_PROFILE" && name != remote::HEADER_VARIABLE)
+        .filter_map(|(name, value)| {
+            let path = name.strip_prefix("CFDNS_")?.to_lowercase();
+            let mut segments = Vec::new();
+            for table in path.split("__") {
+                let mut key: Vec<&str> = Vec::new();
+                for part in table.split('_') {
+                    match part.parse::<usize>() {
+                        Ok(index) => {
+                            if !key.is_empty() {
+                                segments.push(Segment::Key(key.join("_")));
+                                key.clear();
+                            }
+                            segments.push(Segment::Index(index));
+                        }
+                        Err(_) => key.push(part),
+                    }
+                }
+                if !key.is_empty() {
+                    segments.push(Segment::Key(key.join("_")));
+                }
+            }
+            let value = toml::from_str::<toml::Table>(&format!("v = {}", value))
+                .ok()
+                .and_then(|mut table| table.remove("v"))
+                .and_then(|value| serde_json::to_value(value).ok())
+                .unwrap_or(serde_json::Value::String(value));
+            Some((segments, value))
+        })
+        .collect()
+}
+
+/// Puts `value` at `path` in `document`, creating tables and list entries
+/// on the way.
+fn set(document: &mut serde_json::Value, path: &[Segment], value: serde_json::Value) {
+    let Some((first, rest)) = path.split_first() else {
+        *document = value;
+        return;
+    };
+    let next = match first {
+        Segment::Key(key) => {
+            if !document.is_object() {
+                *document = serde_json::Value::Object(Default::default());
+            }
+            document
+                .as_object_mut()
+                .unwrap()
+                .entry(key.clone())
+                .or_insert(serde_json::Value::Null)
+        }
+        Segment::Index(index) => {
+            if !document.is_array() {
+                *document = serde_json::Value::Array(Vec::new());
+            }
+            let list = document.as_array_mut().unwrap();
+            if list.len() <= *index {
+                list.resize(index + 1, serde_json::Value::Null);
+            }
+            &mut list[*index]
+        }
+    };
+    set(next, rest, value);
+}
+
+/// The files `pattern` matches, relative to `dir` unless absolute. Only its
+/// last component may have `*` and `?` wildcards; the matches are sorted.
+fn expand(dir: &Path, pattern: &str) -> Result<Vec<PathBuf>, Error> {
+    let pattern = dir.join(pattern);
+    let name = pattern
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default()
+        .to_string();
+    if !name.contains(['*', '?']) {
+        return Ok(vec![pattern]);
+    }
+    let parent = pattern.parent().unwrap_or(Path::new("."));
+    let entries = std::fs::read_dir(parent)
+        .map_err(|e| format!("could not read {}: {}", parent.display(), e))?;
+    let mut matches: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            let file = entry.file_name();
+            wildcard(&name, &file.to_string_lossy()) && entry.path().is_file()
+        })
+        .map(|entry| entry.path())
+        .collect();
+    matches.sort();
+    Ok(matches)
+}
+
+/// Whether `text` matches `pattern`, where `*` is any run of characters and
+/// `?` any single one.
+fn wildcard(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    // Positions after the last `*` to go back to on a mismatch.
+    let (mut p, mut t, mut star, mut resume) = (0, 0, None, 0);
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some(p);
+            resume = t;
+            p += 1;
+        } else if let Some(s) = star {
+            p = s + 1;
+            resume += 1;
+            t = resume;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Merges an included `fragment` into `document`: lists are concatenated,
+/// tables merged and other keys already in `document` kept.
+fn merge(document: &mut serde_json::Value, fragment: serde_json::Value) {
+    use serde_json::Value;
+    match (document, fragment) {
+        (Value::Object(document), Value::Object(fragment)) => {
+            for (key, value) in fragment {
+                match document.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        document.insert(key, value);
+                    }
+                }
+            }
+        }
+        (Value::Array(document), Value::Array(fragment)) => document.extend(fragment),
+        _ => {}
+    }
+}
+
+/// SOPS keeps its metadata in a top level `sops` key.
+fn is_sops(text: &str) -> bool {
+    serde_yaml::from_str::<serde_yaml::Value>(text)
+        .is_ok_and(|document| document.get("sops").is_some_and(|sops| sops.is_mapping()))
+}
+
+#[cfg(feature = "sops")]
+fn decrypt_sops(path: &Path) -> Result<String, Error> {
+    let mut command = std::process::Command::new("sops");
+    command.arg("--decrypt").arg(path);
+    crate::secrets::output_of(&mut command)
+        .map_err(|e| format!("could not decrypt {}: {}", path.display(), e).into())
+}
+
+#[cfg(not(feature = "sops"))]
+fn decrypt_sops(path: &Path) -> Result<String, Error> {
+    Err(format!(
+        "{} is encrypted with SOPS, but cfdns was built without the `sops` feature",
+        path.display()
+    )
+    .into())
+}
+
+impl Settings {
+    /// Reads the config file (if any) and merges it with the command line,
+    /// command line values win.
+    pub fn load(args: &Args) -> Result<Settings, Error> {
+        let file = paths::config_file(args);
+        let config = Config::load(file.as_deref(), args.profile.as_deref())?;
+        Settings::merge(args, config)
+    }
+
+    fn merge(args: &Args, config: Config) -> Result<Settings, Error> {
+        let mut zones = config.zones;
+        zones.extend(args.zone.iter().map(|zone| ZoneConfig {
+            id: zone.clone(),
+            records: args.name.clone(),
+            comment_prefix: args.comment_prefix.clone(),
+            ..Default::default()
+        }));
+        for account in &config.accounts {
+            zones.extend(account.zones.iter().map(|zone| ZoneConfig {
+                account: Some(account.name.clone()),
+                ..zone.clone()
+            }));
+        }
+        for zone in &mut zones {
+            if let Some(target) = config.zone_aliases.get(&zone.id) {
+                zone.id = target.clone();
+            }
+        }
+
+        if zones.iter().any(|zone| zone.id.is_empty()) {
+            return Err("Zone can not be empty!".into());
+        }
+        if zones.iter().flat_map(|z| &z.records).any(|r| r.is_empty()) {
+            return Err("Record domain can not be empty!".into());
+        }
+        let vars = templated::Vars::new(None);
+        for zone in &mut zones {
+            fleet::expand_names(zone, &vars)?;
+            normalize_names(zone)?;
+        }
+        if let Some(ttl) = zones
+            .iter()
+            .filter_map(|zone| zone.ttl)
+            .find(|ttl| *ttl != 1 && !(30..=86400).contains(ttl))
+        {
+            return Err(format!(
+                "ttl must be 1 (automatic) or between 30 and 86400 seconds, not {}",
+                ttl
+            )
+            .into());
+        }
+
+        let ip_sources = match config.ip_sources.is_empty() {
+            true => vec![DEFAULT_IP_SOURCE.to_string()],
+            false => config.ip_sources,
+        };
+        if cfg!(not(feature = "notify")) && config.notify_url.is_some() {
+            return Err("notify_url needs cfdns built with the `notify` feature".into());
+        }
+        if config.ip_consensus && ip_sources.len() < 2 {
+            return Err("ip_consensus needs at least two ip_sources".into());
+        }
+
+        if let Some(checker) = &config.reachability_checker {
+            if config.reachability_check.is_none() {
+                return Err("reachability_checker needs a reachability_check to run".into());
+            }
+            probe::validate_checker(checker)?;
+        }
+
+        let resolvers = match config.resolvers.is_empty() {
+            true => propagation::DEFAULT_RESOLVERS.map(String::from).to_vec(),
+            false => config.resolvers,
+        };
+        let resolvers = resolvers
+            .iter()
+            .map(|text| propagation::parse_resolver(text))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let token_source =
+            TokenSource::choose(config.token, config.token_file, config.token_source);
+        // The top level token may be left out when every zone is in an account.
+        let token = match token_source.resolve() {
+            Err(_) if !config.accounts.is_empty() && zones.iter().all(|z| z.account.is_some()) => {
+                Secret::default()
+            }
+            token => token?,
+        };
+        let connection = Connection::from_args(args);
+        let cloudflare = CloudflareClient::new(&connection, &token)?;
+        let mut accounts: Vec<Account> = Vec::new();
+        for account in config.accounts {
+            if account.name.is_empty() || accounts.iter().any(|a| a.name == account.name) {
+                return Err(format!("accounts need unique names, not {:?}", account.name).into());
+            }
+            let token_source =
+                TokenSource::choose(account.token, account.token_file, account.token_source);
+            let token = token_source
+                .resolve()
+                .map_err(|e| format!("No token for account {}: {}", account.name, e))?;
+            accounts.push(Account {
+                cloudflare: CloudflareClient::new(&connection, &token)?,
+                name: account.name,
+                token_source,
+                token,
+            });
+        }
+
+        let interval = args
+            .interval
+            .or(config.interval)
+            .unwrap_or(DEFAULT_INTERVAL);
+        let timezone = match config.timezone {
+            Some(name) => name.parse::<Timezone>()?,
+            None => Timezone::default(),
+        };
+        let schedule = match args.schedule.as_ref().or(config.schedule.as_ref()) {
+            Some(text) => {
+                let cron = text.parse::<Cron>()?;
+                if timezone.until_next(&cron).is_none() {
+                    return Err(format!("schedule {} never matches", text).into());
+                }
+                Some(cron)
+            }
+            None => None,
+        };
+        let jitter = args.jitter.or(config.jitter).unwrap_or_default();
+        if !(0.0..=1.0).contains(&jitter) {
+            return Err(format!("jitter must be between 0 and 1, not {}", jitter).into());
+        }
+        let max_interval = args
+            .max_interval
+            .or(config.max_interval)
+            .unwrap_or(DEFAULT_MAX_INTERVAL)
+            .max(interval);
+
+        Ok(Settings {
+            token,
+            token_source,
+            connection,
+            cloudflare,
+            zones,
+            accounts,
+            interval,
+            schedule,
+            timezone,
+            jitter,
+            adaptive: args.adaptive || config.adaptive,
+            enforce: args.enforce || config.enforce,
+            notify_url: config.notify_url,
+            notify_summary: config.notify_summary,
+            update_check: config.update_check,
+            max_interval,
+            parallelism: args
+                .parallelism
+                .or(config.parallelism)
+                .unwrap_or(DEFAULT_PARALLELISM),
+            control_socket: control_socket_path(args, config.control_socket),
+            http_listen: args.http_listen.clone().or(config.http_listen),
+            report_file: args.report_file.clone().or(config.report_file),
+            influx: config.influx.map(|text| text.parse()).transpose()?,
+            influx_token: config.influx_token,
+            syslog: match (
+                config.syslog.map(|text| text.parse()).transpose()?,
+                config.syslog_ca,
+            ) {
+                (Some(Syslog::Tls { address, .. }), ca) => Some(Syslog::Tls { address, ca }),
+                (_, Some(_)) => return Err("syslog_ca needs a tls:// syslog".into()),
+                (syslog, None) => syslog,
+            },
+            grafana: match (config.grafana_url, config.grafana_token) {
+                (Some(url), Some(token)) => Some(Grafana {
+                    url,
+                    token,
+                    tags: config
+                        .grafana_tags
+                        .unwrap_or_else(|| vec!["cfdns".to_string()]),
+                    dashboard: config.grafana_dashboard,
+                }),
+                (None, _) => None,
+                (Some(_), None) => return Err("grafana_url needs a grafana_token".into()),
+            },
+            statsd: config.statsd.map(|address| Statsd {
+                address,
+                prefix: config.statsd_prefix.unwrap_or_else(|| "cfdns".to_string()),
+                tags: config.statsd_tags,
+            }),
+            http_token: config.http_token,
+            force: args.force,
+            mark_records: config.mark_records,
+            safe_mode: config.safe_mode,
+            cleanup_on_shutdown: config.cleanup_on_shutdown,
+            reachability_check: config
+                .reachability_check
+                .map(|text| text.parse())
+                .transpose()?,
+            reachability_checker: config.reachability_checker,
+            on_shutdown: match config.on_shutdown {
+                Some(text) => text.parse()?,
+                None => OnShutdown::Keep,
+            },
+            read_only: args.read_only || config.read_only,
+            frozen: Frozen::parse(&config.frozen)?,
+            allow_private: args.allow_private || config.allow_private,
+            allowed_ranges: config.allowed_ranges,
+            ip_sources,
+            ip_consensus: config.ip_consensus,
+            source_failures: config.source_failures.unwrap_or(DEFAULT_SOURCE_FAILURES),
+            source_quarantine: config
+                .source_quarantine
+                .unwrap_or(DEFAULT_SOURCE_QUARANTINE),
+            ipv6_selection: config.ipv6_selection,
+            ipv6_suffix: config.ipv6_suffix,
+            upnp_check: config.upnp_check,
+            skip_behind_cgnat: config.skip_behind_cgnat,
+            verify_propagation: args.verify_propagation || config.verify_propagation,
+            propagation_timeout: config
+                .propagation_timeout
+                .unwrap_or(DEFAULT_PROPAGATION_TIMEOUT),
+            dns_precheck: args.dns_precheck || config.dns_precheck,
+            from_cache: args.from_cache || config.from_cache,
+            refresh_cache: args.refresh_cache,
+            doh: args.doh.clone().or(config.doh),
+            resolvers,
+            ip_lists: config.ip_lists,
+            access_policies: config.access_policies,
+            pool_origins: config.pool_origins,
+            max_updates_per_hour: config.max_updates_per_hour,
+            update_windows: config
+                .update_windows
+                .map(|windows| UpdateWindows::from_config(windows, timezone))
+                .transpose()?,
+            update_history: config
+                .update_history
+                .unwrap_or_else(guard::default_state_path),
+            id_cache: config.id_cache.unwrap_or_else(ids::default_path),
+            id_cache_ttl: config.id_cache_ttl.unwrap_or(ids::DEFAULT_TTL),
+            journal: config.journal.unwrap_or_else(journal::default_path),
+            new_host: config.new_host,
+        })
+    }
+
+    /// The client for `account`, the top level one for `None`.
+    pub fn account_client(&self, account: Option<&str>) -> &CloudflareClient {
+        self.accounts
+            .iter()
+            .find(|a| Some(a.name.as_str()) == account)
+            .map_or(&self.cloudflare, |a| &a.cloudflare)
+    }
+
+    /// The account zone `zone` is configured in.
+    pub fn account(&self, zone: &str) -> Option<&str> {
+        self.zones
+            .iter()
+            .find(|z| z.id == zone)
+            .and_then(|z| z.account.as_deref())
+    }
+
+    /// The client for the account zone `zone` is configured in.
+    pub fn client(&self, zone: &str) -> &CloudflareClient {
+        self.account_client(self.account(zone))
+    }
+
+    /// Every account's client by name, the top level token's first unless
+    /// there is none.
+    pub fn clients(&self) -> Vec<(Option<&str>, &CloudflareClient)> {
+        let top = (!self.token.expose().is_empty()).then_some((None, &self.cloudflare));
+        top.into_iter()
+            .chain(
+                self.accounts
+                    .iter()
+                    .map(|a| (Some(a.name.as_str()), &a.cloudflare)),
+            )
+            .collect()
+    }
+
+    /// Commands that sync records need at least one of them.
+    pub fn require_records(&self) -> Result<(), Error> {
+        match self.record_count() {
+            0 if !self.selects_by_comment() => {
+                Err("No records to manage, pass --name/--zone or a --config file".into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether anything besides DNS records follows the IP.
+    pub fn has_other_targets(&self) -> bool {
+        !self.ip_lists.is_empty()
+            || !self.access_policies.is_empty()
+            || !self.pool_origins.is_empty()
+            || self.zones.iter().any(|zone| !zone.round_robin.is_empty())
+            || self.zones.iter().any(|zone| !zone.templated.is_empty())
+    }
+
+    /// Whether some records are only known once their comments are read.
+    pub fn selects_by_comment(&self) -> bool {
+        self.zones.iter().any(|zone| zone.comment_prefix.is_some())
+    }
+
+    pub fn record_count(&self) -> usize {
+        self.zones
+            .iter()
+            .map(|zone| {
+                zone.records.len()
+                    + zone.round_robin.len()
+                    + zone.templated.len()
+                    + zone.failover.len()
+            })
+            .sum()
+    }
+}
+
+/// Where the daemon listens for and `cfdns ctl` sends control commands.
+pub fn control_socket_path(args: &Args, configured: Option<PathBuf>) -> PathBuf {
+    args.control_socket
+        .clone()
+        .or(configured)
+        .unwrap_or_else(control::default_socket_path)
+}
+
+/// Human readable durations ("30s", "5m") for optional config values.
+mod humantime_serde_opt {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer};
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match Option::<String>::deserialize(deserializer)? {
+            Some(text) => humantime::parse_duration(&text)
+                .map(Some)
+                .map_err(serde::de::Error::custom),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn test_merge_config_and_args() {
+        let config: Config = toml::from_str(
+            r#"
+            token = "secret"
+            interval = "1m"
+
+            allowed_ranges = ["81.2.64.0/18", "2a02:8010::/32"]
+
+            [zone_aliases]
+            work = "zone-b"
+
+            [[zones]]
+            id = "zone-a"
+            records = ["home.example.com"]
+            "#,
+        )
+        .unwrap();
+        let args = Args::parse_from(["cfdns", "-z", "work", "-n", "vpn.example.com", "-j", "2"]);
+
+        let settings = Settings::merge(&args, config).unwrap();
+        assert_eq!(settings.token.expose(), "secret");
+        assert_eq!(settings.interval, Duration::from_secs(60));
+        assert_eq!(settings.parallelism, 2);
+        assert_eq!(settings.zones.len(), 2);
+        assert_eq!(settings.zones[1].id, "zone-b");
+        assert_eq!(settings.zones[1].records, vec!["vpn.example.com"]);
+        assert_eq!(settings.record_count(), 2);
+        assert_eq!(settings.allowed_ranges[1].to_string(), "2a02:8010::/32");
+    }
+
+    #[test]
+    fn test_names_are_qualified_with_their_zone() {
+        let config: Config = toml::from_str(
+            r#"
+            token = "secret"
+
+            [[zones]]
+            id = "Bücher.example."
+            records = ["vpn", "@", "www.bücher.example", "other.example.net."]
+            round_robin = ["api"]
+
+            [[zones]]
+            id = "zone-b"
+            records = ["vpn", "Home.Example.net."]
+            "#,
+        )
+        .unwrap();
+        let settings = Settings::merge(&Args::parse_from(["cfdns"]), config).unwrap();
+        let zone = &settings.zones[0];
+        assert_eq!(zone.id, "xn--bcher-kva.example");
+        assert_eq!(
+            zone.records,
+            vec![
+                "vpn.xn--bcher-kva.example",
+                "xn--bcher-kva.example",
+                "www.xn--bcher-kva.example",
+                "other.example.net"
+            ]
+        );
+        assert_eq!(zone.round_robin, vec!["api.xn--bcher-kva.example"]);
+        // Left for resolve_zones, which knows the zone's name.
+        assert_eq!(settings.zones[1].records, vec!["vpn", "home.example.net"]);
+    }
+
+    #[test]
+    fn test_accounts_bring_their_zones() {
+        let text = r#"
+            token = "secret"
+
+            [[zones]]
+            id = "zone-a"
+            records = ["home.example.com"]
+
+            [[accounts]]
+            name = "acme"
+            token = "acme-secret"
+
+            [[accounts.zones]]
+            id = "zone-b"
+            records = ["vpn.acme.example"]
+            "#;
+        let config: Config = toml::from_str(text).unwrap();
+        let settings = Settings::merge(&Args::parse_from(["cfdns"]), config).unwrap();
+        assert_eq!(settings.zones.len(), 2);
+        assert_eq!(settings.account("zone-a"), None);
+        assert_eq!(settings.account("zone-b"), Some("acme"));
+        assert_eq!(settings.accounts[0].token.expose(), "acme-secret");
+        let names: Vec<_> = settings.clients().into_iter().map(|(n, _)| n).collect();
+        assert_eq!(names, vec![None, Some("acme")]);
+
+        let twice = format!("{}\n[[accounts]]\nname = \"acme\"\ntoken = \"x\"", text);
+        let config: Config = toml::from_str(&twice).unwrap();
+        assert!(Settings::merge(&Args::parse_from(["cfdns"]), config).is_err());
+    }
+
+    #[test]
+    fn test_profile_replaces_top_level_keys() {
+        let path = std::env::temp_dir().join(format!("cfdns-profile-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"
+            token = "personal"
+            interval = "1m"
+
+            [[zones]]
+            id = "zone-a"
+            records = ["home.example.com"]
+
+            [profiles.staging]
+            token = "client"
+            zones = [{ id = "zone-b", records = ["staging.example.net"] }]
+            "#,
+        )
+        .unwrap();
+        let staging = Config::with_profile(&path, Some("staging"));
+        let missing = Config::with_profile(&path, Some("production"));
+        let plain = Config::with_profile(&path, None);
+        std::fs::remove_file(&path).unwrap();
+
+        let staging = staging.unwrap();
+        assert_eq!(staging.token.unwrap().expose(), "client");
+        assert_eq!(staging.interval, Some(Duration::from_secs(60)));
+        assert_eq!(staging.zones[0].id, "zone-b");
+        assert!(missing.is_err());
+        assert_eq!(plain.unwrap().zones[0].id, "zone-a");
+    }
+
+    #[test]
+    fn test_env_overrides_reach_nested_keys() {
+        let vars = [
+            ("CFDNS_INTERVAL", "5m"),
+            ("CFDNS_ENFORCE", "true"),
+            ("CFDNS_MAX_UPDATES_PER_HOUR", "4"),
+            ("CFDNS_ZONES_0_RECORDS", r#"["home.example.com"]"#),
+            ("CFDNS_ZONES_1_ID", "zone-b"),
+            ("CFDNS_UPDATE_WINDOWS__TIMEZONE", "Europe/Berlin"),
+            ("CFDNS_PROFILE", "staging"),
+            ("HOME", "/root"),
+        ]
+        .map(|(name, value)| (name.to_string(), value.to_string()));
+        let mut document = serde_json::json!({"zones": [{"id": "zone-a"}]});
+        for (path, value) in env_overrides(vars.into_iter()) {
+            set(&mut document, &path, value);
+        }
+        assert_eq!(
+            document,
+            serde_json::json!({
+                "interval": "5m",
+                "enforce": true,
+                "max_updates_per_hour": 4,
+                "zones": [
+                    {"id": "zone-a", "records": ["home.example.com"]},
+                    {"id": "zone-b"}
+                ],
+                "update_windows": {"timezone": "Europe/Berlin"}
+            })
+        );
+        let config: Config = serde_json::from_value(document).unwrap();
+        assert_eq!(config.zones[1].id, "zone-b");
+    }
+
+    #[test]
+    fn test_includes_are_merged() {
+        let dir = std::env::temp_dir().join(format!("cfdns-include-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("zones.d")).unwrap();
+        std::fs::write(
+            dir.join("cfdns.toml"),
+            r#"
+            include = ["zones.d/*.toml"]
+            interval = "1m"
+            zones = [{ id = "zone-a" }]
+            "#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("zones.d/b.toml"),
+            "interval = \"1h\"\nzones = [{ id = \"zone-b\" }]\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("zones.d/c.toml"),
+            "zones = [{ id = \"zone-c\" }]\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("zones.d/notes.txt"), "not config").unwrap();
+        let config = Config::from_file(&dir.join("cfdns.toml"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let config = config.unwrap();
+        let zones: Vec<&str> = config.zones.iter().map(|z| z.id.as_str()).collect();
+        assert_eq!(zones, vec!["zone-a", "zone-b", "zone-c"]);
+        assert_eq!(config.interval, Some(Duration::from_secs(60)));
+        assert!(wildcard("*.toml", "b.toml"));
+        assert!(wildcard("zone-?.t*l", "zone-b.toml"));
+        assert!(!wildcard("*.toml", "notes.txt"));
+    }
+
+    #[test]
+    fn test_detect_sops_documents() {
+        assert!(is_sops(
+            "token: ENC[AES256_GCM,data:abc=,type:str]\nsops:\n  version: 3.8.1\n"
+        ));
+        assert!(is_sops(
+            r#"{"token": "ENC[...]", "sops": {"version": "3.8.1"}}"#
+        ));
+        assert!(!is_sops("token: plain\nzones: []\n"));
+    }
+}
@@ -0,0 +1,42 @@
+//! Confirmation before destructive commands: `purge`, `retarget`, `undo`
+//! and `tunnel route --replace` show what they are about to change and ask
+//! first. Without a terminal on stdin there is nobody to ask, so they fail
+//! instead of hanging or guessing, unless `--yes` says to go ahead.
+
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use crate::Error;
+
+fn is_yes(answer: &str) -> bool {
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+/// Asks a yes/no question on the terminal, `yes` answering it up front.
+/// Anything but yes, including end of input, is a no.
+pub fn confirm(question: &str, yes: bool) -> Result<bool, Error> {
+    if yes {
+        return Ok(true);
+    }
+    if !io::stdin().is_terminal() {
+        return Err(
+            "Refusing to change records without a terminal to confirm on, pass --yes".into(),
+        );
+    }
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(is_yes(&answer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_yes_confirms() {
+        assert!(is_yes("y\n") && is_yes("Yes") && is_yes(" YES "));
+        assert!(!is_yes("") && !is_yes("n") && !is_yes("yep"));
+        assert!(confirm("Go ahead?", true).unwrap());
+    }
+}
@@ -0,0 +1,219 @@
+//! Control socket used by `cfdns ctl` to talk to a running daemon.
+//!
+//! The protocol is a single request line answered by a single response, after
+//! which the daemon closes the connection. Only unix domain sockets are
+//! supported for now.
+
+use std::env;
+#[cfg(unix)]
+use std::io::{BufRead, BufReader, Read, Write};
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use clap::ValueEnum;
+#[cfg(unix)]
+use log::{debug, info, warn};
+
+use crate::daemon::{Event, Status};
+use crate::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Request {
+    /// Print the daemon status as JSON
+    Status,
+    /// Check the records right away
+    ReconcileNow,
+    /// Stop checking until resumed
+    Pause,
+    /// Resume checking and run right away
+    Resume,
+    /// Re-read the config file
+    Reload,
+    /// Re-read the API token from its file
+    RotateToken,
+}
+
+impl Request {
+    #[cfg(unix)]
+    fn name(&self) -> String {
+        self.to_possible_value()
+            .expect("no skipped variants")
+            .get_name()
+            .to_string()
+    }
+}
+
+/// `$XDG_RUNTIME_DIR/cfdns.sock`, or a per-user socket in `/tmp`.
+pub fn default_socket_path() -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("cfdns.sock"),
+        None => env::temp_dir().join(format!(
+            "cfdns-{}.sock",
+            env::var("USER").unwrap_or_default()
+        )),
+    }
+}
+
+/// Removes the socket file once the daemon stops listening.
+pub struct ControlSocket {
+    path: PathBuf,
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn serve(_: &Path, _: Sender<Event>, _: Arc<Mutex<Status>>) -> Result<ControlSocket, Error> {
+    Err("control sockets are only supported on unix".into())
+}
+
+#[cfg(not(unix))]
+pub fn send(_: &Path, _: Request) -> Result<String, Error> {
+    Err("control sockets are only supported on unix".into())
+}
+
+/// Listens on `path` in a background thread, forwarding requests to the
+/// daemon loop through `events`.
+#[cfg(unix)]
+pub fn serve(
+    path: &Path,
+    events: Sender<Event>,
+    status: Arc<Mutex<Status>>,
+) -> Result<ControlSocket, Error> {
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(format!("{} is in use by another daemon", path.display()).into());
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)
+        .map_err(|e| format!("could not bind control socket {}: {}", path.display(), e))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    info!("Listening for control commands on {}", path.display());
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = handle(stream, &events, &status) {
+                        warn!("Control connection failed: {}", e);
+                    }
+                }
+                Err(e) => warn!("Control socket accept failed: {}", e),
+            }
+        }
+    });
+    Ok(ControlSocket {
+        path: path.to_path_buf(),
+    })
+}
+
+#[cfg(unix)]
+fn handle(
+    mut stream: UnixStream,
+    events: &Sender<Event>,
+    status: &Arc<Mutex<Status>>,
+) -> Result<(), Error> {
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let line = line.trim();
+    debug!("Control request: {}", line);
+
+    let response = match Request::from_str(line, true) {
+        Ok(Request::Status) => serde_json::to_string_pretty(&*status.lock().unwrap())?,
+        Ok(request) => {
+            let event = match request {
+                Request::ReconcileNow => Event::ReconcileNow,
+                Request::Pause => Event::Pause,
+                Request::Resume => Event::Resume,
+                Request::Reload => Event::Reload,
+                Request::RotateToken => Event::RotateToken,
+                Request::Status => unreachable!(),
+            };
+            events.send(event).map_err(|_| "daemon is shutting down")?;
+            "ok".to_string()
+        }
+        Err(_) => format!("error: unknown command {:?}", line),
+    };
+    stream.write_all(response.as_bytes())?;
+    stream.write_all(b"\n")?;
+    Ok(())
+}
+
+/// `cfdns healthcheck`: whether the daemon that answered `status` has had
+/// a successful run within `max_age` of `now`, and its last run was one.
+/// A paused daemon is healthy, it was paused on purpose.
+pub fn health(status: &str, max_age: Duration, now: SystemTime) -> Result<String, Error> {
+    let status: serde_json::Value = serde_json::from_str(status)?;
+    if status["paused"].as_bool() == Some(true) {
+        return Ok("paused".to_string());
+    }
+    if let Some(error) = status["last_error"].as_str() {
+        return Err(format!("the last run failed: {}", error).into());
+    }
+    let Some(last_success) = status["last_success"].as_str() else {
+        return Err("no run has succeeded yet".into());
+    };
+    let age = now
+        .duration_since(humantime::parse_rfc3339(last_success)?)
+        .unwrap_or_default();
+    match age <= max_age {
+        true => Ok(format!("last run succeeded at {}", last_success)),
+        false => Err(format!(
+            "no run has succeeded for {}",
+            humantime::format_duration(Duration::from_secs(age.as_secs()))
+        )
+        .into()),
+    }
+}
+
+/// Sends `request` to the daemon listening on `path` and returns its answer.
+#[cfg(unix)]
+pub fn send(path: &Path, request: Request) -> Result<String, Error> {
+    let mut stream = UnixStream::connect(path).map_err(|e| {
+        format!(
+            "could not connect to daemon at {}, is it running? ({})",
+            path.display(),
+            e
+        )
+    })?;
+    stream.write_all(format!("{}\n", request.name()).as_bytes())?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    match response.strip_prefix("error: ") {
+        Some(message) => Err(message.trim().to_string().into()),
+        None => Ok(response.trim_end().to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_health_needs_a_recent_successful_run() {
+        let now = humantime::parse_rfc3339("2024-05-01T10:10:00Z").unwrap();
+        let max_age = Duration::from_secs(15 * 60);
+        let healthy =
+            r#"{"paused": false, "last_success": "2024-05-01T10:00:00Z", "last_error": null}"#;
+        assert!(health(healthy, max_age, now).is_ok());
+        assert!(health(healthy, Duration::from_secs(60), now).is_err());
+        let failed =
+            r#"{"paused": false, "last_success": "2024-05-01T10:00:00Z", "last_error": "timeout"}"#;
+        assert!(health(failed, max_age, now).is_err());
+        assert!(health(r#"{"paused": false}"#, max_age, now).is_err());
+        assert_eq!(
+            health(r#"{"paused": true}"#, max_age, now).unwrap(),
+            "paused"
+        );
+    }
+}
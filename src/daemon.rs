@@ -0,0 +1,434 @@
+use std::collections::VecDeque;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use log::{debug, error, info, warn};
+use serde_derive::Serialize;
+
+use crate::config::Settings;
+use crate::sync::{RecordReport, SyncReport, SyncState};
+use crate::token::Unauthorized;
+use crate::Error;
+use crate::{control, events, hints, jitter, token};
+
+/// Things that can interrupt the daemon while it waits for the next run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Shutdown,
+    Reload,
+    ReconcileNow,
+    Pause,
+    Resume,
+    RotateToken,
+}
+
+/// How many history and error entries the daemon remembers.
+const LOG_LEN: usize = 50;
+
+/// A run taking longer than this is taken for hung, even waiting for
+/// propagation or a rate limit doesn't take that long.
+const HUNG_AFTER: Duration = Duration::from_secs(30 * 60);
+
+/// What the daemon is up to, as reported to control clients.
+#[derive(Default, Debug, Clone, PartialEq, Serialize)]
+pub struct Status {
+    pub paused: bool,
+    pub current_ip: Option<String>,
+    pub last_run: Option<String>,
+    pub last_success: Option<String>,
+    pub last_error: Option<String>,
+    pub next_run: Option<String>,
+    pub records: Vec<RecordReport>,
+    /// IP changes and record updates, newest first
+    pub history: VecDeque<LogEntry>,
+    /// Failed runs and record updates, newest first
+    pub recent_errors: VecDeque<LogEntry>,
+    /// Whether Cloudflare rejected the token on the last run
+    pub token_rejected: bool,
+    /// When the run in progress started
+    #[serde(skip)]
+    pub run_started: Option<Instant>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LogEntry {
+    pub time: String,
+    pub message: String,
+}
+
+impl Status {
+    /// Healthy once the most recent run went through without errors.
+    #[cfg_attr(not(feature = "http-api"), allow(dead_code))]
+    pub fn healthy(&self) -> bool {
+        self.last_run.is_some() && self.last_error.is_none()
+    }
+
+    /// Live unless a run has been going for longer than [`HUNG_AFTER`], which
+    /// a restart is the cure for.
+    #[cfg_attr(not(feature = "http-api"), allow(dead_code))]
+    pub fn live(&self, now: Instant) -> bool {
+        self.run_started
+            .is_none_or(|started| now.duration_since(started) < HUNG_AFTER)
+    }
+
+    /// Ready once the token works and the last run succeeded. Not being
+    /// ready during a Cloudflare outage is no reason to restart.
+    #[cfg_attr(not(feature = "http-api"), allow(dead_code))]
+    pub fn ready(&self) -> bool {
+        self.healthy() && !self.token_rejected
+    }
+
+    /// Takes in the outcome of a run, returns whether the public IP changed
+    /// (or could not be checked).
+    fn record_run(&mut self, result: Result<SyncReport, Error>) -> bool {
+        let now = timestamp(SystemTime::now());
+        self.last_run = Some(now.clone());
+        self.run_started = None;
+        self.token_rejected = result.as_ref().is_err_and(|e| e.is::<Unauthorized>());
+        match result {
+            Ok(report) => {
+                let changed = self.current_ip.as_ref() != Some(&report.current_ip);
+                if changed {
+                    events::emit(&events::Event::IpDetected {
+                        ip: &report.current_ip,
+                        previous: self.current_ip.as_deref(),
+                    });
+                }
+                if let (true, Some(previous)) = (changed, &self.current_ip) {
+                    let message = format!(
+                        "Public IP changed from {} to {}",
+                        previous, report.current_ip
+                    );
+                    push(&mut self.history, &now, message);
+                }
+                for record in &report.records {
+                    emit(record);
+                    match (record.state, &record.error) {
+                        (SyncState::Updated, _) => {
+                            let content = record.content.as_deref().unwrap_or_default();
+                            let message = format!("Updated {} to {}", record.name, content);
+                            push(&mut self.history, &now, message);
+                        }
+                        (SyncState::Reverted, _) => {
+                            let content = record.content.as_deref().unwrap_or_default();
+                            let message = format!(
+                                "Reverted external change of {} to {}",
+                                record.name, content
+                            );
+                            push(&mut self.history, &now, message);
+                        }
+                        (SyncState::Failed, Some(e)) => {
+                            push(&mut self.recent_errors, &now, e.clone())
+                        }
+                        (SyncState::Throttled, Some(e)) => {
+                            let message = format!("Not updating {}, {}", record.name, e);
+                            push(&mut self.recent_errors, &now, message)
+                        }
+                        _ => {}
+                    }
+                }
+                match report.count(SyncState::Failed) {
+                    0 => {
+                        self.last_success = Some(now);
+                        self.last_error = None;
+                    }
+                    failed => {
+                        let message = format!("{} record update(s) failed", failed);
+                        error!("Reconcile failed: {}", message);
+                        self.last_error = Some(message);
+                    }
+                }
+                self.current_ip = Some(report.current_ip);
+                self.records = report.records;
+                changed
+            }
+            Err(e) => {
+                error!("Reconcile failed: {}", e);
+                events::emit(&events::Event::Error {
+                    zone: None,
+                    name: None,
+                    message: &e.to_string(),
+                });
+                if let Some(hint) = hints::hint(&e) {
+                    warn!("Hint: {}", hint);
+                }
+                push(&mut self.recent_errors, &now, e.to_string());
+                self.last_error = Some(e.to_string());
+                // Failures often mean a reconnect, check again soon.
+                true
+            }
+        }
+    }
+}
+
+/// The event for what happened to `record` in a run, if anything did.
+fn emit(record: &RecordReport) {
+    match (record.state, &record.error) {
+        (SyncState::Updated | SyncState::Reverted, _) => {
+            events::emit(&events::Event::RecordUpdated {
+                zone: &record.zone,
+                name: &record.name,
+                content: record.content.as_deref(),
+                state: record.state,
+            })
+        }
+        (SyncState::Failed, Some(e)) => events::emit(&events::Event::Error {
+            zone: Some(&record.zone),
+            name: Some(&record.name),
+            message: e,
+        }),
+        _ => {}
+    }
+}
+
+fn push(log: &mut VecDeque<LogEntry>, time: &str, message: String) {
+    log.push_front(LogEntry {
+        time: time.to_string(),
+        message,
+    });
+    log.truncate(LOG_LEN);
+}
+
+fn timestamp(time: SystemTime) -> String {
+    humantime::format_rfc3339_seconds(time).to_string()
+}
+
+/// Calls `reconcile` every `settings.interval` until a shutdown is requested.
+/// Its second argument asks for a full check rather than one that may stop
+/// at an unchanged IP: on the first run, after a reload and when requested.
+///
+/// Signals are only acted upon between runs, so an in-flight reconcile always
+/// gets to finish its requests before the process exits. On reload `load` is
+/// called for fresh settings, which are only swapped in if they are valid.
+/// The control socket and status API are set up once at startup, reloads do
+/// not move them. Returns the settings in effect at shutdown, for cleanup.
+pub fn run<L, F>(mut settings: Settings, load: L, mut reconcile: F) -> Result<Settings, Error>
+where
+    L: Fn() -> Result<Settings, Error>,
+    F: FnMut(&Settings, bool) -> Result<SyncReport, Error>,
+{
+    let (sender, events) = mpsc::channel();
+    listen_for_signals(sender.clone())?;
+    let status = Arc::new(Mutex::new(Status::default()));
+    let _control = control::serve(&settings.control_socket, sender.clone(), status.clone())
+        .map_err(|e| warn!("Control socket disabled: {}", e))
+        .ok();
+    if let Some(address) = &settings.http_listen {
+        serve_api(address, &settings, sender, status.clone())?;
+    }
+    match &settings.schedule {
+        Some(_) => info!("Running as daemon, checking on schedule"),
+        None => info!(
+            "Running as daemon, checking every {}",
+            humantime::format_duration(settings.interval)
+        ),
+    }
+
+    let mut interval = settings.interval;
+    let mut forced = false;
+    let mut full = true;
+    loop {
+        if status.lock().unwrap().paused && !forced {
+            info!("Paused, skipping check");
+        } else {
+            status.lock().unwrap().run_started = Some(Instant::now());
+            let result = token::retry_unauthorized(&mut settings, |settings| {
+                reconcile(settings, full || forced)
+            });
+            full = false;
+            let changed = status.lock().unwrap().record_run(result);
+
+            interval = match (&settings.schedule, settings.adaptive) {
+                (Some(cron), _) => settings
+                    .timezone
+                    .until_next(cron)
+                    .unwrap_or(settings.interval),
+                (None, true) => next_interval(interval, &settings, changed),
+                (None, false) => settings.interval,
+            };
+            debug!("Next check in {}", humantime::format_duration(interval));
+        }
+        forced = false;
+
+        let wait = match settings.schedule {
+            Some(_) => jitter::delay(interval, settings.jitter),
+            None => jitter::spread(interval, settings.jitter),
+        };
+        let next_run = Instant::now() + wait;
+        status.lock().unwrap().next_run = Some(timestamp(SystemTime::now() + wait));
+        loop {
+            let timeout = next_run.saturating_duration_since(Instant::now());
+            match events.recv_timeout(timeout) {
+                Ok(Event::Shutdown) | Err(RecvTimeoutError::Disconnected) => {
+                    info!("Shut down cleanly");
+                    return Ok(settings);
+                }
+                Ok(Event::Reload) => {
+                    match load().and_then(|new| new.require_records().map(|_| new)) {
+                        Ok(new_settings) => {
+                            info!(
+                                "Reloaded configuration: {} records in {} zones, checking every {}",
+                                new_settings.record_count(),
+                                new_settings.zones.len(),
+                                humantime::format_duration(new_settings.interval)
+                            );
+                            settings = new_settings;
+                            crate::apply(&settings);
+                            interval = settings.interval;
+                            full = true;
+                            // Apply the new configuration right away.
+                            break;
+                        }
+                        Err(e) => error!("Config reload failed, keeping previous settings: {}", e),
+                    }
+                }
+                Ok(Event::ReconcileNow) => {
+                    info!("Reconcile requested");
+                    forced = true;
+                    break;
+                }
+                Ok(Event::RotateToken) => {
+                    if let Err(e) = token::rotate(&mut settings) {
+                        error!("Token rotation failed, keeping the previous token: {}", e);
+                    }
+                }
+                Ok(Event::Pause) => {
+                    info!("Pausing checks until resumed");
+                    status.lock().unwrap().paused = true;
+                }
+                Ok(Event::Resume) => {
+                    info!("Resuming checks");
+                    status.lock().unwrap().paused = false;
+                    break;
+                }
+                Err(RecvTimeoutError::Timeout) => break,
+            }
+        }
+    }
+}
+
+#[cfg(feature = "http-api")]
+fn serve_api(
+    address: &str,
+    settings: &Settings,
+    events: Sender<Event>,
+    status: Arc<Mutex<Status>>,
+) -> Result<(), Error> {
+    crate::api::serve(address, settings.http_token.clone(), events, status)
+}
+
+#[cfg(not(feature = "http-api"))]
+fn serve_api(_: &str, _: &Settings, _: Sender<Event>, _: Arc<Mutex<Status>>) -> Result<(), Error> {
+    Err("http_listen needs cfdns built with the `http-api` feature".into())
+}
+
+/// Adaptive polling: double the wait after every stable check, up to
+/// `max_interval`, and go back to `interval` as soon as something changed.
+fn next_interval(current: Duration, settings: &Settings, changed: bool) -> Duration {
+    if changed {
+        settings.interval
+    } else {
+        (current * 2).clamp(settings.interval, settings.max_interval)
+    }
+}
+
+#[cfg(unix)]
+fn listen_for_signals(tx: Sender<Event>) -> Result<(), Error> {
+    use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+    use signal_hook::iterator::Signals;
+
+    let mut signals = Signals::new([SIGINT, SIGTERM, SIGHUP])?;
+    std::thread::spawn(move || {
+        let mut requested = false;
+        for signal in signals.forever() {
+            if signal == SIGHUP {
+                info!("Received SIGHUP, reloading configuration");
+                if tx.send(Event::Reload).is_err() {
+                    break;
+                }
+                continue;
+            }
+            if requested {
+                // A second signal means the user does not want to wait.
+                error!("Received signal {} again, exiting immediately", signal);
+                std::process::exit(128 + signal);
+            }
+            info!("Received signal {}, finishing current run", signal);
+            requested = true;
+            if tx.send(Event::Shutdown).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn listen_for_signals(tx: Sender<Event>) -> Result<(), Error> {
+    ctrlc::set_handler(move || {
+        info!("Received Ctrl-C, finishing current run");
+        let _ = tx.send(Event::Shutdown);
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{next_interval, Status, HUNG_AFTER};
+    use crate::config::Settings;
+    use crate::token::Unauthorized;
+    use crate::{cloudflare, Error};
+
+    #[test]
+    fn test_adaptive_interval_backs_off_and_snaps_back() {
+        let settings = Settings {
+            interval: Duration::from_secs(60),
+            adaptive: true,
+            max_interval: Duration::from_secs(15 * 60),
+            parallelism: 4,
+            ..Settings::default()
+        };
+        let mut interval = settings.interval;
+        let mut seen = Vec::new();
+        for _ in 0..6 {
+            interval = next_interval(interval, &settings, false);
+            seen.push(interval.as_secs() / 60);
+        }
+        assert_eq!(seen, vec![2, 4, 8, 15, 15, 15]);
+        assert_eq!(next_interval(interval, &settings, true), settings.interval);
+    }
+
+    #[test]
+    fn test_liveness_and_readiness() {
+        let mut status = Status::default();
+        let start = std::time::Instant::now();
+        assert!(status.live(start) && !status.ready());
+        status.run_started = Some(start);
+        assert!(status.live(start + HUNG_AFTER / 2));
+        assert!(!status.live(start + HUNG_AFTER));
+
+        let rejected: Error = Unauthorized(403, None).into();
+        status.record_run(Err(rejected));
+        assert!(status.live(start + HUNG_AFTER) && status.token_rejected && !status.ready());
+        status.record_run(Err("Cloudflare is down".into()));
+        assert!(!status.token_rejected && !status.ready());
+    }
+
+    #[test]
+    fn test_reloaded_read_only_refuses_changes() {
+        let reloaded = Settings {
+            read_only: true,
+            ..Settings::default()
+        };
+        let client = reqwest::blocking::Client::new();
+        let update = client.put("http://127.0.0.1:9/zones/z/dns_records/r");
+        let refusal = cloudflare::refusal(&update, reloaded.read_only).unwrap();
+        assert!(refusal.contains("read_only is set"));
+        let list = client.get("http://127.0.0.1:9/zones");
+        assert_eq!(cloudflare::refusal(&list, reloaded.read_only), None);
+        assert_eq!(cloudflare::refusal(&update, false), None);
+    }
+}
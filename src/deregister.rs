@@ -0,0 +1,203 @@
+//! `on_shutdown`: what the daemon does to the records it manages when it
+//! stops, so that a machine that is decommissioned or suspended doesn't
+//! leave them pointing at an address that may be someone else's by then.
+//! `"delete"` deletes them, and has the records it deleted created again on
+//! the next start; one or more addresses point them there instead, by family.
+//!
+//! The deleted records are kept in `deregistered.json` in the state
+//! directory. Other missing records are left missing, they may be typos.
+
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use log::{error, info, warn};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::config::Settings;
+use crate::sync::{fetch_managed, may_modify};
+use crate::{delete_record, paths, published, update_record, Error};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum OnShutdown {
+    /// Leave the records as they are
+    #[default]
+    Keep,
+    Delete,
+    /// Point the records at these addresses
+    Fallback(Vec<IpAddr>),
+}
+
+impl FromStr for OnShutdown {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text.trim() {
+            "keep" => Ok(OnShutdown::Keep),
+            "delete" => Ok(OnShutdown::Delete),
+            addresses => addresses
+                .split(',')
+                .map(|address| address.trim().parse::<IpAddr>())
+                .collect::<Result<_, _>>()
+                .map(OnShutdown::Fallback)
+                .map_err(|_| {
+                    format!(
+                        "on_shutdown must be \"keep\", \"delete\" or fallback addresses, not {:?}",
+                        text
+                    )
+                    .into()
+                }),
+        }
+    }
+}
+
+impl OnShutdown {
+    /// Where records of `record_type` are pointed, `None` to delete them.
+    fn fallback(&self, record_type: &str) -> Option<IpAddr> {
+        match self {
+            OnShutdown::Fallback(addresses) => addresses
+                .iter()
+                .find(|address| crate::ip::record_type(**address) == record_type)
+                .copied(),
+            _ => None,
+        }
+    }
+}
+
+/// `deregistered.json` in the state directory.
+pub fn default_path() -> PathBuf {
+    paths::state_dir().join("deregistered.json")
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Deleted {
+    zone: String,
+    name: String,
+    #[serde(rename = "type")]
+    record_type: String,
+}
+
+/// The records deleted on shutdown that haven't been created again.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Deregistered(Vec<Deleted>);
+
+impl Deregistered {
+    pub fn load() -> Self {
+        fs::read_to_string(default_path())
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) {
+        let path = default_path();
+        let saved = match self.0.is_empty() {
+            true => fs::remove_file(&path).or_else(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => Ok(()),
+                _ => Err(e),
+            }),
+            false => paths::write(&path, serde_json::to_string(self).expect("serializable")),
+        };
+        if let Err(e) = saved {
+            warn!("Could not write {}: {}", path.display(), e);
+        }
+    }
+
+    fn position(&self, zone: &str, name: &str, record_type: &str) -> Option<usize> {
+        self.0
+            .iter()
+            .position(|d| d.zone == zone && d.name == name && d.record_type == record_type)
+    }
+
+    /// Whether cfdns deleted the `record_type` record `name` of `zone`.
+    pub fn contains(&self, zone: &str, name: &str, record_type: &str) -> bool {
+        self.position(zone, name, record_type).is_some()
+    }
+
+    fn insert(&mut self, zone: &str, name: &str, record_type: &str) {
+        if !self.contains(zone, name, record_type) {
+            self.0.push(Deleted {
+                zone: zone.to_string(),
+                name: name.to_string(),
+                record_type: record_type.to_string(),
+            });
+        }
+    }
+
+    /// Forgets the record once it was created again.
+    pub fn restored(&mut self, zone: &str, name: &str, record_type: &str) {
+        if let Some(at) = self.position(zone, name, record_type) {
+            self.0.remove(at);
+            self.save();
+        }
+    }
+}
+
+/// Deletes or repoints the managed records, on shutdown.
+pub fn run(settings: &Settings) {
+    if settings.on_shutdown == OnShutdown::Keep {
+        return;
+    }
+    let mut deregistered = Deregistered::load();
+    for record_type in ["A", "AAAA"] {
+        let fallback = settings.on_shutdown.fallback(record_type);
+        if settings.on_shutdown != OnShutdown::Delete && fallback.is_none() {
+            info!(
+                "No fallback address for the {} records, keeping them",
+                record_type
+            );
+            continue;
+        }
+        let managed = match fetch_managed(settings, record_type) {
+            Ok(managed) => managed,
+            Err(e) => {
+                error!("Could not deregister the {} records: {}", record_type, e);
+                continue;
+            }
+        };
+        for managed in managed {
+            let Some(record) = managed.record.filter(|r| may_modify(r, settings)) else {
+                continue;
+            };
+            let cloudflare = settings.client(&managed.zone);
+            let result = match fallback {
+                Some(address) => {
+                    update_record(cloudflare, &address.to_string(), &record, &managed.zone)
+                        .map(|()| info!("Pointed {} at the fallback {}", record.name, address))
+                }
+                None => delete_record(cloudflare, &record, &managed.zone).map(|()| {
+                    deregistered.insert(&managed.zone, &record.name, record_type);
+                }),
+            };
+            if let Err(e) = result {
+                error!("Could not deregister {}: {}", record.name, e);
+            }
+        }
+    }
+    deregistered.save();
+    // The records no longer have the IP published last.
+    published::forget();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fallbacks_go_by_family() {
+        assert_eq!("delete".parse::<OnShutdown>().unwrap(), OnShutdown::Delete);
+        let on_shutdown: OnShutdown = "192.0.2.10, 2001:db8::10".parse().unwrap();
+        assert_eq!(
+            on_shutdown.fallback("A"),
+            Some("192.0.2.10".parse().unwrap())
+        );
+        assert_eq!(
+            on_shutdown.fallback("AAAA"),
+            Some("2001:db8::10".parse().unwrap())
+        );
+        let on_shutdown: OnShutdown = "192.0.2.10".parse().unwrap();
+        assert_eq!(on_shutdown.fallback("AAAA"), None);
+        assert!("remove".parse::<OnShutdown>().is_err());
+    }
+}
@@ -0,0 +1,355 @@
+//! A tiny DNS client, only what cfdns needs: ask one server for the A or
+//! AAAA records of one name over UDP, or over HTTPS through [`crate::doh`],
+//! and for TXT and DS records over UDP.
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::Duration;
+
+use crate::{doh, Error};
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_NS: u16 = 2;
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_AAAA: u16 = 28;
+pub const TYPE_DS: u16 = 43;
+
+/// Query type for the given record type name.
+pub fn query_type(record_type: &str) -> u16 {
+    match record_type {
+        "AAAA" => TYPE_AAAA,
+        _ => TYPE_A,
+    }
+}
+
+/// Where address queries go.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolver {
+    Udp(SocketAddr),
+    /// A DNS-over-HTTPS endpoint URL
+    Https(String),
+}
+
+impl Resolver {
+    pub fn query(
+        &self,
+        client: &reqwest::blocking::Client,
+        name: &str,
+        qtype: u16,
+        timeout: Duration,
+    ) -> Result<Vec<IpAddr>, Error> {
+        match self {
+            Resolver::Udp(server) => query(*server, name, qtype, timeout),
+            Resolver::Https(url) => doh::query(client, url, name, qtype, timeout),
+        }
+    }
+}
+
+impl fmt::Display for Resolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Resolver::Udp(server) => write!(f, "{}", server.ip()),
+            Resolver::Https(url) => write!(f, "{}", url),
+        }
+    }
+}
+
+/// The addresses `server` has for `name`. An empty list means the name or
+/// the record type does not exist.
+pub fn query(
+    server: SocketAddr,
+    name: &str,
+    qtype: u16,
+    timeout: Duration,
+) -> Result<Vec<IpAddr>, Error> {
+    let answer = exchange(server, name, qtype, timeout)?;
+    parse_response(&answer, qtype)
+}
+
+/// The name servers `server` lists in the answer section for `name`, empty
+/// unless `name` is the apex of a zone.
+pub fn query_ns(server: SocketAddr, name: &str, timeout: Duration) -> Result<Vec<String>, Error> {
+    let answer = exchange(server, name, TYPE_NS, timeout)?;
+    parse_ns_response(&answer)
+}
+
+/// The TXT records `server` has for `name`, each one's strings joined.
+pub fn query_txt(server: SocketAddr, name: &str, timeout: Duration) -> Result<Vec<String>, Error> {
+    let answer = exchange(server, name, TYPE_TXT, timeout)?;
+    parse_txt_response(&answer)
+}
+
+/// The key tags of the DS records `server` has for `name`, which are only
+/// there if the parent zone delegates to a signed zone.
+pub fn query_ds(server: SocketAddr, name: &str, timeout: Duration) -> Result<Vec<u16>, Error> {
+    let answer = exchange(server, name, TYPE_DS, timeout)?;
+    parse_ds_response(&answer)
+}
+
+/// Sends one query and waits for its answer.
+fn exchange(
+    server: SocketAddr,
+    name: &str,
+    qtype: u16,
+    timeout: Duration,
+) -> Result<Vec<u8>, Error> {
+    let bind: SocketAddr = match server {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(bind)?;
+    socket.set_read_timeout(Some(timeout))?;
+    socket.connect(server)?;
+
+    let id = query_id();
+    socket.send(&encode_query(id, name, qtype)?)?;
+    let mut buffer = [0; 1500];
+    loop {
+        let length = socket
+            .recv(&mut buffer)
+            .map_err(|e| format!("no answer from {}: {}", server, e))?;
+        // Stray answers to an earlier query are dropped.
+        if length >= 2 && u16::from_be_bytes([buffer[0], buffer[1]]) == id {
+            return Ok(buffer[..length].to_vec());
+        }
+    }
+}
+
+fn query_id() -> u16 {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    (nanos ^ (nanos >> 16)) as u16
+}
+
+pub fn encode_query(id: u16, name: &str, qtype: u16) -> Result<Vec<u8>, Error> {
+    let mut packet = Vec::with_capacity(512);
+    packet.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired, one question.
+    packet.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!("{} is not a valid DNS name", name).into());
+        }
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+    packet.extend_from_slice(&qtype.to_be_bytes());
+    packet.extend_from_slice(&1u16.to_be_bytes());
+    Ok(packet)
+}
+
+/// Addresses of type `qtype` in the answer section of `packet`.
+pub fn parse_response(packet: &[u8], qtype: u16) -> Result<Vec<IpAddr>, Error> {
+    let mut addresses = Vec::new();
+    for Answer { rtype, data, .. } in answers(packet)? {
+        match (rtype, data.len()) {
+            (TYPE_A, 4) if qtype == TYPE_A => {
+                let octets: [u8; 4] = data.try_into().expect("length checked");
+                addresses.push(IpAddr::from(octets));
+            }
+            (TYPE_AAAA, 16) if qtype == TYPE_AAAA => {
+                let octets: [u8; 16] = data.try_into().expect("length checked");
+                addresses.push(IpAddr::from(octets));
+            }
+            // CNAMEs and the like on the way to the address.
+            _ => {}
+        }
+    }
+    Ok(addresses)
+}
+
+/// Name server names in the answer section of `packet`.
+pub fn parse_ns_response(packet: &[u8]) -> Result<Vec<String>, Error> {
+    let mut names = Vec::new();
+    for answer in answers(packet)? {
+        if answer.rtype == TYPE_NS {
+            names.push(read_name(packet, answer.offset).ok_or("malformed DNS response")?);
+        }
+    }
+    Ok(names)
+}
+
+/// TXT records in the answer section of `packet`.
+pub fn parse_txt_response(packet: &[u8]) -> Result<Vec<String>, Error> {
+    let mut texts = Vec::new();
+    for answer in answers(packet)?.into_iter().filter(|a| a.rtype == TYPE_TXT) {
+        // One or more strings, each prefixed with its length.
+        let mut text = Vec::new();
+        let mut data = answer.data;
+        while let Some((&length, rest)) = data.split_first() {
+            let string = rest
+                .get(..length as usize)
+                .ok_or("malformed DNS response")?;
+            text.extend_from_slice(string);
+            data = &rest[length as usize..];
+        }
+        texts.push(String::from_utf8_lossy(&text).into_owned());
+    }
+    Ok(texts)
+}
+
+/// Key tags of the DS records in the answer section of `packet`.
+pub fn parse_ds_response(packet: &[u8]) -> Result<Vec<u16>, Error> {
+    let mut tags = Vec::new();
+    for answer in answers(packet)?.into_iter().filter(|a| a.rtype == TYPE_DS) {
+        // Key tag, algorithm and digest type, then the digest.
+        match answer.data {
+            [high, low, _, _, _, ..] => tags.push(u16::from_be_bytes([*high, *low])),
+            _ => return Err("malformed DNS response".into()),
+        }
+    }
+    Ok(tags)
+}
+
+/// One resource record of the answer section.
+struct Answer<'a> {
+    rtype: u16,
+    data: &'a [u8],
+    /// Where `data` starts in the packet, for names compressed against it
+    offset: usize,
+}
+
+/// Every answer in `packet`.
+fn answers(packet: &[u8]) -> Result<Vec<Answer<'_>>, Error> {
+    let malformed = || Error::from("malformed DNS response");
+    if packet.len() < 12 {
+        return Err(malformed());
+    }
+    let flags = u16::from_be_bytes([packet[2], packet[3]]);
+    if flags & 0x0200 != 0 {
+        return Err("DNS response was truncated".into());
+    }
+    match flags & 0x000f {
+        0 | 3 => {}
+        rcode => return Err(format!("DNS server answered with error code {}", rcode).into()),
+    }
+    let questions = u16::from_be_bytes([packet[4], packet[5]]);
+    let answers = u16::from_be_bytes([packet[6], packet[7]]);
+
+    let mut offset = 12;
+    for _ in 0..questions {
+        offset = skip_name(packet, offset).ok_or_else(malformed)? + 4;
+    }
+    let mut records = Vec::new();
+    for _ in 0..answers {
+        offset = skip_name(packet, offset).ok_or_else(malformed)?;
+        let header = packet.get(offset..offset + 10).ok_or_else(malformed)?;
+        let rtype = u16::from_be_bytes([header[0], header[1]]);
+        let length = u16::from_be_bytes([header[8], header[9]]) as usize;
+        let data = packet
+            .get(offset + 10..offset + 10 + length)
+            .ok_or_else(malformed)?;
+        records.push(Answer {
+            rtype,
+            data,
+            offset: offset + 10,
+        });
+        offset += 10 + length;
+    }
+    Ok(records)
+}
+
+/// The name at `offset`, following compression pointers.
+fn read_name(packet: &[u8], mut offset: usize) -> Option<String> {
+    let mut labels = Vec::new();
+    // Bounded so that pointer loops in a hostile answer can't hang us.
+    for _ in 0..128 {
+        let length = *packet.get(offset)?;
+        match length {
+            0 => return Some(labels.join(".")),
+            length if length & 0xc0 == 0xc0 => {
+                let low = *packet.get(offset + 1)?;
+                offset = usize::from(u16::from_be_bytes([length & 0x3f, low]));
+            }
+            length => {
+                let label = packet.get(offset + 1..offset + 1 + length as usize)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                offset += 1 + length as usize;
+            }
+        }
+    }
+    None
+}
+
+/// Offset right after the (possibly compressed) name starting at `offset`.
+fn skip_name(packet: &[u8], mut offset: usize) -> Option<usize> {
+    loop {
+        let length = *packet.get(offset)?;
+        match length {
+            0 => return Some(offset + 1),
+            length if length & 0xc0 == 0xc0 => return Some(offset + 2),
+            length => offset += 1 + length as usize,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_and_parse() {
+        let query = encode_query(0xbeef, "home.example.com", TYPE_A).unwrap();
+        assert_eq!(&query[..2], &[0xbe, 0xef]);
+        assert_eq!(&query[12..17], b"\x04home");
+
+        // The query turned into an answer: a CNAME, then an A record, both
+        // pointing back at the question name.
+        let mut answer = query.clone();
+        answer[2..4].copy_from_slice(&[0x81, 0x80]);
+        answer[6..8].copy_from_slice(&[0, 2]);
+        answer.extend_from_slice(&[0xc0, 12, 0, 5, 0, 1, 0, 0, 1, 44, 0, 2, 0xc0, 12]);
+        answer.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 1, 44, 0, 4, 81, 2, 69, 160]);
+        assert_eq!(
+            parse_response(&answer, TYPE_A).unwrap(),
+            vec!["81.2.69.160".parse::<IpAddr>().unwrap()]
+        );
+        assert!(parse_response(&answer, TYPE_AAAA).unwrap().is_empty());
+        assert!(parse_response(&answer[..20], TYPE_A).is_err());
+        assert!(encode_query(1, "bad..name", TYPE_A).is_err());
+    }
+
+    #[test]
+    fn test_parse_ns_answer() {
+        let mut answer = encode_query(7, "example.com", TYPE_NS).unwrap();
+        answer[2..4].copy_from_slice(&[0x81, 0x80]);
+        answer[6..8].copy_from_slice(&[0, 2]);
+        // ada.ns.cloudflare.com, then bob.ns. pointing into the first one.
+        let first = answer.len() + 12;
+        answer.extend_from_slice(&[0xc0, 12, 0, 2, 0, 1, 0, 1, 81, 128, 0, 23]);
+        answer.extend_from_slice(b"\x03ada\x02ns\x0acloudflare\x03com\x00");
+        answer.extend_from_slice(&[0xc0, 12, 0, 2, 0, 1, 0, 1, 81, 128, 0, 6]);
+        answer.extend_from_slice(&[3, b'b', b'o', b'b', 0xc0, first as u8 + 4]);
+        assert_eq!(
+            parse_ns_response(&answer).unwrap(),
+            vec!["ada.ns.cloudflare.com", "bob.ns.cloudflare.com"]
+        );
+    }
+
+    #[test]
+    fn test_parse_txt_answer() {
+        let mut answer = encode_query(9, "_acme-challenge.example.com", TYPE_TXT).unwrap();
+        answer[2..4].copy_from_slice(&[0x81, 0x80]);
+        answer[6..8].copy_from_slice(&[0, 2]);
+        answer.extend_from_slice(&[0xc0, 12, 0, 16, 0, 1, 0, 0, 0, 60, 0, 4]);
+        answer.extend_from_slice(b"\x03abc");
+        answer.extend_from_slice(&[0xc0, 12, 0, 16, 0, 1, 0, 0, 0, 60, 0, 6]);
+        answer.extend_from_slice(b"\x02de\x02fg");
+        assert_eq!(parse_txt_response(&answer).unwrap(), vec!["abc", "defg"]);
+        answer.truncate(answer.len() - 1);
+        assert!(parse_txt_response(&answer).is_err());
+    }
+
+    #[test]
+    fn test_parse_ds_answer() {
+        let mut answer = encode_query(3, "example.com", TYPE_DS).unwrap();
+        answer[2..4].copy_from_slice(&[0x81, 0x80]);
+        answer[6..8].copy_from_slice(&[0, 1]);
+        answer.extend_from_slice(&[0xc0, 12, 0, 43, 0, 1, 0, 0, 14, 16, 0, 8]);
+        answer.extend_from_slice(&[0x09, 0x6b, 13, 2, 0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(parse_ds_response(&answer).unwrap(), vec![2411]);
+    }
+}
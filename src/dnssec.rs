@@ -0,0 +1,271 @@
+//! `cfdns dnssec status`: the DNSSEC state Cloudflare has for each zone and
+//! the DS records its parent publishes, which have to agree. A DS left at
+//! the registrar after DNSSEC was turned off, or one for another key, makes
+//! validating resolvers refuse every record of the zone, so no update cfdns
+//! makes reaches them. The daemon checks this hourly and notifies when a zone
+//! breaks.
+
+use std::collections::{BTreeSet, HashMap};
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::config::Settings;
+use crate::notify::{self, Notification};
+use crate::output::{self, Format, Highlight, Tabular};
+use crate::{dns, get_zone, names, send_api, Error};
+
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+/// DNSSEC is set up once and rarely touched, the daemon needn't ask often.
+const CHECK_EVERY: Duration = Duration::from_secs(60 * 60);
+
+#[derive(clap::Subcommand, Debug, Clone)]
+pub enum DnssecCommand {
+    /// Show whether DNSSEC of the configured zones validates
+    Status,
+}
+
+/// What `/zones/{id}/dnssec` returns, as far as cfdns cares.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+struct Dnssec {
+    status: String,
+    algorithm: Option<String>,
+    key_tag: Option<u16>,
+    ds: Option<String>,
+    modified_on: Option<String>,
+}
+
+/// Whether resolvers can validate a zone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Verdict {
+    Signed,
+    Unsigned,
+    /// Cloudflare waits for the DS to show up at the parent
+    Pending,
+    Broken(String),
+}
+
+/// Judges Cloudflare's DNSSEC state against the key tags of the DS records
+/// at the parent, `None` if those could not be looked up.
+fn verdict(dnssec: &Dnssec, parent: Option<&[u16]>) -> Verdict {
+    let has_ds = parent.is_some_and(|tags| !tags.is_empty());
+    match dnssec.status.as_str() {
+        "error" => Verdict::Broken("Cloudflare reports a DNSSEC error".to_string()),
+        "active" => match (parent, dnssec.key_tag) {
+            (Some(tags), Some(key_tag)) if has_ds && !tags.contains(&key_tag) => {
+                Verdict::Broken(format!(
+                    "the DS at the parent is for key {}, Cloudflare signs with key {}",
+                    tags.iter()
+                        .map(|tag| tag.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    key_tag
+                ))
+            }
+            (Some(_), _) if !has_ds => Verdict::Unsigned,
+            _ => Verdict::Signed,
+        },
+        "pending" => Verdict::Pending,
+        _ if has_ds => Verdict::Broken(
+            "the parent still has a DS but Cloudflare doesn't sign the zone, remove it at the registrar"
+                .to_string(),
+        ),
+        _ => Verdict::Unsigned,
+    }
+}
+
+/// The DNSSEC state of one zone.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DnssecRow {
+    pub zone: String,
+    pub status: String,
+    pub algorithm: Option<String>,
+    pub key_tag: Option<u16>,
+    pub ds: Option<String>,
+    /// Key tags of the DS records at the parent, if they could be looked up
+    pub parent_ds: Option<Vec<u16>>,
+    pub modified_on: Option<String>,
+    pub ok: bool,
+    pub problem: Option<String>,
+}
+
+impl Tabular for DnssecRow {
+    fn headers() -> Vec<&'static str> {
+        vec![
+            "ZONE",
+            "STATUS",
+            "ALGORITHM",
+            "KEY TAG",
+            "PARENT DS",
+            "PROBLEM",
+        ]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            names::display(&self.zone),
+            self.status.clone(),
+            self.algorithm.clone().unwrap_or_default(),
+            self.key_tag.map(|tag| tag.to_string()).unwrap_or_default(),
+            match &self.parent_ds {
+                Some(tags) if tags.is_empty() => "none".to_string(),
+                Some(tags) => tags
+                    .iter()
+                    .map(|tag| tag.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                None => "unknown".to_string(),
+            },
+            self.problem.clone().unwrap_or_default(),
+        ]
+    }
+
+    fn highlights(&self) -> Vec<Option<Highlight>> {
+        let mut highlights = vec![None; 6];
+        highlights[1] = Some(match self.ok {
+            true => Highlight::Green,
+            false => Highlight::Red,
+        });
+        highlights
+    }
+}
+
+/// The DNSSEC state of zone `id`.
+fn check_zone(settings: &Settings, id: &str) -> Result<DnssecRow, Error> {
+    let cloudflare = settings.client(id);
+    let zone = get_zone(cloudflare, id)?;
+    let request = cloudflare.get(&format!("/zones/{id}/dnssec"));
+    let dnssec: Dnssec = serde_json::from_value(send_api(request, "DNSSEC request")?)
+        .map_err(|e| format!("could not parse the DNSSEC state: {}", e))?;
+    let parent = match settings.resolvers.first() {
+        Some(resolver) => dns::query_ds(*resolver, &zone.name, QUERY_TIMEOUT)
+            .map_err(|e| warn!("Could not look up the DS of {}: {}", zone.name, e))
+            .ok(),
+        None => None,
+    };
+    let verdict = verdict(&dnssec, parent.as_deref());
+    Ok(DnssecRow {
+        zone: zone.name,
+        status: dnssec.status,
+        algorithm: dnssec.algorithm,
+        key_tag: dnssec.key_tag,
+        ds: dnssec.ds,
+        parent_ds: parent,
+        modified_on: dnssec.modified_on,
+        ok: !matches!(verdict, Verdict::Broken(_)),
+        problem: match verdict {
+            Verdict::Broken(problem) => Some(problem),
+            Verdict::Pending => Some("waiting for the DS at the registrar".to_string()),
+            Verdict::Signed | Verdict::Unsigned => None,
+        },
+    })
+}
+
+fn zone_ids(settings: &Settings) -> BTreeSet<&str> {
+    settings.zones.iter().map(|z| z.id.as_str()).collect()
+}
+
+pub fn run(settings: &Settings, command: &DnssecCommand, format: Format) -> Result<(), Error> {
+    match command {
+        DnssecCommand::Status => {
+            if settings.zones.is_empty() {
+                return Err("No zone to check, pass --zone or a --config file".into());
+            }
+            let rows = zone_ids(settings)
+                .into_iter()
+                .map(|id| check_zone(settings, id))
+                .collect::<Result<Vec<_>, _>>()?;
+            output::print(format, &rows)
+        }
+    }
+}
+
+/// Keeps an eye on DNSSEC in daemon mode, warning and notifying when a zone
+/// breaks and logging once it is fixed, not on every check in between.
+#[derive(Debug, Default)]
+pub struct Monitor {
+    last_check: Option<Instant>,
+    broken: HashMap<String, String>,
+}
+
+impl Monitor {
+    /// Checks every zone if the last check is older than [`CHECK_EVERY`].
+    /// Failing to check is only logged, it says nothing about DNSSEC.
+    pub fn check(&mut self, client: &reqwest::blocking::Client, settings: &Settings) {
+        if self
+            .last_check
+            .is_some_and(|last| last.elapsed() < CHECK_EVERY)
+        {
+            return;
+        }
+        self.last_check = Some(Instant::now());
+        for id in zone_ids(settings) {
+            match check_zone(settings, id) {
+                Ok(row) => self.record(client, settings, row),
+                Err(e) => warn!("Could not check DNSSEC of zone {}: {}", id, e),
+            }
+        }
+    }
+
+    fn record(&mut self, client: &reqwest::blocking::Client, settings: &Settings, row: DnssecRow) {
+        let name = names::display(&row.zone);
+        match (row.ok, row.problem) {
+            (false, Some(problem)) => {
+                if self.broken.get(&row.zone) == Some(&problem) {
+                    return;
+                }
+                warn!(
+                    "DNSSEC of {} is broken, resolvers will refuse its records: {}",
+                    name, problem
+                );
+                if let Some(url) = &settings.notify_url {
+                    let notification = Notification::Dnssec {
+                        zone: &row.zone,
+                        status: &row.status,
+                        problem: &problem,
+                    };
+                    notify::send(client, url, &notification);
+                }
+                self.broken.insert(row.zone, problem);
+            }
+            _ => {
+                if self.broken.remove(&row.zone).is_some() {
+                    info!("DNSSEC of {} validates again", name);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dnssec_is_judged_against_the_parent() {
+        let dnssec = |status: &str| Dnssec {
+            status: status.to_string(),
+            key_tag: Some(2371),
+            ..Default::default()
+        };
+        assert_eq!(verdict(&dnssec("active"), Some(&[2371])), Verdict::Signed);
+        assert_eq!(verdict(&dnssec("active"), None), Verdict::Signed);
+        assert_eq!(verdict(&dnssec("active"), Some(&[])), Verdict::Unsigned);
+        assert!(matches!(
+            verdict(&dnssec("active"), Some(&[1234])),
+            Verdict::Broken(_)
+        ));
+        assert_eq!(verdict(&dnssec("pending"), Some(&[])), Verdict::Pending);
+        assert!(matches!(
+            verdict(&dnssec("disabled"), Some(&[2371])),
+            Verdict::Broken(_)
+        ));
+        assert_eq!(verdict(&dnssec("disabled"), Some(&[])), Verdict::Unsigned);
+        assert!(matches!(
+            verdict(&dnssec("error"), None),
+            Verdict::Broken(_)
+        ));
+    }
+}
@@ -0,0 +1,101 @@
+//! DNS-over-HTTPS, for networks that block outbound port 53. Speaks the
+//! RFC 8484 wire format, and Google's JSON API for URLs ending in `/resolve`.
+
+use std::net::IpAddr;
+use std::time::Duration;
+
+use reqwest::header;
+use serde_derive::Deserialize;
+
+use crate::dns;
+use crate::trace::Traced;
+use crate::Error;
+
+pub const DEFAULT_URL: &str = "https://cloudflare-dns.com/dns-query";
+
+const DNS_MESSAGE: &str = "application/dns-message";
+
+#[derive(Deserialize, Debug)]
+struct JsonResponse {
+    #[serde(rename = "Status")]
+    status: u16,
+    #[serde(rename = "Answer", default)]
+    answer: Vec<JsonAnswer>,
+}
+
+#[derive(Deserialize, Debug)]
+struct JsonAnswer {
+    #[serde(rename = "type")]
+    rtype: u16,
+    data: String,
+}
+
+/// The addresses `url` resolves `name` to.
+pub fn query(
+    client: &reqwest::blocking::Client,
+    url: &str,
+    name: &str,
+    qtype: u16,
+    timeout: Duration,
+) -> Result<Vec<IpAddr>, Error> {
+    if url.trim_end_matches('/').ends_with("/resolve") {
+        let body = client
+            .get(url)
+            .query(&[("name", name), ("type", &qtype.to_string())])
+            .header(header::ACCEPT, "application/dns-json")
+            .timeout(timeout)
+            .send_traced()?
+            .error_for_status()?
+            .text()?;
+        return parse_json(&body, qtype);
+    }
+
+    // The ID is 0 so that HTTP caches see identical queries, RFC 8484 4.1.
+    let answer = client
+        .post(url)
+        .header(header::CONTENT_TYPE, DNS_MESSAGE)
+        .header(header::ACCEPT, DNS_MESSAGE)
+        .body(dns::encode_query(0, name, qtype)?)
+        .timeout(timeout)
+        .send_traced()?
+        .error_for_status()?
+        .bytes()?;
+    dns::parse_response(&answer, qtype)
+}
+
+fn parse_json(body: &str, qtype: u16) -> Result<Vec<IpAddr>, Error> {
+    let response: JsonResponse = serde_json::from_str(body)?;
+    match response.status {
+        0 => {}
+        // NXDOMAIN, the name does not exist.
+        3 => return Ok(Vec::new()),
+        status => return Err(format!("DNS query failed with rcode {}", status).into()),
+    }
+    Ok(response
+        .answer
+        .iter()
+        .filter(|answer| answer.rtype == qtype)
+        .filter_map(|answer| answer.data.parse().ok())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_json_answer() {
+        let body = r#"{"Status": 0, "Answer": [
+            {"name": "www.example.com.", "type": 5, "TTL": 300, "data": "home.example.com."},
+            {"name": "home.example.com.", "type": 1, "TTL": 300, "data": "203.0.113.7"}
+        ]}"#;
+        assert_eq!(
+            parse_json(body, dns::TYPE_A).unwrap(),
+            vec!["203.0.113.7".parse::<IpAddr>().unwrap()]
+        );
+        assert!(parse_json(r#"{"Status": 3}"#, dns::TYPE_A)
+            .unwrap()
+            .is_empty());
+        assert!(parse_json(r#"{"Status": 2}"#, dns::TYPE_A).is_err());
+    }
+}
@@ -0,0 +1,101 @@
+//! `--events ndjson`: one JSON object per line on stdout for everything of
+//! note the daemon does, for `docker logs | jq` and log shippers. The log
+//! format for humans, on stderr, stays as it is.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
+
+use clap::ValueEnum;
+use serde_derive::Serialize;
+
+use crate::sync::SyncState;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Newline delimited JSON
+    Ndjson,
+}
+
+/// Whether [`emit`] writes events, `--events` was given.
+pub fn set(format: Option<Format>) {
+    ENABLED.store(format.is_some(), Ordering::Relaxed);
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    /// A run detected a different IP than the previous one, or its first
+    IpDetected {
+        ip: &'a str,
+        previous: Option<&'a str>,
+    },
+    /// A record was updated, or put back after an external change
+    RecordUpdated {
+        zone: &'a str,
+        name: &'a str,
+        content: Option<&'a str>,
+        state: SyncState,
+    },
+    /// A run failed as a whole, or for one record
+    Error {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        zone: Option<&'a str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        name: Option<&'a str>,
+        message: &'a str,
+    },
+    /// A webhook notification went out, `notification` being its event
+    #[cfg_attr(not(feature = "notify"), allow(dead_code))]
+    NotificationSent { url: &'a str, notification: &'a str },
+}
+
+#[derive(Debug, Serialize)]
+struct Line<'a> {
+    time: String,
+    #[serde(flatten)]
+    event: &'a Event<'a>,
+}
+
+fn line(time: SystemTime, event: &Event) -> String {
+    let line = Line {
+        time: humantime::format_rfc3339_millis(time).to_string(),
+        event,
+    };
+    serde_json::to_string(&line).expect("events are serializable")
+}
+
+/// Writes `event` to stdout with `--events`.
+pub fn emit(event: &Event) {
+    if ENABLED.load(Ordering::Relaxed) {
+        println!("{}", line(SystemTime::now(), event));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_events_are_single_lines() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let event = Event::RecordUpdated {
+            zone: "z",
+            name: "home.example.com",
+            content: Some("198.51.100.7"),
+            state: SyncState::Updated,
+        };
+        assert_eq!(
+            line(time, &event),
+            r#"{"time":"2023-11-14T22:13:20.000Z","event":"record_updated","zone":"z","name":"home.example.com","content":"198.51.100.7","state":"updated"}"#
+        );
+        let event = Event::Error {
+            zone: None,
+            name: None,
+            message: "no IP",
+        };
+        assert!(!line(time, &event).contains("zone"));
+    }
+}
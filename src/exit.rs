@@ -0,0 +1,147 @@
+//! Exit codes. `--exit-codes simple`, the default, exits with 0 or 1;
+//! `--exit-codes detailed` tells scripts and monitoring what happened:
+//!
+//! | code | meaning |
+//! |---|---|
+//! | 0 | done, every record already in sync |
+//! | 1 | any other error |
+//! | 2 | records were updated or put back |
+//! | 3 | records were created, by `new-host` |
+//! | 10 | Cloudflare rejected the token |
+//! | 11 | Cloudflare or the IP sources could not be reached |
+//! | 12 | a configured record does not exist |
+//! | 13 | some record updates failed |
+//! | 14 | updates were held back by `update_windows` or `max_updates_per_hour` |
+//!
+//! When several apply, 13 goes before 14, 14 before 12 and 12 before 2.
+//! 1, 10 and 11 mean the run stopped early.
+
+use std::fmt;
+
+use clap::ValueEnum;
+
+use crate::sync::Summary;
+use crate::token::Unauthorized;
+use crate::Error;
+
+pub const IN_SYNC: u8 = 0;
+pub const ERROR: u8 = 1;
+pub const UPDATED: u8 = 2;
+pub const CREATED: u8 = 3;
+pub const AUTH_ERROR: u8 = 10;
+pub const NETWORK_ERROR: u8 = 11;
+pub const NOT_FOUND: u8 = 12;
+pub const UPDATES_FAILED: u8 = 13;
+pub const THROTTLED: u8 = 14;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ExitCodes {
+    /// 0 on success, 1 on failure
+    #[default]
+    Simple,
+    /// A code per outcome, see the README
+    Detailed,
+}
+
+/// What a successful run did, as far as exit codes care.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Done,
+    Synced(Summary),
+    Created(usize),
+}
+
+impl From<()> for Outcome {
+    fn from(_: ()) -> Outcome {
+        Outcome::Done
+    }
+}
+
+/// A one-shot run in which some record updates failed.
+#[derive(Debug)]
+pub struct UpdatesFailed(pub Summary);
+
+impl fmt::Display for UpdatesFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} record update(s) failed", self.0.failed)
+    }
+}
+
+impl std::error::Error for UpdatesFailed {}
+
+fn for_summary(summary: &Summary) -> u8 {
+    if summary.failed > 0 {
+        UPDATES_FAILED
+    } else if summary.throttled > 0 {
+        THROTTLED
+    } else if summary.missing > 0 {
+        NOT_FOUND
+    } else if summary.updated + summary.reverted > 0 {
+        UPDATED
+    } else {
+        IN_SYNC
+    }
+}
+
+pub fn success(codes: ExitCodes, outcome: &Outcome) -> u8 {
+    match (codes, outcome) {
+        (ExitCodes::Simple, _) | (_, Outcome::Done) => IN_SYNC,
+        (_, Outcome::Synced(summary)) => for_summary(summary),
+        (_, Outcome::Created(0)) => IN_SYNC,
+        (_, Outcome::Created(_)) => CREATED,
+    }
+}
+
+pub fn failure(codes: ExitCodes, error: &Error) -> u8 {
+    if codes == ExitCodes::Simple {
+        return ERROR;
+    }
+    if let Some(UpdatesFailed(summary)) = error.downcast_ref::<UpdatesFailed>() {
+        return for_summary(summary);
+    }
+    if error.is::<Unauthorized>() {
+        return AUTH_ERROR;
+    }
+    let unreachable = match error.downcast_ref::<reqwest::Error>() {
+        Some(e) => e.is_connect() || e.is_timeout(),
+        None => error.to_string().contains("could not send request"),
+    };
+    match unreachable {
+        true => NETWORK_ERROR,
+        false => ERROR,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detailed_codes() {
+        let summary = |updated, missing, failed| Summary {
+            checked: 3,
+            updated,
+            missing,
+            failed,
+            ..Summary::default()
+        };
+        let detailed = |summary| success(ExitCodes::Detailed, &Outcome::Synced(summary));
+        assert_eq!(detailed(summary(0, 0, 0)), IN_SYNC);
+        assert_eq!(detailed(summary(1, 0, 0)), UPDATED);
+        assert_eq!(detailed(summary(1, 1, 0)), NOT_FOUND);
+        assert_eq!(success(ExitCodes::Simple, &Outcome::Created(2)), IN_SYNC);
+        assert_eq!(success(ExitCodes::Detailed, &Outcome::Created(2)), CREATED);
+
+        let failed: Error = UpdatesFailed(summary(1, 1, 1)).into();
+        assert_eq!(failed.to_string(), "1 record update(s) failed");
+        assert_eq!(failure(ExitCodes::Detailed, &failed), UPDATES_FAILED);
+        assert_eq!(failure(ExitCodes::Simple, &failed), ERROR);
+        assert_eq!(
+            failure(ExitCodes::Detailed, &Unauthorized(403, None).into()),
+            AUTH_ERROR
+        );
+        let offline: Error = "could not send request to cloudflare api: timed out".into();
+        assert_eq!(failure(ExitCodes::Detailed, &offline), NETWORK_ERROR);
+        assert_eq!(failure(ExitCodes::Detailed, &"bad config".into()), ERROR);
+    }
+}
@@ -0,0 +1,209 @@
+//! Failover pairs, `[[zones.failover]]`: the daemon checks the primary
+//! address of a record on every run and publishes the secondary one after
+//! `failures` failed checks in a row, switching back as soon as the primary
+//! answers again. Each switch is logged and sent to `notify_url`.
+
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+
+use log::{info, warn};
+use serde_derive::Deserialize;
+
+use crate::cloudflare::models::NewRecord;
+use crate::config::{Settings, ZoneConfig};
+use crate::notify::{self, Notification};
+use crate::probe::Probe;
+use crate::sync::marked_comment;
+use crate::templated::{apply, zone_name};
+use crate::{ip, list_dns_records, names, Error};
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FailoverConfig {
+    pub name: String,
+    /// Published while it passes the check, the detected IP unless given
+    pub primary: Option<IpAddr>,
+    pub secondary: Option<IpAddr>,
+    /// `tcp:<port>` or an http(s):// URL, see [`Probe`]
+    pub check: String,
+    /// Failed checks in a row before the secondary is published
+    #[serde(default = "default_failures")]
+    pub failures: u32,
+    #[serde(default)]
+    pub proxied: bool,
+    pub ttl: Option<i64>,
+}
+
+fn default_failures() -> u32 {
+    3
+}
+
+impl FailoverConfig {
+    /// Fails if the pair can't work, for checking the config before any run.
+    pub fn validate(&self) -> Result<(), Error> {
+        self.check.parse::<Probe>()?;
+        let Some(secondary) = self.secondary else {
+            return Err(format!("The failover of {} needs a secondary", self.name).into());
+        };
+        if self
+            .primary
+            .is_some_and(|primary| primary.is_ipv4() != secondary.is_ipv4())
+        {
+            return Err(format!(
+                "The primary and secondary of {} must be the same kind of address",
+                self.name
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// Which of the pairs are failed over, and how often the primaries of the
+/// others failed lately.
+#[derive(Debug, Default)]
+pub struct Monitor {
+    failures: HashMap<(String, String), u32>,
+    failed_over: HashSet<(String, String)>,
+}
+
+impl Monitor {
+    /// Checks every pair and publishes the address it should have now,
+    /// `detected` standing in for primaries that aren't given.
+    pub fn check(
+        &mut self,
+        client: &reqwest::blocking::Client,
+        settings: &Settings,
+        detected: Option<IpAddr>,
+    ) {
+        for zone in settings.zones.iter().filter(|z| !z.failover.is_empty()) {
+            for pair in &zone.failover {
+                let (Some(primary), Some(secondary)) = (pair.primary.or(detected), pair.secondary)
+                else {
+                    continue;
+                };
+                if primary.is_ipv4() != secondary.is_ipv4() {
+                    warn!(
+                        "Not checking the failover of {}, {} and {} are different kinds of address",
+                        pair.name, primary, secondary
+                    );
+                    continue;
+                }
+                let key = (zone.id.clone(), pair.name.clone());
+                let result = pair
+                    .check
+                    .parse::<Probe>()
+                    .and_then(|probe| probe.check(client, primary));
+                self.record(client, settings, &key, pair, primary, secondary, result);
+                let address = match self.failed_over.contains(&key) {
+                    true => secondary,
+                    false => primary,
+                };
+                if let Err(e) = publish(settings, zone, pair, address) {
+                    warn!("Could not publish {} for {}: {}", address, pair.name, e);
+                }
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn record(
+        &mut self,
+        client: &reqwest::blocking::Client,
+        settings: &Settings,
+        key: &(String, String),
+        pair: &FailoverConfig,
+        primary: IpAddr,
+        secondary: IpAddr,
+        result: Result<(), Error>,
+    ) {
+        let name = names::display(&pair.name);
+        match result {
+            Ok(()) => {
+                self.failures.remove(key);
+                if self.failed_over.remove(key) {
+                    info!("{} of {} answers again, switching back", primary, name);
+                    if let Some(url) = &settings.notify_url {
+                        let notification = Notification::Recovered {
+                            zone: &key.0,
+                            name: &pair.name,
+                            primary: &primary.to_string(),
+                        };
+                        notify::send(client, url, &notification);
+                    }
+                }
+            }
+            Err(e) => {
+                let failures = self.failures.entry(key.clone()).or_default();
+                *failures += 1;
+                warn!(
+                    "Check of {} for {} failed ({} of {}): {}",
+                    primary, name, failures, pair.failures, e
+                );
+                if *failures >= pair.failures && self.failed_over.insert(key.clone()) {
+                    warn!("Failing {} over to {}", name, secondary);
+                    if let Some(url) = &settings.notify_url {
+                        let notification = Notification::FailedOver {
+                            zone: &key.0,
+                            name: &pair.name,
+                            primary: &primary.to_string(),
+                            secondary: &secondary.to_string(),
+                            problem: &e.to_string(),
+                        };
+                        notify::send(client, url, &notification);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Makes the record of `pair` point at `address`.
+fn publish(
+    settings: &Settings,
+    zone: &ZoneConfig,
+    pair: &FailoverConfig,
+    address: IpAddr,
+) -> Result<(), Error> {
+    let records = list_dns_records(settings.client(&zone.id), &zone.id)?;
+    let wanted = NewRecord {
+        type_field: ip::record_type(address).to_string(),
+        name: names::qualify(&pair.name, &zone_name(zone, &records))?,
+        content: address.to_string(),
+        proxied: pair.proxied,
+        ttl: pair.ttl.unwrap_or(1),
+        comment: settings.mark_records.then(|| marked_comment(None)),
+    };
+    apply(settings, &zone.id, &records, wanted)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pair_fails_over_and_back() {
+        let client = reqwest::blocking::Client::new();
+        let settings = Settings::default();
+        let pair = FailoverConfig {
+            name: "www.example.com".to_string(),
+            check: "tcp:443".to_string(),
+            failures: 2,
+            ..Default::default()
+        };
+        let key = ("z".to_string(), pair.name.clone());
+        let primary = "198.51.100.7".parse().unwrap();
+        let secondary = "203.0.113.9".parse().unwrap();
+        let mut monitor = Monitor::default();
+        let mut check = |result: Result<(), Error>| {
+            monitor.record(&client, &settings, &key, &pair, primary, secondary, result);
+            monitor.failed_over.contains(&key)
+        };
+        assert!(!check(Err("refused".into())));
+        assert!(check(Err("refused".into())));
+        assert!(check(Err("refused".into())));
+        assert!(!check(Ok(())));
+        assert!(!check(Err("refused".into())));
+    }
+}
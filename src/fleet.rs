@@ -0,0 +1,100 @@
+//! Fleets of machines sharing one config: a record name may contain
+//! `{hostname}` (or `{env:NAME}`), so `records = ["{hostname}.fleet"]`
+//! gives each machine a record of its own, created when it joins the fleet.
+//! With `cleanup_on_shutdown` the
+//! daemon deletes those records again when it stops, so a machine taken out
+//! of the fleet leaves nothing behind.
+
+use std::net::IpAddr;
+
+use log::{error, info};
+
+use crate::cloudflare::models::NewRecord;
+use crate::config::{Settings, ZoneConfig};
+use crate::sync::{marked_comment, may_modify, SyncState};
+use crate::templated::{self, Vars};
+use crate::{create_record, delete_record, ip, list_dns_records, published, Error};
+
+/// Fills in the placeholders of `zone`'s record names, remembering the
+/// names that came from one in `host_records`.
+pub fn expand_names(zone: &mut ZoneConfig, vars: &Vars) -> Result<(), Error> {
+    for name in zone.records.iter_mut().filter(|name| name.contains('{')) {
+        *name = templated::render(name, vars)?
+            .ok_or_else(|| format!("Record names can't contain the IP, as {:?} does", name))?;
+        zone.host_records.push(name.clone());
+    }
+    Ok(())
+}
+
+/// Creates the missing record `name` of `zone` with `ip`, so that a new
+/// machine registers itself.
+pub fn register(
+    settings: &Settings,
+    zone: &ZoneConfig,
+    name: &str,
+    ip: IpAddr,
+) -> Result<SyncState, Error> {
+    let record = NewRecord {
+        type_field: ip::record_type(ip).to_string(),
+        name: name.to_string(),
+        content: ip.to_string(),
+        proxied: zone.proxied.unwrap_or(false),
+        ttl: zone.ttl.unwrap_or(1),
+        comment: settings.mark_records.then(|| marked_comment(None)),
+    };
+    create_record(settings.client(&zone.id), &record, &zone.id)?;
+    Ok(SyncState::Updated)
+}
+
+/// Deletes this machine's records, on shutdown with `cleanup_on_shutdown`.
+pub fn leave(settings: &Settings) {
+    if !settings.cleanup_on_shutdown {
+        return;
+    }
+    for zone in settings.zones.iter().filter(|z| !z.host_records.is_empty()) {
+        let records = match list_dns_records(settings.client(&zone.id), &zone.id) {
+            Ok(records) => records,
+            Err(e) => {
+                error!("Could not clean up the records of {}: {}", zone.id, e);
+                continue;
+            }
+        };
+        for record in records.iter().filter(|r| {
+            matches!(r.type_field.as_str(), "A" | "AAAA") && zone.host_records.contains(&r.name)
+        }) {
+            if !may_modify(record, settings) {
+                continue;
+            }
+            match delete_record(settings.client(&zone.id), record, &zone.id) {
+                Ok(()) => info!("Cleaned up {} {}", record.type_field, record.name),
+                Err(e) => error!("Could not clean up {}: {}", record.name, e),
+            }
+        }
+    }
+    published::forget();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host_names_are_filled_in_and_remembered() {
+        let vars = Vars {
+            ip: None,
+            hostname: "edge-12".to_string(),
+            env: |_| None,
+        };
+        let mut zone = ZoneConfig {
+            id: "example.com".to_string(),
+            records: vec!["home".to_string(), "{hostname}.fleet".to_string()],
+            ..Default::default()
+        };
+        expand_names(&mut zone, &vars).unwrap();
+        assert_eq!(zone.records, vec!["home", "edge-12.fleet"]);
+        assert_eq!(zone.host_records, vec!["edge-12.fleet"]);
+
+        zone.records = vec!["{ip}.example.com".to_string()];
+        assert!(expand_names(&mut zone, &vars).is_err());
+    }
+}
@@ -0,0 +1,48 @@
+//! Frozen records: the `frozen` list names records cfdns never creates,
+//! changes or deletes, whatever the record globs, templates, `--force` or
+//! commands like `purge` say. Meant for MX, verification TXT and other
+//! records a mistake would be costly for.
+//!
+//! Entries are a record name, `example.com`, a name and type,
+//! `example.com/MX`, or a Cloudflare record tag, `tag:keep`. The list is
+//! checked where records are written, so no command can get around it.
+
+use std::sync::RwLock;
+
+use crate::cloudflare::models::Record;
+use crate::Error;
+
+pub use cfdns_core::frozen::Frozen;
+
+static FROZEN: RwLock<Frozen> = RwLock::new(Frozen::new());
+
+/// Makes `frozen` the list [`check`] and [`check_new`] go by.
+pub fn set(frozen: Frozen) {
+    *FROZEN.write().unwrap() = frozen;
+}
+
+/// Whether `record` is on the frozen list.
+pub fn is_frozen(record: &Record) -> bool {
+    FROZEN.read().unwrap().contains_record(record)
+}
+
+/// Fails if `record` is frozen, before it is changed or deleted.
+pub fn check(record: &Record) -> Result<(), Error> {
+    match is_frozen(record) {
+        true => Err(format!(
+            "{} {} is frozen, cfdns never changes it",
+            record.type_field, record.name
+        )
+        .into()),
+        false => Ok(()),
+    }
+}
+
+/// Fails if a `record_type` record called `name` would be frozen, before
+/// it is created.
+pub fn check_new(name: &str, record_type: &str) -> Result<(), Error> {
+    match FROZEN.read().unwrap().contains(name, record_type, &[]) {
+        true => Err(format!("{} {} is frozen, cfdns never creates it", record_type, name).into()),
+        false => Ok(()),
+    }
+}
@@ -0,0 +1,137 @@
+//! `grafana_url`: an annotation in Grafana whenever the public IP changes,
+//! so that dashboards of bandwidth or latency get a marker exactly when the
+//! ISP reassigned the address.
+//!
+//! A run changed the IP when it moved records to it from another address.
+//! Reverted records don't count, cfdns only reverts while the IP stays the
+//! same.
+
+use std::time::SystemTime;
+
+use log::{info, warn};
+use serde_derive::Serialize;
+
+use crate::sync::{SyncReport, SyncState};
+use crate::token::Secret;
+use crate::trace::Traced;
+use crate::Error;
+
+#[derive(Debug, Clone)]
+pub struct Grafana {
+    /// Base URL, e.g. https://grafana.example.net
+    pub url: String,
+    /// Service account token allowed to write annotations
+    pub token: Secret<String>,
+    pub tags: Vec<String>,
+    /// UID of the dashboard the annotations go on, all dashboards otherwise
+    pub dashboard: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Annotation<'a> {
+    time: u128,
+    tags: &'a [String],
+    text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dashboard_uid: Option<&'a str>,
+}
+
+/// The address `report` moved records away from, if its IP is a new one.
+fn previous_ip(report: &SyncReport) -> Option<&str> {
+    if report.count(SyncState::Reverted) > 0 {
+        return None;
+    }
+    report
+        .changes
+        .iter()
+        .find(|change| change.path.ends_with("/content") && change.value == report.current_ip)
+        .map(|change| change.old.as_str())
+}
+
+impl Grafana {
+    fn annotate(
+        &self,
+        client: &reqwest::blocking::Client,
+        time: SystemTime,
+        previous: &str,
+        report: &SyncReport,
+    ) -> Result<(), Error> {
+        let annotation = Annotation {
+            time: time
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis(),
+            tags: &self.tags,
+            text: format!(
+                "Public IP changed from {} to {}",
+                previous, report.current_ip
+            ),
+            dashboard_uid: self.dashboard.as_deref(),
+        };
+        client
+            .post(format!(
+                "{}/api/annotations",
+                self.url.trim_end_matches('/')
+            ))
+            .bearer_auth(self.token.expose())
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(&annotation)?)
+            .send_traced()?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// Annotates the IP change of the run that started at `started`, if it
+    /// changed the IP. Failing to is only logged.
+    pub fn write(
+        &self,
+        client: &reqwest::blocking::Client,
+        started: SystemTime,
+        result: &Result<SyncReport, Error>,
+    ) {
+        let Some((report, previous)) = result
+            .as_ref()
+            .ok()
+            .and_then(|report| previous_ip(report).map(|previous| (report, previous)))
+        else {
+            return;
+        };
+        match self.annotate(client, started, previous, report) {
+            Ok(()) => info!("Annotated the IP change in Grafana"),
+            Err(e) => warn!("Could not annotate the IP change in Grafana: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::Operation;
+    use crate::sync::RecordReport;
+
+    #[test]
+    fn test_only_moved_records_mean_a_new_ip() {
+        let record = |state| RecordReport {
+            zone: "z".to_string(),
+            name: "home.example.com".to_string(),
+            content: Some("198.51.100.7".to_string()),
+            state,
+            error: None,
+        };
+        let mut report = SyncReport {
+            current_ip: "198.51.100.7".to_string(),
+            records: vec![record(SyncState::Updated)],
+            changes: vec![
+                Operation::attribute("z", "home.example.com", "A", "ttl", "1", "300"),
+                Operation::content("z", "home.example.com", "A", "198.51.100.4", "198.51.100.7"),
+            ],
+        };
+        assert_eq!(previous_ip(&report), Some("198.51.100.4"));
+        report.records = vec![record(SyncState::Reverted)];
+        assert_eq!(previous_ip(&report), None);
+        report.changes.pop();
+        report.records = vec![record(SyncState::Updated)];
+        assert_eq!(previous_ip(&report), None);
+    }
+}
@@ -0,0 +1,108 @@
+//! Rate-of-change guard: a record that keeps changing is most likely
+//! flapping detection, e.g. a broken echo service alternating between two
+//! addresses, so after `max_updates_per_hour` updates it is left alone and
+//! someone is told instead, until the hour has passed.
+//!
+//! The update history is kept in a small JSON file so one-shot runs from
+//! cron are counted as well.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::warn;
+
+use crate::paths;
+
+const WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// `updates.json` in the state directory.
+pub fn default_state_path() -> PathBuf {
+    paths::state_dir().join("updates.json")
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[derive(Debug, Default)]
+pub struct RateGuard {
+    limit: u32,
+    path: PathBuf,
+    /// Update times in Unix seconds by `zone/name`
+    history: HashMap<String, Vec<u64>>,
+}
+
+impl RateGuard {
+    /// The guard allowing `limit` updates per record and hour, with the
+    /// history from `path`. A missing or broken file starts afresh.
+    pub fn load(limit: u32, path: PathBuf) -> RateGuard {
+        let history = fs::read_to_string(&path)
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+        RateGuard {
+            limit,
+            path,
+            history,
+        }
+    }
+
+    fn key(zone: &str, name: &str) -> String {
+        format!("{}/{}", zone, name)
+    }
+
+    /// How often the record was updated within the last hour.
+    pub fn recent(&self, zone: &str, name: &str, now: SystemTime) -> usize {
+        let since = unix_seconds(now).saturating_sub(WINDOW.as_secs());
+        self.history
+            .get(&Self::key(zone, name))
+            .map_or(0, |times| times.iter().filter(|t| **t > since).count())
+    }
+
+    pub fn allows(&self, zone: &str, name: &str, now: SystemTime) -> bool {
+        self.recent(zone, name, now) < self.limit as usize
+    }
+
+    /// Remembers an update, forgetting the ones out of the window.
+    pub fn record(&mut self, zone: &str, name: &str, now: SystemTime) {
+        let since = unix_seconds(now).saturating_sub(WINDOW.as_secs());
+        let times = self.history.entry(Self::key(zone, name)).or_default();
+        times.retain(|t| *t > since);
+        times.push(unix_seconds(now));
+    }
+
+    pub fn save(&self) {
+        let text = serde_json::to_string(&self.history).expect("history is serializable");
+        if let Err(e) = paths::write(&self.path, text) {
+            warn!(
+                "Could not save the update history to {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guard_counts_updates_within_the_hour() {
+        let mut guard = RateGuard {
+            limit: 2,
+            ..Default::default()
+        };
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert!(guard.allows("z", "home.example.com", start));
+        guard.record("z", "home.example.com", start);
+        guard.record("z", "home.example.com", start + Duration::from_secs(600));
+        assert!(!guard.allows("z", "home.example.com", start + Duration::from_secs(1200)));
+        assert!(guard.allows("z", "vpn.example.com", start + Duration::from_secs(1200)));
+        assert!(guard.allows("z", "home.example.com", start + Duration::from_secs(3601)));
+    }
+}
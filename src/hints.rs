@@ -0,0 +1,97 @@
+//! Advice for failures with a well known cause, shown along with the error
+//! so that they can be fixed without digging through the code.
+
+use crate::cloudflare::ApiError;
+use crate::token::Unauthorized;
+use crate::Error;
+
+const TOKEN_PERMISSIONS: &str = "the token lacks a permission this needs, usually \
+     Zone > DNS > Edit for the zone; check its permissions and zone resources at \
+     https://dash.cloudflare.com/profile/api-tokens";
+const TOKEN_INVALID: &str = "the token is invalid, expired or revoked, create a new one at \
+     https://dash.cloudflare.com/profile/api-tokens";
+const ZONE_ID: &str = "the zone ID is malformed or unknown: it is the 32 character Zone ID \
+     on the zone's Overview page, or pass the zone name, e.g. --zone example.com";
+const RESOLUTION: &str = "api.cloudflare.com could not be resolved: check the DNS resolver, \
+     and on an IPv6-only network that it has DNS64 and NAT64";
+const CONNECTION: &str = "Cloudflare could not be reached: check the connection and \
+     firewall, -4 or -6 make cfdns use only the address family that works";
+
+/// Advice for `error`, if its cause is a common one.
+pub fn hint(error: &Error) -> Option<&'static str> {
+    if let Some(Unauthorized(status, _)) = error.downcast_ref::<Unauthorized>() {
+        return Some(match status {
+            403 => TOKEN_PERMISSIONS,
+            _ => TOKEN_INVALID,
+        });
+    }
+    if let Some(error) = error.downcast_ref::<ApiError>() {
+        if let Some(hint) = error.codes().into_iter().find_map(for_code) {
+            return Some(hint);
+        }
+    }
+    for_message(&error.to_string())
+}
+
+/// Advice for one of Cloudflare's error codes.
+fn for_code(code: i64) -> Option<&'static str> {
+    match code {
+        7003 | 9109 => Some(ZONE_ID),
+        9103 | 10000 => Some(TOKEN_INVALID),
+        _ => None,
+    }
+}
+
+/// Advice for an error that only survived as text, like the ones of single
+/// record updates.
+pub fn for_message(message: &str) -> Option<&'static str> {
+    let message = message.to_lowercase();
+    let any = |parts: &[&str]| parts.iter().any(|part| message.contains(part));
+    if any(&[
+        "dns error",
+        "failed to lookup address",
+        "name or service not known",
+    ]) {
+        Some(RESOLUTION)
+    } else if any(&["connection refused", "network is unreachable", "timed out"]) {
+        Some(CONNECTION)
+    } else if any(&["(9109)", "(7003)"]) {
+        Some(ZONE_ID)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_hints_for_common_failures() {
+        assert_eq!(
+            hint(&Unauthorized(403, None).into()),
+            Some(TOKEN_PERMISSIONS)
+        );
+        assert_eq!(hint(&Unauthorized(401, None).into()), Some(TOKEN_INVALID));
+
+        let errors = vec![json!({ "code": 9109, "message": "Invalid zone identifier" })];
+        let error: Error = ApiError::new("Listing records", errors).into();
+        assert_eq!(
+            error.to_string(),
+            "Listing records failed: Invalid zone identifier (9109)"
+        );
+        assert_eq!(hint(&error), Some(ZONE_ID));
+        assert_eq!(for_message(&error.to_string()), Some(ZONE_ID));
+
+        let error: Error = "could not send request to cloudflare api: error sending request: \
+             client error (Connect): dns error: failed to lookup address information"
+            .into();
+        assert_eq!(hint(&error), Some(RESOLUTION));
+        assert_eq!(
+            hint(&"Record home.example.com no longer exists".into()),
+            None
+        );
+    }
+}
@@ -0,0 +1,195 @@
+//! `cfdns hook certbot` and `cfdns hook lego`: DNS-01 hooks for ACME clients.
+//! They create or remove the `_acme-challenge` TXT record of a challenge and,
+//! after creating it, wait until the zone's name servers serve it, so that
+//! the CA finds it on its first look.
+
+use std::env;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::{Subcommand, ValueEnum};
+use log::{info, warn};
+
+use crate::authoritative;
+use crate::cloudflare::models::NewRecord;
+use crate::config::Settings;
+use crate::sync::{marked_comment, may_modify};
+use crate::tunnel::zone_of;
+use crate::{create_record, delete_record, list_dns_records, names, Error};
+
+/// Challenge records only live for minutes, so caches shouldn't keep them.
+const TTL: i64 = 60;
+const POLL_EVERY: Duration = Duration::from_secs(5);
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum HookCommand {
+    /// certbot's --manual-auth-hook and --manual-cleanup-hook, reading
+    /// CERTBOT_DOMAIN, CERTBOT_VALIDATION and CERTBOT_AUTH_OUTPUT
+    Certbot,
+    /// lego's exec provider, called as `present|cleanup <fqdn> <value>`
+    Lego {
+        #[arg(value_enum)]
+        action: LegoAction,
+        fqdn: String,
+        value: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LegoAction {
+    Present,
+    Cleanup,
+}
+
+/// A TXT record to publish or remove.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Challenge {
+    name: String,
+    value: String,
+    cleanup: bool,
+}
+
+/// The challenge certbot passes in the environment, `var` reading it. The
+/// cleanup hook is told apart by CERTBOT_AUTH_OUTPUT, which only it gets.
+fn certbot(var: impl Fn(&str) -> Option<String>) -> Result<Challenge, Error> {
+    let missing = |name| format!("No {} set, is this run by certbot?", name);
+    let domain = var("CERTBOT_DOMAIN").ok_or_else(|| missing("CERTBOT_DOMAIN"))?;
+    let value = var("CERTBOT_VALIDATION").ok_or_else(|| missing("CERTBOT_VALIDATION"))?;
+    // Wildcard names share the challenge record of their base name.
+    let domain = domain.strip_prefix("*.").unwrap_or(&domain);
+    Ok(Challenge {
+        name: names::to_ascii(&format!("_acme-challenge.{}", domain))?,
+        value,
+        cleanup: var("CERTBOT_AUTH_OUTPUT").is_some(),
+    })
+}
+
+/// The challenge lego passes on the command line. Its `RAW` mode hands over
+/// the key authorization instead, which would have to be hashed first.
+fn lego(
+    action: LegoAction,
+    fqdn: &str,
+    value: &str,
+    mode: Option<String>,
+) -> Result<Challenge, Error> {
+    if mode.is_some_and(|mode| mode.eq_ignore_ascii_case("raw")) {
+        return Err("EXEC_MODE=RAW is not supported, leave EXEC_MODE unset".into());
+    }
+    Ok(Challenge {
+        name: names::to_ascii(fqdn)?,
+        value: value.to_string(),
+        cleanup: action == LegoAction::Cleanup,
+    })
+}
+
+pub fn run(settings: &Settings, command: &HookCommand) -> Result<(), Error> {
+    let challenge = match command {
+        HookCommand::Certbot => certbot(|name| env::var(name).ok())?,
+        HookCommand::Lego {
+            action,
+            fqdn,
+            value,
+        } => lego(*action, fqdn, value, env::var("EXEC_MODE").ok())?,
+    };
+    match challenge.cleanup {
+        true => clean(settings, &challenge),
+        false => {
+            present(settings, &challenge)?;
+            wait(settings, &challenge);
+            Ok(())
+        }
+    }
+}
+
+fn present(settings: &Settings, challenge: &Challenge) -> Result<(), Error> {
+    let (zone, cloudflare) = zone_of(settings, &challenge.name)?;
+    let exists = list_dns_records(cloudflare, &zone)?
+        .iter()
+        .any(|r| r.type_field == "TXT" && r.name == challenge.name && r.ip_addr == challenge.value);
+    if exists {
+        info!("{} already has the challenge value", challenge.name);
+        return Ok(());
+    }
+    let record = NewRecord {
+        type_field: "TXT".to_string(),
+        name: challenge.name.clone(),
+        content: challenge.value.clone(),
+        proxied: false,
+        ttl: TTL,
+        // Marked so that safe_mode still lets the cleanup remove it.
+        comment: Some(marked_comment(Some("ACME challenge"))),
+    };
+    create_record(cloudflare, &record, &zone)?;
+    info!("Created the challenge record {}", challenge.name);
+    Ok(())
+}
+
+/// Removes the record with the challenge's value, leaving those of other
+/// challenges for the same name, e.g. of a wildcard in the same order.
+fn clean(settings: &Settings, challenge: &Challenge) -> Result<(), Error> {
+    let (zone, cloudflare) = zone_of(settings, &challenge.name)?;
+    let records = list_dns_records(cloudflare, &zone)?;
+    for record in records.iter().filter(|r| {
+        r.type_field == "TXT" && r.name == challenge.name && r.ip_addr == challenge.value
+    }) {
+        if may_modify(record, settings) {
+            delete_record(cloudflare, record, &zone)?;
+            info!("Removed the challenge record {}", challenge.name);
+        }
+    }
+    Ok(())
+}
+
+/// Waits up to `propagation_timeout` for the name servers to serve the
+/// challenge. Running out only warns, the CA may still see it in time.
+fn wait(settings: &Settings, challenge: &Challenge) {
+    let deadline = Instant::now() + settings.propagation_timeout;
+    loop {
+        match authoritative::txt(settings, &challenge.name) {
+            Ok(values) if values.contains(&challenge.value) => {
+                info!("The name servers serve {}", challenge.name);
+                return;
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Could not look up {}: {}", challenge.name, e),
+        }
+        if Instant::now() + POLL_EVERY > deadline {
+            warn!(
+                "The name servers still do not serve {} after {}",
+                challenge.name,
+                humantime::format_duration(settings.propagation_timeout)
+            );
+            return;
+        }
+        thread::sleep(POLL_EVERY);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_challenges_from_certbot_and_lego() {
+        let vars = |cleanup: bool| {
+            move |name: &str| match name {
+                "CERTBOT_DOMAIN" => Some("*.Example.com".to_string()),
+                "CERTBOT_VALIDATION" => Some("token-value".to_string()),
+                "CERTBOT_AUTH_OUTPUT" if cleanup => Some(String::new()),
+                _ => None,
+            }
+        };
+        let challenge = certbot(vars(false)).unwrap();
+        assert_eq!(challenge.name, "_acme-challenge.example.com");
+        assert_eq!(challenge.value, "token-value");
+        assert!(!challenge.cleanup);
+        assert!(certbot(vars(true)).unwrap().cleanup);
+        assert!(certbot(|_| None).is_err());
+
+        let fqdn = "_acme-challenge.example.com.";
+        let challenge = lego(LegoAction::Cleanup, fqdn, "token-value", None).unwrap();
+        assert_eq!(challenge.name, "_acme-challenge.example.com");
+        assert!(challenge.cleanup);
+        assert!(lego(LegoAction::Present, fqdn, "x", Some("RAW".to_string())).is_err());
+    }
+}
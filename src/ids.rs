@@ -0,0 +1,289 @@
+//! Zone IDs by zone name and record IDs by record name, kept on disk for
+//! `id_cache_ttl` so that routine runs don't have to list every zone and
+//! record just to find them again. Whatever is missing, stale or no longer
+//! matches is looked up fresh and the cache updated.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::{debug, warn};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::cloudflare::CloudflareClient;
+use crate::config::{self, Settings, ZoneConfig};
+use crate::{list_zones, names, paths, Error};
+
+pub const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// `ids.json` in the cache directory.
+pub fn default_path() -> PathBuf {
+    paths::cache_dir().join("ids.json")
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Entry {
+    id: String,
+    /// When it was looked up, Unix seconds
+    stored: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Stored {
+    #[serde(default)]
+    zones: HashMap<String, Entry>,
+    /// By `zone/name/type`
+    #[serde(default)]
+    records: HashMap<String, Entry>,
+    /// IDs of zones found active, and when, Unix seconds
+    #[serde(default)]
+    active: HashMap<String, u64>,
+}
+
+#[derive(Debug, Default)]
+pub struct IdCache {
+    path: PathBuf,
+    /// Zero turns the cache off
+    ttl: Duration,
+    stored: Stored,
+    changed: bool,
+}
+
+impl IdCache {
+    /// The cache of `settings`, a missing or broken file starts afresh, as
+    /// does `--refresh-cache`.
+    pub fn load(settings: &Settings) -> IdCache {
+        let stored = match settings.id_cache_ttl.is_zero() || settings.refresh_cache {
+            true => Stored::default(),
+            false => fs::read_to_string(&settings.id_cache)
+                .ok()
+                .and_then(|text| serde_json::from_str(&text).ok())
+                .unwrap_or_default(),
+        };
+        IdCache {
+            path: settings.id_cache.clone(),
+            ttl: settings.id_cache_ttl,
+            stored,
+            changed: false,
+        }
+    }
+
+    fn fresh<'a>(
+        &self,
+        entries: &'a HashMap<String, Entry>,
+        key: &str,
+        now: SystemTime,
+    ) -> Option<&'a str> {
+        let since = unix_seconds(now).saturating_sub(self.ttl.as_secs());
+        entries
+            .get(key)
+            .filter(|entry| entry.stored > since)
+            .map(|entry| entry.id.as_str())
+    }
+
+    fn record_key(zone: &str, name: &str, record_type: &str) -> String {
+        format!("{}/{}/{}", zone, name, record_type)
+    }
+
+    pub fn zone(&self, name: &str, now: SystemTime) -> Option<&str> {
+        self.fresh(&self.stored.zones, name, now)
+    }
+
+    /// The name of the zone with `id`, the other way around.
+    pub fn zone_name(&self, id: &str, now: SystemTime) -> Option<&str> {
+        let since = unix_seconds(now).saturating_sub(self.ttl.as_secs());
+        self.stored
+            .zones
+            .iter()
+            .find(|(_, entry)| entry.id == id && entry.stored > since)
+            .map(|(name, _)| name.as_str())
+    }
+
+    pub fn set_zone(&mut self, name: &str, id: &str, now: SystemTime) {
+        let entry = Entry {
+            id: id.to_string(),
+            stored: unix_seconds(now),
+        };
+        self.stored.zones.insert(name.to_string(), entry);
+        self.changed = true;
+    }
+
+    /// Whether zone `id` was found active within the TTL.
+    pub fn is_active(&self, id: &str, now: SystemTime) -> bool {
+        let since = unix_seconds(now).saturating_sub(self.ttl.as_secs());
+        self.stored.active.get(id).is_some_and(|seen| *seen > since)
+    }
+
+    pub fn set_active(&mut self, id: &str, now: SystemTime) {
+        self.stored.active.insert(id.to_string(), unix_seconds(now));
+        self.changed = true;
+    }
+
+    pub fn record(
+        &self,
+        zone: &str,
+        name: &str,
+        record_type: &str,
+        now: SystemTime,
+    ) -> Option<&str> {
+        let key = Self::record_key(zone, name, record_type);
+        self.fresh(&self.stored.records, &key, now)
+    }
+
+    pub fn set_record(
+        &mut self,
+        zone: &str,
+        name: &str,
+        record_type: &str,
+        id: &str,
+        now: SystemTime,
+    ) {
+        let key = Self::record_key(zone, name, record_type);
+        if self.record(zone, name, record_type, now) == Some(id) {
+            return;
+        }
+        let entry = Entry {
+            id: id.to_string(),
+            stored: unix_seconds(now),
+        };
+        self.stored.records.insert(key, entry);
+        self.changed = true;
+    }
+
+    /// Writes the cache back if anything was looked up.
+    pub fn save(&self) {
+        if self.ttl.is_zero() || !self.changed {
+            return;
+        }
+        let text = serde_json::to_string(&self.stored).expect("ids are serializable");
+        if let Err(e) = paths::write(&self.path, text) {
+            warn!(
+                "Could not save the ID cache to {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
+
+/// Zone IDs are 32 hex digits, anything with a dot is taken for a name.
+pub fn is_zone_name(zone: &str) -> bool {
+    zone.contains('.')
+}
+
+/// Whether `zone` is given by ID and has names relative to it, that can't be
+/// qualified without knowing its name.
+fn needs_name(zone: &ZoneConfig) -> bool {
+    !is_zone_name(&zone.id)
+        && zone
+            .records
+            .iter()
+            .chain(&zone.round_robin)
+            .any(|name| names::is_relative(name))
+}
+
+/// `settings` with every zone given by name, like `example.com`, replaced
+/// by its ID, and the relative names of zones given by ID qualified.
+pub fn resolve_zones(mut settings: Settings) -> Result<Settings, Error> {
+    if !settings
+        .zones
+        .iter()
+        .any(|zone| is_zone_name(&zone.id) || needs_name(zone))
+    {
+        return Ok(settings);
+    }
+    let mut cache = IdCache::load(&settings);
+    let now = SystemTime::now();
+    // Accounts whose zones were listed, each one at most once.
+    let mut listed: Vec<Option<String>> = Vec::new();
+    let clients: Vec<CloudflareClient> = settings
+        .zones
+        .iter()
+        .map(|zone| settings.account_client(zone.account.as_deref()).clone())
+        .collect();
+    let mut list = |zone: &ZoneConfig, cloudflare: &CloudflareClient, cache: &mut IdCache| {
+        if !listed.contains(&zone.account) {
+            for accessible in list_zones(cloudflare)? {
+                cache.set_zone(&accessible.name, &accessible.id, now);
+            }
+            listed.push(zone.account.clone());
+        }
+        Ok::<_, Error>(())
+    };
+    for (zone, cloudflare) in settings.zones.iter_mut().zip(&clients) {
+        if !needs_name(zone) {
+            continue;
+        }
+        if cache.zone_name(&zone.id, now).is_none() {
+            debug!("Looking up the name of zone {}", zone.id);
+            list(zone, cloudflare, &mut cache)?;
+        }
+        let Some(name) = cache.zone_name(&zone.id, now).map(str::to_string) else {
+            return Err(format!(
+                "No zone with ID {} that the token can access, to qualify its relative names",
+                zone.id
+            )
+            .into());
+        };
+        for record in config::names_of(zone) {
+            if names::is_relative(record) {
+                *record = names::qualify(record, &name)?;
+            }
+        }
+    }
+    for (zone, cloudflare) in settings.zones.iter_mut().zip(&clients) {
+        if !is_zone_name(&zone.id) {
+            continue;
+        }
+        if cache.zone(&zone.id, now).is_none() {
+            debug!("Looking up the ID of zone {}", zone.id);
+            list(zone, cloudflare, &mut cache)?;
+        }
+        match cache.zone(&zone.id, now) {
+            Some(id) => zone.id = id.to_string(),
+            None => {
+                return Err(format!("No zone called {} that the token can access", zone.id).into())
+            }
+        }
+    }
+    cache.save();
+    Ok(settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entries_expire_after_ttl() {
+        let mut cache = IdCache {
+            ttl: Duration::from_secs(3600),
+            ..Default::default()
+        };
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        cache.set_zone("example.com", "023e105f4ecef8ad9ca31a8372d0c353", start);
+        cache.set_record("z", "home.example.com", "A", "372e6795", start);
+        assert_eq!(
+            cache.zone("example.com", start + Duration::from_secs(60)),
+            Some("023e105f4ecef8ad9ca31a8372d0c353")
+        );
+        assert_eq!(
+            cache.record("z", "home.example.com", "A", start),
+            Some("372e6795")
+        );
+        assert_eq!(cache.record("z", "home.example.com", "AAAA", start), None);
+        assert_eq!(
+            cache.zone("example.com", start + Duration::from_secs(3601)),
+            None
+        );
+        assert!(is_zone_name("example.com"));
+        assert!(!is_zone_name("023e105f4ecef8ad9ca31a8372d0c353"));
+    }
+}
@@ -0,0 +1,240 @@
+//! `influx`: metrics of every run in InfluxDB line protocol, for monitoring
+//! stacks built on Influx and Grafana. The lines go to a UDP or TCP socket
+//! (Telegraf's socket_listener), to an HTTP write endpoint (InfluxDB's
+//! `/api/v2/write`, with `influx_token`) or, with `-`, to stdout for
+//! Telegraf's exec input.
+//!
+//! Every run writes a `cfdns_run` line with the counts of its summary, and
+//! a `cfdns_record` line for each record:
+//!
+//! ```text
+//! cfdns_run,host=nas success=true,duration_ms=412i,checked=2i,updated=1i,... 1700000000000000000
+//! cfdns_record,host=nas,zone=...,name=home.example.com state="updated",in_sync=true,content="198.51.100.7" 1700000000000000000
+//! ```
+
+use std::io::Write;
+use std::net::{TcpStream, UdpSocket};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use log::warn;
+
+use crate::round_robin::host_id;
+use crate::sync::{SyncReport, SyncState};
+use crate::token::Secret;
+use crate::trace::Traced;
+use crate::Error;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Influx {
+    Udp(String),
+    Tcp(String),
+    Http(String),
+    Stdout,
+}
+
+impl FromStr for Influx {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        if text == "-" {
+            return Ok(Influx::Stdout);
+        }
+        if let Some(address) = text.strip_prefix("udp://") {
+            return Ok(Influx::Udp(address.to_string()));
+        }
+        if let Some(address) = text.strip_prefix("tcp://") {
+            return Ok(Influx::Tcp(address.to_string()));
+        }
+        if text.starts_with("http://") || text.starts_with("https://") {
+            return Ok(Influx::Http(text.to_string()));
+        }
+        Err(format!(
+            "influx must be udp://, tcp://, an http(s):// write URL or -, not {:?}",
+            text
+        )
+        .into())
+    }
+}
+
+/// `text` with the characters that end a tag key or value escaped.
+fn tag(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+/// `text` as a string field value.
+fn string(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn state(state: SyncState) -> String {
+    let value = serde_json::to_value(state).expect("states are serializable");
+    value.as_str().unwrap_or_default().to_string()
+}
+
+/// The lines for a run that took `duration` and ended at `time`.
+fn lines(
+    host: &str,
+    time: SystemTime,
+    duration: Duration,
+    result: &Result<SyncReport, Error>,
+) -> String {
+    let timestamp = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let host = tag(host);
+    let mut fields = vec![
+        format!("success={}", result.is_ok()),
+        format!("duration_ms={}i", duration.as_millis()),
+    ];
+    let mut lines = String::new();
+    if let Ok(report) = result {
+        let summary = report.summary();
+        for (name, count) in [
+            ("checked", summary.checked),
+            ("updated", summary.updated),
+            ("reverted", summary.reverted),
+            ("in_sync", summary.in_sync),
+            ("missing", summary.missing),
+            ("skipped", summary.skipped),
+            ("throttled", summary.throttled),
+            ("failed", summary.failed),
+        ] {
+            fields.push(format!("{}={}i", name, count));
+        }
+        fields.push(format!("ip={}", string(&report.current_ip)));
+        for record in &report.records {
+            let mut fields = vec![
+                format!("state={}", string(&state(record.state))),
+                format!(
+                    "in_sync={}",
+                    matches!(
+                        record.state,
+                        SyncState::InSync | SyncState::Updated | SyncState::Reverted
+                    )
+                ),
+            ];
+            if let Some(content) = &record.content {
+                fields.push(format!("content={}", string(content)));
+            }
+            lines.push_str(&format!(
+                "cfdns_record,host={},zone={},name={} {} {}\n",
+                host,
+                tag(&record.zone),
+                tag(&record.name),
+                fields.join(","),
+                timestamp
+            ));
+        }
+    }
+    format!(
+        "cfdns_run,host={} {} {}\n{}",
+        host,
+        fields.join(","),
+        timestamp,
+        lines
+    )
+}
+
+fn send(
+    client: &reqwest::blocking::Client,
+    influx: &Influx,
+    token: Option<&Secret<String>>,
+    lines: String,
+) -> Result<(), Error> {
+    match influx {
+        Influx::Stdout => print!("{}", lines),
+        Influx::Udp(address) => {
+            let socket = UdpSocket::bind("0.0.0.0:0").or_else(|_| UdpSocket::bind("[::]:0"))?;
+            socket.send_to(lines.as_bytes(), address.as_str())?;
+        }
+        Influx::Tcp(address) => {
+            let mut stream = TcpStream::connect(address.as_str())?;
+            stream.set_write_timeout(Some(TIMEOUT))?;
+            stream.write_all(lines.as_bytes())?;
+        }
+        Influx::Http(url) => {
+            let mut request = client
+                .post(url)
+                .timeout(TIMEOUT)
+                .header("Content-Type", "text/plain; charset=utf-8")
+                .body(lines);
+            if let Some(token) = token {
+                request = request.header("Authorization", format!("Token {}", token.expose()));
+            }
+            request.send_traced()?.error_for_status()?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the metrics of a run that started at `started` and ended with
+/// `result`. Failing to is only logged, the run itself is done.
+pub fn write(
+    client: &reqwest::blocking::Client,
+    influx: &Influx,
+    token: Option<&Secret<String>>,
+    started: SystemTime,
+    result: &Result<SyncReport, Error>,
+) {
+    let now = SystemTime::now();
+    let duration = now.duration_since(started).unwrap_or_default();
+    if let Err(e) = send(
+        client,
+        influx,
+        token,
+        lines(&host_id(), now, duration, result),
+    ) {
+        warn!("Could not write the metrics to {:?}: {}", influx, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::RecordReport;
+
+    #[test]
+    fn test_runs_become_line_protocol() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let duration = Duration::from_millis(412);
+        let run: Result<SyncReport, Error> = Ok(SyncReport {
+            current_ip: "198.51.100.7".to_string(),
+            records: vec![RecordReport {
+                zone: "z".to_string(),
+                name: "home example.com".to_string(),
+                content: Some("198.51.100.7".to_string()),
+                state: SyncState::Updated,
+                error: None,
+            }],
+            changes: Vec::new(),
+        });
+        let text = lines("nas", time, duration, &run);
+        let written: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            written[0],
+            "cfdns_run,host=nas success=true,duration_ms=412i,checked=1i,updated=1i,reverted=0i,\
+             in_sync=0i,missing=0i,skipped=0i,throttled=0i,failed=0i,ip=\"198.51.100.7\" \
+             1700000000000000000"
+        );
+        assert_eq!(
+            written[1],
+            "cfdns_record,host=nas,zone=z,name=home\\ example.com state=\"updated\",in_sync=true,\
+             content=\"198.51.100.7\" 1700000000000000000"
+        );
+
+        let failed: Result<SyncReport, Error> = Err("no IP".into());
+        assert_eq!(
+            lines("nas", time, duration, &failed),
+            "cfdns_run,host=nas success=false,duration_ms=412i 1700000000000000000\n"
+        );
+        assert_eq!("-".parse::<Influx>().unwrap(), Influx::Stdout);
+        assert!("influx.local:8086".parse::<Influx>().is_err());
+    }
+}
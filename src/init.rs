@@ -0,0 +1,69 @@
+//! Running as PID 1, as with `docker run` and no `--init`. The kernel
+//! doesn't give PID 1 the default signal actions, so a SIGTERM that isn't
+//! handled is simply dropped and `docker stop` has to wait for its SIGKILL;
+//! and every orphaned process in the container becomes our child, to stay a
+//! zombie unless we reap it. The daemon handles its signals already, a
+//! one-shot run exits on them here, and both reap children as they exit.
+
+use std::sync::RwLock;
+
+#[cfg(unix)]
+use log::{debug, info};
+
+/// Held while cfdns runs a command itself, so that the reaper doesn't
+/// take the exit status its caller waits for.
+static WAITING: RwLock<()> = RwLock::new(());
+
+/// Runs `wait`, which starts a child process and waits for it, without
+/// the reaper getting in the way.
+#[cfg(any(feature = "aws", feature = "gcp", feature = "sops", feature = "s3"))]
+pub fn waiting<T>(wait: impl FnOnce() -> T) -> T {
+    let _guard = WAITING.read().unwrap();
+    wait()
+}
+
+/// Sets up signal handling and reaping if this process is PID 1.
+#[cfg(unix)]
+pub fn setup(daemon: bool) -> Result<(), crate::Error> {
+    use signal_hook::consts::{SIGCHLD, SIGINT, SIGTERM};
+    use signal_hook::iterator::Signals;
+
+    if std::process::id() != 1 {
+        return Ok(());
+    }
+    info!("Running as PID 1, reaping orphaned processes");
+    let mut signals = match daemon {
+        true => Signals::new([SIGCHLD])?,
+        false => Signals::new([SIGCHLD, SIGINT, SIGTERM])?,
+    };
+    std::thread::spawn(move || {
+        for signal in signals.forever() {
+            match signal {
+                SIGCHLD => reap(),
+                signal => std::process::exit(128 + signal),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn setup(_: bool) -> Result<(), crate::Error> {
+    Ok(())
+}
+
+/// Waits for every child that has exited, once no command cfdns runs
+/// itself is still waited for.
+#[cfg(unix)]
+fn reap() {
+    let _guard = WAITING.write().unwrap();
+    loop {
+        let mut status = 0;
+        // SAFETY: waitpid only writes the status of the reaped child.
+        let pid = unsafe { libc::waitpid(-1, &mut status, libc::WNOHANG) };
+        if pid <= 0 {
+            return;
+        }
+        debug!("Reaped process {}", pid);
+    }
+}
@@ -0,0 +1,168 @@
+//! IPv6 addresses of a local interface, as an alternative to asking an echo
+//! service. Hosts with a global IPv6 address usually have several: a stable
+//! one (EUI-64 or stable-privacy) and rotating temporary privacy addresses,
+//! so which one gets published is a policy choice.
+
+use std::net::Ipv6Addr;
+
+use serde_derive::Deserialize;
+
+use crate::Error;
+
+/// Which global address of the interface to publish.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Ipv6Selection {
+    /// The stable address, so the record doesn't flap on every rotation
+    #[default]
+    PreferStable,
+    /// The newest temporary privacy address
+    PreferTemporary,
+    /// The address whose interface identifier matches `ipv6_suffix`
+    MatchSuffix,
+}
+
+/// One line of `/proc/net/if_inet6`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct InterfaceAddress {
+    address: Ipv6Addr,
+    prefix_len: u8,
+    scope: u8,
+    flags: u32,
+    interface: String,
+}
+
+const IFA_F_TEMPORARY: u32 = 0x01;
+const IFA_F_DADFAILED: u32 = 0x08;
+const IFA_F_DEPRECATED: u32 = 0x20;
+const IFA_F_TENTATIVE: u32 = 0x40;
+const SCOPE_GLOBAL: u8 = 0x00;
+
+impl InterfaceAddress {
+    fn parse(line: &str) -> Option<InterfaceAddress> {
+        let mut fields = line.split_whitespace();
+        let address = u128::from_str_radix(fields.next()?, 16).ok()?;
+        let _index = fields.next()?;
+        let prefix_len = u8::from_str_radix(fields.next()?, 16).ok()?;
+        let scope = u8::from_str_radix(fields.next()?, 16).ok()?;
+        let flags = u32::from_str_radix(fields.next()?, 16).ok()?;
+        Some(InterfaceAddress {
+            address: Ipv6Addr::from(address),
+            prefix_len,
+            scope,
+            flags,
+            interface: fields.next()?.to_string(),
+        })
+    }
+
+    /// Global, and neither on its way out nor in doubt.
+    fn usable(&self) -> bool {
+        self.scope == SCOPE_GLOBAL
+            && self.flags & (IFA_F_DADFAILED | IFA_F_DEPRECATED | IFA_F_TENTATIVE) == 0
+    }
+
+    fn temporary(&self) -> bool {
+        self.flags & IFA_F_TEMPORARY != 0
+    }
+
+    /// Whether the interface identifier (the bits after the prefix) equals
+    /// that of `suffix`.
+    fn matches_suffix(&self, suffix: Ipv6Addr) -> bool {
+        let host_bits = !u128::MAX
+            .checked_shl(128 - u32::from(self.prefix_len))
+            .unwrap_or(0);
+        u128::from(self.address) & host_bits == u128::from(suffix) & host_bits
+    }
+}
+
+/// Picks the address of `interface` that `selection` asks for.
+fn select(
+    addresses: &[InterfaceAddress],
+    interface: &str,
+    selection: Ipv6Selection,
+    suffix: Option<Ipv6Addr>,
+) -> Result<Ipv6Addr, Error> {
+    let candidates: Vec<&InterfaceAddress> = addresses
+        .iter()
+        .filter(|a| a.interface == interface && a.usable())
+        .collect();
+    let chosen = match selection {
+        Ipv6Selection::PreferStable => candidates
+            .iter()
+            .find(|a| !a.temporary())
+            .or_else(|| candidates.first()),
+        // The kernel lists the newest temporary address first.
+        Ipv6Selection::PreferTemporary => candidates
+            .iter()
+            .find(|a| a.temporary())
+            .or_else(|| candidates.first()),
+        Ipv6Selection::MatchSuffix => {
+            let suffix = suffix.ok_or("ipv6_selection = \"match-suffix\" needs ipv6_suffix")?;
+            candidates.iter().find(|a| a.matches_suffix(suffix))
+        }
+    };
+    chosen
+        .map(|a| a.address)
+        .ok_or_else(|| format!("no usable global IPv6 address on {}", interface).into())
+}
+
+/// The global IPv6 address of `interface` chosen by `selection`.
+#[cfg(target_os = "linux")]
+pub fn ipv6_address(
+    interface: &str,
+    selection: Ipv6Selection,
+    suffix: Option<Ipv6Addr>,
+) -> Result<Ipv6Addr, Error> {
+    let table = std::fs::read_to_string("/proc/net/if_inet6")
+        .map_err(|e| format!("could not read /proc/net/if_inet6: {}", e))?;
+    let addresses: Vec<InterfaceAddress> =
+        table.lines().filter_map(InterfaceAddress::parse).collect();
+    select(&addresses, interface, selection, suffix)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn ipv6_address(_: &str, _: Ipv6Selection, _: Option<Ipv6Addr>) -> Result<Ipv6Addr, Error> {
+    Err("interface IP sources are only supported on Linux".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IF_INET6: &str = "\
+00000000000000000000000000000001 01 80 10 80       lo
+20010db8000000a1d1e47a3b93c0ffee 02 40 00 01     eth0
+20010db8000000a1021122fffe334455 02 40 00 00     eth0
+20010db8000000a19a0f6c2d4e5f6071 02 40 00 21     eth0
+fe80000000000000021122fffe334455 02 40 20 80     eth0
+";
+
+    #[test]
+    fn test_ipv6_selection_policies() {
+        let addresses: Vec<InterfaceAddress> = IF_INET6
+            .lines()
+            .filter_map(InterfaceAddress::parse)
+            .collect();
+        assert_eq!(addresses.len(), 5);
+        let pick = |selection, suffix: Option<&str>| {
+            let suffix = suffix.map(|s| s.parse().unwrap());
+            select(&addresses, "eth0", selection, suffix).map(|ip| ip.to_string())
+        };
+
+        assert_eq!(
+            pick(Ipv6Selection::PreferStable, None).unwrap(),
+            "2001:db8:0:a1:211:22ff:fe33:4455"
+        );
+        assert_eq!(
+            pick(Ipv6Selection::PreferTemporary, None).unwrap(),
+            "2001:db8:0:a1:d1e4:7a3b:93c0:ffee"
+        );
+        assert_eq!(
+            pick(Ipv6Selection::MatchSuffix, Some("::211:22ff:fe33:4455")).unwrap(),
+            "2001:db8:0:a1:211:22ff:fe33:4455"
+        );
+        assert!(pick(Ipv6Selection::MatchSuffix, Some("::1")).is_err());
+        assert!(pick(Ipv6Selection::MatchSuffix, None).is_err());
+        assert!(select(&addresses, "wlan0", Ipv6Selection::PreferStable, None).is_err());
+    }
+}
@@ -0,0 +1,306 @@
+//! Detecting the public IP and making sure it is worth publishing.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+use log::{debug, info, warn};
+
+use crate::breaker;
+use crate::config::Settings;
+use crate::interface;
+use crate::notify::{self, Notification};
+use crate::sync::run_bounded;
+use crate::{get_ip_from, upnp, Error};
+
+/// Ranges that never make sense in a public record, with what they are.
+const RESERVED: &[(&str, &str)] = &[
+    ("0.0.0.0/8", "unspecified"),
+    ("10.0.0.0/8", "private (RFC 1918)"),
+    ("100.64.0.0/10", "carrier-grade NAT"),
+    ("127.0.0.0/8", "loopback"),
+    ("169.254.0.0/16", "link-local"),
+    ("172.16.0.0/12", "private (RFC 1918)"),
+    ("192.0.2.0/24", "documentation"),
+    ("192.168.0.0/16", "private (RFC 1918)"),
+    ("198.18.0.0/15", "benchmarking"),
+    ("198.51.100.0/24", "documentation"),
+    ("203.0.113.0/24", "documentation"),
+    ("224.0.0.0/4", "multicast"),
+    ("240.0.0.0/4", "reserved"),
+    ("::/128", "unspecified"),
+    ("::1/128", "loopback"),
+    ("::ffff:0:0/96", "IPv4-mapped"),
+    ("2001:db8::/32", "documentation"),
+    ("fc00::/7", "unique local"),
+    ("fe80::/10", "link-local"),
+    ("ff00::/8", "multicast"),
+];
+
+const CGNAT_HINT: &str = "your ISP shares one public address between many customers, so \
+     inbound connections will not reach this host however correct DNS is; ask the ISP for a \
+     public IPv4 address, or use IPv6 or a tunnel instead";
+
+/// Records holding `ip`: A for IPv4, AAAA for IPv6.
+pub fn record_type(ip: IpAddr) -> &'static str {
+    match ip {
+        IpAddr::V4(_) => "A",
+        IpAddr::V6(_) => "AAAA",
+    }
+}
+
+/// What kind of non-public address `ip` is, if it is one.
+pub fn reserved_range(ip: IpAddr) -> Option<&'static str> {
+    RESERVED.iter().find_map(|(net, kind)| {
+        let net: IpNet = net.parse().expect("valid reserved range");
+        net.contains(&ip).then_some(*kind)
+    })
+}
+
+/// Parses what an echo service answered, refusing anything but a public
+/// address unless `allow_private` is set.
+pub fn parse_public(text: &str, allow_private: bool) -> Result<IpAddr, Error> {
+    let ip: IpAddr = text
+        .trim()
+        .parse()
+        .map_err(|_| format!("IP lookup returned {:?}, which is not an IP address", text))?;
+    match reserved_range(ip) {
+        Some(kind) if !allow_private => {
+            let hint = match is_cgnat(ip) {
+                true => format!(" ({})", CGNAT_HINT),
+                false => String::new(),
+            };
+            Err(format!(
+                "IP lookup returned {}, a {} address{}; pass --allow-private to publish it anyway",
+                ip, kind, hint
+            )
+            .into())
+        }
+        _ => Ok(ip),
+    }
+}
+
+fn is_cgnat(ip: IpAddr) -> bool {
+    reserved_range(ip) == Some("carrier-grade NAT")
+}
+
+/// Warns when this host looks to be behind carrier-grade NAT: either the
+/// published address is in 100.64.0.0/10 or, with `upnp_check`, the router's
+/// own WAN address differs from it. Fails instead with `skip_behind_cgnat`.
+fn check_cgnat(
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+    ip: IpAddr,
+) -> Result<(), Error> {
+    let problem = match is_cgnat(ip) {
+        true => Some(format!("{} is a carrier-grade NAT address", ip)),
+        false if settings.upnp_check => match upnp::wan_address(client) {
+            Ok(wan) if wan != ip => Some(format!(
+                "the router's WAN address is {} but the internet sees {}",
+                wan, ip
+            )),
+            Ok(_) => None,
+            Err(e) => {
+                info!("Could not ask the router for its WAN address: {}", e);
+                None
+            }
+        },
+        false => None,
+    };
+    match (problem, settings.skip_behind_cgnat) {
+        (Some(problem), true) => Err(format!(
+            "Behind carrier-grade NAT, not updating: {}; {}",
+            problem, CGNAT_HINT
+        )
+        .into()),
+        (Some(problem), false) => {
+            warn!("Looks like carrier-grade NAT: {}; {}", problem, CGNAT_HINT);
+            Ok(())
+        }
+        (None, _) => Ok(()),
+    }
+}
+
+/// Whether `ip` is in one of the `allowed_ranges`; no ranges allow all.
+pub fn allowed(ip: IpAddr, allowed_ranges: &[IpNet]) -> bool {
+    allowed_ranges.is_empty() || allowed_ranges.iter().any(|net| net.contains(&ip))
+}
+
+/// Asks the configured `ip_sources`: in order until one gives a usable
+/// answer, or all at once when `ip_consensus` wants a majority to agree.
+/// Sources the [`breaker`] quarantined are only asked in order after all
+/// others failed, and count as failed towards a consensus.
+fn lookup(client: &reqwest::blocking::Client, settings: &Settings) -> Result<IpAddr, Error> {
+    let ask = |source: &String| {
+        let answer = match source.strip_prefix("interface:") {
+            Some(name) => {
+                interface::ipv6_address(name, settings.ipv6_selection, settings.ipv6_suffix)
+                    .map(|ip| ip.to_string())
+            }
+            None if source == "cloudflare" => trace(client, CLOUDFLARE_TRACE),
+            None if source.ends_with("/cdn-cgi/trace") => trace(client, source),
+            None => get_ip_from(client, source),
+        };
+        let answer = answer
+            .and_then(|text| parse_public(&text, settings.allow_private))
+            .map_err(|e| e.to_string());
+        match answer {
+            Ok(_) => breaker::succeeded(source),
+            Err(_) => breaker::failed(source, settings.source_failures, settings.source_quarantine),
+        }
+        answer
+    };
+    let (available, quarantined): (Vec<&String>, Vec<&String>) = settings
+        .ip_sources
+        .iter()
+        .partition(|source| breaker::allows(source));
+    if settings.ip_consensus {
+        let answers =
+            run_bounded(
+                settings.ip_sources.len(),
+                &settings.ip_sources,
+                |source| match available.contains(&source) {
+                    true => ask(source),
+                    false => Err(format!("{} is skipped after failing repeatedly", source)),
+                },
+            );
+        return consensus(&settings.ip_sources, &answers);
+    }
+
+    let mut errors = Vec::new();
+    for url in available.into_iter().chain(quarantined) {
+        match ask(url) {
+            Ok(ip) => return Ok(ip),
+            Err(e) => {
+                warn!("{}", e);
+                errors.push(e);
+            }
+        }
+    }
+    Err(errors.join("; ").into())
+}
+
+/// Reached over both IPv4 and IPv6, unlike `https://1.1.1.1/cdn-cgi/trace`.
+const CLOUDFLARE_TRACE: &str = "https://cloudflare.com/cdn-cgi/trace";
+
+/// Asks a Cloudflare `/cdn-cgi/trace` endpoint, which answers with
+/// `key=value` lines, one of them `ip=`.
+fn trace(client: &reqwest::blocking::Client, url: &str) -> Result<String, Error> {
+    let text = get_ip_from(client, url)?;
+    parse_trace(&text)
+        .map(str::to_string)
+        .ok_or_else(|| format!("{} answered without an ip= line", url).into())
+}
+
+fn parse_trace(text: &str) -> Option<&str> {
+    text.lines().find_map(|line| line.strip_prefix("ip="))
+}
+
+/// The address more than half of `sources` answered with.
+fn consensus(sources: &[String], answers: &[Result<IpAddr, String>]) -> Result<IpAddr, Error> {
+    let mut votes: HashMap<IpAddr, usize> = HashMap::new();
+    for (url, answer) in sources.iter().zip(answers) {
+        match answer {
+            Ok(ip) => {
+                debug!("{} says {}", url, ip);
+                *votes.entry(*ip).or_default() += 1;
+            }
+            Err(e) => warn!("{}", e),
+        }
+    }
+    match votes.iter().max_by_key(|(_, count)| **count) {
+        Some((ip, count)) if count * 2 > sources.len() => Ok(*ip),
+        _ => {
+            let seen: Vec<String> = sources
+                .iter()
+                .zip(answers)
+                .map(|(url, answer)| match answer {
+                    Ok(ip) => format!("{} said {}", url, ip),
+                    Err(_) => format!("{} failed", url),
+                })
+                .collect();
+            Err(format!("IP sources disagree, not publishing: {}", seen.join(", ")).into())
+        }
+    }
+}
+
+/// The validated public IP of this host.
+pub fn detect(client: &reqwest::blocking::Client, settings: &Settings) -> Result<IpAddr, Error> {
+    let ip = lookup(client, settings)?;
+    if !allowed(ip, &settings.allowed_ranges) {
+        if let Some(url) = &settings.notify_url {
+            let address = ip.to_string();
+            notify::send(client, url, &Notification::Disallowed { ip: &address });
+        }
+        return Err(format!(
+            "Detected IP {} is outside allowed_ranges, not updating any record",
+            ip
+        )
+        .into());
+    }
+    if ip.is_ipv4() {
+        check_cgnat(client, settings, ip)?;
+    }
+    Ok(ip)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_public_addresses_pass() {
+        assert_eq!(
+            parse_public(" 93.184.216.34\n", false).unwrap().to_string(),
+            "93.184.216.34"
+        );
+        assert!(parse_public("2606:4700:4700::1111", false).is_ok());
+        for reserved in [
+            "192.168.1.2",
+            "100.72.0.1",
+            "127.0.0.1",
+            "fd00::1",
+            "fe80::1",
+        ] {
+            assert!(parse_public(reserved, false).is_err(), "{}", reserved);
+            assert!(parse_public(reserved, true).is_ok(), "{}", reserved);
+        }
+        assert!(parse_public("<html>502 Bad Gateway</html>", true).is_err());
+        assert_eq!(
+            reserved_range("100.64.0.1".parse().unwrap()),
+            Some("carrier-grade NAT")
+        );
+    }
+
+    #[test]
+    fn test_consensus_needs_a_majority() {
+        let sources: Vec<String> = ["a", "b", "c"].iter().map(|s| s.to_string()).collect();
+        let ip = |text: &str| Ok(text.parse().unwrap());
+        let good = ip("81.2.69.160");
+
+        let answers = [good.clone(), ip("81.2.69.161"), good.clone()];
+        assert_eq!(
+            consensus(&sources, &answers).unwrap(),
+            good.clone().unwrap()
+        );
+        let answers = [good.clone(), Err("timeout".to_string()), good.clone()];
+        assert!(consensus(&sources, &answers).is_ok());
+        let answers = [good.clone(), Err("timeout".to_string()), ip("81.2.69.161")];
+        assert!(consensus(&sources, &answers).is_err());
+    }
+
+    #[test]
+    fn test_parse_trace() {
+        let text = "fl=29f1\nh=1.1.1.1\nip=2a02:8010:1::7\nts=1697000000.1\nvisit_scheme=https\n";
+        assert_eq!(parse_trace(text), Some("2a02:8010:1::7"));
+        assert_eq!(parse_trace("<html></html>"), None);
+    }
+
+    #[test]
+    fn test_allowed_ranges() {
+        let ranges: Vec<IpNet> = vec!["81.2.64.0/18".parse().unwrap()];
+        assert!(allowed("81.2.69.160".parse().unwrap(), &ranges));
+        assert!(!allowed("100.101.102.103".parse().unwrap(), &ranges));
+        assert!(allowed("100.101.102.103".parse().unwrap(), &[]));
+    }
+}
@@ -0,0 +1,45 @@
+//! Random jitter for waits, so that a fleet of instances started with the
+//! same config does not hit the echo services and Cloudflare in lockstep.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A random number in `[0, 1)`. Not for cryptography, only to spread load.
+fn random() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    hasher.write_u32(nanos);
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// `wait` moved randomly by up to `fraction` of it in either direction.
+pub fn spread(wait: Duration, fraction: f64) -> Duration {
+    wait.mul_f64(1.0 + fraction * (2.0 * random() - 1.0))
+}
+
+/// `wait` made longer by a random part of up to `fraction` of it, for waits
+/// that must not end early, like the one for a scheduled run.
+pub fn delay(wait: Duration, fraction: f64) -> Duration {
+    wait + wait.mul_f64(fraction * random())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jitter_stays_in_bounds() {
+        let wait = Duration::from_secs(300);
+        for _ in 0..100 {
+            let spread = spread(wait, 0.1);
+            assert!(Duration::from_secs(270) <= spread && spread <= Duration::from_secs(330));
+            let delay = delay(wait, 0.1);
+            assert!(wait <= delay && delay <= Duration::from_secs(330));
+        }
+        assert_eq!(spread(wait, 0.0), wait);
+    }
+}
@@ -0,0 +1,295 @@
+//! Append-only journal of the DNS record changes cfdns makes, each with the
+//! record as it was before, and `cfdns undo` to revert the latest of them.
+//!
+//! The journal is a JSON lines file. Undoing appends the reverting changes
+//! too, marked with the entry they revert, so they won't be undone again.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::warn;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::cloudflare::models::{NewRecord, Record};
+use crate::config::Settings;
+use crate::confirm::confirm;
+use crate::{create_record, delete_record, paths, update_record, Error};
+
+/// `journal.jsonl` in the state directory.
+pub fn default_path() -> PathBuf {
+    paths::state_dir().join("journal.jsonl")
+}
+
+#[derive(Debug)]
+struct Journal {
+    path: Option<PathBuf>,
+    /// The entry being undone while its reverting change is made
+    undoing: Option<u64>,
+}
+
+static JOURNAL: Mutex<Journal> = Mutex::new(Journal {
+    path: None,
+    undoing: None,
+});
+
+/// Starts journaling record changes to `path`.
+pub fn open(path: PathBuf) {
+    JOURNAL.lock().unwrap().path = Some(path);
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Change {
+    Created { record: Record },
+    Updated { before: Record, content: String },
+    Deleted { before: Record },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Unix nanoseconds, unique enough to refer to the entry by
+    pub id: u64,
+    pub time: String,
+    pub zone: String,
+    #[serde(flatten)]
+    pub change: Change,
+    /// The entry this one reverted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub undoes: Option<u64>,
+}
+
+impl Entry {
+    pub fn record(&self) -> &Record {
+        match &self.change {
+            Change::Created { record } => record,
+            Change::Updated { before, .. } | Change::Deleted { before } => before,
+        }
+    }
+}
+
+impl std::fmt::Display for Entry {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let record = self.record();
+        let what = match &self.change {
+            Change::Created { .. } => format!("creation of {}", record.ip_addr),
+            Change::Updated { content, .. } => {
+                format!("update from {} to {}", record.ip_addr, content)
+            }
+            Change::Deleted { .. } => format!("deletion of {}", record.ip_addr),
+        };
+        write!(
+            f,
+            "{} {} {} at {}",
+            what, record.type_field, record.name, self.time
+        )
+    }
+}
+
+/// Journals a change just made to a record in `zone`. Failing to write the
+/// journal only warns, the change itself went through.
+pub fn append(zone: &str, change: Change) {
+    let journal = JOURNAL.lock().unwrap();
+    let Some(path) = &journal.path else {
+        return;
+    };
+    let now = SystemTime::now();
+    let entry = Entry {
+        id: now
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64),
+        time: humantime::format_rfc3339_seconds(now).to_string(),
+        zone: zone.to_string(),
+        change,
+        undoes: journal.undoing,
+    };
+    let line = serde_json::to_string(&entry).expect("journal entries are serializable");
+    let result = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| OpenOptions::new().create(true).append(true).open(path))
+        .and_then(|mut file| writeln!(file, "{}", line));
+    if let Err(e) = result {
+        warn!("Could not journal to {}: {}", path.display(), e);
+    }
+}
+
+fn read(path: &PathBuf) -> Result<Vec<Entry>, Error> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("could not read {}: {}", path.display(), e).into()),
+    };
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            serde_json::from_str(line)
+                .map_err(|e| format!("broken journal entry in {}: {}", path.display(), e).into())
+        })
+        .collect()
+}
+
+/// Every journaled change, oldest first.
+pub fn history(settings: &Settings) -> Result<Vec<Entry>, Error> {
+    read(&settings.journal)
+}
+
+/// The latest `count` entries not undone yet, newest first.
+fn undoable(entries: &[Entry], count: usize) -> Vec<&Entry> {
+    let undone: HashSet<u64> = entries.iter().filter_map(|e| e.undoes).collect();
+    entries
+        .iter()
+        .rev()
+        .filter(|e| e.undoes.is_none() && !undone.contains(&e.id))
+        .take(count)
+        .collect()
+}
+
+/// Record IDs that changed because a deleted record was re-created by an
+/// earlier undo, old ID to new.
+fn recreated(entries: &[Entry]) -> HashMap<String, String> {
+    let by_id: HashMap<u64, &Entry> = entries.iter().map(|e| (e.id, e)).collect();
+    let mut ids = HashMap::new();
+    for entry in entries {
+        let reverted = entry.undoes.and_then(|id| by_id.get(&id));
+        if let (Some(reverted), Change::Created { record }) = (reverted, &entry.change) {
+            ids.insert(reverted.record().id.clone(), record.id.clone());
+        }
+    }
+    ids
+}
+
+fn revert(
+    settings: &Settings,
+    entry: &Entry,
+    ids: &mut HashMap<String, String>,
+) -> Result<(), Error> {
+    let mut record = entry.record().clone();
+    if let Some(id) = ids.get(&record.id) {
+        record.id = id.clone();
+    }
+    let zone = &entry.zone;
+    match &entry.change {
+        Change::Created { .. } => delete_record(settings.client(zone), &record, zone),
+        Change::Updated { .. } => {
+            let content = record.ip_addr.clone();
+            update_record(settings.client(zone), &content, &record, zone)
+        }
+        Change::Deleted { before } => {
+            let new = NewRecord {
+                type_field: record.type_field.clone(),
+                name: record.name.clone(),
+                content: record.ip_addr.clone(),
+                proxied: record.proxied,
+                ttl: record.ttl,
+                comment: record.comment.clone(),
+            };
+            let created = create_record(settings.client(zone), &new, zone)?;
+            ids.insert(before.id.clone(), created.id);
+            Ok(())
+        }
+    }
+}
+
+/// `cfdns undo`: reverts the latest `count` changes, newest first, and
+/// stops at the first one that can't be reverted.
+pub fn undo(settings: &Settings, count: usize, yes: bool) -> Result<(), Error> {
+    let entries = read(&settings.journal)?;
+    let pending = undoable(&entries, count);
+    if pending.is_empty() {
+        println!("Nothing to undo in {}", settings.journal.display());
+        return Ok(());
+    }
+    for entry in &pending {
+        println!("{}", entry);
+    }
+    if !confirm(&format!("Revert these {} changes?", pending.len()), yes)? {
+        println!("Nothing reverted");
+        return Ok(());
+    }
+    let mut ids = recreated(&entries);
+    for entry in pending {
+        JOURNAL.lock().unwrap().undoing = Some(entry.id);
+        let result = revert(settings, entry, &mut ids);
+        JOURNAL.lock().unwrap().undoing = None;
+        match result {
+            Ok(()) => println!("Reverted {}", entry),
+            Err(e) => return Err(format!("Could not revert {}: {}", entry, e).into()),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: u64, change: Change, undoes: Option<u64>) -> Entry {
+        Entry {
+            id,
+            time: String::new(),
+            zone: "z".to_string(),
+            change,
+            undoes,
+        }
+    }
+
+    fn record(id: &str) -> Record {
+        Record {
+            id: id.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_undo_skips_what_was_undone() {
+        let entries = vec![
+            entry(
+                1,
+                Change::Deleted {
+                    before: record("old"),
+                },
+                None,
+            ),
+            entry(
+                2,
+                Change::Updated {
+                    before: record("a"),
+                    content: "198.51.100.1".to_string(),
+                },
+                None,
+            ),
+            entry(
+                3,
+                Change::Created {
+                    record: record("b"),
+                },
+                None,
+            ),
+            entry(
+                4,
+                Change::Deleted {
+                    before: record("b"),
+                },
+                Some(3),
+            ),
+            entry(
+                5,
+                Change::Created {
+                    record: record("new"),
+                },
+                Some(1),
+            ),
+        ];
+        let pending: Vec<u64> = undoable(&entries, 3).iter().map(|e| e.id).collect();
+        assert_eq!(pending, vec![2]);
+        assert_eq!(recreated(&entries).get("old"), Some(&"new".to_string()));
+
+        let line = serde_json::to_string(&entries[3]).unwrap();
+        assert!(line.contains(r#""op":"deleted""#));
+        assert_eq!(serde_json::from_str::<Entry>(&line).unwrap(), entries[3]);
+    }
+}
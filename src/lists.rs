@@ -0,0 +1,156 @@
+//! Keeping an entry of a Cloudflare IP List (Rules > Lists) on the current
+//! IP, so that WAF and firewall rules about "my home IP" follow it.
+//!
+//! The entry is found by its comment. cfdns adds an entry with the current IP
+//! and removes the other entries with that comment, leaving the rest of the
+//! list alone.
+
+use std::net::IpAddr;
+
+use log::info;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::cloudflare::CloudflareClient;
+use crate::sync::{target_report, RecordReport, SyncState, OWNERSHIP_MARKER};
+use crate::{send_api, Error};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IpListConfig {
+    pub account_id: String,
+    pub list_id: String,
+    /// Comment identifying the entry cfdns keeps up to date
+    #[serde(default = "default_comment")]
+    pub comment: String,
+}
+
+fn default_comment() -> String {
+    OWNERSHIP_MARKER.to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct ListItem {
+    id: String,
+    ip: Option<String>,
+    comment: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct NewItem<'a> {
+    ip: String,
+    comment: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct ItemId<'a> {
+    id: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct DeleteItems<'a> {
+    items: Vec<ItemId<'a>>,
+}
+
+fn items_url(list: &IpListConfig) -> String {
+    format!(
+        "/accounts/{}/rules/lists/{}/items",
+        list.account_id, list.list_id
+    )
+}
+
+/// Ids of the entries with our comment that do not hold `ip`, and whether
+/// one of them already holds it.
+fn stale_entries<'a>(items: &'a [ListItem], comment: &str, ip: IpAddr) -> (Vec<&'a str>, bool) {
+    let mut current = false;
+    let mut stale = Vec::new();
+    for item in items
+        .iter()
+        .filter(|i| i.comment.as_deref() == Some(comment))
+    {
+        match item.ip.as_deref().and_then(|i| i.parse::<IpAddr>().ok()) == Some(ip) {
+            true if !current => current = true,
+            _ => stale.push(item.id.as_str()),
+        }
+    }
+    (stale, current)
+}
+
+/// Points the list entry at `ip`. The changes Cloudflare applies
+/// asynchronously are not waited for.
+fn update(
+    cloudflare: &CloudflareClient,
+    list: &IpListConfig,
+    ip: IpAddr,
+) -> Result<SyncState, Error> {
+    let url = items_url(list);
+    let items: Vec<ListItem> =
+        serde_json::from_value(send_api(cloudflare.get(&url), "IP list request")?)?;
+    let (stale, current) = stale_entries(&items, &list.comment, ip);
+    if current && stale.is_empty() {
+        info!("IP list {} already has {}", list.list_id, ip);
+        return Ok(SyncState::InSync);
+    }
+
+    if !current {
+        let body = [NewItem {
+            ip: ip.to_string(),
+            comment: &list.comment,
+        }];
+        let body = serde_json::to_string(&body)?;
+        send_api(cloudflare.post(&url).body(body), "IP list request")?;
+    }
+    if !stale.is_empty() {
+        let body = DeleteItems {
+            items: stale.iter().map(|id| ItemId { id }).collect(),
+        };
+        let body = serde_json::to_string(&body)?;
+        send_api(cloudflare.delete(&url).body(body), "IP list request")?;
+    }
+    info!("Updated IP list {} to {}", list.list_id, ip);
+    Ok(SyncState::Updated)
+}
+
+/// Brings every configured list in line with `ip`.
+pub fn sync(
+    cloudflare: &CloudflareClient,
+    lists: &[IpListConfig],
+    ip: IpAddr,
+) -> Vec<RecordReport> {
+    lists
+        .iter()
+        .map(|list| {
+            let name = format!("ip list: {}", list.comment);
+            target_report(&list.list_id, name, ip, update(cloudflare, list, ip))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(id: &str, ip: &str, comment: &str) -> ListItem {
+        ListItem {
+            id: id.to_string(),
+            ip: Some(ip.to_string()),
+            comment: Some(comment.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_only_our_entries_are_replaced() {
+        let items = [
+            item("1", "203.0.113.7", "home"),
+            item("2", "198.51.100.1", "office"),
+            item("3", "203.0.113.9", "home"),
+            item("4", "203.0.113.9", "home"),
+        ];
+        let ip = "203.0.113.9".parse().unwrap();
+        assert_eq!(stale_entries(&items, "home", ip), (vec!["1", "4"], true));
+        let ip = "192.0.2.1".parse().unwrap();
+        assert_eq!(
+            stale_entries(&items, "home", ip),
+            (vec!["1", "3", "4"], false)
+        );
+    }
+}
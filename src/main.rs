@@ -1,213 +1,795 @@
-use std::env;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::{Duration, SystemTime};
 
-use clap::command;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use dotenv::dotenv;
-use log::error;
-use reqwest::header;
-use serde_derive::Deserialize;
-use serde_derive::Serialize;
 use serde_json::Value;
+use trace::Traced;
 
-use log::{info, warn};
+use log::info;
+
+mod access;
+mod activation;
+mod adopt;
+#[cfg(feature = "http-api")]
+mod api;
+mod audit;
+mod authoritative;
+mod breaker;
+mod cloudflare;
+mod commands;
+mod config;
+mod confirm;
+mod control;
+mod daemon;
+mod deregister;
+mod dns;
+mod dnssec;
+mod doh;
+mod events;
+mod exit;
+mod failover;
+mod fleet;
+mod frozen;
+mod grafana;
+mod guard;
+mod hints;
+mod hook;
+mod ids;
+mod influx;
+mod init;
+mod interface;
+mod ip;
+mod jitter;
+mod journal;
+mod lists;
+mod names;
+mod notify;
+mod output;
+mod paths;
+mod plan;
+mod pools;
+mod probe;
+mod progress;
+mod propagation;
+mod published;
+mod purge;
+mod remote;
+mod report;
+mod retarget;
+mod round_robin;
+mod schedule;
+mod secrets;
+mod self_update;
+mod statsd;
+mod sync;
+mod syslog;
+mod template;
+mod templated;
+mod token;
+mod trace;
+mod tunnel;
+mod upnp;
+mod validate;
+mod version;
+
+use cloudflare::models::{
+    self, ApiResponse, NewRecord, Record, RecordResponse, Response, Zone, ZonesResponse,
+};
+use cloudflare::{ApiError, CloudflareClient, Connection};
+use config::{Config, Settings};
+use exit::{ExitCodes, Outcome, UpdatesFailed};
+use sync::{sync_records, Reconciler};
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
-struct Args {
-    /// Record name
-    #[arg(short, long)]
-    name: String,
+#[command(author, version, long_version = version::LONG, about, long_about = None)]
+pub struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Record name, may be given multiple times
+    #[arg(short, long, global = true)]
+    name: Vec<String>,
+
+    /// Profile of the config file to use, see `profiles`
+    #[arg(long, global = true, env = "CFDNS_PROFILE")]
+    profile: Option<String>,
+
+    /// Zone ID, name or alias from the config, may be given multiple times
+    #[arg(short, long, global = true)]
+    zone: Vec<String>,
+
+    /// Also manage the records of --zone whose comment starts with this, e.g. ddns:
+    #[arg(long, global = true)]
+    comment_prefix: Option<String>,
+
+    /// Config file with zones and records to manage, may be an http(s):// URL
+    #[arg(short, long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Maximum number of record updates sent to Cloudflare at once [default: 4]
+    #[arg(short = 'j', long)]
+    parallelism: Option<usize>,
 
-    /// Zone Id
+    /// Keep running and re-check the records periodically
     #[arg(short, long)]
-    zone: String,
-}
-
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-#[serde(rename = "Root")]
-pub struct Response {
-    #[serde(rename = "result")]
-    pub records: Vec<Record>,
-    pub success: bool,
-    pub errors: Vec<Value>,
-    pub messages: Vec<Value>,
-    #[serde(rename = "result_info")]
-    pub result_info: ResultInfo,
-}
-
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-#[serde(rename = "result")]
-pub struct Record {
-    pub id: String,
-    #[serde(rename = "zone_id")]
-    pub zone_id: String,
-    #[serde(rename = "zone_name")]
-    pub zone_name: String,
-    pub name: String,
-    #[serde(rename = "type")]
-    pub type_field: String,
-    #[serde(rename = "content")]
-    pub ip_addr: String,
-    pub proxiable: bool,
-    pub proxied: bool,
-    pub ttl: i64,
-    pub locked: bool,
-    pub meta: Meta,
-    pub comment: Option<String>,
-    pub tags: Vec<Value>,
-    #[serde(rename = "created_on")]
-    pub created_on: String,
-    #[serde(rename = "modified_on")]
-    pub modified_on: String,
-    pub priority: Option<i64>,
-}
-
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct Meta {
-    #[serde(rename = "auto_added")]
-    pub auto_added: bool,
-    #[serde(rename = "managed_by_apps")]
-    pub managed_by_apps: bool,
-    #[serde(rename = "managed_by_argo_tunnel")]
-    pub managed_by_argo_tunnel: bool,
-    pub source: String,
-    #[serde(rename = "email_routing")]
-    pub email_routing: Option<bool>,
-    #[serde(rename = "read_only")]
-    pub read_only: Option<bool>,
-}
-
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct ResultInfo {
-    pub page: i64,
-    #[serde(rename = "per_page")]
-    pub per_page: i64,
-    pub count: i64,
-    #[serde(rename = "total_count")]
-    pub total_count: i64,
-    #[serde(rename = "total_pages")]
-    pub total_pages: i64,
-}
-
-fn find_subdomain_record<'a>(records: &'a [Record], record_name: &'a String) -> Option<&'a Record> {
-    records
-        .iter()
-        .find(|record| record.name == *record_name && record.type_field == "A")
-}
-
-fn get_dns_records(client: &reqwest::blocking::Client, zone: &String) -> String {
-    let dotoken = env::var("CF_TOKEN").expect("No CF_TOKEN set in env");
-    let mut headers = header::HeaderMap::new();
-    headers.insert("Content-Type", "application/json".parse().unwrap());
-    headers.insert(
-        "Authorization",
-        format!("Bearer {}", dotoken).parse().unwrap(),
-    );
+    daemon: bool,
 
-    client
-        .get(format!(
-            "https://api.cloudflare.com/client/v4/zones/{zone}/dns_records"
-        ))
-        .headers(headers)
-        .send()
-        .expect("could not send request to cloudflare api")
-        .text()
-        .expect("could not retrieve text from cloudflare api response")
+    /// Time between checks in daemon mode, e.g. 30s, 5m or 1h [default: 5m]
+    #[arg(long, value_parser = humantime::parse_duration)]
+    interval: Option<Duration>,
+
+    /// Cron expression for daemon runs instead of an interval, e.g. "*/5 6-22 * * *"
+    #[arg(long, conflicts_with = "interval")]
+    schedule: Option<String>,
+
+    /// Randomly vary waits between runs and retries by up to this fraction,
+    /// e.g. 0.1 for 10%
+    #[arg(long)]
+    jitter: Option<f64>,
+
+    /// In daemon mode check Cloudflare every time and revert external changes
+    #[arg(long)]
+    enforce: bool,
+
+    /// After updating, wait until public resolvers serve the new content
+    #[arg(long)]
+    verify_propagation: bool,
+
+    /// Read published records from Cloudflare's name servers first and only
+    /// call the API when they are out of date
+    #[arg(long)]
+    dns_precheck: bool,
+
+    /// Only contact Cloudflare when the detected IP differs from the one
+    /// the last successful run published
+    #[arg(long)]
+    from_cache: bool,
+
+    /// Ignore the cached IDs and published IP, and fetch everything afresh
+    #[arg(long)]
+    refresh_cache: bool,
+
+    /// Send verification and pre-check queries over DNS-over-HTTPS, to
+    /// Cloudflare unless another endpoint URL is given
+    #[arg(long, value_name = "URL", num_args = 0..=1, require_equals = true,
+          default_missing_value = doh::DEFAULT_URL)]
+    doh: Option<String>,
+
+    /// Back off the interval while the IP is stable, snapping back on change
+    #[arg(long)]
+    adaptive: bool,
+
+    /// Longest interval adaptive polling backs off to [default: 15m]
+    #[arg(long, value_parser = humantime::parse_duration)]
+    max_interval: Option<Duration>,
+
+    /// Wait up to this long (default 15m) for pending zones to become active
+    /// instead of only warning about them
+    #[arg(long, value_parser = humantime::parse_duration, num_args = 0..=1,
+          default_missing_value = "15m", value_name = "TIMEOUT")]
+    wait_active: Option<Duration>,
+
+    /// Serve the status API on this address in daemon mode, e.g. 127.0.0.1:8053
+    #[arg(long)]
+    http_listen: Option<String>,
+
+    /// Write a JSON report of the run here, rewritten after every daemon run
+    #[arg(long)]
+    report_file: Option<PathBuf>,
+
+    /// Output format of status, list and zones
+    #[arg(short, long, global = true, value_enum, default_value_t)]
+    output: output::Format,
+
+    /// Control socket of the daemon [default: $XDG_RUNTIME_DIR/cfdns.sock]
+    #[arg(long, global = true)]
+    control_socket: Option<PathBuf>,
+
+    /// Only connect over IPv4, to detect and publish the IPv4 address (A records)
+    #[arg(short = '4', long, global = true, conflicts_with = "force_ipv6")]
+    force_ipv4: bool,
+
+    /// Only connect over IPv6, to detect and publish the IPv6 address (AAAA records)
+    #[arg(short = '6', long, global = true)]
+    force_ipv6: bool,
+
+    /// Send all requests from this local address, e.g. that of one uplink
+    #[arg(long, global = true, conflicts_with_all = ["force_ipv4", "force_ipv6"])]
+    bind_address: Option<IpAddr>,
+
+    /// Send all requests through this network interface (SO_BINDTODEVICE, Linux)
+    #[arg(long, global = true)]
+    bind_interface: Option<String>,
+
+    /// Fail on Cloudflare responses with fields cfdns doesn't know or
+    /// expects but didn't get, instead of ignoring and defaulting them
+    #[arg(long, global = true)]
+    strict_parse: bool,
+
+    /// Show internationalized names in punycode (xn--...), as Cloudflare has them
+    #[arg(long, global = true)]
+    ascii: bool,
+
+    /// Log every HTTP request and response, with secrets left out
+    #[arg(long, global = true)]
+    trace_http: bool,
+
+    /// Write an event per line on stdout in daemon mode, for log shippers
+    #[arg(long, value_enum, requires = "daemon")]
+    events: Option<events::Format>,
+
+    /// Exit codes to use, `detailed` tells apart what happened (see README)
+    #[arg(long, global = true, value_enum, default_value_t)]
+    exit_codes: ExitCodes,
+
+    /// Publish loopback, private, CGNAT and other reserved addresses too
+    #[arg(long, global = true)]
+    allow_private: bool,
+
+    /// Also modify records owned by Cloudflare Apps, Argo Tunnel or marked read only
+    #[arg(long, global = true)]
+    force: bool,
+
+    /// Refuse anything that would change DNS, for monitoring setups
+    #[arg(long, global = true)]
+    read_only: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Compare the configured records with the current IP without changing them
+    Status,
+    /// Show the changes a run would make, without making them
+    Plan,
+    /// List all DNS records of the given zones
+    List(commands::ListArgs),
+    /// List the zones the token can access
+    Zones,
+    /// Delete stale records pointing at given addresses
+    Purge(purge::PurgeArgs),
+    /// Point every record at an old address or host name at a new one
+    Retarget(retarget::RetargetArgs),
+    /// Add existing A/AAAA records of the zones to the config file, to manage them
+    Adopt(adopt::AdoptArgs),
+    /// Find records whose name or content contains a pattern, in every zone
+    Search {
+        /// Text to look for, case insensitive
+        pattern: String,
+    },
+    /// Show the DNS changes in Cloudflare's audit log, flagging those cfdns didn't make
+    Audit(audit::AuditArgs),
+    /// Create the records of the new_host template for a new host
+    NewHost(template::NewHostArgs),
+    /// Manage records that point at a Cloudflare Tunnel
+    Tunnel {
+        #[command(subcommand)]
+        command: tunnel::TunnelCommand,
+    },
+    /// Check that DNSSEC of the configured zones validates
+    Dnssec {
+        #[command(subcommand)]
+        command: dnssec::DnssecCommand,
+    },
+    /// Create and remove ACME DNS-01 challenge records for certbot or lego
+    Hook {
+        #[command(subcommand)]
+        command: hook::HookCommand,
+    },
+    /// Revert the latest record changes cfdns made, newest first
+    Undo {
+        /// How many changes to revert
+        #[arg(long, default_value_t = 1)]
+        last: usize,
+        /// Revert without asking for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Show the version, how this binary was built and optionally the latest release
+    Version(version::VersionArgs),
+    /// Replace this binary with the latest release
+    SelfUpdate(self_update::SelfUpdateArgs),
+    /// Show where the config file, state and caches are kept
+    Paths,
+    /// Send a command to a running daemon
+    Ctl {
+        #[arg(value_enum)]
+        request: control::Request,
+    },
+    /// Exit 0 if the running daemon had a successful run lately, for Docker's HEALTHCHECK
+    Healthcheck {
+        /// How long ago the last successful run may be
+        #[arg(long, value_parser = humantime::parse_duration, default_value = "15m")]
+        max_age: Duration,
+    },
+}
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// Records asked for per page when listing the records of a zone.
+const RECORDS_PER_PAGE: i64 = 500;
+
+fn get_dns_records(
+    cloudflare: &CloudflareClient,
+    zone: &str,
+    record_type: Option<&str>,
+    page: i64,
+) -> Result<cloudflare::Answer, Error> {
+    let mut query = vec![
+        ("per_page", RECORDS_PER_PAGE.to_string()),
+        ("page", page.to_string()),
+    ];
+    if let Some(record_type) = record_type {
+        query.push(("type", record_type.to_string()));
+    }
+    cloudflare::send(
+        cloudflare
+            .get(&format!("/zones/{zone}/dns_records"))
+            .query(&query),
+    )
+}
+
+fn get_zones(cloudflare: &CloudflareClient, page: i64) -> Result<String, Error> {
+    Ok(cloudflare::send(cloudflare.get(&format!("/zones?per_page=50&page={page}")))?.body)
+}
+
+/// The records of a zone, fetched and parsed a page at a time as iteration
+/// reaches them, so that a zone with many thousands of records never has
+/// more than a page of them in memory. Stops after the first error.
+pub struct DnsRecords<'a> {
+    cloudflare: &'a CloudflareClient,
+    zone: &'a str,
+    record_type: Option<&'a str>,
+    page: i64,
+    total_pages: i64,
+    buffered: std::vec::IntoIter<Record>,
+}
+
+impl Iterator for DnsRecords<'_> {
+    type Item = Result<Record, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(record) = self.buffered.next() {
+                return Some(Ok(record));
+            }
+            if self.page >= self.total_pages {
+                return None;
+            }
+            self.page += 1;
+            let page = get_dns_records(self.cloudflare, self.zone, self.record_type, self.page)
+                .and_then(|answer| {
+                    let res = models::parse::<Response>(&answer.body)
+                        .map_err(|e| format!("Could not parse Cloudflare response JSON: {}", e))?;
+                    Ok((res, answer.ray))
+                });
+            match page {
+                Ok((res, ray)) if !res.success => {
+                    self.total_pages = 0;
+                    let error = ApiError::new("Listing records", res.errors).with_ray(ray);
+                    return Some(Err(error.into()));
+                }
+                Ok((res, _)) => {
+                    self.total_pages = res.result_info.total_pages;
+                    self.buffered = res.records.into_iter();
+                }
+                Err(e) => {
+                    self.total_pages = 0;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// Records of `zone`, of `record_type` only if given, which Cloudflare
+/// filters on its side.
+pub fn dns_records<'a>(
+    cloudflare: &'a CloudflareClient,
+    zone: &'a str,
+    record_type: Option<&'a str>,
+) -> DnsRecords<'a> {
+    DnsRecords {
+        cloudflare,
+        zone,
+        record_type,
+        page: 0,
+        total_pages: 1,
+        buffered: Vec::new().into_iter(),
+    }
+}
+
+/// All records of `zone`, parsed.
+fn list_dns_records(cloudflare: &CloudflareClient, zone: &str) -> Result<Vec<Record>, Error> {
+    dns_records(cloudflare, zone, None).collect()
+}
+
+/// The record as Cloudflare has it right now.
+fn get_dns_record(
+    cloudflare: &CloudflareClient,
+    record: &Record,
+    zone: &String,
+) -> Result<Record, Error> {
+    let record_id = &record.id;
+    let answer =
+        cloudflare::send(cloudflare.get(&format!("/zones/{zone}/dns_records/{record_id}")))?;
+    let res: RecordResponse = models::parse(&answer.body)
+        .map_err(|e| format!("Could not parse Cloudflare response JSON: {}", e))?;
+    res.record
+        .ok_or_else(|| format!("Record {} no longer exists", record.name).into())
+}
+
+/// All zones the token can access, parsed, following pagination.
+fn list_zones(cloudflare: &CloudflareClient) -> Result<Vec<Zone>, Error> {
+    let mut zones = Vec::new();
+    let mut page = 1;
+    loop {
+        let res: ZonesResponse = models::parse(get_zones(cloudflare, page)?.as_str())
+            .map_err(|e| format!("Could not parse Cloudflare response JSON: {}", e))?;
+        zones.extend(res.zones);
+        if page >= res.result_info.total_pages {
+            return Ok(zones);
+        }
+        page += 1;
+    }
 }
 
-fn get_current_ip_addr(client: &reqwest::blocking::Client) -> String {
+/// The zone with ID `zone`.
+fn get_zone(cloudflare: &CloudflareClient, zone: &str) -> Result<Zone, Error> {
+    let request = cloudflare.get(&format!("/zones/{zone}"));
+    models::parse_value(send_api(request, "Zone request")?)
+}
+
+/// Echo service asked for the public IP unless `ip_sources` says otherwise.
+const DEFAULT_IP_SOURCE: &str = "http://whatismyip.akamai.com/";
+
+/// Asks the echo service at `url`, which answers with the address alone.
+fn get_ip_from(client: &reqwest::blocking::Client, url: &str) -> Result<String, Error> {
     client
-        .get("http://whatismyip.akamai.com/")
-        .send()
-        .expect("could not send request to whatismyip api")
+        .get(url)
+        .send_traced()
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| format!("could not send request to {}: {}", url, e))?
         .text()
-        .expect("could not retrieve text from whatismyip api response")
+        .map_err(|e| format!("could not retrieve text from {} response: {}", url, e).into())
 }
 
 fn update_record(
-    client: &reqwest::blocking::Client,
+    cloudflare: &CloudflareClient,
     current_ip: &String,
     record: &Record,
     zone: &String,
-) {
-    let dotoken = env::var("CF_TOKEN").expect("No CF_TOKEN set in env");
-    let mut headers = header::HeaderMap::new();
-    headers.insert("Content-Type", "application/json".parse().unwrap());
-    headers.insert(
-        "Authorization",
-        format!("Bearer {}", dotoken).parse().unwrap(),
-    );
+) -> Result<(), Error> {
+    let new_record = Record {
+        ip_addr: current_ip.to_string(),
+        ..record.clone()
+    };
+    replace_record(cloudflare, record, &new_record, zone)
+}
 
+/// Puts `new_record` in place of `record`, TTL, proxied flag and all.
+fn replace_record(
+    cloudflare: &CloudflareClient,
+    record: &Record,
+    new_record: &Record,
+    zone: &String,
+) -> Result<(), Error> {
+    let current_ip = &new_record.ip_addr;
+    frozen::check(record)?;
+    validate::content(&record.type_field, &record.name, current_ip)?;
     let record_id = record.id.clone();
-    let mut new_record = record.clone();
-    new_record.ip_addr = String::from(current_ip);
     let payload = serde_json::to_string_pretty(&new_record)
-        .map_err(|e| error!("Failed to construct updated record payload {}", e))
-        .unwrap();
-
-    let response = client
-        .put(format!(
-            "https://api.cloudflare.com/client/v4/zones/{zone}/dns_records/{record_id}"
-        ))
-        .headers(headers)
-        .body(payload)
-        .send()
-        .expect("could not send request to cloudflare api")
-        .text()
-        .expect("could not get respose body");
-    if response.contains("success\":true") {
-        info!("Successfully updated DNS record")
+        .map_err(|e| format!("Failed to construct updated record payload {}", e))?;
+
+    let answer = cloudflare::send(
+        cloudflare
+            .put(&format!("/zones/{zone}/dns_records/{record_id}"))
+            .body(payload),
+    )?;
+    if answer.body.contains("success\":true") {
+        info!("Successfully updated DNS record {}", record.name);
+        let change = journal::Change::Updated {
+            before: record.clone(),
+            content: current_ip.clone(),
+        };
+        journal::append(zone, change);
+        Ok(())
+    } else {
+        let message = format!(
+            "Record Update Failed for {}, DNS not synced with actual ip!",
+            record.name
+        );
+        Err(cloudflare::with_ray(message, answer.ray.as_deref()).into())
+    }
+}
+
+fn create_record(
+    cloudflare: &CloudflareClient,
+    record: &NewRecord,
+    zone: &str,
+) -> Result<Record, Error> {
+    frozen::check_new(&record.name, &record.type_field)?;
+    validate::content(&record.type_field, &record.name, &record.content)?;
+    let payload = serde_json::to_string(record)
+        .map_err(|e| format!("Failed to construct new record payload {}", e))?;
+    let result = send_api(
+        cloudflare
+            .post(&format!("/zones/{zone}/dns_records"))
+            .body(payload),
+        "Record creation",
+    )?;
+    info!("Created {} record {}", record.type_field, record.name);
+    let created: Record = models::parse_value(result)
+        .map_err(|e| format!("Could not parse Cloudflare response JSON: {}", e))?;
+    let change = journal::Change::Created {
+        record: created.clone(),
+    };
+    journal::append(zone, change);
+    Ok(created)
+}
+
+/// Purges the cached `files` of `zone`, or everything when there are none.
+fn purge_cache(cloudflare: &CloudflareClient, zone: &str, files: &[String]) -> Result<(), Error> {
+    let payload = match files.is_empty() {
+        true => serde_json::json!({ "purge_everything": true }),
+        false => serde_json::json!({ "files": files }),
+    };
+    send_api(
+        cloudflare
+            .post(&format!("/zones/{zone}/purge_cache"))
+            .body(payload.to_string()),
+        "Cache purge",
+    )?;
+    info!("Purged the cache of zone {}", zone);
+    Ok(())
+}
+
+fn delete_record(
+    cloudflare: &CloudflareClient,
+    record: &Record,
+    zone: &String,
+) -> Result<(), Error> {
+    frozen::check(record)?;
+    let record_id = record.id.clone();
+    let answer =
+        cloudflare::send(cloudflare.delete(&format!("/zones/{zone}/dns_records/{record_id}")))?;
+    if answer.body.contains("success\":true") {
+        info!("Deleted DNS record {}", record.name);
+        let change = journal::Change::Deleted {
+            before: record.clone(),
+        };
+        journal::append(zone, change);
+        Ok(())
     } else {
-        error!("Record Update Failed, DNS not synced with actual ip!")
+        let message = format!("Deleting record {} failed", record.name);
+        Err(cloudflare::with_ray(message, answer.ray.as_deref()).into())
     }
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// Sends an API request and returns its result, failing unless Cloudflare
+/// reports success. `what` names the request in errors.
+fn send_api(request: reqwest::blocking::RequestBuilder, what: &str) -> Result<Value, Error> {
+    let answer = cloudflare::send(request)?;
+    let response: ApiResponse = models::parse(&answer.body)
+        .map_err(|e| format!("could not parse {} response: {}", what, e))?;
+    match response.success {
+        true => Ok(response.result),
+        false => Err(ApiError::new(what, response.errors)
+            .with_ray(answer.ray)
+            .into()),
+    }
+}
+
+fn main() -> ExitCode {
     dotenv().ok();
-    env_logger::init();
     let args = Args::parse();
-    if args.name.is_empty() {
-        error!("Record domain can not be empty!");
-        panic!();
-    }
-    if args.zone.is_empty() {
-        error!("Zone can not be empty!");
-        panic!();
-    }
-
-    let client = reqwest::blocking::Client::builder()
-        .redirect(reqwest::redirect::Policy::none())
-        .build()
-        .unwrap();
-    let res: Response = serde_json::from_str(get_dns_records(&client, &args.zone).as_str())
-        .expect("Could not parse Cloudflare response JSON");
-    info!(
-        "Fetched All DNS records from Cloudflare for zone {}",
-        args.zone
-    );
-
-    let current_ip = get_current_ip_addr(&client);
-
-    match find_subdomain_record(&res.records, &args.name) {
-        Some(record) => match current_ip != record.ip_addr {
-            true => update_record(&client, &current_ip, record, &args.zone),
-            false => info!("Nothing to update, DNS in sync"),
-        },
-        None => {
-            warn!("No record for subdomain {} found ", &args.name);
+    let mut logger = env_logger::Builder::from_default_env();
+    if args.trace_http {
+        logger.filter_module(trace::TARGET, log::LevelFilter::Trace);
+    }
+    syslog::init(logger);
+    trace::enable(args.trace_http);
+    names::set_ascii(args.ascii);
+    models::set_strict(args.strict_parse);
+    events::set(args.events);
+    let codes = args.exit_codes;
+    match run(args) {
+        Ok(outcome) => ExitCode::from(exit::success(codes, &outcome)),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            if let Some(hint) = hints::hint(&e) {
+                eprintln!("Hint: {}", hint);
+            }
+            ExitCode::from(exit::failure(codes, &e))
+        }
+    }
+}
+
+fn run(args: Args) -> Result<Outcome, Error> {
+    if let Some(Command::Ctl { request }) = args.command {
+        return ctl(&args, request).map(Outcome::from);
+    }
+    if let Some(Command::Healthcheck { max_age }) = args.command {
+        return healthcheck(&args, max_age).map(Outcome::from);
+    }
+    init::setup(args.daemon)?;
+    if let Some(Command::Paths) = args.command {
+        return paths::print(&args, args.output).map(Outcome::from);
+    }
+    if let Some(Command::Version(ref version)) = args.command {
+        return version::run(version, args.output).map(Outcome::from);
+    }
+    if let Some(Command::SelfUpdate(ref update)) = args.command {
+        return self_update::run(update).map(Outcome::from);
+    }
+    let client = build_client(&args)?;
+    let load = || Settings::load(&args).and_then(ids::resolve_zones);
+    let mut settings = load()?;
+    journal::open(settings.journal.clone());
+    apply(&settings);
+    if let Some(what) = mutation(&args).filter(|_| settings.read_only) {
+        return Err(format!("read_only is set, {} would change DNS", what).into());
+    }
+
+    let done = match args.command {
+        Some(Command::Status) => commands::status(&client, &settings, args.output),
+        Some(Command::List(ref list)) => commands::list(&settings, list, args.output),
+        Some(Command::Plan) => plan::plan(&client, &settings, args.output),
+        Some(Command::Zones) => commands::zones(&settings, args.output),
+        Some(Command::Purge(ref purge)) => purge::run(&settings, purge),
+        Some(Command::Retarget(ref retarget)) => retarget::run(&settings, retarget, args.output),
+        Some(Command::Adopt(ref adopt)) => {
+            let config = paths::config_file(&args).unwrap_or_else(paths::default_config);
+            adopt::run(&settings, adopt, &config, args.output)
+        }
+        Some(Command::Search { ref pattern }) => commands::search(&settings, pattern, args.output),
+        Some(Command::Tunnel { ref command }) => tunnel::run(&settings, command, args.output),
+        Some(Command::NewHost(ref new_host)) => {
+            return template::new_host(&client, &settings, new_host).map(Outcome::Created)
+        }
+        Some(Command::Hook { ref command }) => hook::run(&settings, command),
+        Some(Command::Dnssec { ref command }) => dnssec::run(&settings, command, args.output),
+        Some(Command::Audit(ref audit)) => audit::run(&settings, audit, args.output),
+        Some(Command::Undo { last, yes }) => journal::undo(&settings, last, yes),
+        _ => Ok(()),
+    };
+    if args.command.is_some() {
+        return done.map(Outcome::from);
+    }
+    settings.require_records()?;
+    activation::check(&settings, args.wait_active)?;
+    if args.daemon {
+        if settings.update_check {
+            version::log_update_check();
         }
+        let mut reconciler = Reconciler::default();
+        let mut dnssec = dnssec::Monitor::default();
+        let mut failover = failover::Monitor::default();
+        let settings = daemon::run(settings, load, |settings, full| {
+            let started = SystemTime::now();
+            let report = reconciler.run(&client, settings, full);
+            after_run(&client, settings, started, &report);
+            dnssec.check(&client, settings);
+            let detected = report.as_ref().ok().and_then(|r| r.current_ip.parse().ok());
+            failover.check(&client, settings, detected);
+            report
+        })?;
+        round_robin::leave(&settings);
+        fleet::leave(&settings);
+        deregister::run(&settings);
+        if let Some(url) = &settings.notify_url {
+            let host = round_robin::host_id();
+            notify::send(
+                &client,
+                url,
+                &notify::Notification::Shutdown { host: &host },
+            );
+        }
+        return Ok(Outcome::Done);
+    }
+    let started = SystemTime::now();
+    let report =
+        token::retry_unauthorized(&mut settings, |settings| sync_records(&client, settings));
+    after_run(&client, &settings, started, &report);
+    let report = report?;
+    let summary = report.summary();
+    match args.output {
+        // Metrics for Telegraf's exec input are all that may go to stdout.
+        _ if settings.influx == Some(influx::Influx::Stdout) => {}
+        output::Format::Table if summary.eventful() => println!("{}", summary),
+        output::Format::Table => {}
+        format => output::print(format, &report.changes)?,
+    }
+    match summary.failed {
+        0 => Ok(Outcome::Synced(summary)),
+        _ => Err(UpdatesFailed(summary).into()),
+    }
+}
+
+/// Writes what the run that started at `started` did wherever the config
+/// says to: its report file, metrics and annotations.
+fn after_run(
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+    started: SystemTime,
+    report: &Result<sync::SyncReport, Error>,
+) {
+    if let Some(path) = &settings.report_file {
+        report::write(path, settings, started, report);
     }
+    if let Some(sink) = &settings.influx {
+        influx::write(
+            client,
+            sink,
+            settings.influx_token.as_ref(),
+            started,
+            report,
+        );
+    }
+    if let Some(statsd) = &settings.statsd {
+        statsd.write(started, report);
+    }
+    if let Some(grafana) = &settings.grafana {
+        grafana.write(client, started, report);
+    }
+}
+
+/// What `args` asks for if it would change anything in Cloudflare.
+fn mutation(args: &Args) -> Option<&'static str> {
+    match &args.command {
+        None if args.daemon => Some("the daemon"),
+        None => Some("syncing"),
+        Some(Command::Purge(_)) => Some("cfdns purge"),
+        Some(Command::Retarget(retarget)) if !retarget.dry_run => Some("cfdns retarget"),
+        Some(Command::NewHost(_)) => Some("cfdns new-host"),
+        Some(Command::Tunnel {
+            command: tunnel::TunnelCommand::Route { .. },
+        }) => Some("cfdns tunnel route"),
+        Some(Command::Hook { .. }) => Some("cfdns hook"),
+        Some(Command::Undo { .. }) => Some("cfdns undo"),
+        _ => None,
+    }
+}
+
+/// Puts the parts of `settings` that hold for the whole process into
+/// effect, at start and on every reload of the daemon.
+fn apply(settings: &Settings) {
+    cloudflare::set_read_only(settings.read_only);
+    frozen::set(settings.frozen.clone());
+    syslog::set(settings.syslog.clone());
+}
 
+/// The HTTP client for the echo services and anything else that isn't the
+/// Cloudflare API, connecting the way the command line asks for.
+fn build_client(args: &Args) -> Result<reqwest::blocking::Client, Error> {
+    Ok(Connection::from_args(args).builder()?.build()?)
+}
+
+/// `cfdns ctl`, only needs the config file to find the socket.
+fn ctl(args: &Args, request: control::Request) -> Result<(), Error> {
+    let file = paths::config_file(args);
+    let configured = Config::load(file.as_deref(), args.profile.as_deref())?.control_socket;
+    let path = config::control_socket_path(args, configured);
+    println!("{}", control::send(&path, request)?);
+    Ok(())
+}
+
+/// `cfdns healthcheck`, asks the daemon for its status over the control socket.
+fn healthcheck(args: &Args, max_age: Duration) -> Result<(), Error> {
+    let file = paths::config_file(args);
+    let configured = Config::load(file.as_deref(), args.profile.as_deref())?.control_socket;
+    let path = config::control_socket_path(args, configured);
+    let status = control::send(&path, control::Request::Status)?;
+    println!("{}", control::health(&status, max_age, SystemTime::now())?);
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::get_current_ip_addr;
+    use crate::cloudflare::CloudflareClient;
+    use crate::{dns_records, get_ip_from, token::Secret, DEFAULT_IP_SOURCE};
 
     struct Setup {
         client: reqwest::blocking::Client,
@@ -226,7 +808,18 @@ mod tests {
     #[test]
     fn test_current_ip() {
         let setup = Setup::new();
-        let current_ip = get_current_ip_addr(&setup.client);
+        let current_ip = get_ip_from(&setup.client, DEFAULT_IP_SOURCE).unwrap();
         assert_ne!(current_ip, "0.0.0.0");
     }
+
+    #[test]
+    fn test_record_iteration_stops_at_an_error() {
+        let builder = reqwest::blocking::Client::builder()
+            .proxy(reqwest::Proxy::all("http://127.0.0.1:9").unwrap());
+        let token = Secret::new("token".to_string());
+        let cloudflare = CloudflareClient::from_builder(builder, &token).unwrap();
+        let mut records = dns_records(&cloudflare, "zone", Some("A"));
+        assert!(records.next().unwrap().is_err());
+        assert!(records.next().is_none());
+    }
 }
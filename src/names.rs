@@ -0,0 +1,122 @@
+//! Record and zone names as Cloudflare has them: lower case ASCII, with
+//! internationalized labels in punycode (`xn--...`) and without a trailing
+//! dot. Names may be given relative to their zone, `vpn` or `@` for
+//! `vpn.example.com` and `example.com` in zone `example.com`.
+//!
+//! Tables and messages show punycode labels decoded, `bücher.example`,
+//! unless `--ascii` is given. JSON and YAML output has names as they are.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{validate, Error};
+
+static ASCII: AtomicBool = AtomicBool::new(false);
+
+/// Whether [`display`] keeps names as Cloudflare has them.
+pub fn set_ascii(ascii: bool) {
+    ASCII.store(ascii, Ordering::Relaxed);
+}
+
+/// `name` for people to read, with punycode labels decoded.
+pub fn display(name: &str) -> String {
+    if ASCII.load(Ordering::Relaxed) || !name.contains("xn--") {
+        return name.to_string();
+    }
+    match idna::domain_to_unicode(name) {
+        (unicode, Ok(())) => unicode,
+        (_, Err(_)) => name.to_string(),
+    }
+}
+
+/// The content of a `record_type` record for people to read, decoded if it
+/// is a host name.
+pub fn display_content(record_type: &str, content: &str) -> String {
+    match validate::holds_host_name(record_type) {
+        true => display(content),
+        false => content.to_string(),
+    }
+}
+
+/// Whether `name`, or how it is displayed, contains `pattern`, ignoring case.
+pub fn matches(name: &str, pattern: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    name.to_lowercase().contains(&pattern) || display(name).to_lowercase().contains(&pattern)
+}
+
+/// `name` in the form Cloudflare uses, e.g. `bücher.example.com.` becomes
+/// `xn--bcher-kva.example.com`.
+pub fn to_ascii(name: &str) -> Result<String, Error> {
+    Ok(cfdns_core::names::to_ascii(name)?)
+}
+
+/// Whether `name` (already [`to_ascii`]) is within `zone` or `zone` itself.
+pub fn in_zone(name: &str, zone: &str) -> bool {
+    name == zone
+        || name
+            .strip_suffix(zone)
+            .is_some_and(|rest| rest.ends_with('.'))
+}
+
+/// `name` qualified with `zone` unless it is a name in it already, or
+/// ended with a dot to mark it as complete.
+pub fn qualify(name: &str, zone: &str) -> Result<String, Error> {
+    let zone = to_ascii(zone)?;
+    if name.trim() == "@" {
+        return Ok(zone);
+    }
+    let absolute = name.trim().ends_with('.');
+    let name = to_ascii(name)?;
+    match absolute || in_zone(&name, &zone) {
+        true => Ok(name),
+        false => Ok(format!("{}.{}", name, zone)),
+    }
+}
+
+/// Whether `name` can only be meant relative to its zone: a single label,
+/// or `@`.
+pub fn is_relative(name: &str) -> bool {
+    let name = name.trim();
+    name == "@" || !name.contains('.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names_are_normalized_and_qualified() {
+        assert_eq!(to_ascii("Home.Example.com.").unwrap(), "home.example.com");
+        assert_eq!(
+            to_ascii("bücher.example.com").unwrap(),
+            "xn--bcher-kva.example.com"
+        );
+        assert_eq!(to_ascii("*.example.com").unwrap(), "*.example.com");
+        assert_eq!(qualify("vpn", "example.com").unwrap(), "vpn.example.com");
+        assert_eq!(
+            qualify("vpn.office", "example.com").unwrap(),
+            "vpn.office.example.com"
+        );
+        assert_eq!(
+            qualify("vpn.example.com", "example.com").unwrap(),
+            "vpn.example.com"
+        );
+        assert_eq!(
+            qualify("vpn.example.net.", "example.com").unwrap(),
+            "vpn.example.net"
+        );
+        assert_eq!(qualify("@", "Example.com").unwrap(), "example.com");
+        assert_eq!(
+            qualify("café", "bücher.de").unwrap(),
+            "xn--caf-dma.xn--bcher-kva.de"
+        );
+        assert!(!in_zone("myexample.com", "example.com"));
+        assert!(is_relative("vpn") && is_relative("@") && !is_relative("vpn.office"));
+        assert!(to_ascii("a b.example.com").is_err());
+
+        assert_eq!(display("www.xn--bcher-kva.example"), "www.bücher.example");
+        assert_eq!(display("home.example.com"), "home.example.com");
+        assert_eq!(display_content("TXT", "xn--bcher-kva"), "xn--bcher-kva");
+        assert!(matches("www.xn--bcher-kva.example", "BÜCHER"));
+        assert!(matches("www.xn--bcher-kva.example", "xn--b"));
+    }
+}
@@ -0,0 +1,101 @@
+//! Webhook notifications about things a human should know about.
+
+#[cfg(feature = "notify")]
+use log::{info, warn};
+use serde_derive::Serialize;
+
+#[cfg(feature = "notify")]
+use crate::events;
+use crate::sync::Summary;
+#[cfg(feature = "notify")]
+use crate::trace::Traced;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Notification<'a> {
+    /// A managed record was changed by someone else and cfdns put it back
+    Reverted {
+        zone: &'a str,
+        name: &'a str,
+        found: &'a str,
+        restored: &'a str,
+    },
+    /// The detected IP is outside `allowed_ranges`, nothing was updated
+    Disallowed { ip: &'a str },
+    /// A record hit `max_updates_per_hour` and was not updated
+    RateLimited {
+        zone: &'a str,
+        name: &'a str,
+        published: &'a str,
+        detected: &'a str,
+        updates: usize,
+    },
+    /// DNSSEC of a zone stopped validating, resolvers will refuse its records
+    Dnssec {
+        zone: &'a str,
+        status: &'a str,
+        problem: &'a str,
+    },
+    /// The primary of a failover pair failed its checks, the secondary is
+    /// published instead
+    FailedOver {
+        zone: &'a str,
+        name: &'a str,
+        primary: &'a str,
+        secondary: &'a str,
+        problem: &'a str,
+    },
+    /// The primary of a failover pair passes its check again and is back
+    Recovered {
+        zone: &'a str,
+        name: &'a str,
+        primary: &'a str,
+    },
+    /// What a run did, with `notify_summary` on and something changed or failed
+    Summary(&'a Summary),
+    /// The daemon on `host` stopped gracefully, after its last run
+    Shutdown { host: &'a str },
+}
+
+/// POSTs `notification` as JSON to `url`. Failures are only logged, a broken
+/// webhook must not stop records from being kept up to date.
+#[cfg(feature = "notify")]
+pub fn send(client: &reqwest::blocking::Client, url: &str, notification: &Notification) {
+    let body = serde_json::to_string(notification).expect("notifications are serializable");
+    let result = client
+        .post(url)
+        .header("Content-Type", "application/json")
+        .body(body)
+        .send_traced()
+        .and_then(|response| response.error_for_status());
+    match result {
+        Ok(_) => {
+            info!("Sent notification to {}", url);
+            let value = serde_json::to_value(notification).expect("notifications are serializable");
+            events::emit(&events::Event::NotificationSent {
+                url,
+                notification: value["event"].as_str().unwrap_or_default(),
+            });
+        }
+        Err(e) => warn!("Could not send notification to {}: {}", url, e),
+    }
+}
+
+/// Without the `notify` feature a `notify_url` is refused when the config is
+/// loaded, so there is never anything to send.
+#[cfg(not(feature = "notify"))]
+pub fn send(_: &reqwest::blocking::Client, _: &str, _: &Notification) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shutdown_names_the_host() {
+        let notification = Notification::Shutdown { host: "nas" };
+        assert_eq!(
+            serde_json::to_string(&notification).unwrap(),
+            r#"{"event":"shutdown","host":"nas"}"#
+        );
+    }
+}
@@ -0,0 +1,188 @@
+//! Shared rendering of command output as a table, JSON or YAML.
+//!
+//! Tables are colored when stdout is a terminal, unless `NO_COLOR` is set.
+
+use std::env;
+use std::io::IsTerminal;
+
+use clap::ValueEnum;
+use comfy_table::{presets, Cell, Color, ContentArrangement, Table};
+use serde::Serialize;
+
+use crate::Error;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Human readable table
+    #[default]
+    Table,
+    Json,
+    Yaml,
+}
+
+/// Colors used across commands: green for additions and things that are in
+/// order, yellow for changes, red for deletions and problems.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Highlight {
+    Green,
+    Yellow,
+    Red,
+}
+
+impl Highlight {
+    fn color(self) -> Color {
+        match self {
+            Highlight::Green => Color::Green,
+            Highlight::Yellow => Color::Yellow,
+            Highlight::Red => Color::Red,
+        }
+    }
+}
+
+/// Something that can be shown as a row of a table.
+pub trait Tabular: Serialize {
+    fn headers() -> Vec<&'static str>;
+    fn row(&self) -> Vec<String>;
+
+    /// Per column highlight, columns without an entry stay uncolored.
+    fn highlights(&self) -> Vec<Option<Highlight>> {
+        Vec::new()
+    }
+}
+
+/// Whether output should use colors: only on a terminal and only when the
+/// `NO_COLOR` convention does not ask otherwise.
+pub fn colors_enabled() -> bool {
+    let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    !no_color && std::io::stdout().is_terminal()
+}
+
+/// Renders `items` in the requested format, ready to be printed.
+pub fn render<T: Tabular>(format: Format, items: &[T]) -> Result<String, Error> {
+    Ok(match format {
+        Format::Table => table(
+            T::headers(),
+            items.iter().map(|item| (item.row(), item.highlights())),
+        ),
+        Format::Json => serde_json::to_string_pretty(items)?,
+        Format::Yaml => serde_yaml::to_string(items)?.trim_end().to_string(),
+    })
+}
+
+/// A column of `T` that can be picked at runtime.
+pub trait Selectable<T> {
+    fn header(&self) -> &'static str;
+    /// Key of the column in the serialized `T`
+    fn field(&self) -> &'static str;
+    fn show(&self, item: &T) -> String;
+}
+
+/// Renders only `columns` of `items`, both as a table and as JSON/YAML.
+pub fn render_columns<T, C>(format: Format, items: &[T], columns: &[C]) -> Result<String, Error>
+where
+    T: Serialize,
+    C: Selectable<T>,
+{
+    if format == Format::Table {
+        let headers = columns.iter().map(|column| column.header()).collect();
+        let rows = items.iter().map(|item| {
+            let row = columns.iter().map(|column| column.show(item)).collect();
+            (row, Vec::new())
+        });
+        return Ok(table(headers, rows));
+    }
+
+    let mut selected = Vec::new();
+    for item in items {
+        let value = serde_json::to_value(item)?;
+        let fields: serde_json::Map<String, serde_json::Value> = columns
+            .iter()
+            .map(|column| (column.field().to_string(), value[column.field()].clone()))
+            .collect();
+        selected.push(fields);
+    }
+    Ok(match format {
+        Format::Yaml => serde_yaml::to_string(&selected)?.trim_end().to_string(),
+        _ => serde_json::to_string_pretty(&selected)?,
+    })
+}
+
+fn table<I>(headers: Vec<&str>, rows: I) -> String
+where
+    I: Iterator<Item = (Vec<String>, Vec<Option<Highlight>>)>,
+{
+    let colors = colors_enabled();
+    let mut table = Table::new();
+    table
+        .load_preset(presets::UTF8_FULL_CONDENSED)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(headers);
+    if colors {
+        table.enforce_styling();
+    } else {
+        table.force_no_tty();
+    }
+    for (row, highlights) in rows {
+        table.add_row(row.into_iter().enumerate().map(|(column, text)| {
+            match highlights.get(column).copied().flatten() {
+                Some(highlight) if colors => Cell::new(text).fg(highlight.color()),
+                _ => Cell::new(text),
+            }
+        }));
+    }
+    table.to_string()
+}
+
+/// Prints `items` in the requested format.
+pub fn print<T: Tabular>(format: Format, items: &[T]) -> Result<(), Error> {
+    println!("{}", render(format, items)?);
+    Ok(())
+}
+
+/// Cloudflare uses a TTL of 1 for "automatic".
+pub fn ttl(ttl: i64) -> String {
+    match ttl {
+        1 => "auto".to_string(),
+        ttl => ttl.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_derive::Serialize;
+
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Row {
+        name: &'static str,
+        ttl: i64,
+    }
+
+    impl Tabular for Row {
+        fn headers() -> Vec<&'static str> {
+            vec!["NAME", "TTL"]
+        }
+
+        fn row(&self) -> Vec<String> {
+            vec![self.name.to_string(), ttl(self.ttl)]
+        }
+    }
+
+    #[test]
+    fn test_render_formats() {
+        let rows = [Row {
+            name: "home.example.com",
+            ttl: 1,
+        }];
+        let table = render(Format::Table, &rows).unwrap();
+        assert!(table.contains("home.example.com") && table.contains("auto"));
+        assert_eq!(
+            render(Format::Yaml, &rows).unwrap(),
+            "- name: home.example.com\n  ttl: 1"
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&render(Format::Json, &rows).unwrap()).unwrap();
+        assert_eq!(json[0]["name"], "home.example.com");
+    }
+}
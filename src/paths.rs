@@ -0,0 +1,236 @@
+//! Where cfdns looks for its config file and keeps its state and caches,
+//! following the XDG base directories on Linux and the platform's own
+//! conventions on macOS and Windows.
+//!
+//! | | Linux | macOS | Windows |
+//! |---|---|---|---|
+//! | config | `$XDG_CONFIG_HOME/cfdns` | `~/Library/Application Support/cfdns` | `%APPDATA%\cfdns` |
+//! | state | `$XDG_STATE_HOME/cfdns` | `~/Library/Application Support/cfdns` | `%LOCALAPPDATA%\cfdns` |
+//! | cache | `$XDG_CACHE_HOME/cfdns` | `~/Library/Caches/cfdns` | `%LOCALAPPDATA%\cfdns\cache` |
+//!
+//! The XDG variables are honored on every platform when set.
+
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use serde_derive::Serialize;
+
+use crate::config::{control_socket_path, Config};
+use crate::output::{self, Format, Tabular};
+use crate::{deregister, guard, ids, journal, published, Args, Error};
+
+const APP: &str = "cfdns";
+
+fn home() -> PathBuf {
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+        .unwrap_or_else(env::temp_dir)
+}
+
+/// `$<variable>/cfdns` if set, else the platform's directory.
+fn base(variable: &str, platform: impl FnOnce() -> PathBuf) -> PathBuf {
+    match env::var_os(variable).filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir).join(APP),
+        None => platform(),
+    }
+}
+
+fn windows_dir(variable: &str) -> PathBuf {
+    env::var_os(variable)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| home().join("AppData").join("Local"))
+        .join(APP)
+}
+
+pub fn config_dir() -> PathBuf {
+    base("XDG_CONFIG_HOME", || match env::consts::OS {
+        "macos" => home().join("Library/Application Support").join(APP),
+        "windows" => windows_dir("APPDATA"),
+        _ => home().join(".config").join(APP),
+    })
+}
+
+pub fn state_dir() -> PathBuf {
+    base("XDG_STATE_HOME", || match env::consts::OS {
+        "macos" => home().join("Library/Application Support").join(APP),
+        "windows" => windows_dir("LOCALAPPDATA"),
+        _ => home().join(".local/state").join(APP),
+    })
+}
+
+pub fn cache_dir() -> PathBuf {
+    base("XDG_CACHE_HOME", || match env::consts::OS {
+        "macos" => home().join("Library/Caches").join(APP),
+        "windows" => windows_dir("LOCALAPPDATA").join("cache"),
+        _ => home().join(".cache").join(APP),
+    })
+}
+
+/// `config.toml` in the config directory, read when no `--config` is given.
+pub fn default_config() -> PathBuf {
+    config_dir().join("config.toml")
+}
+
+/// `--config`, or the default config file if there is one.
+pub fn config_file(args: &Args) -> Option<PathBuf> {
+    args.config
+        .clone()
+        .or_else(|| Some(default_config()).filter(|path| path.is_file()))
+}
+
+/// Writes `contents` to `path`, creating its directory first.
+pub fn write(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, contents)
+}
+
+/// Opens `path` for writing, readable by its owner only on Unix.
+fn create_private(path: &Path) -> io::Result<fs::File> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
+
+/// Writes `contents` to `path` readable by its owner only, in a directory
+/// only its owner may enter, for files that may hold the API token.
+pub fn write_private(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        let mut builder = fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder.create(parent)?;
+        #[cfg(unix)]
+        fs::set_permissions(parent, std::os::unix::fs::PermissionsExt::from_mode(0o700))?;
+    }
+    let mut file = create_private(path)?;
+    // Copies from before are tightened as well.
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    file.write_all(contents.as_ref())
+}
+
+/// Replaces `path` with `contents` by renaming a temporary file over it, so
+/// that it is never left half written. An existing file keeps its
+/// permissions.
+pub fn replace(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!(".{}.tmp", name));
+    let written = create_private(&temp)
+        .and_then(|mut file| {
+            file.write_all(contents.as_ref())?;
+            file.sync_all()
+        })
+        .and_then(|_| match fs::metadata(path) {
+            Ok(metadata) => fs::set_permissions(&temp, metadata.permissions()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+        })
+        .and_then(|_| fs::rename(&temp, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PathRow {
+    pub what: &'static str,
+    pub path: String,
+    pub exists: bool,
+}
+
+impl Tabular for PathRow {
+    fn headers() -> Vec<&'static str> {
+        vec!["WHAT", "PATH", "EXISTS"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.what.to_string(),
+            self.path.clone(),
+            self.exists.to_string(),
+        ]
+    }
+}
+
+/// `cfdns paths`: where everything is read from and written to. Only
+/// needs the config file, not a token.
+pub fn print(args: &Args, format: Format) -> Result<(), Error> {
+    let file = config_file(args);
+    let config = Config::load(file.as_deref(), args.profile.as_deref())?;
+    let paths = [
+        ("config file", file.unwrap_or_else(default_config)),
+        ("state directory", state_dir()),
+        ("cache directory", cache_dir()),
+        (
+            "update history",
+            config
+                .update_history
+                .unwrap_or_else(guard::default_state_path),
+        ),
+        (
+            "journal",
+            config.journal.unwrap_or_else(journal::default_path),
+        ),
+        (
+            "id cache",
+            config.id_cache.unwrap_or_else(ids::default_path),
+        ),
+        ("published ip", published::default_path()),
+        ("deregistered records", deregister::default_path()),
+        (
+            "control socket",
+            control_socket_path(args, config.control_socket),
+        ),
+    ];
+    let rows: Vec<PathRow> = paths
+        .into_iter()
+        .map(|(what, path)| PathRow {
+            what,
+            exists: path.exists(),
+            path: path.display().to_string(),
+        })
+        .collect();
+    output::print(format, &rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_directories_end_in_app_name() {
+        for dir in [config_dir(), state_dir(), cache_dir()] {
+            assert!(dir.components().any(|part| part.as_os_str() == APP));
+        }
+        assert!(default_config().starts_with(config_dir()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_private_files_are_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = env::temp_dir().join(format!("cfdns-private-{}", std::process::id()));
+        let path = dir.join("remote").join("config.toml");
+        write_private(&path, "token = \"cf-token\"").unwrap();
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(mode(path.parent().unwrap()), 0o700);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
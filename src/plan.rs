@@ -0,0 +1,66 @@
+//! Record changes as a change set of JSON Patch (RFC 6902) like operations,
+//! for `cfdns plan` and for the output of a run, so that other tools can see
+//! exactly what cfdns is going to change or did change.
+
+pub use cfdns_core::Operation;
+
+use crate::config::Settings;
+use crate::output::{self, Format, Highlight, Tabular};
+use crate::sync::{attribute_changes, fetch_by_zone, refusal, wanted};
+use crate::{ip, Error};
+
+impl Tabular for Operation {
+    fn headers() -> Vec<&'static str> {
+        vec!["OP", "PATH", "OLD", "VALUE"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.op.to_string(),
+            self.path.clone(),
+            self.old.clone(),
+            self.value.clone(),
+        ]
+    }
+
+    fn highlights(&self) -> Vec<Option<Highlight>> {
+        vec![Some(Highlight::Yellow)]
+    }
+}
+
+/// `cfdns plan`: the changes a run would make to the configured records
+/// right now. Records cfdns may not modify are left out; update windows and
+/// `max_updates_per_hour` may still hold some back.
+pub fn plan(
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+    format: Format,
+) -> Result<(), Error> {
+    settings.require_records()?;
+    let current_ip = ip::detect(client, settings)?;
+    let record_type = ip::record_type(current_ip);
+    let current_ip = current_ip.to_string();
+    let mut operations = Vec::new();
+    for (zone, fetched) in fetch_by_zone(settings, record_type) {
+        for managed in fetched? {
+            let Some(record) = managed.record else {
+                continue;
+            };
+            if refusal(&record, settings).is_some() {
+                continue;
+            }
+            if record.ip_addr != current_ip {
+                operations.push(Operation::content(
+                    &managed.zone,
+                    &managed.name,
+                    record_type,
+                    &record.ip_addr,
+                    &current_ip,
+                ));
+            }
+            let wanted = wanted(zone, &record);
+            operations.extend(attribute_changes(&managed.zone, &record, &wanted));
+        }
+    }
+    output::print(format, &operations)
+}
@@ -0,0 +1,106 @@
+//! Keeping the address of an origin in a Cloudflare Load Balancer pool on
+//! the current IP, for home services fronted by a load balancer.
+
+use std::net::IpAddr;
+
+use log::info;
+use serde_derive::Deserialize;
+use serde_json::{json, Value};
+
+use crate::cloudflare::CloudflareClient;
+use crate::sync::{target_report, RecordReport, SyncState};
+use crate::{send_api, Error};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PoolOriginConfig {
+    pub account_id: String,
+    pub pool_id: String,
+    /// Name of the origin in the pool
+    pub origin: String,
+}
+
+fn pool_url(pool: &PoolOriginConfig) -> String {
+    format!(
+        "/accounts/{}/load_balancers/pools/{}",
+        pool.account_id, pool.pool_id
+    )
+}
+
+/// `origins` with the address of the one called `name` set to `ip`, or
+/// `None` when it already has it.
+fn with_address(origins: &[Value], name: &str, ip: IpAddr) -> Result<Option<Vec<Value>>, Error> {
+    let Some(position) = origins.iter().position(|o| o["name"] == name) else {
+        return Err(format!("the pool has no origin called {}", name).into());
+    };
+    let address = origins[position]["address"].as_str();
+    if address.and_then(|a| a.parse::<IpAddr>().ok()) == Some(ip) {
+        return Ok(None);
+    }
+    let mut origins = origins.to_vec();
+    origins[position]["address"] = json!(ip.to_string());
+    Ok(Some(origins))
+}
+
+fn update(
+    cloudflare: &CloudflareClient,
+    pool: &PoolOriginConfig,
+    ip: IpAddr,
+) -> Result<SyncState, Error> {
+    let url = pool_url(pool);
+    let current = send_api(cloudflare.get(&url), "Load Balancer pool request")?;
+    let origins = current["origins"].as_array().cloned().unwrap_or_default();
+    let Some(origins) = with_address(&origins, &pool.origin, ip)? else {
+        info!("Origin {} already points to {}", pool.origin, ip);
+        return Ok(SyncState::InSync);
+    };
+
+    // Origins are replaced as a whole, so the others are sent back as read.
+    let body = serde_json::to_string(&json!({ "origins": origins }))?;
+    send_api(
+        cloudflare.patch(&url).body(body),
+        "Load Balancer pool update",
+    )?;
+    info!("Updated origin {} to {}", pool.origin, ip);
+    Ok(SyncState::Updated)
+}
+
+/// Brings every configured pool origin in line with `ip`.
+pub fn sync(
+    cloudflare: &CloudflareClient,
+    pools: &[PoolOriginConfig],
+    ip: IpAddr,
+) -> Vec<RecordReport> {
+    pools
+        .iter()
+        .map(|pool| {
+            let name = format!("pool origin: {}", pool.origin);
+            target_report(&pool.pool_id, name, ip, update(cloudflare, pool, ip))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_the_named_origin_changes() {
+        let origins = vec![
+            json!({ "name": "home", "address": "203.0.113.7", "weight": 1 }),
+            json!({ "name": "backup", "address": "198.51.100.1", "weight": 0.5 }),
+        ];
+        let ip: IpAddr = "203.0.113.9".parse().unwrap();
+        let updated = with_address(&origins, "home", ip).unwrap().unwrap();
+        assert_eq!(
+            updated[0],
+            json!({ "name": "home", "address": "203.0.113.9", "weight": 1 })
+        );
+        assert_eq!(updated[1], origins[1]);
+        assert_eq!(
+            with_address(&origins, "backup", "198.51.100.1".parse().unwrap()).unwrap(),
+            None
+        );
+        assert!(with_address(&origins, "office", ip).is_err());
+    }
+}
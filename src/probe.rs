@@ -0,0 +1,163 @@
+//! Checks whether a service answers on an address: `tcp:<port>` connects to
+//! the port, an `http://` or `https://` URL must answer with a success or
+//! redirect status. `{ip}` in the URL is the address checked, so that
+//! `http://{ip}:8080/health` asks the machine itself rather than whatever
+//! DNS has for it.
+//!
+//! From inside the LAN such a check may pass where nobody outside gets
+//! through, e.g. thanks to NAT loopback. A `reachability_checker` URL hands
+//! the check to a service outside instead: it is asked with `ip` and `port`,
+//! or `url`, as query parameters and must answer with a success status, and
+//! `{"reachable": false}` in a JSON answer fails the check too.
+
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde_derive::Deserialize;
+
+use crate::templated::{self, Vars};
+use crate::trace::Traced;
+use crate::Error;
+
+/// How long a check may take before it counts as failed.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Probe {
+    Tcp(u16),
+    Http(String),
+}
+
+impl FromStr for Probe {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        if let Some(port) = text.strip_prefix("tcp:") {
+            return port
+                .parse()
+                .map(Probe::Tcp)
+                .map_err(|_| format!("{:?} is not a port", port).into());
+        }
+        if text.starts_with("http://") || text.starts_with("https://") {
+            templated::check(text)?;
+            return Ok(Probe::Http(text.to_string()));
+        }
+        Err(format!("Checks are tcp:<port> or an http(s):// URL, not {:?}", text).into())
+    }
+}
+
+/// Answer of a `reachability_checker`, anything but JSON counts as reachable.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+struct Verdict {
+    reachable: Option<bool>,
+    error: Option<String>,
+}
+
+/// Fails unless `checker` is a URL the checks can be handed to.
+pub fn validate_checker(checker: &str) -> Result<(), Error> {
+    reqwest::Url::parse(checker)
+        .map(drop)
+        .map_err(|e| format!("reachability_checker {:?} is not a URL: {}", checker, e).into())
+}
+
+impl Probe {
+    /// The request asking `checker` for this check on `ip`.
+    fn checker_url(&self, checker: &str, ip: IpAddr) -> Result<reqwest::Url, Error> {
+        let mut url = reqwest::Url::parse(checker)?;
+        {
+            let mut query = url.query_pairs_mut();
+            query.append_pair("ip", &ip.to_string());
+            match self {
+                Probe::Tcp(port) => query.append_pair("port", &port.to_string()),
+                Probe::Http(target) => {
+                    let target = templated::render(target, &Vars::new(Some(ip)))?
+                        .ok_or_else(|| format!("{} has no address for {}", target, ip))?;
+                    query.append_pair("url", &target)
+                }
+            };
+        }
+        Ok(url)
+    }
+
+    /// Fails unless the service answers on `ip`, as seen from `checker` if
+    /// given.
+    pub fn check_from(
+        &self,
+        client: &reqwest::blocking::Client,
+        checker: Option<&str>,
+        ip: IpAddr,
+    ) -> Result<(), Error> {
+        let Some(checker) = checker else {
+            return self.check(client, ip);
+        };
+        let url = self.checker_url(checker, ip)?;
+        let body = client
+            .get(url.clone())
+            .timeout(TIMEOUT * 3)
+            .send_traced()
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.text())
+            .map_err(|e| format!("reachability checker {}: {}", checker, e))?;
+        match serde_json::from_str::<Verdict>(&body) {
+            Ok(Verdict {
+                reachable: Some(false),
+                error,
+            }) => Err(error
+                .unwrap_or_else(|| format!("{} says it can't reach {}", checker, ip))
+                .into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fails unless the service answers on `ip`.
+    pub fn check(&self, client: &reqwest::blocking::Client, ip: IpAddr) -> Result<(), Error> {
+        match self {
+            Probe::Tcp(port) => {
+                TcpStream::connect_timeout(&SocketAddr::new(ip, *port), TIMEOUT)
+                    .map_err(|e| format!("{} port {}: {}", ip, port, e))?;
+            }
+            Probe::Http(url) => {
+                let url = templated::render(url, &Vars::new(Some(ip)))?
+                    .ok_or_else(|| format!("{} has no address for {}", url, ip))?;
+                client
+                    .get(&url)
+                    .timeout(TIMEOUT)
+                    .send_traced()
+                    .and_then(|response| response.error_for_status())
+                    .map_err(|e| format!("{}: {}", url, e))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn test_tcp_probe_needs_a_listener() {
+        assert_eq!("tcp:443".parse::<Probe>().unwrap(), Probe::Tcp(443));
+        assert!("http://{ip}:8080/health".parse::<Probe>().is_ok());
+        assert!("ping".parse::<Probe>().is_err() && "tcp:https".parse::<Probe>().is_err());
+
+        let client = reqwest::blocking::Client::new();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let localhost = "127.0.0.1".parse().unwrap();
+        assert!(Probe::Tcp(port).check(&client, localhost).is_ok());
+        drop(listener);
+        assert!(Probe::Tcp(port).check(&client, localhost).is_err());
+
+        let url = Probe::Tcp(443)
+            .checker_url("https://check.example.net/probe?key=k", localhost)
+            .unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://check.example.net/probe?key=k&ip=127.0.0.1&port=443"
+        );
+    }
+}
@@ -0,0 +1,95 @@
+//! Progress of fetching many zones at once. On a terminal it is a single
+//! line on stderr, redrawn as zones complete; otherwise, as in cron jobs and
+//! containers, each zone is logged as it completes.
+
+use std::io::{self, IsTerminal, Write};
+use std::sync::Mutex;
+
+use log::info;
+
+const WIDTH: usize = 30;
+
+pub struct Progress {
+    total: usize,
+    /// Whether to draw the bar rather than log
+    bar: bool,
+    done: Mutex<(usize, usize)>,
+}
+
+impl Progress {
+    /// Progress over `total` zones, drawn as a bar if there are several and
+    /// stderr is a terminal.
+    pub fn new(total: usize) -> Progress {
+        let progress = Progress {
+            total,
+            bar: total > 1 && io::stderr().is_terminal(),
+            done: Mutex::new((0, 0)),
+        };
+        progress.draw(0, 0);
+        progress
+    }
+
+    /// Records that `zone` completed with `records` records fetched.
+    pub fn zone_done(&self, zone: &str, records: usize) {
+        let mut done = self.done.lock().unwrap();
+        done.0 += 1;
+        done.1 += records;
+        match self.bar {
+            true => self.draw(done.0, done.1),
+            false => info!("Looked up {} records of zone {}", records, zone),
+        }
+    }
+
+    fn draw(&self, zones: usize, records: usize) {
+        if self.bar {
+            eprint!("\r{}", line(zones, self.total, records));
+            let _ = io::stderr().flush();
+        }
+    }
+
+    /// Clears the bar, so that what is printed next starts on a clean line.
+    pub fn finish(&self) {
+        if self.bar {
+            eprint!("\r{}\r", " ".repeat(WIDTH + 40));
+            let _ = io::stderr().flush();
+        }
+    }
+}
+
+/// The bar for `zones` of `total` zones done.
+fn line(zones: usize, total: usize, records: usize) -> String {
+    let filled = (WIDTH * zones).checked_div(total).unwrap_or(WIDTH);
+    format!(
+        "[{}{}] {}/{} zones, {} records",
+        "#".repeat(filled),
+        "-".repeat(WIDTH - filled),
+        zones,
+        total,
+        records
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bar_fills_with_zones() {
+        assert_eq!(
+            line(0, 3, 0),
+            format!("[{}] 0/3 zones, 0 records", "-".repeat(30))
+        );
+        assert_eq!(
+            line(1, 3, 42),
+            format!(
+                "[{}{}] 1/3 zones, 42 records",
+                "#".repeat(10),
+                "-".repeat(20)
+            )
+        );
+        assert_eq!(
+            line(3, 3, 42),
+            format!("[{}] 3/3 zones, 42 records", "#".repeat(30))
+        );
+    }
+}
@@ -0,0 +1,146 @@
+//! Checking that updated records are really served by public resolvers,
+//! not just accepted by the API.
+
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+use crate::config::Settings;
+use crate::dns::{self, Resolver};
+use crate::jitter;
+use crate::sync::run_bounded;
+
+pub const DEFAULT_RESOLVERS: [&str; 3] = ["1.1.1.1", "8.8.8.8", "9.9.9.9"];
+
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+const RETRY_EVERY: Duration = Duration::from_secs(5);
+
+/// A record that was just updated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expected {
+    pub name: String,
+    pub record_type: &'static str,
+    pub content: IpAddr,
+}
+
+/// The configured resolvers, or only the DoH endpoint when `doh` is set.
+pub fn resolvers(settings: &Settings) -> Vec<Resolver> {
+    match &settings.doh {
+        Some(url) => vec![Resolver::Https(url.clone())],
+        None => settings
+            .resolvers
+            .iter()
+            .copied()
+            .map(Resolver::Udp)
+            .collect(),
+    }
+}
+
+/// Whether `resolver` has `expected` by now.
+fn visible(
+    client: &reqwest::blocking::Client,
+    resolver: &Resolver,
+    expected: &Expected,
+) -> Result<bool, String> {
+    let qtype = dns::query_type(expected.record_type);
+    resolver
+        .query(client, &expected.name, qtype, QUERY_TIMEOUT)
+        .map(|answers| answers.contains(&expected.content))
+        .map_err(|e| e.to_string())
+}
+
+/// Asks every resolver in [`resolvers`] for every record until all of
+/// them answer with the new content or `propagation_timeout` runs out, and
+/// logs what each resolver saw. Returns whether everything propagated.
+pub fn verify(
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+    expected: &[Expected],
+) -> bool {
+    let resolvers = resolvers(settings);
+    let deadline = Instant::now() + settings.propagation_timeout;
+    let started = Instant::now();
+    let mut pending: Vec<(&Resolver, &Expected)> = expected
+        .iter()
+        .flat_map(|record| resolvers.iter().map(move |r| (r, record)))
+        .collect();
+    let mut last_errors: Vec<Option<String>> = vec![None; pending.len()];
+
+    while !pending.is_empty() {
+        let results = run_bounded(pending.len(), &pending, |(resolver, record)| {
+            visible(client, resolver, record)
+        });
+        let mut still_pending = Vec::new();
+        let mut errors = Vec::new();
+        for (((resolver, record), result), previous) in
+            pending.into_iter().zip(results).zip(last_errors)
+        {
+            match result {
+                Ok(true) => info!(
+                    "{} serves {} {} after {}s",
+                    resolver,
+                    record.name,
+                    record.content,
+                    started.elapsed().as_secs()
+                ),
+                Ok(false) => {
+                    still_pending.push((resolver, record));
+                    errors.push(previous);
+                }
+                Err(e) => {
+                    still_pending.push((resolver, record));
+                    errors.push(Some(e));
+                }
+            }
+        }
+        pending = still_pending;
+        last_errors = errors;
+        if pending.is_empty() || Instant::now() + RETRY_EVERY > deadline {
+            break;
+        }
+        std::thread::sleep(jitter::spread(RETRY_EVERY, settings.jitter));
+    }
+
+    for ((resolver, record), error) in pending.iter().zip(&last_errors) {
+        match error {
+            Some(e) => warn!("Could not check {} at {}: {}", record.name, resolver, e),
+            None => warn!(
+                "{} still does not serve {} {} after {}",
+                resolver,
+                record.name,
+                record.content,
+                humantime::format_duration(settings.propagation_timeout)
+            ),
+        }
+    }
+    pending.is_empty()
+}
+
+/// Resolver addresses as written in the config file, the port defaulting
+/// to 53.
+pub fn parse_resolver(text: &str) -> Result<SocketAddr, String> {
+    text.parse::<SocketAddr>()
+        .or_else(|_| text.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+        .map_err(|_| format!("{} is not a resolver address", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_resolver() {
+        assert_eq!(
+            parse_resolver("9.9.9.9").unwrap(),
+            "9.9.9.9:53".parse().unwrap()
+        );
+        assert_eq!(
+            parse_resolver("[2606:4700:4700::1111]:5353")
+                .unwrap()
+                .port(),
+            5353
+        );
+        assert!(parse_resolver("dns.google").is_err());
+    }
+}
@@ -0,0 +1,189 @@
+//! The IP the last fully successful run published, kept in the cache
+//! directory for `--from-cache`: while the detected IP is still that one,
+//! and the config names the same targets, Cloudflare isn't asked at all.
+//! `--refresh-cache` ignores this and the ID cache for one run.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use log::warn;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::config::Settings;
+use crate::ip;
+use crate::paths;
+use crate::sync::{RecordReport, SyncReport, SyncState};
+
+/// `published.json` in the cache directory.
+pub fn default_path() -> PathBuf {
+    paths::cache_dir().join("published.json")
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Published {
+    ip: String,
+    /// Of everything that decides what a run touches, to notice config changes
+    targets: u64,
+    records: Vec<(String, String)>,
+}
+
+/// By record type, as `-4` and `-6` runs publish different addresses.
+type Stored = HashMap<String, Published>;
+
+/// Changes whenever the configured zones, records or other targets do.
+fn targets(settings: &Settings) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    format!(
+        "{:?} {:?} {:?} {:?}",
+        settings.zones, settings.ip_lists, settings.access_policies, settings.pool_origins
+    )
+    .hash(&mut hasher);
+    hasher.finish()
+}
+
+fn load() -> Stored {
+    fs::read_to_string(default_path())
+        .ok()
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or_default()
+}
+
+fn cached(stored: &Stored, settings: &Settings, current_ip: IpAddr) -> Option<SyncReport> {
+    let published = stored.get(ip::record_type(current_ip))?;
+    if published.ip != current_ip.to_string() || published.targets != targets(settings) {
+        return None;
+    }
+    let records = published
+        .records
+        .iter()
+        .map(|(zone, name)| RecordReport {
+            zone: zone.clone(),
+            name: name.clone(),
+            content: Some(published.ip.clone()),
+            state: SyncState::InSync,
+            error: None,
+        })
+        .collect();
+    Some(SyncReport {
+        current_ip: published.ip.clone(),
+        records,
+        changes: Vec::new(),
+    })
+}
+
+/// An all in sync report when `--from-cache` is on and the last run
+/// published `current_ip` for the same config.
+pub fn report(settings: &Settings, current_ip: IpAddr) -> Option<SyncReport> {
+    match settings.from_cache && !settings.refresh_cache {
+        true => cached(&load(), settings, current_ip),
+        false => None,
+    }
+}
+
+fn remember(stored: &mut Stored, settings: &Settings, report: &SyncReport) {
+    let Ok(current_ip) = report.current_ip.parse::<IpAddr>() else {
+        return;
+    };
+    let record_type = ip::record_type(current_ip);
+    let done = report.records.iter().all(|r| {
+        matches!(
+            r.state,
+            SyncState::InSync | SyncState::Updated | SyncState::Reverted
+        )
+    });
+    if !done {
+        stored.remove(record_type);
+        return;
+    }
+    let published = Published {
+        ip: report.current_ip.clone(),
+        targets: targets(settings),
+        records: report
+            .records
+            .iter()
+            .map(|r| (r.zone.clone(), r.name.clone()))
+            .collect(),
+    };
+    stored.insert(record_type.to_string(), published);
+}
+
+/// Remembers what `report` published, or forgets it unless every record
+/// ended up with the current IP.
+pub fn save(settings: &Settings, report: &SyncReport) {
+    let mut stored = load();
+    let before = stored.clone();
+    remember(&mut stored, settings, report);
+    if stored == before {
+        return;
+    }
+    let path = default_path();
+    let text = serde_json::to_string(&stored).expect("published IPs are serializable");
+    if let Err(e) = paths::write(&path, text) {
+        warn!(
+            "Could not save the published IP to {}: {}",
+            path.display(),
+            e
+        );
+    }
+}
+
+/// Forgets every published IP, after the records were changed otherwise.
+pub fn forget() {
+    let path = default_path();
+    if let Err(e) = fs::remove_file(&path) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            warn!("Could not remove {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ZoneConfig;
+
+    #[test]
+    fn test_cache_needs_same_ip_and_config() {
+        let mut settings = Settings {
+            zones: vec![ZoneConfig {
+                id: "z".to_string(),
+                records: vec!["home.example.com".to_string()],
+                ..ZoneConfig::default()
+            }],
+            ..Settings::default()
+        };
+        let record = |state| RecordReport {
+            zone: "z".to_string(),
+            name: "home.example.com".to_string(),
+            content: Some("93.184.216.34".to_string()),
+            state,
+            error: None,
+        };
+        let mut report = SyncReport {
+            current_ip: "93.184.216.34".to_string(),
+            records: vec![record(SyncState::Updated)],
+            changes: Vec::new(),
+        };
+        let mut stored = Stored::default();
+        remember(&mut stored, &settings, &report);
+
+        let ip: IpAddr = "93.184.216.34".parse().unwrap();
+        let hit = cached(&stored, &settings, ip).unwrap();
+        assert_eq!(hit.records, vec![record(SyncState::InSync)]);
+        assert!(cached(&stored, &settings, "93.184.216.35".parse().unwrap()).is_none());
+        assert!(cached(&stored, &settings, "2606:4700::1".parse().unwrap()).is_none());
+
+        settings.zones[0]
+            .records
+            .push("vpn.example.com".to_string());
+        assert!(cached(&stored, &settings, ip).is_none());
+
+        report.records = vec![record(SyncState::Failed)];
+        remember(&mut stored, &settings, &report);
+        assert!(stored.is_empty());
+    }
+}
@@ -0,0 +1,138 @@
+//! `cfdns purge`: deletes records that point at old addresses and have not
+//! been touched for a while.
+
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime};
+
+use ipnet::IpNet;
+use log::{error, info};
+
+use crate::cloudflare::models::Record;
+use crate::commands::Column;
+use crate::config::Settings;
+use crate::confirm::confirm;
+use crate::output::{self, Format};
+use crate::sync::{may_modify, run_bounded};
+use crate::{delete_record, dns_records, Error};
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct PurgeArgs {
+    /// Address or CIDR range the records point to, may be given multiple times
+    #[arg(long, required = true, value_parser = parse_net)]
+    pointing_to: Vec<IpNet>,
+
+    /// Only records last modified longer ago than this, e.g. 90d
+    #[arg(long, value_parser = humantime::parse_duration)]
+    older_than: Option<Duration>,
+
+    /// Delete without asking for confirmation
+    #[arg(short, long)]
+    yes: bool,
+}
+
+/// Accepts plain addresses as single host networks.
+fn parse_net(text: &str) -> Result<IpNet, String> {
+    text.parse::<IpNet>()
+        .or_else(|_| text.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| format!("{} is neither an IP address nor a CIDR range", text))
+}
+
+impl PurgeArgs {
+    fn matches(&self, record: &Record, now: SystemTime) -> bool {
+        let Ok(address) = record.ip_addr.parse::<IpAddr>() else {
+            return false;
+        };
+        if !self.pointing_to.iter().any(|net| net.contains(&address)) {
+            return false;
+        }
+        match self.older_than {
+            Some(age) => humantime::parse_rfc3339(&record.modified_on)
+                .is_ok_and(|modified| modified + age <= now),
+            None => true,
+        }
+    }
+}
+
+pub fn run(settings: &Settings, args: &PurgeArgs) -> Result<(), Error> {
+    if settings.zones.is_empty() {
+        return Err("No zone to purge, pass --zone or a --config file".into());
+    }
+
+    let now = SystemTime::now();
+    let mut stale: Vec<(String, Record)> = Vec::new();
+    for zone in &settings.zones {
+        for record in dns_records(settings.client(&zone.id), &zone.id, None) {
+            let record = record?;
+            if args.matches(&record, now) && may_modify(&record, settings) {
+                stale.push((zone.id.clone(), record));
+            }
+        }
+    }
+    if stale.is_empty() {
+        info!("No stale records found");
+        return Ok(());
+    }
+
+    let records: Vec<Record> = stale.iter().map(|(_, record)| record.clone()).collect();
+    let columns = [
+        Column::Zone,
+        Column::Name,
+        Column::Type,
+        Column::Content,
+        Column::Modified,
+    ];
+    println!(
+        "{}",
+        output::render_columns(Format::Table, &records, &columns)?
+    );
+    if !confirm(&format!("Delete these {} records?", stale.len()), args.yes)? {
+        info!("Nothing deleted");
+        return Ok(());
+    }
+
+    let results = run_bounded(settings.parallelism, &stale, |(zone, record)| {
+        delete_record(settings.client(zone), record, zone)
+    });
+    let failed = results.iter().filter(|result| result.is_err()).count();
+    for e in results.iter().filter_map(|result| result.as_ref().err()) {
+        error!("{}", e);
+    }
+    info!(
+        "Deleted {} of {} records",
+        stale.len() - failed,
+        stale.len()
+    );
+    match failed {
+        0 => Ok(()),
+        failed => Err(format!("{} record deletion(s) failed", failed).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_purge_matches_range_and_age() {
+        let args = PurgeArgs {
+            pointing_to: vec![
+                parse_net("203.0.113.0/24").unwrap(),
+                parse_net("2001:db8::1").unwrap(),
+            ],
+            older_than: Some(humantime::parse_duration("90d").unwrap()),
+            yes: true,
+        };
+        let now = humantime::parse_rfc3339("2023-06-01T00:00:00Z").unwrap();
+        let record = |content: &str, modified: &str| Record {
+            ip_addr: content.to_string(),
+            modified_on: modified.to_string(),
+            ..Default::default()
+        };
+
+        assert!(args.matches(&record("203.0.113.9", "2023-01-02T10:00:00.123456Z"), now));
+        assert!(args.matches(&record("2001:db8::1", "2023-01-02T10:00:00Z"), now));
+        assert!(!args.matches(&record("203.0.113.9", "2023-05-20T10:00:00Z"), now));
+        assert!(!args.matches(&record("198.51.100.1", "2023-01-02T10:00:00Z"), now));
+        assert!(!args.matches(&record("example.com", "2023-01-02T10:00:00Z"), now));
+    }
+}
@@ -0,0 +1,112 @@
+//! Config files fetched from `http(s)://` URLs, or `s3://` ones with the
+//! `s3` feature, so a fleet can share a centrally managed config. They are
+//! fetched again on every load, which includes reloads, and the last copy
+//! fetched is kept in the cache directory for when the server can't be
+//! reached.
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::{info, warn};
+
+use crate::trace::Traced;
+use crate::{paths, Error};
+
+/// Header sent along with config requests, e.g. `Authorization: Bearer ...`.
+pub const HEADER_VARIABLE: &str = "CFDNS_CONFIG_HEADER";
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+pub fn is_remote(path: &Path) -> bool {
+    let text = path.to_string_lossy();
+    ["http://", "https://", "s3://"]
+        .iter()
+        .any(|scheme| text.starts_with(scheme))
+}
+
+/// Where the last copy of `url` is kept, named after it so that the file
+/// extension still tells the format.
+fn cached_copy(url: &str) -> PathBuf {
+    let name: String = url
+        .chars()
+        .map(
+            |c| match c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                true => c,
+                false => '_',
+            },
+        )
+        .collect();
+    paths::cache_dir().join("remote").join(name)
+}
+
+fn download(url: &str) -> Result<String, Error> {
+    if url.starts_with("s3://") {
+        return download_s3(url);
+    }
+    let client = reqwest::blocking::Client::builder()
+        .timeout(TIMEOUT)
+        .build()?;
+    let mut request = client.get(url);
+    if let Ok(header) = env::var(HEADER_VARIABLE) {
+        let Some((name, value)) = header.split_once(':') else {
+            return Err(format!("{} must look like `Name: value`", HEADER_VARIABLE).into());
+        };
+        request = request.header(name.trim(), value.trim());
+    }
+    let text = request
+        .send_traced()
+        .and_then(|response| response.error_for_status())
+        .and_then(|response| response.text())?;
+    Ok(text)
+}
+
+#[cfg(feature = "s3")]
+fn download_s3(url: &str) -> Result<String, Error> {
+    let mut command = std::process::Command::new("aws");
+    command.args(["s3", "cp", url, "-"]);
+    crate::secrets::output_of(&mut command)
+}
+
+#[cfg(not(feature = "s3"))]
+fn download_s3(_: &str) -> Result<String, Error> {
+    Err("s3:// configs need cfdns built with the `s3` feature".into())
+}
+
+/// Fetches the config at `url` and returns the local copy to read, or the
+/// previous copy if fetching fails.
+pub fn fetch(url: &Path) -> Result<PathBuf, Error> {
+    let url = url.to_string_lossy();
+    let copy = cached_copy(&url);
+    match download(&url) {
+        Ok(text) => {
+            paths::write_private(&copy, text)
+                .map_err(|e| format!("could not save {} to {}: {}", url, copy.display(), e))?;
+            info!("Fetched config from {}", url);
+            Ok(copy)
+        }
+        Err(e) if copy.is_file() => {
+            warn!("Could not fetch {}, using the copy from before: {}", url, e);
+            Ok(copy)
+        }
+        Err(e) => Err(format!("could not fetch config from {}: {}", url, e).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remote_configs_are_cached_by_url() {
+        assert!(is_remote(Path::new("https://config.internal/cfdns.toml")));
+        assert!(is_remote(Path::new("s3://fleet/cfdns.yaml")));
+        assert!(!is_remote(Path::new("/etc/cfdns.toml")));
+        let copy = cached_copy("https://config.internal/cfdns.toml");
+        assert_eq!(
+            copy.file_name().unwrap(),
+            "https___config.internal_cfdns.toml"
+        );
+        assert_eq!(copy.extension().unwrap(), "toml");
+    }
+}
@@ -0,0 +1,140 @@
+//! `--report-file`: a JSON report of each sync run, with what it was asked
+//! to do, the IP it detected, what happened to every record and how long it
+//! took, for archiving or for pipelines that attach it to a PR or ticket.
+//! The daemon rewrites the file after every run.
+
+use std::path::Path;
+use std::time::SystemTime;
+
+use log::warn;
+use serde_derive::Serialize;
+
+use crate::config::Settings;
+use crate::plan::Operation;
+use crate::sync::{RecordReport, Summary, SyncReport};
+use crate::{paths, version, Error};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct Report<'a> {
+    version: &'static str,
+    started: String,
+    finished: String,
+    duration_ms: u128,
+    inputs: Inputs<'a>,
+    detected_ip: Option<&'a str>,
+    records: &'a [RecordReport],
+    changes: &'a [Operation],
+    summary: Option<Summary>,
+    /// Why the run failed as a whole, e.g. the IP couldn't be detected
+    error: Option<String>,
+}
+
+/// What the run was asked to keep up to date.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct Inputs<'a> {
+    zones: Vec<ZoneInput<'a>>,
+    ip_sources: &'a [String],
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct ZoneInput<'a> {
+    id: &'a str,
+    records: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    account: Option<&'a str>,
+}
+
+fn report<'a>(
+    settings: &'a Settings,
+    started: SystemTime,
+    finished: SystemTime,
+    result: &'a Result<SyncReport, Error>,
+) -> Report<'a> {
+    let (detected_ip, records, changes, summary, error) = match result {
+        Ok(run) => (
+            Some(run.current_ip.as_str()),
+            run.records.as_slice(),
+            run.changes.as_slice(),
+            Some(run.summary()),
+            None,
+        ),
+        Err(e) => (None, &[][..], &[][..], None, Some(e.to_string())),
+    };
+    Report {
+        version: version::VERSION,
+        started: humantime::format_rfc3339_millis(started).to_string(),
+        finished: humantime::format_rfc3339_millis(finished).to_string(),
+        duration_ms: finished
+            .duration_since(started)
+            .unwrap_or_default()
+            .as_millis(),
+        inputs: Inputs {
+            zones: settings
+                .zones
+                .iter()
+                .map(|zone| ZoneInput {
+                    id: &zone.id,
+                    records: &zone.records,
+                    account: zone.account.as_deref(),
+                })
+                .collect(),
+            ip_sources: &settings.ip_sources,
+        },
+        detected_ip,
+        records,
+        changes,
+        summary,
+        error,
+    }
+}
+
+/// Writes the report of a run that started at `started` and ended with
+/// `result` to `path`. Failing to is only logged, the run itself is done.
+pub fn write(
+    path: &Path,
+    settings: &Settings,
+    started: SystemTime,
+    result: &Result<SyncReport, Error>,
+) {
+    let report = report(settings, started, SystemTime::now(), result);
+    let text = serde_json::to_string_pretty(&report).expect("reports are serializable");
+    if let Err(e) = paths::write(path, text) {
+        warn!("Could not write the report to {}: {}", path.display(), e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::SyncState;
+    use std::time::Duration;
+
+    #[test]
+    fn test_report_covers_records_and_failures() {
+        let settings = Settings::default();
+        let started = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let finished = started + Duration::from_millis(1250);
+        let run: Result<SyncReport, Error> = Ok(SyncReport {
+            current_ip: "198.51.100.7".to_string(),
+            records: vec![RecordReport {
+                zone: "z".to_string(),
+                name: "home.example.com".to_string(),
+                content: Some("198.51.100.7".to_string()),
+                state: SyncState::Updated,
+                error: None,
+            }],
+            changes: Vec::new(),
+        });
+        let value = serde_json::to_value(report(&settings, started, finished, &run)).unwrap();
+        assert_eq!(value["started"], "2023-11-14T22:13:20.000Z");
+        assert_eq!(value["duration_ms"], 1250);
+        assert_eq!(value["detected_ip"], "198.51.100.7");
+        assert_eq!(value["records"][0]["name"], "home.example.com");
+        assert_eq!(value["summary"]["updated"], 1);
+
+        let failed: Result<SyncReport, Error> = Err("no IP".into());
+        let value = serde_json::to_value(report(&settings, started, finished, &failed)).unwrap();
+        assert_eq!(value["error"], "no IP");
+        assert!(value["detected_ip"].is_null());
+    }
+}
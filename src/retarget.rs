@@ -0,0 +1,190 @@
+//! `cfdns retarget`: rewrites every record that points at an old address,
+//! or at an old CNAME target, to point at a new one, e.g. after a server
+//! moved. The changes are shown as a plan first and only made once
+//! confirmed, or right away with `--yes`.
+
+use std::net::IpAddr;
+
+use log::{error, info};
+
+use crate::cloudflare::models::Record;
+use crate::config::Settings;
+use crate::confirm::confirm;
+use crate::output::{self, Format};
+use crate::plan::Operation;
+use crate::sync::{may_modify, run_bounded};
+use crate::{dns_records, names, replace_record, validate, Error};
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct RetargetArgs {
+    /// Address or host name the records point at now
+    #[arg(long, value_parser = parse_target)]
+    from: Target,
+
+    /// Address or host name they should point at instead
+    #[arg(long, value_parser = parse_target)]
+    to: Target,
+
+    /// Only show the changes, don't make them
+    #[arg(long, conflicts_with = "yes")]
+    pub dry_run: bool,
+
+    /// Make the changes without asking for confirmation
+    #[arg(short, long)]
+    yes: bool,
+}
+
+/// What a record points at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Address(IpAddr),
+    /// A host name as Cloudflare has it, for CNAME, MX and the like
+    Host(String),
+}
+
+fn parse_target(text: &str) -> Result<Target, String> {
+    if let Ok(address) = text.parse::<IpAddr>() {
+        return Ok(Target::Address(address));
+    }
+    match names::to_ascii(text) {
+        Ok(name) if validate::is_host_name(&name) => Ok(Target::Host(name)),
+        _ => Err(format!("{} is neither an IP address nor a host name", text)),
+    }
+}
+
+impl Target {
+    fn matches(&self, record: &Record) -> bool {
+        match self {
+            Target::Address(address) => {
+                matches!(record.type_field.as_str(), "A" | "AAAA")
+                    && record.ip_addr.parse::<IpAddr>() == Ok(*address)
+            }
+            Target::Host(name) => {
+                validate::holds_host_name(&record.type_field)
+                    && names::to_ascii(&record.ip_addr).is_ok_and(|content| &content == name)
+            }
+        }
+    }
+
+    fn content(&self) -> String {
+        match self {
+            Target::Address(address) => address.to_string(),
+            Target::Host(name) => name.clone(),
+        }
+    }
+}
+
+/// Fails unless records pointing at `from` can point at `to` instead: an
+/// A record can't hold an IPv6 address, nor a CNAME an address.
+fn check_targets(from: &Target, to: &Target) -> Result<(), Error> {
+    match (from, to) {
+        (Target::Address(from), Target::Address(to)) if from.is_ipv4() != to.is_ipv4() => {
+            Err(format!("{} and {} are of different address families", from, to).into())
+        }
+        (Target::Address(_), Target::Host(_)) | (Target::Host(_), Target::Address(_)) => {
+            Err("--from and --to must both be addresses or both host names".into())
+        }
+        _ if from == to => Err("--from and --to are the same".into()),
+        _ => Ok(()),
+    }
+}
+
+pub fn run(settings: &Settings, args: &RetargetArgs, format: Format) -> Result<(), Error> {
+    check_targets(&args.from, &args.to)?;
+    if settings.zones.is_empty() {
+        return Err("No zone to retarget records in, pass --zone or a --config file".into());
+    }
+
+    let content = args.to.content();
+    let mut changes: Vec<(String, Record, Record)> = Vec::new();
+    for zone in &settings.zones {
+        for record in dns_records(settings.client(&zone.id), &zone.id, None) {
+            let record = record?;
+            if args.from.matches(&record) && may_modify(&record, settings) {
+                let retargeted = Record {
+                    ip_addr: content.clone(),
+                    ..record.clone()
+                };
+                changes.push((zone.id.clone(), record, retargeted));
+            }
+        }
+    }
+    if changes.is_empty() {
+        info!("No records point at {}", args.from.content());
+        return Ok(());
+    }
+
+    let operations: Vec<Operation> = changes
+        .iter()
+        .map(|(zone, record, retargeted)| {
+            Operation::content(
+                zone,
+                &record.name,
+                &record.type_field,
+                &record.ip_addr,
+                &retargeted.ip_addr,
+            )
+        })
+        .collect();
+    output::print(format, &operations)?;
+    if args.dry_run {
+        return Ok(());
+    }
+    if !confirm(
+        &format!("Retarget these {} records?", changes.len()),
+        args.yes,
+    )? {
+        info!("Nothing changed");
+        return Ok(());
+    }
+
+    let results = run_bounded(
+        settings.parallelism,
+        &changes,
+        |(zone, record, retargeted)| {
+            replace_record(settings.client(zone), record, retargeted, zone)
+        },
+    );
+    let failed = results.iter().filter(|result| result.is_err()).count();
+    for e in results.iter().filter_map(|result| result.as_ref().err()) {
+        error!("{}", e);
+    }
+    info!(
+        "Retargeted {} of {} records",
+        changes.len() - failed,
+        changes.len()
+    );
+    match failed {
+        0 => Ok(()),
+        failed => Err(format!("{} record update(s) failed", failed).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records_are_matched_by_address_or_target() {
+        let record = |record_type: &str, content: &str| Record {
+            type_field: record_type.to_string(),
+            ip_addr: content.to_string(),
+            ..Default::default()
+        };
+        let address = parse_target("198.51.100.7").unwrap();
+        assert!(address.matches(&record("A", "198.51.100.7")));
+        assert!(!address.matches(&record("A", "198.51.100.70")));
+        assert!(!address.matches(&record("TXT", "198.51.100.7")));
+        let host = parse_target("Old.Example.com.").unwrap();
+        assert_eq!(host, Target::Host("old.example.com".to_string()));
+        assert!(host.matches(&record("CNAME", "old.example.com")));
+        assert!(!host.matches(&record("TXT", "old.example.com")));
+        assert!(parse_target("not a host").is_err());
+
+        let new_address = parse_target("203.0.113.9").unwrap();
+        assert!(check_targets(&address, &new_address).is_ok());
+        assert!(check_targets(&address, &parse_target("2001:db8::9").unwrap()).is_err());
+        assert!(check_targets(&address, &host).is_err());
+        assert!(check_targets(&host, &host).is_err());
+    }
+}
@@ -0,0 +1,151 @@
+//! DNS round-robin across several hosts: every host running cfdns keeps one
+//! record of its own in the set of A/AAAA records under a shared name, and
+//! the daemon takes it out again when it shuts down.
+//!
+//! A host recognizes its record by a `host=<hostname>` tag in the comment,
+//! next to the ownership marker.
+
+use std::env;
+use std::fs;
+use std::net::IpAddr;
+
+use log::{error, info};
+
+use crate::cloudflare::models::{NewRecord, Record};
+use crate::config::Settings;
+use crate::sync::{target_report, RecordReport, SyncState, OWNERSHIP_MARKER};
+use crate::Error;
+use crate::{create_record, delete_record, ip, list_dns_records, update_record};
+
+/// Name of this host, telling its record apart from the others in a set.
+pub fn host_id() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| fs::read_to_string("/etc/hostname"))
+        .ok()
+        .or_else(|| env::var("HOSTNAME").ok())
+        .or_else(|| env::var("COMPUTERNAME").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn host_tag(host: &str) -> String {
+    format!("host={}", host)
+}
+
+/// Whether `record` is the one `host` keeps in the set called `name`.
+fn is_ours(record: &Record, name: &str, host: &str) -> bool {
+    let tag = host_tag(host);
+    record.name == name
+        && record
+            .comment
+            .as_deref()
+            .is_some_and(|comment| comment.split_whitespace().any(|word| word == tag))
+}
+
+fn join(
+    settings: &Settings,
+    zone: &String,
+    records: &[Record],
+    name: &str,
+    ip: IpAddr,
+) -> Result<SyncState, Error> {
+    let host = host_id();
+    let record_type = ip::record_type(ip);
+    let mut ours = records
+        .iter()
+        .filter(|r| r.type_field == record_type && is_ours(r, name, &host));
+    let state = match ours.next() {
+        Some(record) if record.ip_addr == ip.to_string() => SyncState::InSync,
+        Some(record) => {
+            update_record(settings.client(zone), &ip.to_string(), record, zone)?;
+            SyncState::Updated
+        }
+        None => {
+            let record = NewRecord {
+                type_field: record_type.to_string(),
+                name: name.to_string(),
+                content: ip.to_string(),
+                proxied: false,
+                ttl: 1,
+                comment: Some(format!("{} {}", OWNERSHIP_MARKER, host_tag(&host))),
+            };
+            create_record(settings.client(zone), &record, zone)?;
+            SyncState::Updated
+        }
+    };
+    // Duplicates, e.g. left over from a crash during an update.
+    for record in ours {
+        delete_record(settings.client(zone), record, zone)?;
+    }
+    Ok(state)
+}
+
+/// Makes sure this host has a record with `ip` in every round-robin set.
+pub fn sync(settings: &Settings, ip: IpAddr) -> Vec<RecordReport> {
+    let mut reports = Vec::new();
+    for zone in settings.zones.iter().filter(|z| !z.round_robin.is_empty()) {
+        let records = list_dns_records(settings.client(&zone.id), &zone.id);
+        for name in &zone.round_robin {
+            let result = match &records {
+                Ok(records) => join(settings, &zone.id, records, name, ip),
+                Err(e) => Err(e.to_string().into()),
+            };
+            reports.push(target_report(&zone.id, name.clone(), ip, result));
+        }
+    }
+    reports
+}
+
+/// Removes this host's records from every round-robin set, on shutdown.
+pub fn leave(settings: &Settings) {
+    let host = host_id();
+    for zone in settings.zones.iter().filter(|z| !z.round_robin.is_empty()) {
+        let records = match list_dns_records(settings.client(&zone.id), &zone.id) {
+            Ok(records) => records,
+            Err(e) => {
+                error!("Could not leave the round-robin sets of {}: {}", zone.id, e);
+                continue;
+            }
+        };
+        for record in records
+            .iter()
+            .filter(|r| zone.round_robin.iter().any(|name| is_ours(r, name, &host)))
+        {
+            match delete_record(settings.client(&zone.id), record, &zone.id) {
+                Ok(()) => info!("Left the round-robin set {}", record.name),
+                Err(e) => error!("Could not leave the round-robin set {}: {}", record.name, e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_own_record_is_found_by_host_tag() {
+        let record = |comment: &str| Record {
+            name: "lb.example.com".to_string(),
+            comment: Some(comment.to_string()),
+            ..Default::default()
+        };
+        assert!(is_ours(
+            &record("managed-by=cfdns host=nas"),
+            "lb.example.com",
+            "nas"
+        ));
+        assert!(!is_ours(
+            &record("managed-by=cfdns host=nas2"),
+            "lb.example.com",
+            "nas"
+        ));
+        assert!(!is_ours(
+            &record("managed-by=cfdns host=nas"),
+            "www.example.com",
+            "nas"
+        ));
+        assert!(!host_id().is_empty());
+    }
+}
@@ -0,0 +1,310 @@
+//! Time based rules: cron expressions and `HH:MM-HH:MM` ranges, evaluated
+//! in a configurable timezone.
+//!
+//! Cron expressions have the usual five fields, minute, hour, day of month,
+//! month and day of week (0 or 7 is Sunday), each `*`, a number, a range
+//! `a-b`, a step `*/n` or `a-b/n`, or a comma separated list of those. As in
+//! cron, when both day fields are restricted either one matching is enough.
+
+use std::str::FromStr;
+
+use std::time::Duration;
+
+use chrono::{Datelike, Local, NaiveDateTime, NaiveTime, TimeDelta, Timelike, Utc};
+use chrono_tz::Tz;
+use serde_derive::Deserialize;
+
+use crate::Error;
+
+/// One cron field as the set of values it matches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Field {
+    values: Vec<u32>,
+    /// Written as `*`, which matters for the day fields
+    any: bool,
+}
+
+impl Field {
+    fn parse(text: &str, min: u32, max: u32) -> Result<Field, String> {
+        let mut values = Vec::new();
+        for part in text.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => {
+                    let step: u32 = step.parse().map_err(|_| format!("bad step in {}", part))?;
+                    if step == 0 {
+                        return Err(format!("bad step in {}", part));
+                    }
+                    (range, step)
+                }
+                None => (part, 1),
+            };
+            let number = |n: &str| match n.parse::<u32>() {
+                Ok(n) if (min..=max).contains(&n) => Ok(n),
+                _ => Err(format!("{} is not between {} and {}", n, min, max)),
+            };
+            let (low, high) = match range {
+                "*" => (min, max),
+                range => match range.split_once('-') {
+                    Some((low, high)) => (number(low)?, number(high)?),
+                    None if step > 1 => (number(range)?, max),
+                    None => (number(range)?, number(range)?),
+                },
+            };
+            if low > high {
+                return Err(format!("{} is an empty range", range));
+            }
+            values.extend((low..=high).step_by(step as usize));
+        }
+        Ok(Field {
+            values,
+            any: text == "*",
+        })
+    }
+
+    fn matches(&self, value: u32) -> bool {
+        self.values.contains(&value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cron {
+    minute: Field,
+    hour: Field,
+    day: Field,
+    month: Field,
+    weekday: Field,
+}
+
+impl FromStr for Cron {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Cron, String> {
+        let fields: Vec<&str> = text.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!("{} does not have 5 cron fields", text));
+        };
+        let invalid = |e: String| format!("invalid cron expression {}: {}", text, e);
+        let mut weekday = Field::parse(weekday, 0, 7).map_err(invalid)?;
+        // Both 0 and 7 are Sunday.
+        if weekday.values.contains(&7) {
+            weekday.values.push(0);
+        }
+        Ok(Cron {
+            minute: Field::parse(minute, 0, 59).map_err(invalid)?,
+            hour: Field::parse(hour, 0, 23).map_err(invalid)?,
+            day: Field::parse(day, 1, 31).map_err(invalid)?,
+            month: Field::parse(month, 1, 12).map_err(invalid)?,
+            weekday,
+        })
+    }
+}
+
+impl Cron {
+    /// Whether the minute `time` falls into is one the expression matches.
+    pub fn matches(&self, time: &NaiveDateTime) -> bool {
+        let day = self.day.matches(time.day());
+        let weekday = self.weekday.matches(time.weekday().num_days_from_sunday());
+        let date = match (self.day.any, self.weekday.any) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        };
+        date && self.minute.matches(time.minute())
+            && self.hour.matches(time.hour())
+            && self.month.matches(time.month())
+    }
+
+    /// The first matching minute after the one `time` falls into, looking a
+    /// year ahead at most.
+    pub fn next_after(&self, time: &NaiveDateTime) -> Option<NaiveDateTime> {
+        let mut next = time.with_second(0)?.with_nanosecond(0)?;
+        for _ in 0..366 * 24 * 60 {
+            next += TimeDelta::minutes(1);
+            if self.matches(&next) {
+                return Some(next);
+            }
+        }
+        None
+    }
+}
+
+/// Times of day or cron matched minutes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Window {
+    /// From the first time up to the second, wrapping past midnight if it
+    /// is earlier
+    Range(NaiveTime, NaiveTime),
+    Cron(Cron),
+}
+
+impl FromStr for Window {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Window, String> {
+        let time = |t: &str| NaiveTime::parse_from_str(t.trim(), "%H:%M");
+        if let Some((start, end)) = text.split_once('-') {
+            if let (Ok(start), Ok(end)) = (time(start), time(end)) {
+                return Ok(Window::Range(start, end));
+            }
+        }
+        text.parse()
+            .map(Window::Cron)
+            .map_err(|e| format!("{} is neither HH:MM-HH:MM nor cron: {}", text, e))
+    }
+}
+
+impl Window {
+    pub fn contains(&self, time: &NaiveDateTime) -> bool {
+        let clock = time.time();
+        match self {
+            Window::Range(start, end) if start <= end => *start <= clock && clock < *end,
+            Window::Range(start, end) => *start <= clock || clock < *end,
+            Window::Cron(cron) => cron.matches(time),
+        }
+    }
+}
+
+/// Timezone rules are evaluated in, the system's unless configured.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timezone(Option<Tz>);
+
+impl FromStr for Timezone {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Timezone, String> {
+        text.parse::<Tz>()
+            .map(|tz| Timezone(Some(tz)))
+            .map_err(|_| format!("{} is not a known timezone", text))
+    }
+}
+
+impl Timezone {
+    pub fn now(&self) -> NaiveDateTime {
+        match self.0 {
+            Some(tz) => Utc::now().with_timezone(&tz).naive_local(),
+            None => Local::now().naive_local(),
+        }
+    }
+
+    /// How long until `cron` matches next, `None` if it never does.
+    pub fn until_next(&self, cron: &Cron) -> Option<Duration> {
+        let now = self.now();
+        (cron.next_after(&now)? - now).to_std().ok()
+    }
+}
+
+/// `update_windows` as written in the config file.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WindowsConfig {
+    /// IANA name such as Europe/Berlin
+    pub timezone: Option<String>,
+    /// Update only within these
+    #[serde(default)]
+    pub allow: Vec<String>,
+    /// Never update within these
+    #[serde(default)]
+    pub suppress: Vec<String>,
+}
+
+/// When updates may be made.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UpdateWindows {
+    pub timezone: Timezone,
+    pub allow: Vec<Window>,
+    pub suppress: Vec<Window>,
+}
+
+impl UpdateWindows {
+    /// `timezone` applies unless the config names its own.
+    pub fn from_config(config: WindowsConfig, timezone: Timezone) -> Result<UpdateWindows, Error> {
+        let windows = |texts: Vec<String>| {
+            texts
+                .iter()
+                .map(|text| text.parse::<Window>())
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(UpdateWindows {
+            timezone: match config.timezone {
+                Some(name) => name.parse()?,
+                None => timezone,
+            },
+            allow: windows(config.allow)?,
+            suppress: windows(config.suppress)?,
+        })
+    }
+
+    /// Whether updates are allowed at `time`, local to `timezone`.
+    pub fn allows(&self, time: &NaiveDateTime) -> bool {
+        let allowed = self.allow.is_empty() || self.allow.iter().any(|w| w.contains(time));
+        allowed && !self.suppress.iter().any(|w| w.contains(time))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(text: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn test_cron_matching() {
+        let cron: Cron = "*/15 6-22 * * 1-5".parse().unwrap();
+        // 2024-03-04 is a Monday.
+        assert!(cron.matches(&at("2024-03-04 06:45")));
+        assert!(!cron.matches(&at("2024-03-04 06:50")));
+        assert!(!cron.matches(&at("2024-03-04 23:00")));
+        assert!(!cron.matches(&at("2024-03-03 12:00")));
+
+        // Either day field matching is enough once both are restricted.
+        let cron: Cron = "0 0 1 * 7".parse().unwrap();
+        assert!(cron.matches(&at("2024-03-03 00:00")));
+        assert!(cron.matches(&at("2024-02-01 00:00")));
+        assert!(!cron.matches(&at("2024-02-02 00:00")));
+
+        let cron: Cron = "*/5 6-22 * * *".parse().unwrap();
+        assert_eq!(
+            cron.next_after(&at("2024-03-04 06:07")),
+            Some(at("2024-03-04 06:10"))
+        );
+        assert_eq!(
+            cron.next_after(&at("2024-03-04 22:55")),
+            Some(at("2024-03-05 06:00"))
+        );
+        assert_eq!(
+            "0 0 30 2 *"
+                .parse::<Cron>()
+                .unwrap()
+                .next_after(&at("2024-03-04 06:07")),
+            None
+        );
+
+        assert!("* * *".parse::<Cron>().is_err());
+        assert!("61 * * * *".parse::<Cron>().is_err());
+        assert!("*/0 * * * *".parse::<Cron>().is_err());
+    }
+
+    #[test]
+    fn test_update_windows() {
+        let windows = UpdateWindows {
+            allow: vec![
+                "22:00-06:00".parse().unwrap(),
+                "* * * * 0,6".parse().unwrap(),
+            ],
+            suppress: vec!["00:00-00:30".parse().unwrap()],
+            ..Default::default()
+        };
+        assert!(windows.allows(&at("2024-03-04 23:10")));
+        assert!(windows.allows(&at("2024-03-05 05:59")));
+        assert!(!windows.allows(&at("2024-03-05 00:10")));
+        assert!(!windows.allows(&at("2024-03-05 12:00")));
+        // Saturday
+        assert!(windows.allows(&at("2024-03-09 12:00")));
+        assert_eq!(
+            "Europe/Berlin".parse::<Timezone>(),
+            Ok(Timezone(Some(chrono_tz::Europe::Berlin)))
+        );
+        assert!("Mars/Olympus".parse::<Timezone>().is_err());
+    }
+}
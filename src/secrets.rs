@@ -0,0 +1,343 @@
+//! API tokens kept in a central secret store: HashiCorp Vault, AWS Secrets
+//! Manager or SSM Parameter Store, and GCP Secret Manager.
+//!
+//! Each backend sits behind a cargo feature of the same name (`vault`, `aws`,
+//! `gcp`). The config syntax is understood either way, so a config file can
+//! be shared between builds; resolving a disabled backend is an error. Vault
+//! is spoken to over HTTP, AWS and GCP through their `aws` and `gcloud`
+//! command line tools, which already know how to find credentials.
+
+use std::path::PathBuf;
+#[cfg(any(feature = "aws", feature = "gcp", feature = "sops", feature = "s3"))]
+use std::process::Command;
+
+use serde_derive::Deserialize;
+
+#[cfg(feature = "vault")]
+use crate::trace::Traced;
+use crate::Error;
+
+/// `[token_source]` in the config file.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum SecretSource {
+    /// A key of a KV v2 secret
+    Vault {
+        /// Defaults to `VAULT_ADDR`
+        address: Option<String>,
+        #[serde(default = "default_mount")]
+        mount: String,
+        path: String,
+        #[serde(default = "default_key")]
+        key: String,
+        #[serde(default)]
+        auth: VaultAuth,
+    },
+    AwsSecretsManager {
+        secret_id: String,
+        region: Option<String>,
+        /// Field to pick when the secret string is a JSON object
+        key: Option<String>,
+    },
+    AwsSsm {
+        name: String,
+        region: Option<String>,
+    },
+    GcpSecretManager {
+        secret: String,
+        project: Option<String>,
+        #[serde(default = "default_version")]
+        version: String,
+    },
+}
+
+/// How cfdns logs in to Vault.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(tag = "method", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum VaultAuth {
+    /// `VAULT_TOKEN`, or `~/.vault-token` as left by `vault login`
+    #[default]
+    Token,
+    #[serde(rename = "approle")]
+    AppRole {
+        role_id: String,
+        secret_id_file: PathBuf,
+        #[serde(default = "default_approle_mount")]
+        mount: String,
+    },
+    Kubernetes {
+        role: String,
+        #[serde(default = "default_kubernetes_jwt")]
+        jwt_file: PathBuf,
+        #[serde(default = "default_kubernetes_mount")]
+        mount: String,
+    },
+}
+
+fn default_mount() -> String {
+    "secret".to_string()
+}
+
+fn default_key() -> String {
+    "token".to_string()
+}
+
+fn default_version() -> String {
+    "latest".to_string()
+}
+
+fn default_approle_mount() -> String {
+    "approle".to_string()
+}
+
+fn default_kubernetes_mount() -> String {
+    "kubernetes".to_string()
+}
+
+fn default_kubernetes_jwt() -> PathBuf {
+    PathBuf::from("/var/run/secrets/kubernetes.io/serviceaccount/token")
+}
+
+impl SecretSource {
+    /// Fetches the current value of the secret.
+    pub fn fetch(&self) -> Result<String, Error> {
+        let secret = match self {
+            SecretSource::Vault {
+                address,
+                mount,
+                path,
+                key,
+                auth,
+            } => vault(address.as_deref(), mount, path, key, auth)?,
+            SecretSource::AwsSecretsManager {
+                secret_id,
+                region,
+                key,
+            } => aws_secrets_manager(secret_id, region.as_deref(), key.as_deref())?,
+            SecretSource::AwsSsm { name, region } => aws_ssm(name, region.as_deref())?,
+            SecretSource::GcpSecretManager {
+                secret,
+                project,
+                version,
+            } => gcp_secret_manager(secret, project.as_deref(), version)?,
+        };
+        match secret.trim() {
+            "" => Err("secret store returned an empty token".into()),
+            secret => Ok(secret.to_string()),
+        }
+    }
+}
+
+#[cfg(not(feature = "vault"))]
+fn vault(_: Option<&str>, _: &str, _: &str, _: &str, _: &VaultAuth) -> Result<String, Error> {
+    Err("cfdns was built without the `vault` feature".into())
+}
+
+#[cfg(feature = "vault")]
+fn vault(
+    address: Option<&str>,
+    mount: &str,
+    path: &str,
+    key: &str,
+    auth: &VaultAuth,
+) -> Result<String, Error> {
+    use serde_json::{json, Value};
+
+    let address = match address {
+        Some(address) => address.to_string(),
+        None => std::env::var("VAULT_ADDR").map_err(|_| "No Vault address or VAULT_ADDR set")?,
+    };
+    let address = address.trim_end_matches('/');
+    let client = reqwest::blocking::Client::new();
+    let login = |mount: &str, body: Value| -> Result<String, Error> {
+        let response: Value = client
+            .post(format!("{address}/v1/auth/{mount}/login"))
+            .header("Content-Type", "application/json")
+            .body(body.to_string())
+            .send_traced()
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| format!("Vault login failed: {}", e))?
+            .text()
+            .map_err(|e| format!("could not read Vault response: {}", e))
+            .and_then(|text| {
+                serde_json::from_str(&text).map_err(|e| format!("invalid Vault response: {}", e))
+            })?;
+        response["auth"]["client_token"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| "Vault login returned no client token".into())
+    };
+
+    let vault_token = match auth {
+        VaultAuth::Token => match std::env::var("VAULT_TOKEN") {
+            Ok(token) => token,
+            Err(_) => {
+                let home = std::env::var_os("HOME").ok_or("No VAULT_TOKEN set in env")?;
+                std::fs::read_to_string(PathBuf::from(home).join(".vault-token"))
+                    .map_err(|_| "No VAULT_TOKEN set in env and no ~/.vault-token")?
+                    .trim()
+                    .to_string()
+            }
+        },
+        VaultAuth::AppRole {
+            role_id,
+            secret_id_file,
+            mount,
+        } => {
+            let secret_id = read_trimmed(secret_id_file)?;
+            login(mount, json!({ "role_id": role_id, "secret_id": secret_id }))?
+        }
+        VaultAuth::Kubernetes {
+            role,
+            jwt_file,
+            mount,
+        } => login(
+            mount,
+            json!({ "role": role, "jwt": read_trimmed(jwt_file)? }),
+        )?,
+    };
+
+    let text = client
+        .get(format!("{address}/v1/{mount}/data/{path}"))
+        .header("X-Vault-Token", vault_token)
+        .send_traced()
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| format!("could not read {} from Vault: {}", path, e))?
+        .text()
+        .map_err(|e| format!("could not read Vault response: {}", e))?;
+    let secret: Value =
+        serde_json::from_str(&text).map_err(|e| format!("invalid Vault response: {}", e))?;
+    secret["data"]["data"][key]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| format!("Vault secret {} has no key {}", path, key).into())
+}
+
+#[cfg(feature = "vault")]
+fn read_trimmed(path: &std::path::Path) -> Result<String, Error> {
+    std::fs::read_to_string(path)
+        .map(|text| text.trim().to_string())
+        .map_err(|e| format!("could not read {}: {}", path.display(), e).into())
+}
+
+#[cfg(not(feature = "aws"))]
+fn aws_secrets_manager(_: &str, _: Option<&str>, _: Option<&str>) -> Result<String, Error> {
+    Err("cfdns was built without the `aws` feature".into())
+}
+
+#[cfg(not(feature = "aws"))]
+fn aws_ssm(_: &str, _: Option<&str>) -> Result<String, Error> {
+    Err("cfdns was built without the `aws` feature".into())
+}
+
+#[cfg(feature = "aws")]
+fn aws_secrets_manager(
+    secret_id: &str,
+    region: Option<&str>,
+    key: Option<&str>,
+) -> Result<String, Error> {
+    let mut command = Command::new("aws");
+    command.args([
+        "secretsmanager",
+        "get-secret-value",
+        "--secret-id",
+        secret_id,
+    ]);
+    command.args(["--query", "SecretString", "--output", "text"]);
+    if let Some(region) = region {
+        command.args(["--region", region]);
+    }
+    let secret = output_of(&mut command)?;
+    match key {
+        Some(key) => {
+            let fields: serde_json::Value = serde_json::from_str(&secret)
+                .map_err(|e| format!("secret {} is not a JSON object: {}", secret_id, e))?;
+            fields[key]
+                .as_str()
+                .map(str::to_string)
+                .ok_or_else(|| format!("secret {} has no key {}", secret_id, key).into())
+        }
+        None => Ok(secret),
+    }
+}
+
+#[cfg(feature = "aws")]
+fn aws_ssm(name: &str, region: Option<&str>) -> Result<String, Error> {
+    let mut command = Command::new("aws");
+    command.args(["ssm", "get-parameter", "--name", name, "--with-decryption"]);
+    command.args(["--query", "Parameter.Value", "--output", "text"]);
+    if let Some(region) = region {
+        command.args(["--region", region]);
+    }
+    output_of(&mut command)
+}
+
+#[cfg(not(feature = "gcp"))]
+fn gcp_secret_manager(_: &str, _: Option<&str>, _: &str) -> Result<String, Error> {
+    Err("cfdns was built without the `gcp` feature".into())
+}
+
+#[cfg(feature = "gcp")]
+fn gcp_secret_manager(secret: &str, project: Option<&str>, version: &str) -> Result<String, Error> {
+    let mut command = Command::new("gcloud");
+    command.args(["secrets", "versions", "access", version]);
+    command.arg(format!("--secret={}", secret));
+    if let Some(project) = project {
+        command.arg(format!("--project={}", project));
+    }
+    output_of(&mut command)
+}
+
+/// Stdout of `command`, failing with its stderr.
+#[cfg(any(feature = "aws", feature = "gcp", feature = "sops", feature = "s3"))]
+pub fn output_of(command: &mut Command) -> Result<String, Error> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = crate::init::waiting(|| command.output())
+        .map_err(|e| format!("could not run {}: {}", program, e))?;
+    match output.status.success() {
+        true => Ok(String::from_utf8(output.stdout)?),
+        false => Err(format!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_secret_sources() {
+        let vault: SecretSource = toml::from_str(
+            r#"
+            type = "vault"
+            path = "cfdns"
+            auth = { method = "approle", role_id = "cfdns", secret_id_file = "/run/secret_id" }
+            "#,
+        )
+        .unwrap();
+        match vault {
+            SecretSource::Vault {
+                mount, key, auth, ..
+            } => {
+                assert_eq!((mount.as_str(), key.as_str()), ("secret", "token"));
+                assert!(matches!(auth, VaultAuth::AppRole { mount, .. } if mount == "approle"));
+            }
+            other => panic!("parsed as {:?}", other),
+        }
+
+        let ssm: SecretSource =
+            toml::from_str("type = \"aws-ssm\"\nname = \"/cfdns/token\"").unwrap();
+        assert_eq!(
+            ssm,
+            SecretSource::AwsSsm {
+                name: "/cfdns/token".to_string(),
+                region: None
+            }
+        );
+        assert!(toml::from_str::<SecretSource>("type = \"aws-ssm\"\nnmae = \"x\"").is_err());
+    }
+}
@@ -0,0 +1,229 @@
+//! `cfdns self-update`: replaces the running binary with the latest GitHub
+//! release, for installs without a package manager such as routers and NAS
+//! boxes.
+//!
+//! Releases carry one binary per platform, `cfdns-<arch>-<os>`, and a
+//! `SHA256SUMS` file in the format `sha256sum` writes. A download whose
+//! checksum is missing from it or doesn't match is never installed.
+//!
+//! Builds without the `self-update` feature keep the release lookup, which
+//! `cfdns version --check` uses, but can't install anything.
+
+use std::env;
+#[cfg(feature = "self-update")]
+use std::fs;
+#[cfg(feature = "self-update")]
+use std::path::Path;
+use std::time::Duration;
+
+use clap::Args;
+use serde_derive::Deserialize;
+#[cfg(feature = "self-update")]
+use sha2::{Digest, Sha256};
+
+use crate::trace::Traced;
+use crate::Error;
+
+pub const REPOSITORY: &str = "dovvla/cfdns";
+#[cfg(feature = "self-update")]
+const CHECKSUMS: &str = "SHA256SUMS";
+const TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Args, Debug, Clone)]
+pub struct SelfUpdateArgs {
+    /// Only tell whether there is a newer release
+    #[arg(long)]
+    check: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Release {
+    pub tag_name: String,
+    #[serde(default)]
+    #[cfg_attr(not(feature = "self-update"), allow(dead_code))]
+    pub assets: Vec<Asset>,
+}
+
+#[cfg_attr(not(feature = "self-update"), allow(dead_code))]
+#[derive(Debug, Clone, Deserialize)]
+pub struct Asset {
+    pub name: String,
+    pub browser_download_url: String,
+}
+
+impl Release {
+    pub fn version(&self) -> &str {
+        self.tag_name.trim_start_matches('v')
+    }
+
+    #[cfg(feature = "self-update")]
+    fn asset(&self, name: &str) -> Result<&Asset, Error> {
+        self.assets
+            .iter()
+            .find(|asset| asset.name == name)
+            .ok_or_else(|| format!("Release {} has no {}", self.tag_name, name).into())
+    }
+}
+
+/// Name of the release binary for the platform cfdns runs on.
+#[cfg(feature = "self-update")]
+fn asset_name() -> String {
+    format!("cfdns-{}-{}", env::consts::ARCH, env::consts::OS)
+}
+
+/// Whether version `a` is newer than `b`, comparing dotted numbers.
+pub fn newer(a: &str, b: &str) -> bool {
+    let parts = |version: &str| -> Vec<u64> {
+        version
+            .split(['.', '-', '+'])
+            .map_while(|part| part.parse().ok())
+            .collect()
+    };
+    parts(a) > parts(b)
+}
+
+/// The SHA-256 `SHA256SUMS` lists for `name`.
+#[cfg(feature = "self-update")]
+fn expected_checksum<'a>(sums: &'a str, name: &str) -> Option<&'a str> {
+    sums.lines().find_map(|line| {
+        let (sum, file) = line.split_once(char::is_whitespace)?;
+        (file.trim().trim_start_matches('*') == name).then_some(sum)
+    })
+}
+
+#[cfg(feature = "self-update")]
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// A client that follows redirects, which release downloads need.
+pub fn github_client() -> Result<reqwest::blocking::Client, Error> {
+    Ok(reqwest::blocking::Client::builder()
+        .user_agent(concat!("cfdns/", env!("CARGO_PKG_VERSION")))
+        .timeout(TIMEOUT)
+        .build()?)
+}
+
+pub fn latest_release(client: &reqwest::blocking::Client) -> Result<Release, Error> {
+    let text = client
+        .get(format!(
+            "https://api.github.com/repos/{REPOSITORY}/releases/latest"
+        ))
+        .header("Accept", "application/vnd.github+json")
+        .send_traced()
+        .and_then(|response| response.error_for_status())
+        .and_then(|response| response.text())
+        .map_err(|e| format!("could not look up the latest release: {}", e))?;
+    serde_json::from_str(&text).map_err(|e| format!("could not parse the release: {}", e).into())
+}
+
+#[cfg(feature = "self-update")]
+fn download(client: &reqwest::blocking::Client, asset: &Asset) -> Result<Vec<u8>, Error> {
+    let bytes = client
+        .get(&asset.browser_download_url)
+        .send_traced()
+        .and_then(|response| response.error_for_status())
+        .and_then(|response| response.bytes())
+        .map_err(|e| format!("could not download {}: {}", asset.name, e))?;
+    Ok(bytes.to_vec())
+}
+
+/// Puts `binary` in place of `current`, through a file next to it so that
+/// the rename doesn't cross file systems.
+#[cfg(feature = "self-update")]
+fn replace(current: &Path, binary: &[u8]) -> Result<(), Error> {
+    let staged = current.with_extension("new");
+    fs::write(&staged, binary)
+        .map_err(|e| format!("could not write {}: {}", staged.display(), e))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&staged, fs::Permissions::from_mode(0o755))?;
+    }
+    // Windows can't replace a running binary, but it can rename it.
+    #[cfg(windows)]
+    fs::rename(current, current.with_extension("old"))?;
+    fs::rename(&staged, current).map_err(|e| {
+        let _ = fs::remove_file(&staged);
+        format!("could not replace {}: {}", current.display(), e).into()
+    })
+}
+
+#[cfg(feature = "self-update")]
+pub fn run(args: &SelfUpdateArgs) -> Result<(), Error> {
+    let client = github_client()?;
+    let release = latest_release(&client)?;
+    let current = env!("CARGO_PKG_VERSION");
+    if !newer(release.version(), current) {
+        println!("cfdns {} is the latest release", current);
+        return Ok(());
+    }
+    if args.check {
+        println!(
+            "cfdns {} is available, this is {}",
+            release.version(),
+            current
+        );
+        return Ok(());
+    }
+
+    let name = asset_name();
+    let binary = download(&client, release.asset(&name)?)?;
+    let sums = String::from_utf8(download(&client, release.asset(CHECKSUMS)?)?)?;
+    let Some(expected) = expected_checksum(&sums, &name) else {
+        return Err(format!("{} has no checksum for {}", CHECKSUMS, name).into());
+    };
+    let actual = hex(&Sha256::digest(&binary));
+    if !actual.eq_ignore_ascii_case(expected) {
+        return Err(format!(
+            "Checksum of {} is {} instead of {}, not installing it",
+            name, actual, expected
+        )
+        .into());
+    }
+
+    let path = env::current_exe()?;
+    replace(&path, &binary)?;
+    println!(
+        "Updated {} from {} to {}",
+        path.display(),
+        current,
+        release.version()
+    );
+    Ok(())
+}
+
+#[cfg(not(feature = "self-update"))]
+pub fn run(_: &SelfUpdateArgs) -> Result<(), Error> {
+    Err("cfdns was built without the `self-update` feature".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_versions() {
+        assert!(newer("0.10.0", "0.9.3"));
+        assert!(!newer("0.1.0", "0.1.0"));
+        assert!(!newer("0.1.0-rc1", "0.1.0"));
+    }
+
+    #[cfg(feature = "self-update")]
+    #[test]
+    fn test_checksums() {
+        let sums = "\
+e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  cfdns-x86_64-linux
+9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08 *cfdns-aarch64-linux
+";
+        assert_eq!(
+            expected_checksum(sums, "cfdns-aarch64-linux"),
+            Some("9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08")
+        );
+        assert_eq!(expected_checksum(sums, "cfdns-x86_64-windows"), None);
+        assert_eq!(
+            hex(&Sha256::digest(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+}
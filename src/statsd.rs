@@ -0,0 +1,118 @@
+//! `statsd`: counters and timings of every run sent over UDP, for setups
+//! standardized on Datadog or a statsd pipeline. `statsd_tags` are added in
+//! DogStatsD format, `|#env:home,site:ams`; without tags the metrics are
+//! plain statsd.
+//!
+//! Every run counts `<prefix>.runs`, and `<prefix>.runs.failed` if it failed
+//! as a whole, times itself as `<prefix>.run.duration` and counts its
+//! records by state as `<prefix>.records.<state>`, e.g. `.records.updated`.
+
+use std::net::UdpSocket;
+use std::time::{Duration, SystemTime};
+
+use log::warn;
+
+use crate::sync::{SyncReport, SyncState};
+use crate::Error;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statsd {
+    /// `host:port` of the statsd server or Datadog agent
+    pub address: String,
+    pub prefix: String,
+    pub tags: Vec<String>,
+}
+
+const STATES: [(SyncState, &str); 7] = [
+    (SyncState::InSync, "in_sync"),
+    (SyncState::Updated, "updated"),
+    (SyncState::Reverted, "reverted"),
+    (SyncState::Failed, "failed"),
+    (SyncState::Missing, "missing"),
+    (SyncState::Skipped, "skipped"),
+    (SyncState::Throttled, "throttled"),
+];
+
+impl Statsd {
+    fn metric(&self, name: &str, value: impl std::fmt::Display, kind: &str) -> String {
+        let mut metric = format!("{}.{}:{}|{}", self.prefix, name, value, kind);
+        if !self.tags.is_empty() {
+            metric.push_str("|#");
+            metric.push_str(&self.tags.join(","));
+        }
+        metric
+    }
+
+    /// The metrics of a run that took `duration`, one per line.
+    fn metrics(&self, duration: Duration, result: &Result<SyncReport, Error>) -> String {
+        let mut metrics = vec![
+            self.metric("runs", 1, "c"),
+            self.metric("run.duration", duration.as_millis(), "ms"),
+        ];
+        match result {
+            Ok(report) => {
+                for (state, name) in STATES {
+                    let count = report.count(state);
+                    if count > 0 {
+                        metrics.push(self.metric(&format!("records.{}", name), count, "c"));
+                    }
+                }
+            }
+            Err(_) => metrics.push(self.metric("runs.failed", 1, "c")),
+        }
+        metrics.join("\n")
+    }
+
+    fn send(&self, metrics: &str) -> Result<(), Error> {
+        let socket = UdpSocket::bind("0.0.0.0:0").or_else(|_| UdpSocket::bind("[::]:0"))?;
+        socket.send_to(metrics.as_bytes(), self.address.as_str())?;
+        Ok(())
+    }
+
+    /// Sends the metrics of a run that started at `started` and ended with
+    /// `result`. Failing to is only logged, the run itself is done.
+    pub fn write(&self, started: SystemTime, result: &Result<SyncReport, Error>) {
+        let duration = started.elapsed().unwrap_or_default();
+        if let Err(e) = self.send(&self.metrics(duration, result)) {
+            warn!("Could not send the metrics to {}: {}", self.address, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::RecordReport;
+
+    #[test]
+    fn test_metrics_carry_prefix_and_tags() {
+        let mut statsd = Statsd {
+            address: "127.0.0.1:8125".to_string(),
+            prefix: "cfdns".to_string(),
+            tags: vec!["env:home".to_string(), "site:ams".to_string()],
+        };
+        let run: Result<SyncReport, Error> = Ok(SyncReport {
+            current_ip: "198.51.100.7".to_string(),
+            records: vec![RecordReport {
+                zone: "z".to_string(),
+                name: "home.example.com".to_string(),
+                content: Some("198.51.100.7".to_string()),
+                state: SyncState::Updated,
+                error: None,
+            }],
+            changes: Vec::new(),
+        });
+        assert_eq!(
+            statsd.metrics(Duration::from_millis(412), &run),
+            "cfdns.runs:1|c|#env:home,site:ams\n\
+             cfdns.run.duration:412|ms|#env:home,site:ams\n\
+             cfdns.records.updated:1|c|#env:home,site:ams"
+        );
+        statsd.tags.clear();
+        let failed: Result<SyncReport, Error> = Err("no IP".into());
+        assert_eq!(
+            statsd.metrics(Duration::from_millis(5), &failed),
+            "cfdns.runs:1|c\ncfdns.run.duration:5|ms\ncfdns.runs.failed:1|c"
+        );
+    }
+}
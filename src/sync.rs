@@ -0,0 +1,1123 @@
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+use log::{debug, error, info, warn};
+use serde_derive::Serialize;
+
+use crate::access;
+use crate::authoritative;
+use crate::cloudflare::models::Record;
+use crate::config::{Settings, ZoneConfig};
+use crate::deregister::Deregistered;
+use crate::fleet;
+use crate::frozen;
+use crate::guard::RateGuard;
+use crate::hints;
+use crate::ids::IdCache;
+use crate::lists;
+use crate::names;
+use crate::notify::{self, Notification};
+use crate::plan::Operation;
+use crate::pools;
+use crate::progress::Progress;
+use crate::propagation::{self, Expected};
+use crate::published;
+use crate::round_robin;
+use crate::templated;
+use crate::token::Unauthorized;
+use crate::{dns_records, get_dns_record, ip, purge_cache};
+use crate::{replace_record, Error};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncState {
+    InSync,
+    Updated,
+    Failed,
+    Missing,
+    /// Left alone because cfdns may not modify the record
+    Skipped,
+    /// Changed by someone else while the IP stayed the same, and put back
+    Reverted,
+    /// Held back for now, outside the update windows or because it changed
+    /// too often within the last hour
+    Throttled,
+}
+
+/// What happened to a single managed record during a run.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RecordReport {
+    /// Zone of the record, or what other targets live in, see [`target_report`]
+    pub zone: String,
+    pub name: String,
+    /// Content published in Cloudflare after the run
+    pub content: Option<String>,
+    pub state: SyncState,
+    pub error: Option<String>,
+}
+
+/// Outcome of a [`sync_records`] run that got as far as checking records.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncReport {
+    pub current_ip: String,
+    pub records: Vec<RecordReport>,
+    /// Record contents, TTLs and proxied flags this run changed
+    pub changes: Vec<Operation>,
+}
+
+impl SyncReport {
+    pub fn count(&self, state: SyncState) -> usize {
+        self.records.iter().filter(|r| r.state == state).count()
+    }
+
+    pub fn summary(&self) -> Summary {
+        Summary {
+            checked: self.records.len(),
+            updated: self.count(SyncState::Updated),
+            reverted: self.count(SyncState::Reverted),
+            in_sync: self.count(SyncState::InSync),
+            missing: self.count(SyncState::Missing),
+            skipped: self.count(SyncState::Skipped),
+            throttled: self.count(SyncState::Throttled),
+            failed: self.count(SyncState::Failed),
+            failures: self
+                .records
+                .iter()
+                .filter(|r| r.state == SyncState::Failed)
+                .map(|r| Failure {
+                    zone: r.zone.clone(),
+                    name: r.name.clone(),
+                    error: r.error.clone().unwrap_or_default(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Counts of what a run did, with the failures spelled out.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Summary {
+    pub checked: usize,
+    pub updated: usize,
+    pub reverted: usize,
+    pub in_sync: usize,
+    pub missing: usize,
+    pub skipped: usize,
+    pub throttled: usize,
+    pub failed: usize,
+    pub failures: Vec<Failure>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Failure {
+    pub zone: String,
+    pub name: String,
+    pub error: String,
+}
+
+impl Summary {
+    /// Whether anything but checking happened, worth telling someone about.
+    pub fn eventful(&self) -> bool {
+        self.in_sync != self.checked
+    }
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} checked", self.checked)?;
+        let counts = [
+            (self.updated, "updated"),
+            (self.reverted, "reverted"),
+            (self.in_sync, "in sync"),
+            (self.missing, "missing"),
+            (self.skipped, "skipped"),
+            (self.throttled, "throttled"),
+            (self.failed, "failed"),
+        ];
+        for (count, what) in counts.iter().filter(|(count, _)| *count > 0) {
+            write!(f, ", {} {}", count, what)?;
+        }
+        for failure in &self.failures {
+            write!(
+                f,
+                "\n  failed: {} in {}: {}",
+                names::display(&failure.name),
+                failure.zone,
+                failure.error
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Report for something other than a DNS record that follows the IP, like
+/// an IP list entry. `target` goes in the zone column.
+pub fn target_report(
+    target: &str,
+    name: String,
+    ip: IpAddr,
+    result: Result<SyncState, Error>,
+) -> RecordReport {
+    let (state, error) = match result {
+        Ok(state) => (state, None),
+        Err(e) => {
+            error!("Could not update {} {}: {}", name, target, e);
+            (SyncState::Failed, Some(e.to_string()))
+        }
+    };
+    RecordReport {
+        zone: target.to_string(),
+        name,
+        content: (state != SyncState::Failed).then(|| ip.to_string()),
+        state,
+        error,
+    }
+}
+
+/// A configured record together with what Cloudflare currently has for it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Managed {
+    pub zone: String,
+    pub name: String,
+    pub record: Option<Record>,
+}
+
+/// Looks up every configured record of `record_type` in Cloudflare.
+pub fn fetch_managed(settings: &Settings, record_type: &str) -> Result<Vec<Managed>, Error> {
+    let mut managed = Vec::new();
+    for (_, fetched) in fetch_by_zone(settings, record_type) {
+        managed.extend(fetched?);
+    }
+    Ok(managed)
+}
+
+/// [`fetch_managed`] a zone at a time, so that one zone failing to list
+/// doesn't keep the others from being looked up. Zones are fetched
+/// `parallelism` at a time.
+pub fn fetch_by_zone<'a>(
+    settings: &'a Settings,
+    record_type: &str,
+) -> Vec<(&'a ZoneConfig, Result<Vec<Managed>, Error>)> {
+    let mut cache = IdCache::load(settings);
+    let now = SystemTime::now();
+    let progress = Progress::new(settings.zones.len());
+    let fetched = run_bounded(settings.parallelism, &settings.zones, |zone| {
+        if let Some(cached) = fetch_cached(settings, &cache, zone, record_type) {
+            debug!(
+                "Fetched the records of zone {} by their cached IDs",
+                zone.id
+            );
+            progress.zone_done(&zone.id, cached.len());
+            return Ok(cached);
+        }
+        let records: Vec<Record> =
+            dns_records(settings.client(&zone.id), &zone.id, Some(record_type))
+                .collect::<Result<_, _>>()?;
+        progress.zone_done(&zone.id, records.len());
+        Ok(selected(zone, &records, record_type)
+            .into_iter()
+            .map(|name| Managed {
+                zone: zone.id.clone(),
+                record: cfdns_core::names::find(&records, &name, record_type).cloned(),
+                name,
+            })
+            .collect())
+    });
+    progress.finish();
+    for managed in fetched.iter().flatten().flatten() {
+        if let Some(record) = &managed.record {
+            cache.set_record(&managed.zone, &managed.name, record_type, &record.id, now);
+        }
+    }
+    cache.save();
+    settings.zones.iter().zip(fetched).collect()
+}
+
+/// Reports every configured record of `zone` as failed because the zone
+/// could not be looked up. A rejected token is left to fail the whole run,
+/// as it would for every other zone too and may be fixed by re-reading it.
+fn zone_failed(zone: &ZoneConfig, error: Error) -> Result<Vec<RecordReport>, Error> {
+    if error.is::<Unauthorized>() {
+        return Err(error);
+    }
+    error!(
+        "Could not look up the records of zone {}: {}",
+        zone.id, error
+    );
+    if let Some(hint) = hints::hint(&error) {
+        warn!("Hint: {}", hint);
+    }
+    Ok(zone
+        .records
+        .iter()
+        .map(|name| RecordReport {
+            zone: zone.id.clone(),
+            name: name.clone(),
+            content: None,
+            state: SyncState::Failed,
+            error: Some(error.to_string()),
+        })
+        .collect())
+}
+
+/// The records of `zone` fetched one by one by their cached IDs, if every
+/// one of them has a cached ID that still belongs to it. Records picked by
+/// their comment can only be found by listing the zone.
+fn fetch_cached(
+    settings: &Settings,
+    cache: &IdCache,
+    zone: &ZoneConfig,
+    record_type: &str,
+) -> Option<Vec<Managed>> {
+    if zone.comment_prefix.is_some() {
+        return None;
+    }
+    let now = SystemTime::now();
+    let ids: Vec<&str> = zone
+        .records
+        .iter()
+        .map(|name| cache.record(&zone.id, name, record_type, now))
+        .collect::<Option<_>>()?;
+    let mut managed = Vec::new();
+    for (name, id) in zone.records.iter().zip(ids) {
+        let cached = Record {
+            id: id.to_string(),
+            name: name.clone(),
+            ..Default::default()
+        };
+        match get_dns_record(settings.client(&zone.id), &cached, &zone.id) {
+            Ok(record) if record.name == *name && record.type_field == record_type => {
+                managed.push(Managed {
+                    zone: zone.id.clone(),
+                    name: name.clone(),
+                    record: Some(record),
+                })
+            }
+            _ => {
+                debug!("Cached ID of {} is stale, listing zone {}", name, zone.id);
+                return None;
+            }
+        }
+    }
+    Some(managed)
+}
+
+/// The configured names of `zone`, plus those of `record_type` records whose
+/// comment starts with the zone's `comment_prefix`.
+fn selected(zone: &ZoneConfig, records: &[Record], record_type: &str) -> Vec<String> {
+    let mut names = zone.records.clone();
+    let Some(prefix) = &zone.comment_prefix else {
+        return names;
+    };
+    for record in records {
+        let tagged = record
+            .comment
+            .as_deref()
+            .is_some_and(|comment| comment.starts_with(prefix.as_str()));
+        if tagged && record.type_field == record_type && !names.contains(&record.name) {
+            names.push(record.name.clone());
+        }
+    }
+    names
+}
+
+pub use cfdns_core::{attribute_changes, foreign_owner, marked_comment, OWNERSHIP_MARKER};
+
+/// Why cfdns must not change `record`, if it must not, by the rule the
+/// Worker goes by as well.
+pub fn refusal(record: &Record, settings: &Settings) -> Option<String> {
+    let frozen = frozen::is_frozen(record);
+    let refusal = cfdns_core::refusal(record, frozen, settings.force, settings.safe_mode);
+    if let (None, Some(owner)) = (&refusal, foreign_owner(record)) {
+        warn!(
+            "Modifying {} {} managed by {} because of --force",
+            record.type_field, record.name, owner
+        );
+    }
+    refusal
+}
+
+/// Whether `record` may be changed, warning when it may not.
+pub fn may_modify(record: &Record, settings: &Settings) -> bool {
+    match refusal(record, settings) {
+        Some(reason) => {
+            warn!(
+                "Not touching {} {}, {}",
+                record.type_field, record.name, reason
+            );
+            false
+        }
+        None => true,
+    }
+}
+
+/// Brings every requested record in line with the current public IP.
+pub fn sync_records(
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+) -> Result<SyncReport, Error> {
+    let current_ip = ip::detect(client, settings)?;
+    if let Some(report) = cached(settings, current_ip) {
+        return Ok(report);
+    }
+    if let Some(report) = precheck(client, settings, current_ip) {
+        return Ok(report);
+    }
+    reconcile(client, settings, current_ip, None)
+}
+
+/// With `--from-cache`, the cached report when the last run published
+/// `current_ip` already.
+fn cached(settings: &Settings, current_ip: IpAddr) -> Option<SyncReport> {
+    let report = published::report(settings, current_ip)?;
+    info!(
+        "{} is the IP published last time, not contacting Cloudflare",
+        current_ip
+    );
+    Some(report)
+}
+
+/// With `dns_precheck` on, an all in sync report when the name servers
+/// already publish `current_ip` for every record, so that no API call is
+/// needed.
+fn precheck(
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+    current_ip: IpAddr,
+) -> Option<SyncReport> {
+    // Other targets, and records picked by their comment, can't be looked
+    // up over DNS.
+    if !settings.dns_precheck || settings.has_other_targets() || settings.selects_by_comment() {
+        return None;
+    }
+    if !authoritative::all_published(client, settings, current_ip) {
+        return None;
+    }
+    info!("Name servers publish {} for every record", current_ip);
+    let records = settings
+        .zones
+        .iter()
+        .flat_map(|zone| {
+            zone.records.iter().map(|name| RecordReport {
+                zone: zone.id.clone(),
+                name: name.clone(),
+                content: Some(current_ip.to_string()),
+                state: SyncState::InSync,
+                error: None,
+            })
+        })
+        .collect();
+    Some(SyncReport {
+        current_ip: current_ip.to_string(),
+        records,
+        changes: Vec::new(),
+    })
+}
+
+/// Remembers the last run of the daemon, so that a stable public IP does not
+/// cost any Cloudflare API calls unless `enforce` is on.
+#[derive(Debug, Default)]
+pub struct Reconciler {
+    last: Option<SyncReport>,
+}
+
+impl Reconciler {
+    /// `full` asks for a look at Cloudflare even if the IP did not change,
+    /// e.g. after a reload or an explicit reconcile request.
+    pub fn run(
+        &mut self,
+        client: &reqwest::blocking::Client,
+        settings: &Settings,
+        full: bool,
+    ) -> Result<SyncReport, Error> {
+        let current_ip = ip::detect(client, settings)?;
+        if let Some(last) = &self.last {
+            let clean = last.count(SyncState::Failed) + last.count(SyncState::Throttled) == 0;
+            if !full && !settings.enforce && clean && last.current_ip == current_ip.to_string() {
+                debug!("Public IP unchanged, not checking Cloudflare");
+                return Ok(last.clone());
+            }
+        }
+        if !full {
+            if let Some(report) = self
+                .last
+                .is_none()
+                .then(|| cached(settings, current_ip))
+                .flatten()
+                .or_else(|| precheck(client, settings, current_ip))
+            {
+                self.last = Some(report.clone());
+                return Ok(report);
+            }
+        }
+        let report = reconcile(client, settings, current_ip, self.last.as_ref())?;
+        let mut last = report.clone();
+        last.changes.clear();
+        // What was updated this time is simply in sync from here on.
+        for record in &mut last.records {
+            if matches!(record.state, SyncState::Updated | SyncState::Reverted) {
+                record.state = SyncState::InSync;
+            }
+        }
+        self.last = Some(last);
+        Ok(report)
+    }
+}
+
+/// Whether the missing `record_type` record `name` is created: this
+/// machine's own records of a fleet, and those cfdns deleted when it last
+/// stopped. Anything else may be a typo and stays missing.
+fn creates_missing(
+    zone: &ZoneConfig,
+    name: &str,
+    record_type: &str,
+    deregistered: &Deregistered,
+) -> bool {
+    zone.host_records.iter().any(|host| host == name)
+        || deregistered.contains(&zone.id, name, record_type)
+}
+
+/// Whether `record` was known to be right at `previous`, with the same IP as
+/// now, so that a different content means someone else changed it.
+fn drifted(previous: Option<&SyncReport>, current_ip: &str, zone: &str, name: &str) -> bool {
+    previous.is_some_and(|previous| {
+        previous.current_ip == current_ip
+            && previous.records.iter().any(|r| {
+                r.zone == zone
+                    && r.name == name
+                    && matches!(
+                        r.state,
+                        SyncState::InSync | SyncState::Updated | SyncState::Reverted
+                    )
+            })
+    })
+}
+
+fn reconcile(
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+    current_ip: IpAddr,
+    previous: Option<&SyncReport>,
+) -> Result<SyncReport, Error> {
+    let ip = current_ip;
+    let record_type = ip::record_type(ip);
+    let current_ip = ip.to_string();
+    // Once per new IP, the daemon keeps trying until it passes.
+    if let Some(probe) = &settings.reachability_check {
+        if previous.is_none_or(|previous| previous.current_ip != current_ip) {
+            probe
+                .check_from(client, settings.reachability_checker.as_deref(), ip)
+                .map_err(|e| format!("{} is not reachable, not publishing it: {}", ip, e))?;
+            info!("{} is reachable", ip);
+        }
+    }
+    let mut records = Vec::new();
+    let mut outdated: Vec<(String, Record, Record, bool)> = Vec::new();
+    let mut guard = settings
+        .max_updates_per_hour
+        .map(|limit| RateGuard::load(limit, settings.update_history.clone()));
+    let now = SystemTime::now();
+    let closed = settings
+        .update_windows
+        .as_ref()
+        .is_some_and(|windows| !windows.allows(&windows.timezone.now()));
+    let outside_window = "updates are not allowed at this time, see update_windows";
+    let mut deregistered = Deregistered::load();
+    let mut fetched = Vec::new();
+    for (zone, result) in fetch_by_zone(settings, record_type) {
+        match result {
+            Ok(managed) => fetched.extend(managed.into_iter().map(|m| (zone, m))),
+            Err(e) => records.extend(zone_failed(zone, e)?),
+        }
+    }
+    for (zone, managed) in fetched {
+        match managed.record {
+            Some(mut record) => match current_ip != record.ip_addr
+                || !attribute_changes(&managed.zone, &record, &wanted(zone, &record)).is_empty()
+            {
+                true => match refusal(&record, settings)
+                    .map(|reason| (SyncState::Skipped, reason))
+                    .or_else(|| closed.then(|| (SyncState::Throttled, outside_window.to_string())))
+                    .or_else(|| {
+                        let zone = &managed.zone;
+                        rate_limited(client, settings, guard.as_ref(), zone, &record, &current_ip)
+                            .map(|reason| (SyncState::Throttled, reason))
+                    }) {
+                    Some((state, reason)) => {
+                        warn!("Not touching {}, {}", managed.name, reason);
+                        records.push(RecordReport {
+                            zone: managed.zone,
+                            name: managed.name,
+                            content: Some(record.ip_addr),
+                            state,
+                            error: Some(reason),
+                        });
+                    }
+                    None => {
+                        let drift = drifted(previous, &current_ip, &managed.zone, &managed.name);
+                        if drift {
+                            warn!(
+                                "{} was changed to {} behind our back, restoring {}",
+                                managed.name, record.ip_addr, current_ip
+                            );
+                        }
+                        if settings.mark_records {
+                            record.comment = Some(marked_comment(record.comment.as_deref()));
+                        }
+                        let wanted = wanted(zone, &record);
+                        for change in attribute_changes(&managed.zone, &record, &wanted) {
+                            let attribute = change.path.rsplit('/').next().unwrap_or_default();
+                            info!(
+                                "{} of {} is {}, setting it to {}",
+                                attribute, managed.name, change.old, change.value
+                            );
+                        }
+                        outdated.push((managed.zone, record, wanted, drift));
+                    }
+                },
+                false => {
+                    info!("Nothing to update for {}, DNS in sync", managed.name);
+                    records.push(RecordReport {
+                        zone: managed.zone,
+                        name: managed.name,
+                        content: Some(record.ip_addr),
+                        state: SyncState::InSync,
+                        error: None,
+                    });
+                }
+            },
+            None if !closed && creates_missing(zone, &managed.name, record_type, &deregistered) => {
+                let result = fleet::register(settings, zone, &managed.name, ip);
+                if result.is_ok() {
+                    deregistered.restored(&managed.zone, &managed.name, record_type);
+                }
+                records.push(target_report(&managed.zone, managed.name, ip, result));
+            }
+            None => {
+                warn!(
+                    "No {} record for subdomain {} found in zone {}",
+                    record_type, managed.name, managed.zone
+                );
+                records.push(RecordReport {
+                    zone: managed.zone,
+                    name: managed.name,
+                    content: None,
+                    state: SyncState::Missing,
+                    error: None,
+                });
+            }
+        }
+    }
+
+    let results = run_bounded(
+        settings.parallelism,
+        &outdated,
+        |(zone, record, wanted, _)| update_unchanged(settings, &current_ip, record, wanted, zone),
+    );
+    let mut updated = Vec::new();
+    let mut changes = Vec::new();
+    let mut moved_origins: Vec<&String> = Vec::new();
+    for ((zone, record, wanted, drift), result) in outdated.iter().zip(results) {
+        if let (Some(guard), Ok(())) = (&mut guard, &result) {
+            guard.record(zone, &record.name, now);
+        }
+        // Proxied records resolve to Cloudflare's edge, not to the content.
+        if result.is_ok() && !wanted.proxied {
+            updated.push(Expected {
+                name: record.name.clone(),
+                record_type,
+                content: ip,
+            });
+        } else if result.is_ok() && !drift && !moved_origins.contains(&zone) {
+            moved_origins.push(zone);
+        }
+        if result.is_ok() {
+            let name = &record.name;
+            if record.ip_addr != current_ip {
+                changes.push(Operation::content(
+                    zone,
+                    name,
+                    record_type,
+                    &record.ip_addr,
+                    &current_ip,
+                ));
+            }
+            changes.extend(attribute_changes(zone, record, wanted));
+        }
+        let report = match result {
+            Ok(()) if *drift => {
+                if let Some(url) = &settings.notify_url {
+                    let notification = Notification::Reverted {
+                        zone,
+                        name: &record.name,
+                        found: &record.ip_addr,
+                        restored: &current_ip,
+                    };
+                    notify::send(client, url, &notification);
+                }
+                RecordReport {
+                    zone: zone.clone(),
+                    name: record.name.clone(),
+                    content: Some(current_ip.clone()),
+                    state: SyncState::Reverted,
+                    error: None,
+                }
+            }
+            Ok(()) => RecordReport {
+                zone: zone.clone(),
+                name: record.name.clone(),
+                content: Some(current_ip.clone()),
+                state: SyncState::Updated,
+                error: None,
+            },
+            Err(e) => {
+                error!("{}", e);
+                if let Some(hint) = hints::hint(&e) {
+                    warn!("Hint: {}", hint);
+                }
+                RecordReport {
+                    zone: zone.clone(),
+                    name: record.name.clone(),
+                    content: Some(record.ip_addr.clone()),
+                    state: SyncState::Failed,
+                    error: Some(e.to_string()),
+                }
+            }
+        };
+        records.push(report);
+    }
+
+    if let Some(guard) = guard.filter(|_| !outdated.is_empty()) {
+        guard.save();
+    }
+    for zone in settings
+        .zones
+        .iter()
+        .filter(|zone| zone.purges_cache() && moved_origins.contains(&&zone.id))
+    {
+        if let Err(e) = purge_cache(settings.client(&zone.id), &zone.id, &zone.purge_urls) {
+            error!("Could not purge the cache of zone {}: {}", zone.id, e);
+        }
+    }
+
+    let mut report = SyncReport {
+        current_ip,
+        records,
+        changes,
+    };
+    if !outdated.is_empty() {
+        info!(
+            "Updated {} of {} outdated records",
+            report.count(SyncState::Updated),
+            outdated.len()
+        );
+    }
+    report
+        .records
+        .extend(lists::sync(&settings.cloudflare, &settings.ip_lists, ip));
+    report.records.extend(access::sync(
+        &settings.cloudflare,
+        &settings.access_policies,
+        ip,
+    ));
+    report.records.extend(pools::sync(
+        &settings.cloudflare,
+        &settings.pool_origins,
+        ip,
+    ));
+    report.records.extend(round_robin::sync(settings, ip));
+    report.records.extend(templated::sync(settings, ip));
+    published::save(settings, &report);
+    let summary = report.summary();
+    match summary.failed {
+        0 => info!("{}", summary),
+        _ => error!("{}", summary),
+    }
+    if let Some(url) = settings
+        .notify_url
+        .as_ref()
+        .filter(|_| settings.notify_summary)
+    {
+        if summary.eventful() {
+            notify::send(client, url, &Notification::Summary(&summary));
+        }
+    }
+    if settings.verify_propagation
+        && !updated.is_empty()
+        && propagation::verify(client, settings, &updated)
+    {
+        info!("All updates are visible on every resolver");
+    }
+    Ok(report)
+}
+
+/// Why `record` must not be updated to `current_ip` because it changed too
+/// often recently, if so. Sends a notification when it is.
+fn rate_limited(
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+    guard: Option<&RateGuard>,
+    zone: &str,
+    record: &Record,
+    current_ip: &str,
+) -> Option<String> {
+    let guard = guard?;
+    let now = SystemTime::now();
+    if guard.allows(zone, &record.name, now) {
+        return None;
+    }
+    let updates = guard.recent(zone, &record.name, now);
+    if let Some(url) = &settings.notify_url {
+        let notification = Notification::RateLimited {
+            zone,
+            name: &record.name,
+            published: &record.ip_addr,
+            detected: current_ip,
+            updates,
+        };
+        notify::send(client, url, &notification);
+    }
+    Some(format!(
+        "it was updated {} times in the last hour already, detection may be flapping",
+        updates
+    ))
+}
+
+/// Updates `record` unless someone else changed it since it was fetched at
+/// the start of the run. Cloudflare has no conditional PUT, so this narrows
+/// the window rather than closing it; a conflicting record is picked up fresh
+/// on the next run.
+fn update_unchanged(
+    settings: &Settings,
+    current_ip: &String,
+    record: &Record,
+    wanted: &Record,
+    zone: &String,
+) -> Result<(), Error> {
+    let latest = get_dns_record(settings.client(zone), record, zone)?;
+    if changed_since(record, &latest) {
+        return Err(format!(
+            "Record {} was changed by someone else at {}, not updating it this run",
+            record.name, latest.modified_on
+        )
+        .into());
+    }
+    let new_record = Record {
+        ip_addr: current_ip.to_string(),
+        ..wanted.clone()
+    };
+    replace_record(settings.client(zone), record, &new_record, zone)
+}
+
+/// `record` with the TTL and proxied flag `zone` asks for.
+pub fn wanted(zone: &ZoneConfig, record: &Record) -> Record {
+    cfdns_core::wanted(zone.proxied, zone.ttl, record)
+}
+
+/// Whether `latest` differs from the `fetched` copy in a way that means
+/// someone else touched it.
+fn changed_since(fetched: &Record, latest: &Record) -> bool {
+    fetched.modified_on != latest.modified_on || fetched.ip_addr != latest.ip_addr
+}
+
+/// Runs `job` over every item using at most `parallelism` worker threads and
+/// returns the results in the same order as `items`.
+pub fn run_bounded<T, R, F>(parallelism: usize, items: &[T], job: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let workers = parallelism.clamp(1, items.len().max(1));
+    let mut results: Vec<(usize, R)> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        match items.get(index) {
+                            Some(item) => done.push((index, job(item))),
+                            None => break done,
+                        }
+                    }
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("update worker panicked"))
+            .collect()
+    });
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_foreign_records_are_left_alone() {
+        let mut record = Record::default();
+        let mut settings = Settings::default();
+        assert!(may_modify(&record, &settings));
+
+        record.meta.managed_by_argo_tunnel = true;
+        assert_eq!(foreign_owner(&record), Some("Argo Tunnel"));
+        assert!(!may_modify(&record, &settings));
+
+        record.meta.managed_by_argo_tunnel = false;
+        record.meta.read_only = Some(true);
+        assert!(!may_modify(&record, &settings));
+        settings.force = true;
+        assert!(may_modify(&record, &settings));
+    }
+
+    #[test]
+    fn test_frozen_records_are_never_modified() {
+        frozen::set(frozen::Frozen::parse(&["frozen.example.org/MX".to_string()]).unwrap());
+        let mut record = Record {
+            name: "frozen.example.org".to_string(),
+            type_field: "MX".to_string(),
+            comment: Some(marked_comment(None)),
+            ..Default::default()
+        };
+        let settings = Settings {
+            force: true,
+            ..Settings::default()
+        };
+        assert!(!may_modify(&record, &settings));
+        assert!(frozen::check(&record).is_err());
+        assert!(frozen::check_new("frozen.example.org", "MX").is_err());
+        record.type_field = "A".to_string();
+        assert!(may_modify(&record, &settings));
+        assert!(frozen::check(&record).is_ok());
+    }
+
+    #[test]
+    fn test_only_records_cfdns_deleted_are_created_again() {
+        let zone = ZoneConfig {
+            id: "zone".to_string(),
+            records: vec![
+                "home.example.com".to_string(),
+                "hmoe.example.com".to_string(),
+            ],
+            ..ZoneConfig::default()
+        };
+        let deregistered: Deregistered =
+            serde_json::from_str(r#"[{"zone":"zone","name":"home.example.com","type":"A"}]"#)
+                .unwrap();
+        assert!(creates_missing(
+            &zone,
+            "home.example.com",
+            "A",
+            &deregistered
+        ));
+        assert!(!creates_missing(
+            &zone,
+            "home.example.com",
+            "AAAA",
+            &deregistered
+        ));
+        assert!(!creates_missing(
+            &zone,
+            "hmoe.example.com",
+            "A",
+            &deregistered
+        ));
+    }
+
+    #[test]
+    fn test_drift_needs_same_ip_and_known_good_record() {
+        let previous = SyncReport {
+            current_ip: "198.51.100.1".to_string(),
+            records: vec![RecordReport {
+                zone: "zone".to_string(),
+                name: "home.example.com".to_string(),
+                content: Some("198.51.100.1".to_string()),
+                state: SyncState::InSync,
+                error: None,
+            }],
+            changes: Vec::new(),
+        };
+        let previous = Some(&previous);
+        assert!(drifted(
+            previous,
+            "198.51.100.1",
+            "zone",
+            "home.example.com"
+        ));
+        assert!(!drifted(
+            previous,
+            "198.51.100.2",
+            "zone",
+            "home.example.com"
+        ));
+        assert!(!drifted(
+            previous,
+            "198.51.100.1",
+            "zone",
+            "vpn.example.com"
+        ));
+        assert!(!drifted(None, "198.51.100.1", "zone", "home.example.com"));
+    }
+
+    #[test]
+    fn test_concurrent_change_is_detected() {
+        let fetched = Record {
+            ip_addr: "198.51.100.1".to_string(),
+            modified_on: "2023-01-02T10:00:00Z".to_string(),
+            ..Default::default()
+        };
+        let mut latest = fetched.clone();
+        latest.comment = Some(marked_comment(None));
+        assert!(!changed_since(&fetched, &latest));
+        latest.modified_on = "2023-01-03T08:00:00Z".to_string();
+        assert!(changed_since(&fetched, &latest));
+    }
+
+    #[test]
+    fn test_ttl_and_proxied_drift_is_detected() {
+        let record = Record {
+            name: "home.example.com".to_string(),
+            type_field: "A".to_string(),
+            ttl: 300,
+            ..Default::default()
+        };
+        let mut zone = ZoneConfig::default();
+        assert!(attribute_changes("z", &record, &wanted(&zone, &record)).is_empty());
+
+        zone.ttl = Some(120);
+        let changes = attribute_changes("z", &record, &wanted(&zone, &record));
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].path, "/z/home.example.com/A/ttl");
+        assert_eq!(
+            (changes[0].old.as_str(), changes[0].value.as_str()),
+            ("300", "120")
+        );
+
+        // Proxying forces the automatic TTL whatever the zone asks for.
+        zone.proxied = Some(true);
+        let wanted = wanted(&zone, &record);
+        assert!(wanted.proxied && wanted.ttl == 1);
+        let paths: Vec<_> = attribute_changes("z", &record, &wanted)
+            .into_iter()
+            .map(|change| change.path)
+            .collect();
+        assert_eq!(
+            paths,
+            vec!["/z/home.example.com/A/proxied", "/z/home.example.com/A/ttl"]
+        );
+    }
+
+    #[test]
+    fn test_records_selected_by_comment_prefix() {
+        let record = |name: &str, type_field: &str, comment: Option<&str>| Record {
+            name: name.to_string(),
+            type_field: type_field.to_string(),
+            comment: comment.map(String::from),
+            ..Default::default()
+        };
+        let records = [
+            record("home.example.com", "A", Some("ddns: home router")),
+            record("nas.example.com", "A", Some("ddns:")),
+            record("nas.example.com", "AAAA", Some("ddns:")),
+            record("www.example.com", "A", Some("web ddns:")),
+            record("mail.example.com", "A", None),
+        ];
+        let mut zone = ZoneConfig {
+            records: vec![
+                "vpn.example.com".to_string(),
+                "home.example.com".to_string(),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(selected(&zone, &records, "A"), zone.records);
+        zone.comment_prefix = Some("ddns:".to_string());
+        assert_eq!(
+            selected(&zone, &records, "A"),
+            ["vpn.example.com", "home.example.com", "nas.example.com"]
+        );
+        assert_eq!(selected(&zone, &records, "AAAA")[2], "nas.example.com");
+    }
+
+    #[test]
+    fn test_safe_mode_requires_marker() {
+        let mut record = Record::default();
+        let settings = Settings {
+            safe_mode: true,
+            ..Settings::default()
+        };
+        assert!(!may_modify(&record, &settings));
+
+        record.comment = Some(marked_comment(Some("home router")));
+        assert_eq!(
+            record.comment.as_deref(),
+            Some("home router managed-by=cfdns")
+        );
+        assert!(may_modify(&record, &settings));
+        assert_eq!(
+            marked_comment(record.comment.as_deref()),
+            "home router managed-by=cfdns"
+        );
+        assert_eq!(marked_comment(None), OWNERSHIP_MARKER);
+    }
+
+    #[test]
+    fn test_summary_lists_failures() {
+        let record = |name: &str, state, error: Option<&str>| RecordReport {
+            zone: "z".to_string(),
+            name: name.to_string(),
+            content: None,
+            state,
+            error: error.map(str::to_string),
+        };
+        let report = SyncReport {
+            current_ip: "198.51.100.1".to_string(),
+            records: vec![
+                record("a.example.com", SyncState::InSync, None),
+                record("b.example.com", SyncState::Updated, None),
+                record("c.example.com", SyncState::Failed, Some("timed out")),
+            ],
+            changes: Vec::new(),
+        };
+        let summary = report.summary();
+        assert!(summary.eventful());
+        assert_eq!(
+            summary.to_string(),
+            "3 checked, 1 updated, 1 in sync, 1 failed\n  failed: c.example.com in z: timed out"
+        );
+        let quiet = SyncReport {
+            records: report.records[..1].to_vec(),
+            ..report
+        };
+        assert!(!quiet.summary().eventful());
+    }
+
+    #[test]
+    fn test_failed_zone_fails_only_its_records() {
+        let zone = ZoneConfig {
+            id: "z".to_string(),
+            records: vec!["a.example.com".to_string(), "b.example.com".to_string()],
+            ..ZoneConfig::default()
+        };
+        let reports = zone_failed(&zone, "Listing records failed: bad zone (7003)".into()).unwrap();
+        assert_eq!(reports.len(), 2);
+        assert!(reports.iter().all(|r| r.state == SyncState::Failed));
+        assert_eq!(
+            reports[1].error.as_deref(),
+            Some("Listing records failed: bad zone (7003)")
+        );
+        assert!(zone_failed(&zone, Unauthorized(401, None).into()).is_err());
+    }
+
+    #[test]
+    fn test_run_bounded_keeps_order() {
+        let items: Vec<u32> = (0..20).collect();
+        let doubled = run_bounded(4, &items, |n| n * 2);
+        assert_eq!(doubled, items.iter().map(|n| n * 2).collect::<Vec<_>>());
+    }
+}
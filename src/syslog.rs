@@ -0,0 +1,294 @@
+//! `syslog`: the log goes to syslog rather than stderr, for routers and
+//! NASes where syslog is the only log aggregation there is. `local` writes
+//! to `/dev/log` as syslog(3) does, `udp://`, `tcp://` and `tls://` send
+//! RFC 5424 messages to a remote server, framed by octet counting on TCP and
+//! TLS (RFC 6587, RFC 5425). TLS servers are verified against the public
+//! roots, or against `syslog_ca` for servers with a private CA.
+//!
+//! Until the config is loaded, and whenever the server can't be reached,
+//! messages are written to stderr as usual. Every message tries to
+//! reconnect.
+
+use std::io::Write;
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, TimeZone};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName};
+
+use crate::round_robin::host_id;
+use crate::Error;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// The facility of system daemons.
+const DAEMON: u8 = 3;
+const APP: &str = "cfdns";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Syslog {
+    /// `/dev/log`
+    Local,
+    Udp(String),
+    Tcp(String),
+    Tls {
+        address: String,
+        /// PEM file of the CA to verify the server with
+        ca: Option<PathBuf>,
+    },
+}
+
+impl FromStr for Syslog {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        if text == "local" {
+            return Ok(Syslog::Local);
+        }
+        if let Some(address) = text.strip_prefix("udp://") {
+            return Ok(Syslog::Udp(address.to_string()));
+        }
+        if let Some(address) = text.strip_prefix("tcp://") {
+            return Ok(Syslog::Tcp(address.to_string()));
+        }
+        if let Some(address) = text.strip_prefix("tls://") {
+            return Ok(Syslog::Tls {
+                address: address.to_string(),
+                ca: None,
+            });
+        }
+        Err(format!(
+            "syslog must be local, udp://, tcp:// or tls:// with host:port, not {:?}",
+            text
+        )
+        .into())
+    }
+}
+
+fn priority(level: log::Level) -> u8 {
+    let severity = match level {
+        log::Level::Error => 3,
+        log::Level::Warn => 4,
+        log::Level::Info => 6,
+        log::Level::Debug | log::Level::Trace => 7,
+    };
+    DAEMON * 8 + severity
+}
+
+/// `text` in the BSD format local syslog daemons read from `/dev/log`.
+fn local_message<Tz: TimeZone>(time: DateTime<Tz>, level: log::Level, text: &str) -> String
+where
+    Tz::Offset: std::fmt::Display,
+{
+    format!(
+        "<{}>{} {}[{}]: {}",
+        priority(level),
+        time.format("%b %e %H:%M:%S"),
+        APP,
+        std::process::id(),
+        text
+    )
+}
+
+/// `text` as an RFC 5424 message from `host`.
+fn message(host: &str, time: SystemTime, level: log::Level, text: &str) -> String {
+    format!(
+        "<{}>1 {} {} {} {} - - {}",
+        priority(level),
+        humantime::format_rfc3339_millis(time),
+        host,
+        APP,
+        std::process::id(),
+        text
+    )
+}
+
+fn tcp(address: &str) -> Result<TcpStream, Error> {
+    let addr = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| format!("{} has no address", address))?;
+    let stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    Ok(stream)
+}
+
+fn tls(address: &str, ca: Option<&Path>) -> Result<Box<dyn Write + Send>, Error> {
+    let mut roots = rustls::RootCertStore::empty();
+    match ca {
+        Some(ca) => {
+            for cert in CertificateDer::pem_file_iter(ca)
+                .map_err(|e| format!("Could not read {}: {}", ca.display(), e))?
+            {
+                roots.add(cert.map_err(|e| format!("Invalid {}: {}", ca.display(), e))?)?;
+            }
+        }
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()?
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+    let host = address.rsplit_once(':').map_or(address, |(host, _)| host);
+    let name = ServerName::try_from(host.trim_matches(['[', ']']).to_string())?;
+    let connection = rustls::ClientConnection::new(Arc::new(config), name)?;
+    Ok(Box::new(rustls::StreamOwned::new(
+        connection,
+        tcp(address)?,
+    )))
+}
+
+enum Connection {
+    #[cfg(unix)]
+    Local(std::os::unix::net::UnixDatagram),
+    Udp(UdpSocket),
+    Stream(Box<dyn Write + Send>),
+}
+
+struct Sender {
+    syslog: Syslog,
+    host: String,
+    connection: Option<Connection>,
+    /// The last message could not be sent
+    failing: bool,
+}
+
+impl Sender {
+    fn connect(&self) -> Result<Connection, Error> {
+        Ok(match &self.syslog {
+            #[cfg(unix)]
+            Syslog::Local => {
+                let socket = std::os::unix::net::UnixDatagram::unbound()?;
+                socket.connect("/dev/log")?;
+                Connection::Local(socket)
+            }
+            #[cfg(not(unix))]
+            Syslog::Local => return Err("There is no local syslog on this platform".into()),
+            Syslog::Udp(address) => {
+                let socket = UdpSocket::bind("0.0.0.0:0").or_else(|_| UdpSocket::bind("[::]:0"))?;
+                socket.connect(address.as_str())?;
+                Connection::Udp(socket)
+            }
+            Syslog::Tcp(address) => Connection::Stream(Box::new(tcp(address)?)),
+            Syslog::Tls { address, ca } => Connection::Stream(tls(address, ca.as_deref())?),
+        })
+    }
+
+    fn send(&mut self, level: log::Level, text: &str) -> Result<(), Error> {
+        if self.connection.is_none() {
+            self.connection = Some(self.connect()?);
+        }
+        let sent = match self.connection.as_mut().expect("connected above") {
+            #[cfg(unix)]
+            Connection::Local(socket) => socket
+                .send(local_message(chrono::Local::now(), level, text).as_bytes())
+                .map(drop),
+            Connection::Udp(socket) => socket
+                .send(message(&self.host, SystemTime::now(), level, text).as_bytes())
+                .map(drop),
+            Connection::Stream(stream) => {
+                let message = message(&self.host, SystemTime::now(), level, text);
+                write!(stream, "{} {}", message.len(), message).and_then(|_| stream.flush())
+            }
+        };
+        if sent.is_err() {
+            self.connection = None;
+        }
+        Ok(sent?)
+    }
+}
+
+static SYSLOG: Mutex<Option<Sender>> = Mutex::new(None);
+
+/// Where the log goes from now on, stderr unless `syslog` is given.
+pub fn set(syslog: Option<Syslog>) {
+    let mut sender = SYSLOG.lock().unwrap_or_else(|e| e.into_inner());
+    if sender.as_ref().map(|s| &s.syslog) != syslog.as_ref() {
+        *sender = syslog.map(|syslog| Sender {
+            syslog,
+            host: host_id(),
+            connection: None,
+            failing: false,
+        });
+    }
+}
+
+/// env_logger's filters, in front of stderr or syslog.
+struct Logger(env_logger::Logger);
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.0.matches(record) {
+            return;
+        }
+        let mut sender = SYSLOG.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(sender) = sender.as_mut() else {
+            return self.0.log(record);
+        };
+        match sender.send(record.level(), &record.args().to_string()) {
+            Ok(()) => sender.failing = false,
+            Err(e) => {
+                if !sender.failing {
+                    eprintln!("Could not log to syslog {:?}: {}", sender.syslog, e);
+                    sender.failing = true;
+                }
+                self.0.log(record);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        self.0.flush()
+    }
+}
+
+/// Installs the logger built by `builder`, writing to stderr until [`set`].
+pub fn init(mut builder: env_logger::Builder) {
+    let logger = builder.build();
+    let level = logger.filter();
+    log::set_boxed_logger(Box::new(Logger(logger))).expect("the logger is only installed once");
+    log::set_max_level(level);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages_in_both_formats() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let pid = std::process::id();
+        assert_eq!(
+            message("nas", time, log::Level::Warn, "Could not get the IP"),
+            format!(
+                "<28>1 2023-11-14T22:13:20.000Z nas cfdns {} - - Could not get the IP",
+                pid
+            )
+        );
+        let time = chrono::Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        assert_eq!(
+            local_message(time, log::Level::Info, "Updated home.example.com"),
+            format!(
+                "<30>Nov 14 22:13:20 cfdns[{}]: Updated home.example.com",
+                pid
+            )
+        );
+        assert_eq!(
+            "tls://logs.example.net:6514".parse::<Syslog>().unwrap(),
+            Syslog::Tls {
+                address: "logs.example.net:6514".to_string(),
+                ca: None
+            }
+        );
+        assert!("logs.example.net:514".parse::<Syslog>().is_err());
+    }
+}
@@ -0,0 +1,175 @@
+//! `cfdns new-host`: creates the standard bundle of records for a new host
+//! from the `new_host` template in the config file.
+//!
+//! Names and contents may use `{name}` (the host name given on the command
+//! line), `{domain}` and `{ip}`, which is the IPv4 address in A records, the
+//! IPv6 one in AAAA records and the first one given anywhere else.
+
+use std::net::IpAddr;
+
+use clap::Args;
+use serde_derive::Deserialize;
+
+use crate::cloudflare::models::NewRecord;
+use crate::config::Settings;
+use crate::sync::marked_comment;
+use crate::tunnel::zone_of;
+use crate::{create_record, ip, list_dns_records, names, validate, Error};
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HostTemplate {
+    /// Domain the hosts are created in, e.g. example.com
+    pub domain: String,
+    pub records: Vec<TemplateRecord>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateRecord {
+    #[serde(rename = "type")]
+    pub type_field: String,
+    /// Full name, `{name}.{domain}` unless given
+    pub name: Option<String>,
+    pub content: String,
+    #[serde(default)]
+    pub proxied: bool,
+    pub ttl: Option<i64>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct NewHostArgs {
+    /// Name of the host, e.g. gamernuc for gamernuc.example.com
+    name: String,
+    /// Address of the host, may be given for both families, default is the
+    /// detected public IP
+    #[arg(long)]
+    ip: Vec<IpAddr>,
+}
+
+/// The address `{ip}` stands for in a record of `record_type`.
+fn address_for(record_type: &str, addresses: &[IpAddr]) -> Option<IpAddr> {
+    match record_type {
+        "A" => addresses.iter().find(|a| a.is_ipv4()).copied(),
+        "AAAA" => addresses.iter().find(|a| a.is_ipv6()).copied(),
+        _ => addresses.first().copied(),
+    }
+}
+
+/// `record` filled in for `host`, `None` when it needs an address of a
+/// family there is none of.
+fn render(
+    template: &HostTemplate,
+    record: &TemplateRecord,
+    host: &str,
+    addresses: &[IpAddr],
+) -> Option<NewRecord> {
+    let address = address_for(&record.type_field, addresses);
+    let fill = |text: &str| -> Option<String> {
+        let text = text
+            .replace("{name}", host)
+            .replace("{domain}", &template.domain);
+        match (text.contains("{ip}"), address) {
+            (false, _) => Some(text),
+            (true, Some(address)) => Some(text.replace("{ip}", &address.to_string())),
+            (true, None) => None,
+        }
+    };
+    Some(NewRecord {
+        type_field: record.type_field.clone(),
+        name: fill(record.name.as_deref().unwrap_or("{name}.{domain}"))?,
+        content: fill(&record.content)?,
+        proxied: record.proxied,
+        ttl: record.ttl.unwrap_or(1),
+        comment: None,
+    })
+}
+
+pub fn new_host(
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+    args: &NewHostArgs,
+) -> Result<usize, Error> {
+    let Some(template) = &settings.new_host else {
+        return Err("No [new_host] template in the config file".into());
+    };
+    let addresses = match args.ip.is_empty() {
+        true => vec![ip::detect(client, settings)?],
+        false => args.ip.clone(),
+    };
+    let (zone, cloudflare) = zone_of(settings, &template.domain)?;
+    let host = names::to_ascii(&args.name)?;
+    let mut rendered = Vec::new();
+    for record in &template.records {
+        match render(template, record, &host, &addresses) {
+            Some(mut new) => {
+                new.name = names::to_ascii(&new.name)?;
+                if validate::holds_host_name(&new.type_field) {
+                    new.content = names::to_ascii(&new.content)?;
+                }
+                validate::content(&new.type_field, &new.name, &new.content)?;
+                rendered.push(new);
+            }
+            None => println!(
+                "Skipping the {} record, there is no address for it",
+                record.type_field
+            ),
+        }
+    }
+    let existing = list_dns_records(cloudflare, &zone)?;
+    let mut created = 0;
+    for mut new in rendered {
+        // Names may have several TXT records, but only one of the others.
+        let exists = existing.iter().any(|r| {
+            r.name == new.name
+                && r.type_field == new.type_field
+                && (new.type_field != "TXT" || r.ip_addr == new.content)
+        });
+        if exists {
+            println!("{} {} already exists, leaving it", new.type_field, new.name);
+            continue;
+        }
+        if settings.mark_records && new.type_field != "TXT" {
+            new.comment = Some(marked_comment(None));
+        }
+        create_record(cloudflare, &new, &zone)?;
+        println!("Created {} {} {}", new.type_field, new.name, new.content);
+        created += 1;
+    }
+    Ok(created)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_template_is_filled_in_per_family() {
+        let template: HostTemplate = toml::from_str(
+            r#"
+            domain = "example.com"
+            records = [
+                { type = "A", content = "{ip}" },
+                { type = "AAAA", content = "{ip}" },
+                { type = "CNAME", name = "www.{name}.{domain}", content = "{name}.{domain}" },
+                { type = "TXT", content = "managed-by=cfdns" },
+            ]
+            "#,
+        )
+        .unwrap();
+        let addresses = ["198.51.100.1".parse().unwrap()];
+        let rendered: Vec<Option<NewRecord>> = template
+            .records
+            .iter()
+            .map(|record| render(&template, record, "gamernuc", &addresses))
+            .collect();
+        let a = rendered[0].as_ref().unwrap();
+        assert_eq!(a.name, "gamernuc.example.com");
+        assert_eq!(a.content, "198.51.100.1");
+        assert_eq!(rendered[1], None);
+        let cname = rendered[2].as_ref().unwrap();
+        assert_eq!(cname.name, "www.gamernuc.example.com");
+        assert_eq!(cname.content, "gamernuc.example.com");
+        assert_eq!(rendered[3].as_ref().unwrap().content, "managed-by=cfdns");
+    }
+}
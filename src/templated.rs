@@ -0,0 +1,250 @@
+//! Records whose content is a template, `[[zones.templated]]`, filled in on
+//! every run so that one config serves many machines:
+//!
+//! * `{ip}` is the detected address, `{ipv4}` and `{ipv6}` the same but only
+//!   in runs detecting that family, the other runs leave the record alone
+//! * `{hostname}` is the name of the machine
+//! * `{env:NAME}` is the environment variable `NAME`
+//!
+//! Names may use the same placeholders. Records that don't exist yet are
+//! created, relative names are in the zone.
+
+use std::env;
+use std::net::IpAddr;
+
+use log::warn;
+use serde_derive::Deserialize;
+
+use crate::cloudflare::models::{NewRecord, Record};
+use crate::config::{Settings, ZoneConfig};
+use crate::round_robin::host_id;
+use crate::sync::{marked_comment, may_modify, RecordReport, SyncState};
+use crate::{create_record, list_dns_records, names, update_record, validate, Error};
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TemplatedRecord {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub type_field: String,
+    pub content: String,
+    #[serde(default)]
+    pub proxied: bool,
+    pub ttl: Option<i64>,
+}
+
+/// What placeholders stand for in one run.
+pub struct Vars {
+    pub ip: Option<IpAddr>,
+    pub hostname: String,
+    /// Looks up `{env:NAME}`
+    pub env: fn(&str) -> Option<String>,
+}
+
+impl Vars {
+    pub fn new(ip: Option<IpAddr>) -> Vars {
+        Vars {
+            ip,
+            hostname: host_id(),
+            env: |name| env::var(name).ok(),
+        }
+    }
+}
+
+/// `template` with each placeholder replaced by what `value` says it
+/// stands for, `None` as soon as one stands for nothing.
+fn fill(
+    template: &str,
+    mut value: impl FnMut(&str) -> Result<Option<String>, Error>,
+) -> Result<Option<String>, Error> {
+    let mut filled = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("Unclosed placeholder in {:?}", template))?;
+        match value(&rest[start + 1..start + end])? {
+            Some(value) => filled.push_str(&value),
+            None => return Ok(None),
+        }
+        rest = &rest[start + end + 1..];
+    }
+    filled.push_str(rest);
+    Ok(Some(filled))
+}
+
+fn unknown(placeholder: &str, template: &str) -> Error {
+    format!("Unknown placeholder {{{}}} in {:?}", placeholder, template).into()
+}
+
+/// Fails unless every placeholder of `template` is one cfdns knows, for
+/// checking the config before any run.
+pub fn check(template: &str) -> Result<(), Error> {
+    fill(template, |placeholder| match placeholder {
+        "ip" | "ipv4" | "ipv6" | "hostname" => Ok(Some(String::new())),
+        _ if placeholder.starts_with("env:") => Ok(Some(String::new())),
+        _ => Err(unknown(placeholder, template)),
+    })
+    .map(drop)
+}
+
+/// `template` filled in, `None` if it needs an `{ipv4}` or `{ipv6}` that
+/// this run didn't detect.
+pub fn render(template: &str, vars: &Vars) -> Result<Option<String>, Error> {
+    fill(template, |placeholder| match placeholder {
+        "ip" => Ok(vars.ip.map(|ip| ip.to_string())),
+        "ipv4" => Ok(vars.ip.filter(IpAddr::is_ipv4).map(|ip| ip.to_string())),
+        "ipv6" => Ok(vars.ip.filter(IpAddr::is_ipv6).map(|ip| ip.to_string())),
+        "hostname" => Ok(Some(vars.hostname.clone())),
+        _ => match placeholder.strip_prefix("env:") {
+            Some(name) => (vars.env)(name)
+                .map(Some)
+                .ok_or_else(|| format!("{} is not set, but {:?} needs it", name, template).into()),
+            None => Err(unknown(placeholder, template)),
+        },
+    })
+}
+
+/// Name of `zone`, for completing relative names, which the `records`
+/// listed in it have.
+pub fn zone_name(zone: &ZoneConfig, records: &[Record]) -> String {
+    records
+        .first()
+        .map(|r| r.zone_name.clone())
+        .unwrap_or_else(|| zone.id.clone())
+}
+
+/// The record `template` of `zone` stands for in this run, if any.
+fn wanted(
+    zone: &ZoneConfig,
+    records: &[Record],
+    template: &TemplatedRecord,
+    vars: &Vars,
+) -> Result<Option<NewRecord>, Error> {
+    let (Some(name), Some(content)) = (
+        render(&template.name, vars)?,
+        render(&template.content, vars)?,
+    ) else {
+        return Ok(None);
+    };
+    let name = names::qualify(&name, &zone_name(zone, records))?;
+    let content = match validate::holds_host_name(&template.type_field) {
+        true => names::to_ascii(&content)?,
+        false => content,
+    };
+    validate::content(&template.type_field, &name, &content)?;
+    Ok(Some(NewRecord {
+        type_field: template.type_field.clone(),
+        name,
+        content,
+        proxied: template.proxied,
+        ttl: template.ttl.unwrap_or(1),
+        comment: None,
+    }))
+}
+
+/// Creates or updates `wanted` among the `records` of `zone`.
+pub fn apply(
+    settings: &Settings,
+    zone: &String,
+    records: &[Record],
+    mut wanted: NewRecord,
+) -> Result<SyncState, Error> {
+    let existing = records
+        .iter()
+        .find(|r| r.name == wanted.name && r.type_field == wanted.type_field);
+    match existing {
+        Some(record) if record.ip_addr == wanted.content => Ok(SyncState::InSync),
+        Some(record) if !may_modify(record, settings) => Ok(SyncState::Skipped),
+        Some(record) => {
+            let mut record = record.clone();
+            if settings.mark_records {
+                record.comment = Some(marked_comment(record.comment.as_deref()));
+            }
+            update_record(settings.client(zone), &wanted.content, &record, zone)?;
+            Ok(SyncState::Updated)
+        }
+        None => {
+            wanted.comment = settings.mark_records.then(|| marked_comment(None));
+            create_record(settings.client(zone), &wanted, zone)?;
+            Ok(SyncState::Updated)
+        }
+    }
+}
+
+/// Brings every templated record in line with this run's `ip`.
+pub fn sync(settings: &Settings, ip: IpAddr) -> Vec<RecordReport> {
+    let vars = Vars::new(Some(ip));
+    let mut reports = Vec::new();
+    for zone in settings.zones.iter().filter(|z| !z.templated.is_empty()) {
+        let records = list_dns_records(settings.client(&zone.id), &zone.id);
+        for template in &zone.templated {
+            let result = records
+                .as_ref()
+                .map_err(|e| e.to_string().into())
+                .and_then(|records| {
+                    let wanted = wanted(zone, records, template, &vars)?;
+                    match wanted {
+                        Some(wanted) => apply(settings, &zone.id, records, wanted.clone())
+                            .map(|state| Some((wanted, state))),
+                        None => Ok(None),
+                    }
+                });
+            let report = match result {
+                Ok(None) => continue,
+                Ok(Some((wanted, state))) => RecordReport {
+                    zone: zone.id.clone(),
+                    name: wanted.name,
+                    content: Some(wanted.content),
+                    state,
+                    error: None,
+                },
+                Err(e) => {
+                    warn!(
+                        "Could not update the templated record {}: {}",
+                        template.name, e
+                    );
+                    RecordReport {
+                        zone: zone.id.clone(),
+                        name: template.name.clone(),
+                        content: None,
+                        state: SyncState::Failed,
+                        error: Some(e.to_string()),
+                    }
+                }
+            };
+            reports.push(report);
+        }
+    }
+    reports
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_templates_are_filled_in() {
+        let vars = Vars {
+            ip: Some("198.51.100.7".parse().unwrap()),
+            hostname: "edge-12".to_string(),
+            env: |name| (name == "SITE").then(|| "ams".to_string()),
+        };
+        let render = |template: &str| render(template, &vars);
+        assert_eq!(render("{ipv4}").unwrap().as_deref(), Some("198.51.100.7"));
+        assert_eq!(render("{ipv6}").unwrap(), None);
+        assert_eq!(
+            render("{hostname}.tunnel.example.net").unwrap().as_deref(),
+            Some("edge-12.tunnel.example.net")
+        );
+        assert_eq!(
+            render("v=site {env:SITE}").unwrap().as_deref(),
+            Some("v=site ams")
+        );
+        assert!(render("{env:UNSET}").is_err());
+        assert!(render("{nope}").is_err());
+        assert!(check("{ip").is_err() && check("{nope}.example.com").is_err());
+        assert!(check("{hostname}.{env:SITE}.example.com").is_ok());
+    }
+}
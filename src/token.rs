@@ -0,0 +1,276 @@
+//! Where the Cloudflare API token comes from, and getting a fresh one when
+//! Cloudflare stops accepting the current one.
+
+use std::env;
+use std::fmt;
+use std::path::PathBuf;
+
+use log::{info, warn};
+use reqwest::header::HeaderValue;
+use serde_derive::Deserialize;
+
+use crate::cloudflare::{self, CloudflareClient};
+use crate::config::Settings;
+use crate::secrets::SecretSource;
+use crate::Error;
+
+/// A value that must never end up in logs or error messages. Both `Debug`
+/// and `Display` print a placeholder; [`Secret::expose`] is the only way in.
+#[derive(Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Secret<T> {
+        Secret(value)
+    }
+
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[redacted]")
+    }
+}
+
+impl<T> fmt::Display for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[redacted]")
+    }
+}
+
+/// `Authorization` header value for `token`, flagged sensitive so that
+/// request dumps leave it out.
+pub fn bearer(token: &Secret<String>) -> HeaderValue {
+    let mut value = HeaderValue::from_str(&format!("Bearer {}", token.expose()))
+        .expect("tokens are valid header values");
+    value.set_sensitive(true);
+    value
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum TokenSource {
+    /// `token` in the config file
+    Inline(Secret<String>),
+    /// A file holding nothing but the token, rewritten by whatever rotates it
+    File(PathBuf),
+    /// `[token_source]`, a secret store
+    Store(SecretSource),
+    /// The `CF_TOKEN` environment variable
+    #[default]
+    Env,
+}
+
+impl TokenSource {
+    /// Picks the source from the config file, falling back to the environment:
+    /// `token`, `token_file`, `[token_source]`, the systemd credential,
+    /// `CF_TOKEN`, then `CF_TOKEN_FILE`.
+    pub fn choose(
+        token: Option<Secret<String>>,
+        token_file: Option<PathBuf>,
+        store: Option<SecretSource>,
+    ) -> TokenSource {
+        match (token, token_file, store) {
+            (Some(token), _, _) => TokenSource::Inline(token),
+            (None, Some(path), _) => TokenSource::File(path),
+            (None, None, Some(store)) => TokenSource::Store(store),
+            (None, None, None) => match systemd_credential() {
+                Some(path) => TokenSource::File(path),
+                None if env::var_os("CF_TOKEN").is_some() => TokenSource::Env,
+                None => match env::var_os("CF_TOKEN_FILE") {
+                    Some(path) => TokenSource::File(path.into()),
+                    None => TokenSource::Env,
+                },
+            },
+        }
+    }
+
+    /// Reads the token as it is right now.
+    pub fn resolve(&self) -> Result<Secret<String>, Error> {
+        let token = match self {
+            TokenSource::Inline(token) => return Ok(token.clone()),
+            TokenSource::File(path) => {
+                let token = std::fs::read_to_string(path)
+                    .map_err(|e| format!("could not read token file {}: {}", path.display(), e))?;
+                match token.trim() {
+                    "" => return Err(format!("token file {} is empty", path.display()).into()),
+                    token => token.to_string(),
+                }
+            }
+            TokenSource::Store(store) => store.fetch()?,
+            TokenSource::Env => env::var("CF_TOKEN").map_err(|_| "No CF_TOKEN set in env")?,
+        };
+        Ok(Secret::new(token))
+    }
+}
+
+/// `$CREDENTIALS_DIRECTORY/cf_token`, passed in by systemd's
+/// `LoadCredential=` or `SetCredentialEncrypted=`, if there is one.
+fn systemd_credential() -> Option<PathBuf> {
+    let path = PathBuf::from(env::var_os("CREDENTIALS_DIRECTORY")?).join("cf_token");
+    path.is_file().then_some(path)
+}
+
+/// Cloudflare answered 401 or 403, the token is wrong, expired or revoked.
+/// Comes with the Ray ID of the request if Cloudflare sent one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unauthorized(pub u16, pub Option<String>);
+
+impl fmt::Display for Unauthorized {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = format!("Cloudflare rejected the API token ({})", self.0);
+        f.write_str(&cloudflare::with_ray(message, self.1.as_deref()))
+    }
+}
+
+impl std::error::Error for Unauthorized {}
+
+/// Turns 401 and 403 answers into [`Unauthorized`] so callers can tell them
+/// apart from other failures.
+pub fn check_authorized(
+    response: reqwest::blocking::Response,
+) -> Result<reqwest::blocking::Response, Error> {
+    match response.status().as_u16() {
+        status @ (401 | 403) => Err(Unauthorized(status, cloudflare::ray_id(&response)).into()),
+        _ => Ok(response),
+    }
+}
+
+/// Runs `job`, and if Cloudflare rejected the token re-reads it from its
+/// source and runs `job` once more.
+pub fn retry_unauthorized<T, F>(settings: &mut Settings, mut job: F) -> Result<T, Error>
+where
+    F: FnMut(&Settings) -> Result<T, Error>,
+{
+    match job(settings) {
+        Err(e) if e.is::<Unauthorized>() => {
+            warn!("{}, re-reading the token and retrying once", e);
+            rotate(settings)?;
+            job(settings)
+        }
+        result => result,
+    }
+}
+
+/// Swaps in the tokens their sources hold now.
+pub fn rotate(settings: &mut Settings) -> Result<(), Error> {
+    if !settings.token.expose().is_empty() {
+        let token = settings.token_source.resolve()?;
+        match token == settings.token {
+            true => info!("Token unchanged"),
+            false => info!("Switched to a new API token"),
+        }
+        settings.cloudflare = CloudflareClient::new(&settings.connection, &token)?;
+        settings.token = token;
+    }
+    for account in &mut settings.accounts {
+        let token = account.token_source.resolve()?;
+        if token != account.token {
+            info!("Switched to a new API token for account {}", account.name);
+            account.cloudflare = CloudflareClient::new(&settings.connection, &token)?;
+            account.token = token;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    static LOGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    /// Keeps every log line, including those of reqwest and hyper.
+    struct Capture;
+
+    impl log::Log for Capture {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            let line = format!("{} {}: {}", record.level(), record.target(), record.args());
+            LOGS.lock().unwrap().push(line);
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn test_token_never_printed() {
+        const TOKEN: &str = "cf-test-token-4c1d2e";
+        log::set_boxed_logger(Box::new(Capture)).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+
+        let config: crate::config::Config =
+            toml::from_str(&format!("token = \"{TOKEN}\"\nhttp_token = \"{TOKEN}\"")).unwrap();
+        let mut settings = Settings {
+            token: TokenSource::Inline(config.token.clone().unwrap())
+                .resolve()
+                .unwrap(),
+            token_source: TokenSource::File(env::temp_dir().join("cfdns-no-such-token")),
+            http_token: config.http_token.clone(),
+            ..Settings::default()
+        };
+        let mut printed = vec![format!("{:?}", config), format!("{:?}", settings)];
+
+        // Cloudflare unreachable: the request fails on the way out.
+        let builder = reqwest::blocking::Client::builder()
+            .proxy(reqwest::Proxy::all("http://127.0.0.1:9").unwrap());
+        settings.cloudflare = CloudflareClient::from_builder(builder, &settings.token).unwrap();
+        let e = crate::list_dns_records(&settings.cloudflare, "zone").unwrap_err();
+        log::error!("{}", e);
+        printed.push(format!("{:?}", e));
+
+        // Rejected token that can't be re-read.
+        let e = retry_unauthorized(&mut settings, |_| {
+            Err::<(), _>(Unauthorized(401, None).into())
+        })
+        .unwrap_err();
+        log::error!("{}", e);
+        printed.push(format!("{:?}", e));
+
+        let panic = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            panic!("settings: {:?}", settings)
+        }))
+        .unwrap_err();
+        printed.push(panic.downcast_ref::<String>().unwrap().clone());
+
+        printed.extend(LOGS.lock().unwrap().iter().cloned());
+        assert!(printed.iter().any(|line| line.contains("[redacted]")));
+        for line in printed {
+            assert!(!line.contains(TOKEN), "token leaked: {}", line);
+        }
+    }
+
+    #[test]
+    fn test_token_file_is_reread() {
+        let path = env::temp_dir().join(format!("cfdns-token-test-{}", std::process::id()));
+        std::fs::write(&path, "old-token\n").unwrap();
+        let mut settings = Settings {
+            token_source: TokenSource::File(path.clone()),
+            ..Settings::default()
+        };
+        settings.token = settings.token_source.resolve().unwrap();
+        assert_eq!(settings.token.expose(), "old-token");
+
+        std::fs::write(&path, "new-token\n").unwrap();
+        let mut attempts = 0;
+        let result = retry_unauthorized(&mut settings, |settings| {
+            attempts += 1;
+            match settings.token.expose().as_str() {
+                "new-token" => Ok(()),
+                _ => Err(Unauthorized(403, None).into()),
+            }
+        });
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_ok());
+        assert_eq!(attempts, 2);
+        assert_eq!(settings.token.expose(), "new-token");
+    }
+}
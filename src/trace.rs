@@ -0,0 +1,173 @@
+//! `--trace-http`: logs the method, URL, status, latency and body of every
+//! HTTP request, to the Cloudflare API, echo services, secret stores and
+//! anything else. Every request is sent through [`Traced::send_traced`],
+//! which costs nothing while tracing is off.
+//!
+//! Headers aren't logged, so neither is the API token, and JSON values
+//! under keys that look like they hold a secret are replaced.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+use log::trace;
+use reqwest::blocking::{RequestBuilder, Response};
+use reqwest::ResponseBuilderExt;
+use serde_json::Value;
+
+/// The logger target, which `--trace-http` turns up to `trace`.
+pub const TARGET: &str = "cfdns::http";
+
+/// Bodies are cut off after this many bytes.
+const MAX_BODY: usize = 2048;
+
+/// Parts of JSON keys whose values are never logged.
+const SECRET_KEYS: &[&str] = &["token", "secret", "password", "credential", "jwt"];
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enable(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub trait Traced {
+    /// `send`, logging the request and response with `--trace-http`.
+    fn send_traced(self) -> reqwest::Result<Response>;
+}
+
+impl Traced for RequestBuilder {
+    fn send_traced(self) -> reqwest::Result<Response> {
+        if !ENABLED.load(Ordering::Relaxed) {
+            return self.send();
+        }
+        let (client, request) = self.build_split();
+        let request = request?;
+        let (method, url) = (request.method().clone(), request.url().clone());
+        match request.body().and_then(|body| body.as_bytes()) {
+            Some(body) => trace!(target: TARGET, "{} {} {}", method, url, redacted(body)),
+            None => trace!(target: TARGET, "{} {}", method, url),
+        }
+        let start = Instant::now();
+        let response = match client.execute(request) {
+            Ok(response) => response,
+            Err(e) => {
+                let elapsed = start.elapsed();
+                trace!(target: TARGET, "{} {} failed after {:?}: {}", method, url, elapsed, e);
+                return Err(e);
+            }
+        };
+        // The body has to be read to be logged, so the response is put
+        // back together around it.
+        let status = response.status();
+        let mut builder = http::Response::builder()
+            .status(status)
+            .version(response.version())
+            .url(response.url().clone());
+        for (name, value) in response.headers() {
+            builder = builder.header(name, value);
+        }
+        let body = response.bytes()?;
+        trace!(
+            target: TARGET,
+            "{} {} {} in {:?} {}",
+            method,
+            url,
+            status.as_u16(),
+            start.elapsed(),
+            redacted(&body)
+        );
+        Ok(builder
+            .body(body)
+            .expect("parts of a valid response")
+            .into())
+    }
+}
+
+fn secret_key(key: &str) -> bool {
+    let key = key.to_lowercase();
+    SECRET_KEYS.iter().any(|part| key.contains(part))
+}
+
+fn redact_value(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                match secret_key(key) && !value.is_object() && !value.is_array() {
+                    true => *value = Value::String("[redacted]".to_string()),
+                    false => redact_value(value),
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact_value),
+        _ => {}
+    }
+}
+
+/// What of `body` is fit to log.
+fn redacted(body: &[u8]) -> String {
+    if let Ok(mut value) = serde_json::from_slice::<Value>(body) {
+        redact_value(&mut value);
+        return truncated(value.to_string());
+    }
+    match std::str::from_utf8(body) {
+        Ok(text) => truncated(
+            text.trim()
+                .lines()
+                .map(redact_line)
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ),
+        Err(_) => format!("<{} bytes>", body.len()),
+    }
+}
+
+/// `line` without the value if it is a `key = value` or `key: value` one
+/// with a secret key, as in config files.
+fn redact_line(line: &str) -> String {
+    let Some(at) = line.find(['=', ':']) else {
+        return line.to_string();
+    };
+    let key = line[..at].trim().trim_matches(['"', '\'']);
+    match secret_key(key) {
+        true => format!("{} [redacted]", &line[..=at]),
+        false => line.to_string(),
+    }
+}
+
+fn truncated(mut text: String) -> String {
+    if text.len() > MAX_BODY {
+        let mut end = MAX_BODY;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+        text.push_str("...");
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secrets_are_redacted() {
+        assert_eq!(
+            redacted(br#"{"auth":{"client_token":"hvs.1234","lease_duration":60}}"#),
+            r#"{"auth":{"client_token":"[redacted]","lease_duration":60}}"#
+        );
+        assert_eq!(
+            redacted(br#"[{"SecretString":"cf-token","Name":"cfdns"}]"#),
+            r#"[{"Name":"cfdns","SecretString":"[redacted]"}]"#
+        );
+        assert_eq!(redacted(b"93.184.216.34\n"), "93.184.216.34");
+        assert_eq!(
+            redacted(b"token = \"cf-token\"\nzones = []\n"),
+            "token = [redacted]\nzones = []"
+        );
+        assert_eq!(redacted(&[0xff, 0xfe]), "<2 bytes>");
+        assert_eq!(
+            redacted("ä".repeat(MAX_BODY).as_bytes()).len(),
+            MAX_BODY + 3
+        );
+    }
+}
@@ -0,0 +1,252 @@
+//! `cfdns tunnel`: records served through a Cloudflare Tunnel, which are
+//! proxied CNAMEs to `<tunnel-id>.cfargotunnel.com`, next to the ones that
+//! follow the public IP.
+
+use clap::Subcommand;
+use log::info;
+use serde_json::Value;
+
+use crate::cloudflare::models::{NewRecord, Record};
+use crate::cloudflare::CloudflareClient;
+use crate::commands::Column;
+use crate::config::Settings;
+use crate::confirm::confirm;
+use crate::output::{self, Format};
+use crate::sync::{marked_comment, may_modify};
+use crate::{create_record, delete_record, list_dns_records, list_zones};
+use crate::{names, send_api, update_record, Error};
+
+const TUNNEL_DOMAIN: &str = "cfargotunnel.com";
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum TunnelCommand {
+    /// Point a record at a tunnel, creating the record if needed
+    Route {
+        /// Full record name, e.g. app.example.com
+        name: String,
+        /// Tunnel ID, or its name together with --account
+        #[arg(long)]
+        tunnel: String,
+        /// Account ID the tunnel belongs to, to look it up by name
+        #[arg(long)]
+        account: Option<String>,
+        /// Replace A/AAAA records of that name, which a CNAME can't coexist with
+        #[arg(long)]
+        replace: bool,
+        /// Replace them without asking for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// List records pointing at tunnels
+    List,
+}
+
+/// The tunnel ID a record points to, if it points to one.
+pub fn tunnel_of(record: &Record) -> Option<&str> {
+    let target = record.ip_addr.trim_end_matches('.');
+    match record.type_field.as_str() {
+        "CNAME" => target.strip_suffix(TUNNEL_DOMAIN)?.strip_suffix('.'),
+        _ => None,
+    }
+}
+
+/// Whether `text` already is a tunnel ID rather than a name.
+fn is_tunnel_id(text: &str) -> bool {
+    text.len() == 36
+        && text.chars().enumerate().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
+fn tunnel_id(settings: &Settings, tunnel: &str, account: Option<&str>) -> Result<String, Error> {
+    if is_tunnel_id(tunnel) {
+        return Ok(tunnel.to_lowercase());
+    }
+    let Some(account) = account else {
+        return Err(format!(
+            "{} is not a tunnel ID, pass --account to look it up",
+            tunnel
+        )
+        .into());
+    };
+    let tunnels = send_api(
+        settings
+            .cloudflare
+            .get(&format!("/accounts/{account}/cfd_tunnel"))
+            .query(&[("name", tunnel), ("is_deleted", "false")]),
+        "Tunnel lookup",
+    )?;
+    match tunnels.as_array().and_then(|t| t.first()).map(|t| &t["id"]) {
+        Some(Value::String(id)) => Ok(id.clone()),
+        _ => Err(format!("No tunnel called {} in account {}", tunnel, account).into()),
+    }
+}
+
+/// The ID of the accessible zone `name` belongs to, the longest match, and
+/// the client of the account it is in.
+pub fn zone_of<'a>(
+    settings: &'a Settings,
+    name: &str,
+) -> Result<(String, &'a CloudflareClient), Error> {
+    let mut zones = Vec::new();
+    for (_, cloudflare) in settings.clients() {
+        zones.extend(list_zones(cloudflare)?.into_iter().map(|z| (z, cloudflare)));
+    }
+    zones
+        .into_iter()
+        .filter(|(zone, _)| name == zone.name || name.ends_with(&format!(".{}", zone.name)))
+        .max_by_key(|(zone, _)| zone.name.len())
+        .map(|(zone, cloudflare)| (zone.id, cloudflare))
+        .ok_or_else(|| format!("No zone the token can access contains {}", name).into())
+}
+
+fn route(
+    settings: &Settings,
+    name: &str,
+    tunnel: &str,
+    account: Option<&str>,
+    replace: bool,
+    yes: bool,
+) -> Result<(), Error> {
+    let tunnel = tunnel_id(settings, tunnel, account)?;
+    let target = format!("{}.{}", tunnel, TUNNEL_DOMAIN);
+    let (zone, cloudflare) = zone_of(settings, name)?;
+    let records = list_dns_records(cloudflare, &zone)?;
+    let existing: Vec<&Record> = records.iter().filter(|r| r.name == name).collect();
+
+    if let Some(cname) = existing.iter().find(|r| r.type_field == "CNAME") {
+        if tunnel_of(cname) == Some(tunnel.as_str()) && cname.proxied {
+            info!("{} already routes to tunnel {}", name, tunnel);
+            return Ok(());
+        }
+        if !may_modify(cname, settings) {
+            return Err(format!("Not allowed to change {}", name).into());
+        }
+        let mut record = (*cname).clone();
+        record.proxied = true;
+        update_record(cloudflare, &target, &record, &zone)?;
+        info!("{} now routes to tunnel {}", name, tunnel);
+        return Ok(());
+    }
+
+    let addresses: Vec<&&Record> = existing
+        .iter()
+        .filter(|r| matches!(r.type_field.as_str(), "A" | "AAAA"))
+        .collect();
+    if !addresses.is_empty() && !replace {
+        return Err(format!(
+            "{} has {} address record(s), pass --replace to swap them for the tunnel",
+            name,
+            addresses.len()
+        )
+        .into());
+    }
+    if let Some(record) = addresses.iter().find(|r| !may_modify(r, settings)) {
+        return Err(format!("Not allowed to replace {} {}", record.type_field, name).into());
+    }
+    if !addresses.is_empty() {
+        for record in &addresses {
+            println!(
+                "{} {} {}",
+                record.type_field,
+                names::display(name),
+                record.ip_addr
+            );
+        }
+        let question = format!("Delete these {} records for the tunnel?", addresses.len());
+        if !confirm(&question, yes)? {
+            info!("Nothing changed");
+            return Ok(());
+        }
+    }
+    for record in addresses {
+        delete_record(cloudflare, record, &zone)?;
+    }
+
+    let record = NewRecord {
+        type_field: "CNAME".to_string(),
+        name: name.to_string(),
+        content: target,
+        // Tunnels are only reachable through Cloudflare's proxy.
+        proxied: true,
+        ttl: 1,
+        comment: settings.mark_records.then(|| marked_comment(None)),
+    };
+    create_record(cloudflare, &record, &zone)?;
+    info!("{} now routes to tunnel {}", name, tunnel);
+    Ok(())
+}
+
+/// Tunnel records in the configured zones, or in every zone without any.
+fn list(settings: &Settings, format: Format) -> Result<(), Error> {
+    let mut zones = Vec::new();
+    match settings.zones.is_empty() {
+        true => {
+            for (_, cloudflare) in settings.clients() {
+                zones.extend(
+                    list_zones(cloudflare)?
+                        .into_iter()
+                        .map(|z| (z.id, cloudflare)),
+                );
+            }
+        }
+        false => zones.extend(
+            settings
+                .zones
+                .iter()
+                .map(|zone| (zone.id.clone(), settings.client(&zone.id))),
+        ),
+    }
+    let mut tunneled = Vec::new();
+    for (zone, cloudflare) in &zones {
+        let records = list_dns_records(cloudflare, zone)?;
+        tunneled.extend(records.into_iter().filter(|r| tunnel_of(r).is_some()));
+    }
+    let columns = [Column::Zone, Column::Name, Column::Content, Column::Proxied];
+    println!("{}", output::render_columns(format, &tunneled, &columns)?);
+    Ok(())
+}
+
+pub fn run(settings: &Settings, command: &TunnelCommand, format: Format) -> Result<(), Error> {
+    match command {
+        TunnelCommand::Route {
+            name,
+            tunnel,
+            account,
+            replace,
+            yes,
+        } => route(
+            settings,
+            &names::to_ascii(name)?,
+            tunnel,
+            account.as_deref(),
+            *replace,
+            *yes,
+        ),
+        TunnelCommand::List => list(settings, format),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tunnel_records_are_recognized() {
+        let record = |type_field: &str, content: &str| Record {
+            type_field: type_field.to_string(),
+            ip_addr: content.to_string(),
+            ..Default::default()
+        };
+        let id = "c1744f8b-faa1-48a4-9e5c-02ac921467fa";
+        assert_eq!(
+            tunnel_of(&record("CNAME", &format!("{}.cfargotunnel.com", id))),
+            Some(id)
+        );
+        assert_eq!(tunnel_of(&record("CNAME", "example.com")), None);
+        assert_eq!(tunnel_of(&record("A", "203.0.113.7")), None);
+        assert!(is_tunnel_id(id));
+        assert!(!is_tunnel_id("homelab"));
+    }
+}
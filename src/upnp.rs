@@ -0,0 +1,142 @@
+//! Just enough UPnP IGD to ask the router for its WAN address.
+
+use std::net::{IpAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use log::debug;
+
+use crate::trace::Traced;
+use crate::Error;
+
+const SSDP_ADDRESS: &str = "239.255.255.250:1900";
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The external address the router on this network reports.
+pub fn wan_address(client: &reqwest::blocking::Client) -> Result<IpAddr, Error> {
+    let location = discover()?;
+    debug!("Found internet gateway at {}", location);
+    let description = client
+        .get(&location)
+        .send_traced()
+        .and_then(|response| response.error_for_status())
+        .and_then(|response| response.text())
+        .map_err(|e| format!("could not read gateway description: {}", e))?;
+    let (service, control) =
+        find_wan_service(&description).ok_or("gateway offers no WAN connection service")?;
+    let control = reqwest::Url::parse(&location)
+        .and_then(|base| base.join(&control))
+        .map_err(|e| format!("invalid gateway control URL {}: {}", control, e))?;
+
+    let body = format!(
+        concat!(
+            r#"<?xml version="1.0"?>"#,
+            r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" "#,
+            r#"s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">"#,
+            r#"<s:Body><u:GetExternalIPAddress xmlns:u="{}"/></s:Body></s:Envelope>"#
+        ),
+        service
+    );
+    let answer = client
+        .post(control)
+        .header("Content-Type", "text/xml; charset=\"utf-8\"")
+        .header(
+            "SOAPAction",
+            format!("\"{}#GetExternalIPAddress\"", service),
+        )
+        .body(body)
+        .send_traced()
+        .and_then(|response| response.error_for_status())
+        .and_then(|response| response.text())
+        .map_err(|e| format!("gateway did not tell its external address: {}", e))?;
+    let address = element(&answer, "NewExternalIPAddress")
+        .ok_or("gateway answer has no NewExternalIPAddress")?;
+    address
+        .trim()
+        .parse()
+        .map_err(|_| format!("gateway reported {:?} as its address", address).into())
+}
+
+/// Sends an SSDP search and returns the description URL of the first
+/// internet gateway that answers.
+fn discover() -> Result<String, Error> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    let search = format!(
+        "M-SEARCH * HTTP/1.1\r\nHOST: {}\r\nMAN: \"ssdp:discover\"\r\nMX: 1\r\n\
+         ST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\r\n",
+        SSDP_ADDRESS
+    );
+    socket.send_to(search.as_bytes(), SSDP_ADDRESS)?;
+
+    let deadline = Instant::now() + DISCOVERY_TIMEOUT;
+    let mut buffer = [0; 2048];
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err("no UPnP internet gateway answered".into());
+        }
+        socket.set_read_timeout(Some(left))?;
+        let Ok((length, _)) = socket.recv_from(&mut buffer) else {
+            return Err("no UPnP internet gateway answered".into());
+        };
+        let answer = String::from_utf8_lossy(&buffer[..length]);
+        let location = answer.lines().find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.eq_ignore_ascii_case("location")
+                .then(|| value.trim().to_string())
+        });
+        if let Some(location) = location {
+            return Ok(location);
+        }
+    }
+}
+
+/// Service type and control URL of the WANIPConnection or WANPPPConnection
+/// service in a device description.
+fn find_wan_service(description: &str) -> Option<(String, String)> {
+    description.split("<service>").skip(1).find_map(|service| {
+        let service_type = element(service, "serviceType")?;
+        let wan = service_type.contains(":WANIPConnection:")
+            || service_type.contains(":WANPPPConnection:");
+        match wan {
+            true => Some((
+                service_type.trim().to_string(),
+                element(service, "controlURL")?.trim().to_string(),
+            )),
+            false => None,
+        }
+    })
+}
+
+/// Text of the first `<name>` element, ignoring any namespace prefix.
+fn element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("{}>", name))? + name.len() + 1;
+    let end = start + xml[start..].find("</")?;
+    Some(&xml[start..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_gateway_answers() {
+        let description = r#"<root><device><serviceList>
+            <service><serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType>
+            <controlURL>/ctl/L3F</controlURL></service>
+            <service><serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>
+            <controlURL>/ctl/IPConn</controlURL></service>
+            </serviceList></device></root>"#;
+        assert_eq!(
+            find_wan_service(description),
+            Some((
+                "urn:schemas-upnp-org:service:WANIPConnection:1".to_string(),
+                "/ctl/IPConn".to_string()
+            ))
+        );
+
+        let answer = r#"<s:Envelope><s:Body><u:GetExternalIPAddressResponse>
+            <NewExternalIPAddress>100.72.14.3</NewExternalIPAddress>
+            </u:GetExternalIPAddressResponse></s:Body></s:Envelope>"#;
+        assert_eq!(element(answer, "NewExternalIPAddress"), Some("100.72.14.3"));
+    }
+}
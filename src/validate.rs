@@ -0,0 +1,93 @@
+//! Checks record contents before they are sent, so that a typo in a
+//! template or a broken restore fails with a clear error instead of
+//! whatever Cloudflare makes of it, and before any record is touched.
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::Error;
+
+/// Cloudflare's limit on the content of a TXT record.
+pub const MAX_TXT_LENGTH: usize = 2048;
+
+/// Whether `name` is a host name: dot separated labels of letters, digits,
+/// hyphens and underscores, none of them empty or longer than 63 bytes, and
+/// no more than 253 bytes in all. A trailing dot is fine.
+pub fn is_host_name(name: &str) -> bool {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    !name.is_empty()
+        && name.len() <= 253
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+}
+
+/// Whether records of `record_type` point at a host name.
+pub fn holds_host_name(record_type: &str) -> bool {
+    matches!(record_type, "CNAME" | "MX" | "NS" | "PTR")
+}
+
+/// Why `content` can't be the content of a `record_type` record, if so.
+fn problem(record_type: &str, content: &str) -> Option<String> {
+    match record_type {
+        "A" if content.parse::<Ipv4Addr>().is_err() => {
+            Some(format!("{:?} is not an IPv4 address", content))
+        }
+        "AAAA" if content.parse::<Ipv6Addr>().is_err() => {
+            Some(format!("{:?} is not an IPv6 address", content))
+        }
+        _ if holds_host_name(record_type) && !is_host_name(content) => {
+            Some(format!("{:?} is not a host name", content))
+        }
+        "TXT" if content.len() > MAX_TXT_LENGTH => Some(format!(
+            "it is {} bytes long, TXT records hold at most {}",
+            content.len(),
+            MAX_TXT_LENGTH
+        )),
+        _ => None,
+    }
+}
+
+/// Fails unless `content` fits a `record_type` record called `name`.
+pub fn content(record_type: &str, name: &str, content: &str) -> Result<(), Error> {
+    match problem(record_type, content) {
+        Some(problem) => Err(format!(
+            "Invalid content for {} record {}: {}",
+            record_type, name, problem
+        )
+        .into()),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contents_are_checked_per_type() {
+        assert!(content("A", "home.example.com", "198.51.100.1").is_ok());
+        assert!(content("A", "home.example.com", "2001:db8::1").is_err());
+        assert!(content("AAAA", "home.example.com", "2001:db8::1").is_ok());
+        assert!(content("AAAA", "home.example.com", "198.51.100.1").is_err());
+        assert!(content("CNAME", "www.example.com", "home.example.com.").is_ok());
+        assert!(content("CNAME", "_acme.example.com", "_acme.dns.example.net").is_ok());
+        assert!(content("MX", "example.com", "10 mail.example.com").is_err());
+        assert!(content("CNAME", "www.example.com", "-home.example.com").is_err());
+        assert!(content("CNAME", "www.example.com", "home..example.com").is_err());
+        assert!(content("TXT", "example.com", &"x".repeat(MAX_TXT_LENGTH)).is_ok());
+        assert!(content("SRV", "example.com", "anything").is_ok());
+        assert_eq!(
+            content("TXT", "example.com", &"x".repeat(3000))
+                .unwrap_err()
+                .to_string(),
+            "Invalid content for TXT record example.com: it is 3000 bytes long, TXT records hold at most 2048"
+        );
+        assert!(!is_host_name(&format!("{}.com", "a".repeat(64))));
+    }
+}
@@ -0,0 +1,114 @@
+//! `cfdns version`: what exactly is running, for bug reports and for
+//! telling fleet machines apart. The build details come from `build.rs`.
+
+use clap::Args;
+use log::{info, warn};
+use serde_derive::Serialize;
+
+use crate::output::{self, Format, Tabular};
+use crate::self_update::{github_client, latest_release, newer};
+use crate::Error;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// What `--version` prints.
+pub const LONG: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("CFDNS_GIT_COMMIT"),
+    " ",
+    env!("CFDNS_BUILD_DATE"),
+    ") ",
+    env!("CFDNS_TARGET")
+);
+
+#[derive(Args, Debug, Clone)]
+pub struct VersionArgs {
+    /// Also look up whether there is a newer release
+    #[arg(long)]
+    check: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub commit: &'static str,
+    pub build_date: &'static str,
+    pub target: &'static str,
+    pub features: Vec<&'static str>,
+    /// Newest release, only with `--check`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latest: Option<String>,
+}
+
+impl BuildInfo {
+    pub fn current() -> BuildInfo {
+        BuildInfo {
+            version: VERSION,
+            commit: env!("CFDNS_GIT_COMMIT"),
+            build_date: env!("CFDNS_BUILD_DATE"),
+            target: env!("CFDNS_TARGET"),
+            features: features(env!("CFDNS_FEATURES")),
+            latest: None,
+        }
+    }
+}
+
+fn features(list: &'static str) -> Vec<&'static str> {
+    list.split(',').filter(|name| !name.is_empty()).collect()
+}
+
+impl Tabular for BuildInfo {
+    fn headers() -> Vec<&'static str> {
+        vec!["VERSION", "COMMIT", "BUILT", "TARGET", "FEATURES", "LATEST"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.version.to_string(),
+            self.commit.to_string(),
+            self.build_date.to_string(),
+            self.target.to_string(),
+            self.features.join(", "),
+            self.latest.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+pub fn run(args: &VersionArgs, format: Format) -> Result<(), Error> {
+    let mut build = BuildInfo::current();
+    if args.check {
+        let release = latest_release(&github_client()?)?;
+        build.latest = Some(release.version().to_string());
+    }
+    output::print(format, &[build])
+}
+
+/// Logs whether a newer release is out, for daemons with `update_check` on.
+/// Failing to look it up is not worth more than a warning.
+pub fn log_update_check() {
+    match github_client().and_then(|client| latest_release(&client)) {
+        Ok(release) if newer(release.version(), VERSION) => warn!(
+            "cfdns {} is available, this is {}",
+            release.version(),
+            VERSION
+        ),
+        Ok(_) => info!("cfdns {} is the latest release", VERSION),
+        Err(e) => warn!("Could not check for a newer release: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_info() {
+        assert_eq!(features(""), Vec::<&str>::new());
+        assert_eq!(features("aws,vault"), vec!["aws", "vault"]);
+        let build = BuildInfo::current();
+        assert!(!build.commit.is_empty());
+        assert_eq!(build.build_date.len(), "2024-01-01".len());
+        assert!(LONG.starts_with(VERSION));
+    }
+}
@@ -0,0 +1,91 @@
+// cfdns as a Cloudflare Worker: the home machine reports its address with a
+// request to /ping, and a cron trigger points the records of ZONES at it.
+// Which records change, and how, is decided by cfdns-core built for wasm32,
+// see "Cloudflare Worker" in the README.
+import init, { plan } from "./pkg/cfdns_core.js";
+import wasm from "./pkg/cfdns_core_bg.wasm";
+
+const API = "https://api.cloudflare.com/client/v4";
+// Records asked for per page when listing the records of a zone, as cfdns does.
+const RECORDS_PER_PAGE = 500;
+
+let ready;
+
+async function api(env, path, options = {}) {
+  const response = await fetch(API + path, {
+    ...options,
+    headers: {
+      Authorization: `Bearer ${env.CF_TOKEN}`,
+      "Content-Type": "application/json",
+    },
+  });
+  const body = await response.json();
+  if (!body.success) {
+    throw new Error(`${path} failed: ${JSON.stringify(body.errors)}`);
+  }
+  return body;
+}
+
+// The `type` records of zone `id`, from all pages of the listing.
+async function records(env, id, type) {
+  const records = [];
+  for (let page = 1; ; page++) {
+    const path = `/zones/${id}/dns_records?type=${type}&per_page=${RECORDS_PER_PAGE}&page=${page}`;
+    const body = await api(env, path);
+    records.push(...body.result);
+    if (!body.result_info || page >= body.result_info.total_pages) {
+      return records;
+    }
+  }
+}
+
+async function reconcile(env) {
+  const ip = await env.CFDNS.get("ip");
+  if (!ip) {
+    console.log("No address reported to /ping yet");
+    return;
+  }
+  ready ??= init(wasm);
+  await ready;
+  const type = ip.includes(":") ? "AAAA" : "A";
+  for (const zone of JSON.parse(env.ZONES)) {
+    const listed = await records(env, zone.id, type);
+    const result = JSON.parse(plan(JSON.stringify(zone), JSON.stringify(listed), ip));
+    for (const name of result.missing) {
+      console.log(`No ${type} record for ${name} found in zone ${zone.id}`);
+    }
+    for (const [name, reason] of result.skipped) {
+      console.log(`Not touching ${type} ${name}, ${reason}`);
+    }
+    for (const update of result.updates) {
+      await api(env, `/zones/${zone.id}/dns_records/${update.id}`, {
+        method: "PATCH",
+        body: JSON.stringify({
+          content: update.content,
+          proxied: update.proxied,
+          ttl: update.ttl,
+        }),
+      });
+      console.log(`Updated ${update.name} to ${update.content}`);
+    }
+  }
+}
+
+export default {
+  async fetch(request, env) {
+    if (new URL(request.url).pathname !== "/ping") {
+      return new Response("Not found\n", { status: 404 });
+    }
+    const token = request.headers.get("Authorization");
+    if (!env.PING_TOKEN || token !== `Bearer ${env.PING_TOKEN}`) {
+      return new Response("Unauthorized\n", { status: 401 });
+    }
+    const ip = request.headers.get("CF-Connecting-IP");
+    await env.CFDNS.put("ip", ip);
+    return new Response(`${ip}\n`);
+  },
+
+  async scheduled(event, env, ctx) {
+    ctx.waitUntil(reconcile(env));
+  },
+};